@@ -0,0 +1,31 @@
+//! Elects leaders in simulated rings of growing size and reports how long
+//! each election took and how many messages it cost, e.g.
+//!
+//! ```sh
+//! cargo bench --bench election > /dev/null
+//! cargo bench --bench election -- 100 200 > /dev/null
+//! ```
+//!
+//! for the default sizes or the ones given. The nodes print their message
+//! log to stdout, hence the redirection; the results go to stderr.
+use std::time::{Duration, Instant};
+
+use grpc_le::simulation::{Delivery, Simulation};
+
+const SIZES: [u16; 4] = [10, 100, 1_000, 10_000];
+/// Far more virtual time than any of these elections needs.
+const LIMIT: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn main() {
+    let sizes = std::env::args().skip(1).filter_map(|arg| arg.parse::<u16>().ok()).collect::<Vec<_>>();
+    eprintln!("{:>6} {:>12} {:>10} {:>12}", "nodes", "wall clock", "messages", "virtual time");
+    for &size in if sizes.is_empty() { &SIZES[..] } else { &sizes } {
+        // scatter the IDs so the smallest is not always first
+        let ids = (0..size).map(|i| (i as u64 * 65537 % size as u64) as u16 + 1).collect::<Vec<_>>();
+        let start = Instant::now();
+        let report = Simulation::new(&ids, Delivery::Shuffled(size.into())).until_decided().run(LIMIT);
+        let elapsed = start.elapsed();
+        assert_eq!(report.leader(), Some(1), "a ring of {} ended in {:?}", size, report.results);
+        eprintln!("{:>6} {:>12.3?} {:>10} {:>12.3?}", size, elapsed, report.delivered, report.elapsed);
+    }
+}
@@ -0,0 +1,203 @@
+//! Runs the same elections on rings of nodes served on localhost, once with
+//! each message a call of its own to the neighbour's `ProbeRaw`,
+//! `NotifyElectedRaw` or `CheckDigestRaw`, and once over the per-neighbour
+//! `Relay` streams the nodes use, and reports how long the elections took,
+//! how many messages they cost and how many gRPC calls carried them, e.g.
+//!
+//! ```sh
+//! cargo bench --bench relay
+//! cargo bench --bench relay -- 3 20
+//! ```
+//!
+//! for the default ring sizes or the ones given. Both modes keep one
+//! connection to each neighbour, so they differ in the calls made over it.
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::stream;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::Channel;
+use tonic::Status;
+
+use grpc_le::config::{Config, TimingConfig};
+use grpc_le::leader_election_service::leader_election_service_client::LeaderElectionServiceClient;
+use grpc_le::leader_election_service::{peer_message::Body, PeerAck, PeerMessage, Sequence};
+use grpc_le::testkit::free_addrs;
+use grpc_le::topology::{Link, NodeSpec};
+use grpc_le::transport::{Acks, Opening, Peer, Transport};
+use grpc_le::{run_node, ElectionResult, Node};
+
+const SIZES: [u16; 3] = [3, 5, 10];
+/// Elections timed on each ring, after the first.
+const ROUNDS: u64 = 10;
+/// Far longer than any of these elections takes.
+const LIMIT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Unary,
+    Relay,
+}
+
+/// How many relay streams a node opened and messages it sent over them.
+#[derive(Debug, Default)]
+struct Counts {
+    streams: AtomicU64,
+    messages: AtomicU64,
+}
+
+/// Counts the streams another transport opens and the messages it relays.
+#[derive(Debug)]
+struct Counting {
+    inner: Arc<dyn Transport>,
+    counts: Arc<Counts>,
+}
+
+impl Transport for Counting {
+    fn relay<'a>(&'a self, peer: &'a Peer, mut messages: mpsc::Receiver<PeerMessage>) -> Opening<'a> {
+        self.counts.streams.fetch_add(1, Ordering::Relaxed);
+        let (counted, rx) = mpsc::channel(16);
+        let counts = self.counts.clone();
+        tokio::spawn(async move {
+            while let Some(message) = messages.recv().await {
+                counts.messages.fetch_add(1, Ordering::Relaxed);
+                if counted.send(message).await.is_err() {
+                    return
+                }
+            }
+        });
+        self.inner.relay(peer, rx)
+    }
+}
+
+/// Sends each message relayed through it as a call of its own to the
+/// `*Raw` RPC of its kind, waiting for the answer before the next.
+#[derive(Debug)]
+struct Unary;
+
+impl Transport for Unary {
+    fn relay<'a>(&'a self, peer: &'a Peer, mut messages: mpsc::Receiver<PeerMessage>) -> Opening<'a> {
+        Box::pin(async move {
+            let mut client = LeaderElectionServiceClient::connect(peer.endpoint.clone()).await?;
+            let (acks, rx) = mpsc::channel(16);
+            let id = peer.id;
+            tokio::spawn(async move {
+                while let Some(message) = messages.recv().await {
+                    // the node sends the message again over a new stream
+                    let Ok(ack) = call(&mut client, id, message).await else { return };
+                    if acks.send(ack).await.is_err() {
+                        return
+                    }
+                }
+            });
+            Ok(Box::pin(ReceiverStream::new(rx)) as Acks)
+        })
+    }
+}
+
+/// Sends `message` to node `peer` in a call of its own, acknowledging it
+/// with the answer.
+async fn call(client: &mut LeaderElectionServiceClient<Channel>, peer: u64, message: PeerMessage) -> Result<PeerAck, Status> {
+    let number = |seq: &Option<Sequence>| seq.as_ref().map_or(0, |seq| seq.number);
+    let answered = || Status::unavailable("no answer");
+    let (number, decision, responder_state, responder_phase) = match message.body {
+        Some(Body::Probe(probe)) => {
+            let number = number(&probe.seq);
+            let answer = client.probe_raw(stream::iter([probe])).await?.into_inner().message().await?.ok_or_else(answered)?;
+            (number, answer.decision, answer.responder_state, answer.responder_phase)
+        },
+        Some(Body::Notify(notify)) => {
+            let number = number(&notify.seq);
+            let answer = client.notify_elected_raw(stream::iter([notify])).await?.into_inner().message().await?.ok_or_else(answered)?;
+            (number, answer.decision, 0, 0)
+        },
+        Some(Body::Digest(digest)) => {
+            let number = number(&digest.seq);
+            let answer = client.check_digest_raw(stream::iter([digest])).await?.into_inner().message().await?.ok_or_else(answered)?;
+            (number, answer.decision, 0, 0)
+        },
+        None => return Err(Status::invalid_argument("empty relayed message")),
+    };
+    Ok(PeerAck { number, decision, responder_id: peer, responder_state, responder_phase, ..PeerAck::default() })
+}
+
+/// Serves a ring of `size` nodes relaying their messages in `mode`, counting
+/// them into `counts`, and returns the nodes once they elected node 1.
+async fn ring(size: u16, mode: Mode, counts: &Arc<Counts>) -> Vec<Node> {
+    let timing = TimingConfig {
+        poll_interval: Duration::from_millis(10),
+        poll_jitter: 0.0,
+        startup_grace: Duration::from_millis(100),
+        startup_jitter: Duration::ZERO,
+        ..TimingConfig::default()
+    };
+    let config = Config { timing, ..Config::default() };
+    let addrs = (0..size).map(|_| free_addrs::<1>()[0].parse::<SocketAddr>().unwrap()).collect::<Vec<_>>();
+    let link = |id: u16| Link { id, url: format!("http://{}", addrs[id as usize - 1]) };
+    let mut nodes = vec![];
+    for id in 1..=size {
+        let (left, right) = ((id + size - 2) % size + 1, id % size + 1);
+        let node = Node::new(&NodeSpec::ring(id, addrs[id as usize - 1], link(left), link(right)), size.into(), &config, None).unwrap();
+        let inner = match mode {
+            Mode::Unary => Arc::new(Unary),
+            Mode::Relay => node.transport(),
+        };
+        let node = node.with_transport(Arc::new(Counting { inner, counts: counts.clone() }));
+        let (served, addr, config) = (node.clone(), addrs[id as usize - 1], config.clone());
+        tokio::spawn(async move { run_node(served, addr, &config).await });
+        nodes.push(node);
+    }
+    for node in &nodes {
+        let elected = tokio::time::timeout(LIMIT, node.await_ring_acknowledged()).await;
+        assert_eq!(elected, Ok(1), "node {} of a ring of {} over {:?}", node.id(), size, mode);
+    }
+    nodes
+}
+
+/// Restarts the election at node 1 and waits until every node knows the
+/// leader of the new term.
+async fn reelect(nodes: &[Node]) {
+    assert!(nodes[0].start_election().await);
+    let term = nodes[0].term();
+    let decided = async {
+        for node in nodes {
+            while node.term() < term || *node.subscribe().borrow() == ElectionResult::Undecided {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        }
+    };
+    tokio::time::timeout(LIMIT, decided).await.expect("the ring elects again");
+}
+
+#[tokio::main]
+async fn main() {
+    let sizes = std::env::args().skip(1).filter_map(|arg| arg.parse::<u16>().ok()).collect::<Vec<_>>();
+    eprintln!("{:>6} {:>6} {:>12} {:>10} {:>8} {:>12}", "mode", "nodes", "election", "messages", "calls", "messages/s");
+    for &size in if sizes.is_empty() { &SIZES[..] } else { &sizes } {
+        for mode in [Mode::Unary, Mode::Relay] {
+            let counts = Arc::new(Counts::default());
+            let nodes = ring(size, mode, &counts).await;
+            let (streams, messages) = (counts.streams.load(Ordering::Relaxed), counts.messages.load(Ordering::Relaxed));
+            let start = Instant::now();
+            for _ in 0..ROUNDS {
+                reelect(&nodes).await;
+            }
+            let elapsed = start.elapsed();
+            let streams = counts.streams.load(Ordering::Relaxed) - streams;
+            let messages = counts.messages.load(Ordering::Relaxed) - messages;
+            // a unary call carries a single message, a relay stream all of them
+            let calls = match mode {
+                Mode::Unary => messages,
+                Mode::Relay => streams,
+            };
+            eprintln!("{:>6} {:>6} {:>12.3?} {:>10} {:>8.1} {:>12.0}", format!("{:?}", mode).to_lowercase(), size,
+                elapsed / ROUNDS as u32, messages / ROUNDS, calls as f64 / ROUNDS as f64, messages as f64 / elapsed.as_secs_f64());
+            for node in &nodes {
+                node.shutdown();
+            }
+        }
+    }
+}
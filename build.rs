@@ -1,5 +1,9 @@
-
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("proto/le.proto")?;
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?);
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("le_descriptor.bin"))
+        .type_attribute(".me.viluon.le", "#[cfg_attr(feature = \"serde\", derive(serde::Serialize, serde::Deserialize))]")
+        .compile(&["proto/le.proto"], &["proto"])?;
+    tonic_build::configure().build_server(false).compile(&["proto/otlp.proto"], &["proto"])?;
     Ok(())
 }
@@ -0,0 +1,11 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // the generated client connects through tonic's transport, which does not build for wasm32
+    let transport = std::env::var_os("CARGO_FEATURE_TRANSPORT").is_some();
+    tonic_build::configure()
+        .build_server(false)
+        .build_client(transport)
+        // for the leaders carrying it to compare equal as a whole
+        .type_attribute(".me.viluon.le.Payload", "#[derive(Eq)]")
+        .compile(&["../proto/le.proto"], &["../proto"])?;
+    Ok(())
+}
@@ -0,0 +1,156 @@
+//! Follows who leads a ring of grpc-le nodes, for applications that only
+//! consume the outcome of the election and take no part in it, without the
+//! node implementation. A [`LeaderWatcher`] watches one node at a time
+//! through its `WatchLeader` stream, moving on to the next of the addresses
+//! it was given whenever that node goes away.
+//!
+//! With the `web` feature the watcher speaks gRPC-web through the fetch API
+//! instead, and builds for `wasm32-unknown-unknown`, so that dashboards in
+//! browsers can follow the leader without a proxy in between.
+
+use leader_election_service::{LeaderRequest, LeaderResponse, Payload};
+use tonic::{Status, Streaming};
+
+#[cfg(not(any(feature = "transport", feature = "web")))]
+compile_error!("the watcher needs either the transport or the web feature to reach the nodes");
+
+pub mod leader_election_service {
+    tonic::include_proto!("me.viluon.le");
+}
+#[cfg(feature = "web")]
+pub mod web;
+
+/// The leader of the ring as the watched node knows it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Leader {
+    pub id: u64,
+    /// The leader's gRPC URL, if the node learned it.
+    pub addr: Option<String>,
+    /// The metadata the leader announced itself with, if any.
+    pub metadata: Vec<u8>,
+    /// The payload the leader attached to its win, if any.
+    pub payload: Option<Payload>,
+    /// The term the leader was elected in, which grows with every
+    /// leadership grant, for fencing off the writes of deposed leaders.
+    pub fencing_token: u64,
+    /// Whether the leader leads only the quorate segment of a ring split by
+    /// a partition, until the ring is whole again.
+    pub provisional: bool,
+}
+
+impl From<LeaderResponse> for Option<Leader> {
+    fn from(response: LeaderResponse) -> Self {
+        let LeaderResponse { leader_id, leader_known, leader_addr, leader_metadata, payload, fencing_token, provisional } = response;
+        let addr = Some(leader_addr).filter(|addr| !addr.is_empty());
+        leader_known.then_some(Leader { id: leader_id, addr, metadata: leader_metadata, payload, fencing_token, provisional })
+    }
+}
+
+/// Yields the leader of a ring, or of one of its election groups or roles,
+/// as it changes, e.g.
+///
+/// ```text
+/// let mut leaders = LeaderWatcher::connect(["le-1:50001", "le-2:50002"]).await?;
+/// while let Some(leader) = leaders.next().await? { ... }
+/// ```
+///
+/// Addresses without a scheme are taken to be `http://`.
+#[derive(Debug)]
+pub struct LeaderWatcher {
+    addrs: Vec<String>,
+    request: LeaderRequest,
+    /// Which of the addresses the node being watched has.
+    watching: usize,
+    updates: Streaming<LeaderResponse>,
+    /// The leader last yielded, if any was.
+    last: Option<Option<Leader>>,
+}
+
+impl LeaderWatcher {
+    /// Watches the leader of the default group through the first node of
+    /// `addrs` that answers.
+    pub async fn connect<S: Into<String>>(addrs: impl IntoIterator<Item = S>) -> Result<Self, Status> {
+        Self::connect_group(addrs, 0).await
+    }
+
+    /// Watches the leader of election group `group` through the first node
+    /// of `addrs` that answers.
+    pub async fn connect_group<S: Into<String>>(addrs: impl IntoIterator<Item = S>, group: u64) -> Result<Self, Status> {
+        Self::connect_to(addrs, LeaderRequest { group_id: group, ..Default::default() }).await
+    }
+
+    /// Watches the leader elected for role `role` through the first node of
+    /// `addrs` that answers, which has to take part in that election.
+    pub async fn connect_role<S: Into<String>>(addrs: impl IntoIterator<Item = S>, role: &str) -> Result<Self, Status> {
+        Self::connect_to(addrs, LeaderRequest { role: role.to_string(), ..Default::default() }).await
+    }
+
+    async fn connect_to<S: Into<String>>(addrs: impl IntoIterator<Item = S>, request: LeaderRequest) -> Result<Self, Status> {
+        let addrs = addrs.into_iter().map(|addr| url(addr.into())).collect::<Vec<_>>();
+        let (watching, updates) = watch(&addrs, 0, &request).await?;
+        Ok(LeaderWatcher { addrs, request, watching, updates, last: None })
+    }
+
+    /// Waits until the leader changes and returns the new one, or `None` if
+    /// the ring is electing one. The first call returns the leader as it
+    /// stands. Fails only once none of the nodes answer.
+    pub async fn next(&mut self) -> Result<Option<Leader>, Status> {
+        loop {
+            match self.updates.message().await {
+                Ok(Some(response)) => {
+                    let leader = Option::<Leader>::from(response);
+                    if self.last.as_ref() != Some(&leader) {
+                        self.last = Some(leader.clone());
+                        return Ok(leader)
+                    }
+                },
+                // the node went away, so another has to tell
+                Ok(None) | Err(_) => (self.watching, self.updates) = watch(&self.addrs, self.watching + 1, &self.request).await?,
+            }
+        }
+    }
+
+    /// The address of the node being watched.
+    pub fn watching(&self) -> &str {
+        &self.addrs[self.watching]
+    }
+}
+
+fn url(addr: String) -> String {
+    match addr.contains("://") {
+        true => addr,
+        false => format!("http://{}", addr),
+    }
+}
+
+/// Starts watching the leader `request` asks for through the first node of
+/// `addrs` that answers, trying them in turn from the one at `first`.
+async fn watch(addrs: &[String], first: usize, request: &LeaderRequest) -> Result<(usize, Streaming<LeaderResponse>), Status> {
+    let mut failures = vec![];
+    for i in (first..first + addrs.len()).map(|i| i % addrs.len()) {
+        match open(addrs[i].clone(), request.clone()).await {
+            Ok(updates) => return Ok((i, updates)),
+            Err(e) => failures.push(format!("{}: {}", addrs[i], e.message())),
+        }
+    }
+    Err(Status::unavailable(format!("no node to watch the leader through ({})", failures.join(", "))))
+}
+
+/// Opens the `WatchLeader` stream `request` asks for at the node at `addr`.
+#[cfg(not(feature = "web"))]
+async fn open(addr: String, request: LeaderRequest) -> Result<Streaming<LeaderResponse>, Status> {
+    use leader_election_service::leader_election_service_client::LeaderElectionServiceClient;
+    let mut client = LeaderElectionServiceClient::connect(addr).await.map_err(|e| Status::unavailable(e.to_string()))?;
+    Ok(client.watch_leader(request).await?.into_inner())
+}
+
+/// Opens the `WatchLeader` stream `request` asks for at the node at `addr`,
+/// over gRPC-web.
+#[cfg(feature = "web")]
+async fn open(addr: String, request: LeaderRequest) -> Result<Streaming<LeaderResponse>, Status> {
+    let mut grpc = tonic::client::Grpc::new(web::Fetch::new(addr));
+    grpc.ready().await?;
+    let path = tonic::codegen::http::uri::PathAndQuery::from_static("/me.viluon.le.LeaderElectionService/WatchLeader");
+    let request = tonic::Request::new(request);
+    Ok(grpc.server_streaming(request, path, tonic::codec::ProstCodec::default()).await?.into_inner())
+}
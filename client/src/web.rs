@@ -0,0 +1,202 @@
+//! gRPC-web over the fetch API of browsers, for watching the leader from
+//! wasm32-unknown-unknown, where tonic's own transport does not build.
+
+use std::cell::RefCell;
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{ready, Context, Poll, Waker};
+
+use bytes::{Bytes, BytesMut};
+use js_sys::{Array, Promise, Reflect, Uint8Array};
+use tonic::body::BoxBody;
+use tonic::codegen::http::header::{HeaderName, HeaderValue};
+use tonic::codegen::http::{self, HeaderMap};
+use tonic::codegen::{Body, Service};
+use tonic::Status;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{Headers, ReadableStreamDefaultReader, RequestInit, Response};
+
+/// The flag of the frame that carries the trailers, after the messages.
+const TRAILERS: u8 = 0x80;
+
+type Settling = Pin<Box<dyn Future<Output = Result<JsValue, JsValue>>>>;
+
+/// What a promise settled with, or who waits for it to.
+#[derive(Default)]
+struct Settled {
+    value: Option<Result<JsValue, JsValue>>,
+    waker: Option<Waker>,
+}
+
+/// Waits for `promise` to settle.
+fn settled(promise: Promise) -> Settling {
+    let state = Rc::new(RefCell::new(Settled::default()));
+    let settle = |fulfilled: bool| {
+        let state = state.clone();
+        Closure::<dyn FnMut(JsValue)>::new(move |value| {
+            let mut state = state.borrow_mut();
+            state.value = Some(if fulfilled { Ok(value) } else { Err(value) });
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        })
+    };
+    let (fulfil, reject) = (settle(true), settle(false));
+    let _ = promise.then2(&fulfil, &reject);
+    Box::pin(poll_fn(move |cx| {
+        // the callbacks have to outlive the promise settling
+        let _ = (&fulfil, &reject);
+        let mut state = state.borrow_mut();
+        match state.value.take() {
+            Some(value) => Poll::Ready(value),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }))
+}
+
+fn failed(e: JsValue) -> Status {
+    Status::unavailable(format!("fetch failed: {:?}", e))
+}
+
+/// Sends tonic's requests to the node at `base` as gRPC-web, with `fetch`.
+#[derive(Debug, Clone)]
+pub struct Fetch {
+    base: String,
+}
+
+impl Fetch {
+    pub fn new(base: String) -> Self {
+        Fetch { base }
+    }
+}
+
+impl Service<http::Request<BoxBody>> for Fetch {
+    type Response = http::Response<WebBody>;
+    type Error = Status;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Status>>>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Status>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        Box::pin(fetch(format!("{}{}", self.base, request.uri().path()), request))
+    }
+}
+
+async fn fetch(url: String, request: http::Request<BoxBody>) -> Result<http::Response<WebBody>, Status> {
+    let (parts, mut body) = request.into_parts();
+    let mut sent = vec![];
+    while let Some(chunk) = body.data().await {
+        sent.extend_from_slice(&chunk?);
+    }
+    let headers = Headers::new().map_err(failed)?;
+    headers.set("content-type", "application/grpc-web+proto").map_err(failed)?;
+    headers.set("x-grpc-web", "1").map_err(failed)?;
+    for (name, value) in &parts.headers {
+        // the browser sets the others itself
+        if !["content-type", "te", "user-agent"].contains(&name.as_str()) {
+            if let Ok(value) = value.to_str() {
+                headers.set(name.as_str(), value).map_err(failed)?;
+            }
+        }
+    }
+    let mut init = RequestInit::new();
+    init.method("POST").headers(&headers).body(Some(&Uint8Array::from(&sent[..])));
+    let window = web_sys::window().ok_or_else(|| Status::unavailable("no window to fetch from"))?;
+    let response: Response = settled(window.fetch_with_str_and_init(&url, &init)).await.map_err(failed)?.unchecked_into();
+    if response.status() != 200 {
+        return Err(Status::unavailable(format!("{} answered {} {}", url, response.status(), response.status_text())))
+    }
+    let mut received = http::Response::builder();
+    for entry in js_sys::try_iter(&response.headers()).map_err(failed)?.into_iter().flatten() {
+        let entry: Array = entry.map_err(failed)?.unchecked_into();
+        if let (Some(name), Some(value)) = (entry.get(0).as_string(), entry.get(1).as_string()) {
+            received = received.header(name, value);
+        }
+    }
+    let reader = response.body().map(|body| body.get_reader().unchecked_into());
+    let body = WebBody { reader, reading: None, buffer: BytesMut::new(), trailers: None };
+    received.body(body).map_err(|e| Status::internal(e.to_string()))
+}
+
+/// The body of a gRPC-web response as tonic takes that of a gRPC one: the
+/// frames of its messages as data and its last frame as trailers.
+pub struct WebBody {
+    /// Reads the body as it arrives, until it ends.
+    reader: Option<ReadableStreamDefaultReader>,
+    reading: Option<Settling>,
+    /// What arrived of the frames not yet handed on.
+    buffer: BytesMut,
+    trailers: Option<HeaderMap>,
+}
+
+// SAFETY: the JS values of the body only exist on wasm32-unknown-unknown,
+// where everything runs on one thread
+unsafe impl Send for WebBody {}
+
+impl WebBody {
+    /// Takes the first frame off the buffer once it arrived whole: a flag
+    /// byte, the length of the payload and the payload.
+    fn frame(&mut self) -> Option<Bytes> {
+        let len = u32::from_be_bytes(self.buffer.get(1..5)?.try_into().ok()?) as usize;
+        (self.buffer.len() >= 5 + len).then(|| self.buffer.split_to(5 + len).freeze())
+    }
+}
+
+impl Body for WebBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Status>>> {
+        let body = self.get_mut();
+        loop {
+            if let Some(frame) = body.frame() {
+                if frame[0] & TRAILERS == 0 {
+                    return Poll::Ready(Some(Ok(frame)))
+                }
+                body.trailers = Some(trailers(&frame[5..]));
+                body.reader = None;
+                return Poll::Ready(None)
+            }
+            let Some(reader) = &body.reader else {
+                return Poll::Ready(match body.buffer.is_empty() {
+                    true => None,
+                    false => Some(Err(Status::internal("the response ended halfway through a frame"))),
+                })
+            };
+            let reading = body.reading.get_or_insert_with(|| settled(reader.read()));
+            let read = ready!(reading.as_mut().poll(cx));
+            body.reading = None;
+            let read = match read {
+                Ok(read) => read,
+                Err(e) => return Poll::Ready(Some(Err(failed(e)))),
+            };
+            let done = Reflect::get(&read, &"done".into()).map_or(true, |done| done.is_truthy());
+            match Reflect::get(&read, &"value".into()) {
+                Ok(chunk) if !done => body.buffer.extend_from_slice(&Uint8Array::new(&chunk).to_vec()),
+                _ => body.reader = None,
+            }
+        }
+    }
+
+    fn poll_trailers(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Status>> {
+        Poll::Ready(Ok(self.get_mut().trailers.take()))
+    }
+}
+
+/// Parses the trailers of a gRPC-web response, written like HTTP/1.1 headers,
+/// e.g. `grpc-status:0\r\ngrpc-message:\r\n`.
+fn trailers(block: &[u8]) -> HeaderMap {
+    String::from_utf8_lossy(block).split("\r\n")
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((HeaderName::from_bytes(name.trim().to_lowercase().as_bytes()).ok()?, HeaderValue::from_str(value.trim()).ok()?))
+        })
+        .collect()
+}
@@ -0,0 +1,30 @@
+#![cfg(all(feature = "transport", not(feature = "web")))]
+
+use std::time::Duration;
+
+use grpc_le::Node;
+use grpc_le_client::LeaderWatcher;
+
+#[tokio::test]
+async fn the_watcher_follows_the_leader_past_a_node_going_away() {
+    let one = Node::builder().id(1).listen("[::1]:41701").left(2, "[::1]:41702").right(2, "[::1]:41702").build().unwrap();
+    let two = Node::builder().id(2).listen("[::1]:41702").left(1, "[::1]:41701").right(1, "[::1]:41701").build().unwrap();
+    // give the servers the time to come up
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut leaders = LeaderWatcher::connect(["[::1]:41702", "[::1]:41701"]).await.unwrap();
+    assert_eq!(leaders.watching(), "http://[::1]:41702");
+    let leader = loop {
+        if let Some(leader) = leaders.next().await.unwrap() {
+            break leader
+        }
+    };
+    assert_eq!(leader.id, 1);
+    two.shutdown().await.unwrap();
+    // node 1 may not know its own address like node 2 did, but it follows no other leader
+    if let Ok(next) = tokio::time::timeout(Duration::from_millis(500), leaders.next()).await {
+        assert_eq!(next.unwrap().map(|leader| leader.id), Some(1));
+    }
+    assert_eq!(leaders.watching(), "http://[::1]:41701");
+    one.shutdown().await.unwrap();
+    assert!(leaders.next().await.is_err());
+}
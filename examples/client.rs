@@ -0,0 +1,94 @@
+use std::io::{stdin, stdout, BufRead, IsTerminal, Write};
+
+use grpc_le::leader_election_service::leader_election_service_client::LeaderElectionServiceClient;
+use grpc_le::leader_election_service::{ForwardRequest, LeaderRequest, ProbeMessage, StateRequest};
+use grpc_le::traces;
+use tonic::transport::Channel;
+
+const HELP: &str = "commands: leader, state, probe SENDER PHASE [left], forward ROUTE PAYLOAD, quit";
+
+/// A test client for a node of a running ring, e.g.
+/// `cargo run --example client -- http://[::1]:40001 leader`. Runs the command
+/// given after the address, or without one reads commands from stdin:
+/// `leader` asks who leads the ring, `state` dumps the node's state and
+/// `probe SENDER PHASE [left]` sends the node a probe, rightward unless told
+/// otherwise, and prints what the node decided on it, as part of the trace
+/// `TRACEPARENT` names, if set. `forward ROUTE PAYLOAD` has the node forward
+/// the payload to the leader, e.g. one running the `embedded` example, and
+/// prints the leader's answer.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let addr = args.next().unwrap_or_else(|| "http://[::1]:40001".to_string());
+    let mut client = LeaderElectionServiceClient::connect(addr.clone()).await?;
+    let command = args.collect::<Vec<_>>();
+    if !command.is_empty() {
+        return run(&mut client, &addr, &command).await
+    }
+    let interactive = stdin().is_terminal();
+    if interactive {
+        println!("connected to {}; {}", addr, HELP);
+    }
+    let mut lines = stdin().lock().lines();
+    loop {
+        if interactive {
+            print!("> ");
+            stdout().flush()?;
+        }
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => return Ok(()),
+        };
+        let command = line.split_whitespace().map(str::to_string).collect::<Vec<_>>();
+        match command.first().map(String::as_str) {
+            None => continue,
+            Some("quit") => return Ok(()),
+            Some(_) => if let Err(e) = run(&mut client, &addr, &command).await {
+                println!("error: {}", e);
+            },
+        }
+    }
+}
+
+async fn run(client: &mut LeaderElectionServiceClient<Channel>, addr: &str, command: &[String])
+-> Result<(), Box<dyn std::error::Error>> {
+    match command.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["leader"] => {
+            let leader = client.get_leader(LeaderRequest::default()).await?.into_inner();
+            match (leader.leader_known, leader.leader_addr.as_str()) {
+                (false, _) => println!("{} is still electing a leader", addr),
+                (true, "") => println!("node {} leads, {} does not know where", leader.leader_id, addr),
+                (true, leader_addr) => println!("node {} leads, at {}", leader.leader_id, leader_addr),
+            }
+            if leader.leader_known {
+                println!("its fencing token is {}", leader.fencing_token);
+            }
+            if !leader.leader_metadata.is_empty() {
+                println!("it says {}", String::from_utf8_lossy(&leader.leader_metadata));
+            }
+        },
+        ["state"] => println!("{:#?}", client.get_state(StateRequest::default()).await?.into_inner()),
+        ["probe", sender_id, phase, ref direction @ ..] if matches!(direction, [] | ["left"]) => {
+            let term = client.get_state(StateRequest::default()).await?.into_inner().term;
+            let probe = ProbeMessage {
+                sender_id: sender_id.parse()?, headed_left: !direction.is_empty(), phase: phase.parse()?, seq: None, term, priority: 0, group_id: 0, hops: 0, hops_remaining: 0, zone: String::new(),
+            };
+            let mut request = tonic::Request::new(futures::stream::iter([probe]));
+            if let Some(parent) = std::env::var("TRACEPARENT").ok().and_then(|parent| parent.parse().ok()) {
+                request.metadata_mut().insert(traces::TRACEPARENT, parent);
+            }
+            let mut responses = client.probe_raw(request).await?.into_inner();
+            while let Some(response) = responses.message().await? {
+                println!("node {} at {} decided {:?}, standing as {:?} in phase {}",
+                    response.responder_id, addr, response.decision(), response.responder_state(), response.responder_phase);
+            }
+        },
+        ["forward", route, payload] => {
+            let request = ForwardRequest { route: route.to_string(), payload: payload.as_bytes().to_vec(), ..Default::default() };
+            let response = client.forward_to_leader(request).await?.into_inner();
+            println!("leader {} answered {}", response.leader_id, String::from_utf8_lossy(&response.payload));
+        },
+        _ => println!("{}", HELP),
+    }
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use grpc_le::config::Config;
+use std::sync::Arc;
+
+use grpc_le::embed::LeaderElectionLayer;
+use grpc_le::forward::{Handling, LeaderHandler};
+use grpc_le::Node;
+use tonic::transport::Server;
+
+/// Answers the requests forwarded to the leader with their route and
+/// payload, as the application's stand-in for serving them.
+#[derive(Debug)]
+struct Echo(u64);
+
+impl LeaderHandler for Echo {
+    fn handle<'a>(&'a self, route: &'a str, payload: Vec<u8>) -> Handling<'a> {
+        Box::pin(async move { Ok(format!("node {} handled {} {}", self.0, route, String::from_utf8_lossy(&payload)).into_bytes()) })
+    }
+}
+
+/// Runs a node of a ring inside an application's own gRPC server, which
+/// serves the standard health service as its stand-in for the application's
+/// services, on the node's address, e.g.
+/// `cargo run --example embedded -- --id 1 --left 3=[::1]:40003
+/// --right 2=[::1]:40002 --ring-size 3`. Any other nodes of the ring may run
+/// embedded or on their own. While the node leads, it echoes the requests
+/// the other nodes forward to it.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let (spec, config) = Config::node_from_args(std::env::args().skip(1))?;
+    let node = Node::new(&spec, config.ring_size.unwrap(), &config, None)?.with_handler(Arc::new(Echo(spec.id.into())));
+    let (_, application) = tonic_health::server::health_reporter();
+    let server = Server::builder()
+        .layer(LeaderElectionLayer::new(node.clone()))
+        .add_service(application)
+        .serve_with_shutdown(spec.listen, node.stopped());
+    let (served, ()) = futures::future::join(server, grpc_le::take_part(node.clone(), spec.listen, &config)).await;
+    Ok(served?)
+}
@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+use grpc_le::config::Config;
+use grpc_le::{ElectionAlgorithm, Node};
+
+/// Runs a node of a ring like `grpc-le node` does, doing a job every second
+/// while it leads, so that only one node of the ring does it at a time, and
+/// saying when the whole ring acknowledged the leader, e.g.
+/// `cargo run --example singleton -- --id 1 --left 3=[::1]:40003
+/// --right 2=[::1]:40002 --ring-size 3 --lease-ms 1000`.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let (spec, config) = Config::node_from_args(std::env::args().skip(1))?;
+    let node = Node::new(&spec, config.ring_size.unwrap(), &config, None)?;
+    let job = || async {
+        let mut ticks = tokio::time::interval(Duration::from_secs(1));
+        for run in 1.. {
+            ticks.tick().await;
+            println!("node {} doing the job for the {}. time", spec.id, run);
+        }
+    };
+    let jobs = async {
+        loop {
+            node.run_when_leader(job()).await;
+            println!("node {} stopped doing the job", spec.id);
+        }
+    };
+    let acknowledged = async {
+        let mut results = node.subscribe();
+        loop {
+            let leader = node.await_ring_acknowledged().await;
+            results.borrow_and_update();
+            println!("the ring acknowledged node {} as its leader", leader);
+            if results.changed().await.is_err() {
+                return
+            }
+        }
+    };
+    tokio::select! {
+        _ = acknowledged => (),
+        served = node.clone().run(spec.listen, &config) => served?,
+        _ = jobs => (),
+    }
+    Ok(())
+}
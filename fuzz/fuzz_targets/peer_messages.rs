@@ -0,0 +1,96 @@
+//! Relays arbitrary messages to a node of a running ring, as its neighbour
+//! would, and fails on any of them panicking a node. Run with
+//!
+//! ```sh
+//! cargo +nightly fuzz run peer_messages
+//! ```
+#![no_main]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use arbitrary::Arbitrary;
+use futures::StreamExt;
+use libfuzzer_sys::fuzz_target;
+use prost::Message;
+use tokio::sync::mpsc;
+use tonic::transport::Endpoint;
+
+use grpc_le::config::Config;
+use grpc_le::leader_election_service::peer_message::Body;
+use grpc_le::topology::Topology;
+use grpc_le::transport::{MemoryTransport, Peer, Transport};
+use grpc_le::{node_client, DigestMessage, Node, NotifyMessage, PeerMessage, ProbeMessage, Sequence};
+
+/// A message as relayed: bytes off the wire, which may not even decode, or
+/// a message that does, of any values at all.
+#[derive(Debug, Arbitrary)]
+enum Relayed {
+    Bytes(Vec<u8>),
+    Probe { lamport: u64, sender_id: u64, headed_left: bool, phase: u64, seq: Option<Seq>, term: u64, priority: u64, hops: u64, hops_remaining: u64 },
+    Notify { lamport: u64, leader_id: u64, headed_left: bool, seq: Option<Seq>, ranking: Vec<u64>, leader_addr: String, term: u64, priorities: Vec<u64> },
+    Digest { lamport: u64, leader_id: u64, ring_size: u64, seq: Option<Seq>, ranking: Vec<u64>, term: u64, hops: u64 },
+    Empty { lamport: u64, version: u32 },
+}
+
+#[derive(Debug, Arbitrary)]
+struct Seq {
+    sender: u64,
+    incarnation: u64,
+    number: u64,
+    leftward: bool,
+}
+
+impl From<Seq> for Sequence {
+    fn from(Seq { sender, incarnation, number, leftward }: Seq) -> Self {
+        Sequence { sender, incarnation, number, leftward }
+    }
+}
+
+impl Relayed {
+    /// The message a node receives, if the bytes decode.
+    fn message(self) -> Option<PeerMessage> {
+        let (lamport, body) = match self {
+            Relayed::Bytes(bytes) => return PeerMessage::decode(&bytes[..]).ok(),
+            Relayed::Probe { lamport, sender_id, headed_left, phase, seq, term, priority, hops, hops_remaining } => (lamport, Body::Probe(ProbeMessage {
+                sender_id, headed_left, phase, seq: seq.map(Into::into), term, priority, group_id: 0, hops, hops_remaining, zone: String::new(),
+            })),
+            Relayed::Notify { lamport, leader_id, headed_left, seq, ranking, leader_addr, term, priorities } => (lamport, Body::Notify(NotifyMessage {
+                leader_id, headed_left, seq: seq.map(Into::into), ranking, leader_addr, term, priorities, ..NotifyMessage::default()
+            })),
+            Relayed::Digest { lamport, leader_id, ring_size, seq, ranking, term, hops } => (lamport, Body::Digest(DigestMessage {
+                leader_id, ring_size, seq: seq.map(Into::into), ranking, term, group_id: 0, hops,
+            })),
+            Relayed::Empty { lamport, version } => return Some(PeerMessage { lamport, version, ..PeerMessage::default() }),
+        };
+        Some(PeerMessage { lamport, body: Some(body), ..PeerMessage::default() })
+    }
+}
+
+fuzz_target!(|relayed: Vec<Relayed>| {
+    let messages = relayed.into_iter().filter_map(Relayed::message).collect::<Vec<_>>();
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().start_paused(true).build().unwrap();
+    // libFuzzer aborts on a panic in any task, not just this one
+    runtime.block_on(async {
+        let specs = Topology::from_ids(&[3, 5, 7]).nodes();
+        let network = Arc::new(MemoryTransport::default());
+        for spec in &specs {
+            let node = Node::new(spec, specs.len() as u64, &Config::default(), None).unwrap().with_transport(network.clone());
+            network.add(node.clone());
+            tokio::spawn(node_client(node));
+        }
+        let (tx, rx) = mpsc::channel(messages.len().max(1));
+        for message in messages {
+            tx.send(message).await.unwrap();
+        }
+        drop(tx);
+        // node 5, as if its left neighbour sent them
+        let target = Peer { id: 5, endpoint: Endpoint::from_static("http://[::1]:40005") };
+        let mut acks = network.relay(&target, rx).await.unwrap();
+        while acks.next().await.is_some() {}
+        // a message that sends the ring round in circles keeps it from ever
+        // going idle for the clock to move on, which libFuzzer reports as a
+        // timeout
+        tokio::time::sleep(Duration::from_secs(30)).await;
+    });
+});
@@ -0,0 +1,297 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::sync::atomic::Ordering as AtomicOrdering;
+
+use futures::stream;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Duration;
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+
+use crate::audit::Cause;
+use crate::config::Reload;
+use crate::error::ElectionError;
+use crate::leader_election_service::admin_service_server::AdminService;
+use crate::leader_election_service::leader_election_service_server::LeaderElectionService;
+use crate::leader_election_service::{AdminCall, AuditLogRequest, AuditLogResponse, Transition};
+use crate::leader_election_service::{state_response::Kind, DrainRequest, DrainResponse, DumpStateRequest, DumpStateResponse};
+use crate::leader_election_service::{ElectionHistoryRequest, ElectionHistoryResponse, ForceStateRequest, ForceStateResponse, Neighbor, NeighborLatency, StateRequest};
+use crate::leader_election_service::{PauseRequest, PauseResponse, ResumeRequest, ResumeResponse};
+use crate::leader_election_service::{StepDownRequest, StepDownResponse, TriggerReelectionRequest, TriggerReelectionResponse};
+use crate::leader_election_service::{TakeOverRequest, TransferLeadershipRequest, TransferLeadershipResponse, UpdateConfigRequest, UpdateConfigResponse};
+use crate::outbound::{Latency, NeighborQueue};
+use crate::spiffe;
+use crate::timers::TimerKind;
+use crate::{ElectionResult, Node, NodeState, Responses, DELAY_MODIFIER};
+
+/// How long a drained node waits for its neighbours by default.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(50 * DELAY_MODIFIER);
+
+impl Node {
+    /// Puts the node into `forced` as if the election had got it there.
+    async fn force(&self, forced: NodeState) {
+        let mut state = self.state.lock().await;
+        warn!(node = self.id, "forced from {:?} into {:?}", *state, forced);
+        let from = std::mem::replace(&mut *state, forced);
+        self.changed_state(&from, &state, Cause::Forced);
+        self.end_phase_span();
+        let (leader, result) = match *state {
+            NodeState::Leader => (Some(self.id), ElectionResult::Leader),
+            NodeState::Defeated { leader: Some(leader) } => (Some(leader), ElectionResult::Defeated { leader }),
+            _ => (None, ElectionResult::Undecided),
+        };
+        if leader.is_some_and(|leader| leader != self.id) {
+            self.saw_leader(self.clock.now());
+        }
+        self.observe_leader(leader);
+        self.publish(result);
+        // a candidate probes its phase, a leader announces itself
+        self.timers.set(TimerKind::Poll, self.poll_interval());
+    }
+
+    /// Hands the leadership over to node `target`, reached at `addr` unless it
+    /// is a neighbour, for it to lead the next term without an election.
+    /// Steps down instead if the target cannot take over. Returns the term
+    /// the target leads, if it does.
+    async fn transfer_leadership(&self, target: u64, addr: &str) -> Result<Option<u64>, ElectionError> {
+        let state = self.state.lock().await.clone();
+        if state != NodeState::Leader {
+            return Err(self.wrong_state(&state, "transfer the leadership"))
+        }
+        let invalid = |reason: String| ElectionError::InvalidMessage { node: self.id, state: None, reason };
+        if target == self.id {
+            return Err(invalid(format!("node {} already leads", target)))
+        }
+        let endpoint = match addr {
+            "" => [self.left.peer(), self.right.peer()].into_iter()
+                .find(|peer| peer.id == target)
+                .map(|peer| peer.endpoint)
+                .ok_or_else(|| invalid(format!("node {} is no neighbour, its address is needed", target)))?,
+            addr => self.peer_of(Neighbor { id: target, addr: addr.to_string() })?.endpoint,
+        };
+        let term = self.next_term()?;
+        info!(node = self.id, "handing the leadership over to node {} for term {}", target, term);
+        let take_over = async {
+            let mut client = self.connect(&endpoint).await?;
+            let request = self.deadline(TakeOverRequest { leader_id: self.id, term, group_id: self.group });
+            Ok::<_, Status>(client.take_over(request).await?.into_inner().taken_over)
+        };
+        match take_over.await {
+            Ok(true) => {
+                self.follow_successor(target, term, &endpoint.uri().to_string()).await;
+                return Ok(Some(term))
+            },
+            Ok(false) => warn!(node = self.id, "node {} would not take over, stepping down instead", target),
+            Err(e) => warn!(node = self.id, "cannot hand the leadership over to node {}, stepping down instead: {}", target, e),
+        }
+        self.step_down().await;
+        Ok(None)
+    }
+
+    /// Makes the admin call `name` with `request` through `call`, auditing
+    /// it along with its caller and outcome.
+    async fn audited<R, T, F>(&self, name: &str, request: Request<R>, call: impl FnOnce(Request<R>) -> F) -> Result<Response<T>, Status>
+    where
+        R: Debug,
+        T: Debug,
+        F: Future<Output = Result<Response<T>, Status>>,
+    {
+        let (caller, arguments) = (caller(&request), format!("{:?}", request.get_ref()));
+        let outcome = call(request).await;
+        self.audit.record_call(AdminCall {
+            unix_ms: self.clock.wall_now().timestamp_millis() as u64,
+            call: name.to_string(),
+            arguments,
+            caller,
+            ok: outcome.is_ok(),
+            outcome: match &outcome {
+                Ok(response) => format!("{:?}", response.get_ref()),
+                Err(status) => status.message().to_string(),
+            },
+        });
+        outcome
+    }
+
+    /// The messages on their way to the neighbours, waiting or sent and not
+    /// acknowledged.
+    fn undelivered(&self) -> u64 {
+        [&self.left, &self.right].iter().map(|queue| (queue.len() + queue.unacknowledged().len()) as u64).sum()
+    }
+}
+
+/// The SPIFFE ID and address of whoever made `request`, as far as they are
+/// known, empty for a call made in-process.
+fn caller<R>(request: &Request<R>) -> String {
+    let id = request.peer_certs().and_then(|certificates| spiffe::spiffe_id(certificates.first()?.get_ref()));
+    match (id, request.remote_addr()) {
+        (Some(id), Some(addr)) => format!("{} at {}", id, addr),
+        (Some(id), None) => id,
+        (None, Some(addr)) => addr.to_string(),
+        (None, None) => String::new(),
+    }
+}
+
+/// How long `neighbor` takes to acknowledge messages.
+fn latency(neighbor: &NeighborQueue) -> NeighborLatency {
+    let Latency { smoothed, last, samples, slow, episodes } = neighbor.latency();
+    let us = |rtt: Option<Duration>| rtt.map_or(0, |rtt| rtt.as_micros() as u64);
+    NeighborLatency { smoothed_rtt_us: us(smoothed), last_rtt_us: us(last), samples, slow, slow_count: episodes }
+}
+
+#[tonic::async_trait]
+impl AdminService for Node {
+    type WatchAuditLogStream = Responses<Transition>;
+
+    async fn dump_state(&self, request: Request<DumpStateRequest>) -> Result<Response<DumpStateResponse>, Status> {
+        let state = self.get_state(Request::new(StateRequest { group_id: request.into_inner().group_id })).await?.into_inner();
+        let (left, right) = (self.left.peer(), self.right.peer());
+        Ok(Response::new(DumpStateResponse {
+            state: Some(state),
+            left: Some(Neighbor { id: left.id, addr: left.endpoint.uri().to_string() }),
+            right: Some(Neighbor { id: right.id, addr: right.endpoint.uri().to_string() }),
+            left_queued: self.left.len() as u64,
+            right_queued: self.right.len() as u64,
+            left_unacknowledged: self.left.unacknowledged().len() as u64,
+            right_unacknowledged: self.right.unacknowledged().len() as u64,
+            topology_epoch: self.topology_epoch.load(AtomicOrdering::SeqCst),
+            reelection_epoch: self.reelection_epoch.load(AtomicOrdering::SeqCst),
+            incarnation: self.incarnation,
+            paused: self.is_paused(),
+            timed_out: self.election_timeout(),
+            left_latency: Some(latency(&self.left)),
+            right_latency: Some(latency(&self.right)),
+        }))
+    }
+
+    async fn force_state(&self, request: Request<ForceStateRequest>) -> Result<Response<ForceStateResponse>, Status> {
+        self.audited("ForceState", request, |request| async move {
+            let request = request.into_inner();
+            self.check_group(request.group_id)?;
+            let invalid = |reason: String| ElectionError::InvalidMessage { node: self.id, state: None, reason };
+            let state = match request.kind() {
+                Kind::Candidate if request.phase == 0 => return Err(invalid("candidates start from phase 1".to_string()).into()),
+                Kind::Candidate => NodeState::Candidate { phase: request.phase, last_phase_probed: request.phase - 1 },
+                Kind::Defeated if request.leader_known && request.leader_id == self.id =>
+                    return Err(invalid(format!("node {} cannot follow itself", self.id)).into()),
+                Kind::Defeated => NodeState::Defeated { leader: request.leader_known.then_some(request.leader_id) },
+                Kind::Leader => NodeState::Leader,
+            };
+            self.force(state).await;
+            Ok(Response::new(ForceStateResponse {}))
+        }).await
+    }
+
+    async fn trigger_reelection(&self, request: Request<TriggerReelectionRequest>)
+    -> Result<Response<TriggerReelectionResponse>, Status> {
+        self.audited("TriggerReelection", request, |request| async move {
+            let caller = request.remote_addr();
+            let TriggerReelectionRequest { epoch, group_id } = request.into_inner();
+            self.check_group(group_id)?;
+            self.admit_restart(caller)?;
+            let epoch = match epoch {
+                0 => self.reelection_epoch.load(AtomicOrdering::SeqCst) + 1,
+                epoch => epoch,
+            };
+            info!(node = self.id, "restarting the election for epoch {} on request", epoch);
+            self.reelect(epoch, self.ring_size(), self.next_term()?);
+            Ok(Response::new(TriggerReelectionResponse { epoch }))
+        }).await
+    }
+
+    async fn drain(&self, request: Request<DrainRequest>) -> Result<Response<DrainResponse>, Status> {
+        self.audited("Drain", request, |request| async move {
+            let DrainRequest { timeout_ms, group_id } = request.into_inner();
+            self.check_group(group_id)?;
+            let timeout = match timeout_ms {
+                0 => DRAIN_TIMEOUT,
+                ms => Duration::from_millis(ms),
+            };
+            info!(node = self.id, "draining");
+            for kind in [TimerKind::StartupGrace, TimerKind::Poll, TimerKind::Digest] {
+                self.timers.cancel(kind);
+            }
+            let deadline = self.clock.now() + timeout;
+            while self.undelivered() > 0 && self.clock.now() < deadline {
+                self.clock.sleep_until(self.clock.now() + self.timing().poll_interval).await;
+            }
+            let undelivered = self.undelivered();
+            if undelivered > 0 {
+                warn!(node = self.id, "shutting down with {} messages undelivered", undelivered);
+            }
+            self.shutdown();
+            Ok(Response::new(DrainResponse { undelivered }))
+        }).await
+    }
+
+    async fn step_down(&self, request: Request<StepDownRequest>) -> Result<Response<StepDownResponse>, Status> {
+        self.audited("StepDown", request, |request| async move {
+            self.check_group(request.into_inner().group_id)?;
+            Ok(Response::new(StepDownResponse { stepped_down: Node::step_down(self).await }))
+        }).await
+    }
+
+    async fn transfer_leadership(&self, request: Request<TransferLeadershipRequest>)
+    -> Result<Response<TransferLeadershipResponse>, Status> {
+        self.audited("TransferLeadership", request, |request| async move {
+            let TransferLeadershipRequest { target_id, target_addr, group_id } = request.into_inner();
+            self.check_group(group_id)?;
+            let term = Node::transfer_leadership(self, target_id, &target_addr).await?;
+            Ok(Response::new(TransferLeadershipResponse { transferred: term.is_some(), term: term.unwrap_or_default() }))
+        }).await
+    }
+
+    async fn get_election_history(&self, request: Request<ElectionHistoryRequest>) -> Result<Response<ElectionHistoryResponse>, Status> {
+        self.check_group(request.into_inner().group_id)?;
+        Ok(Response::new(ElectionHistoryResponse { elections: self.history.recent(), total: self.history.total() }))
+    }
+
+    async fn get_audit_log(&self, request: Request<AuditLogRequest>) -> Result<Response<AuditLogResponse>, Status> {
+        self.check_group(request.into_inner().group_id)?;
+        Ok(Response::new(AuditLogResponse {
+            transitions: self.audit.recent(),
+            total: self.audit.total(),
+            admin_calls: self.audit.recent_calls(),
+            admin_calls_total: self.audit.calls_total(),
+        }))
+    }
+
+    async fn watch_audit_log(&self, request: Request<AuditLogRequest>) -> Result<Response<Self::WatchAuditLogStream>, Status> {
+        self.check_group(request.into_inner().group_id)?;
+        let id = self.id;
+        let transitions = stream::unfold(self.audit.subscribe(), move |mut feed| async move {
+            loop {
+                match feed.recv().await {
+                    Ok(transition) => return Some((Ok(transition), feed)),
+                    // GetAuditLog still lists what the watcher missed
+                    Err(RecvError::Lagged(missed)) => warn!(node = id, "an audit log watcher fell behind by {} transitions", missed),
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(self.respond(transitions))))
+    }
+
+    async fn update_config(&self, request: Request<UpdateConfigRequest>) -> Result<Response<UpdateConfigResponse>, Status> {
+        self.audited("UpdateConfig", request, |request| async move {
+            let UpdateConfigRequest { settings, group_id } = request.into_inner();
+            self.check_group(group_id)?;
+            let reload = Reload::parse(self.timing(), settings)
+                .map_err(|reason| ElectionError::ReloadFailed { node: self.id, reason })?;
+            Ok(Response::new(UpdateConfigResponse { reelected: self.reload(reload)? }))
+        }).await
+    }
+
+    async fn pause(&self, request: Request<PauseRequest>) -> Result<Response<PauseResponse>, Status> {
+        self.audited("Pause", request, |request| async move {
+            self.check_group(request.into_inner().group_id)?;
+            Ok(Response::new(PauseResponse { was_paused: Node::pause(self) }))
+        }).await
+    }
+
+    async fn resume(&self, request: Request<ResumeRequest>) -> Result<Response<ResumeResponse>, Status> {
+        self.audited("Resume", request, |request| async move {
+            self.check_group(request.into_inner().group_id)?;
+            Ok(Response::new(ResumeResponse { was_paused: Node::resume(self) }))
+        }).await
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+
+use crate::leader_election_service::{anomaly::Kind, Anomaly};
+
+/// How many anomalies a node keeps around for `GetAnomalies`.
+const RECENT_LIMIT: usize = 64;
+
+/// Evidence a node has seen of the ring having two leaders at once, the worst
+/// way an election can go wrong.
+#[derive(Debug, Default)]
+pub struct Anomalies {
+    recent: Mutex<VecDeque<Anomaly>>,
+    /// Notifications that contradicted an already known leader.
+    pub conflicting_leaders: AtomicU64,
+    /// Leader digests that disagreed with this node's view of the cluster.
+    pub diverged_digests: AtomicU64,
+    /// Digests that reached the leader from another leader.
+    pub rival_digests: AtomicU64,
+}
+
+impl Kind {
+    pub fn label(self) -> &'static str {
+        match self {
+            Kind::ConflictingLeaders => "conflicting_leaders",
+            Kind::DivergedDigest => "diverged_digest",
+            Kind::RivalDigest => "rival_digest",
+            Kind::Unknown => "unknown",
+        }
+    }
+}
+
+impl Anomalies {
+    /// Records an anomaly and returns how many of its kind were seen so far.
+    pub fn record(&self, kind: Kind, detail: String, at: DateTime<Utc>) -> u64 {
+        let counter = match kind {
+            Kind::ConflictingLeaders => &self.conflicting_leaders,
+            Kind::DivergedDigest => &self.diverged_digests,
+            Kind::RivalDigest => &self.rival_digests,
+            Kind::Unknown => unreachable!("recorded an anomaly of unknown kind"),
+        };
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_LIMIT {
+            recent.pop_front();
+        }
+        recent.push_back(Anomaly { kind: kind as i32, detail, unix_ms: at.timestamp_millis() as u64 });
+        counter.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// The most recent anomalies, oldest first.
+    pub fn recent(&self) -> Vec<Anomaly> {
+        self.recent.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn total(&self) -> u64 {
+        [&self.conflicting_leaders, &self.diverged_digests, &self.rival_digests].iter()
+            .map(|counter| counter.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Appends the anomaly total to `out` in the Prometheus text format.
+    pub fn render(&self, node: u64, out: &mut String) {
+        let _ = writeln!(out, "# TYPE grpc_le_leader_anomalies_total counter");
+        let _ = writeln!(out, "grpc_le_leader_anomalies_total{{node=\"{}\"}} {}", node, self.total());
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Debug, Display};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use tokio::sync::broadcast;
+use tracing::error;
+
+use crate::leader_election_service::{AdminCall, Transition};
+use crate::NodeState;
+
+/// How many transitions, and how many admin calls, a node keeps around for
+/// `GetAuditLog`.
+const AUDIT_LIMIT: usize = 256;
+
+/// How many transitions `WatchAuditLog` keeps for watchers that fall behind.
+const FEED_CAPACITY: usize = 64;
+
+/// What made a node change its state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cause {
+    /// The poll timer fired, so a candidate probed its phase.
+    Poll,
+    /// The probe of candidate `sender` in `phase`, relayed by `peer`.
+    Probe { sender: u64, phase: u64, peer: Option<u64> },
+    /// The notification that `leader` won, relayed by `peer`.
+    Notification { leader: u64, peer: Option<u64> },
+    /// A message of a newer term, relayed by `peer`.
+    NewerTerm { kind: &'static str, peer: Option<u64> },
+    /// The election was restarted, here or around the ring.
+    Restart,
+    /// Neighbour `voucher` vouched for a live leader when asked for a
+    /// pre-vote.
+    PreVote { voucher: u64 },
+    /// `leader` handed its leadership over to the node.
+    TakeOver { leader: u64 },
+    /// The node handed its leadership over to `successor`.
+    Handover { successor: u64 },
+    /// `leader` fell silent, so its deputy, the node, took over once its
+    /// neighbours confirmed it.
+    Failover { leader: u64 },
+    /// The segments of a split ring were merged back together in `epoch`,
+    /// under the leader of the segment that won the merge.
+    Merge { leader: u64, epoch: u64 },
+    /// An operator forced the state through `ForceState`.
+    Forced,
+}
+
+impl Cause {
+    /// The neighbour that relayed the message behind the change, if one did.
+    pub fn peer(&self) -> Option<u64> {
+        match *self {
+            Cause::Probe { peer, .. } | Cause::Notification { peer, .. } | Cause::NewerTerm { peer, .. } => peer,
+            Cause::PreVote { voucher } => Some(voucher),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Cause::Poll => write!(f, "the poll timer"),
+            Cause::Probe { sender, phase, .. } => write!(f, "the probe of {} in phase {}", sender, phase),
+            Cause::Notification { leader, .. } => write!(f, "the notification that {} leads", leader),
+            Cause::NewerTerm { kind, .. } => write!(f, "a {} of a newer term", kind),
+            Cause::Restart => write!(f, "a restart of the election"),
+            Cause::PreVote { voucher } => write!(f, "node {} vouching for a live leader", voucher),
+            Cause::TakeOver { leader } => write!(f, "node {} handing its leadership over", leader),
+            Cause::Handover { successor } => write!(f, "handing the leadership over to node {}", successor),
+            Cause::Failover { leader } => write!(f, "leader {} falling silent", leader),
+            Cause::Merge { leader, epoch } => write!(f, "the merge of a split ring under leader {} in epoch {}", leader, epoch),
+            Cause::Forced => write!(f, "an operator forcing the state"),
+        }
+    }
+}
+
+/// Somewhere a node's audit log is written to, as it changes state and
+/// takes admin calls.
+pub trait AuditSink: Debug + Send + Sync {
+    fn record(&self, node: u64, transition: &Transition);
+    fn record_call(&self, node: u64, call: &AdminCall);
+}
+
+/// Writes a line per transition, e.g.
+///
+/// ```text
+/// 2021-11-21T18:22:03.520Z node 2 term 0: Leader -> Defeated { leader: Some(1) } on the notification that 1 leads, from node 3
+/// ```
+fn line(node: u64, transition: &Transition) -> String {
+    let time = Utc.timestamp_millis(transition.unix_ms as i64);
+    let mut line = format!("{} node {} term {}: {} -> {} on {}", time.to_rfc3339_opts(SecondsFormat::Millis, true), node,
+        transition.term, transition.from, transition.to, transition.cause);
+    if transition.peer_known {
+        line += &format!(", from node {}", transition.peer_id);
+    }
+    line
+}
+
+/// Writes a line per admin call, e.g.
+///
+/// ```text
+/// 2021-11-21T18:22:05.113Z node 2 StepDown(StepDownRequest { group_id: 0 }) by [::1]:50412 succeeded: StepDownResponse { stepped_down: true }
+/// ```
+fn call_line(node: u64, call: &AdminCall) -> String {
+    let time = Utc.timestamp_millis(call.unix_ms as i64);
+    let caller = match call.caller.as_str() {
+        "" => "in-process",
+        caller => caller,
+    };
+    format!("{} node {} {}({}) by {} {}: {}", time.to_rfc3339_opts(SecondsFormat::Millis, true), node, call.call, call.arguments,
+        caller, if call.ok { "succeeded" } else { "failed" }, call.outcome)
+}
+
+/// Writes the audit log to stderr, among the diagnostics.
+#[derive(Debug)]
+pub struct Stderr;
+
+impl AuditSink for Stderr {
+    fn record(&self, node: u64, transition: &Transition) {
+        eprintln!("{}", line(node, transition));
+    }
+
+    fn record_call(&self, node: u64, call: &AdminCall) {
+        eprintln!("{}", call_line(node, call));
+    }
+}
+
+/// Appends the audit log to a file.
+#[derive(Debug)]
+pub struct AuditFile {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl AuditFile {
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(AuditFile { path, file: Mutex::new(file) })
+    }
+}
+
+impl AuditSink for AuditFile {
+    fn record(&self, node: u64, transition: &Transition) {
+        if let Err(e) = writeln!(self.file.lock().unwrap(), "{}", line(node, transition)) {
+            error!(node, "failed to audit a transition in {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn record_call(&self, node: u64, call: &AdminCall) {
+        if let Err(e) = writeln!(self.file.lock().unwrap(), "{}", call_line(node, call)) {
+            error!(node, "failed to audit an admin call in {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Every change of a node's state and its cause, for finding out why a node
+/// gave up its leadership. Keeps the latest transitions for `GetAuditLog`,
+/// hands them to `WatchAuditLog`'s watchers as they happen and writes them
+/// to its sinks, if any. Does the same with the admin calls that change the
+/// node or the election, for finding out who made them, except for handing
+/// them to watchers.
+#[derive(Debug)]
+pub struct AuditLog {
+    node: u64,
+    recent: Mutex<VecDeque<Transition>>,
+    total: AtomicU64,
+    calls: Mutex<VecDeque<AdminCall>>,
+    calls_total: AtomicU64,
+    sinks: Vec<Box<dyn AuditSink>>,
+    feed: broadcast::Sender<Transition>,
+}
+
+impl AuditLog {
+    pub fn new(node: u64, sinks: Vec<Box<dyn AuditSink>>) -> Self {
+        AuditLog {
+            node,
+            recent: Mutex::default(),
+            total: AtomicU64::new(0),
+            calls: Mutex::default(),
+            calls_total: AtomicU64::new(0),
+            sinks,
+            feed: broadcast::channel(FEED_CAPACITY).0,
+        }
+    }
+
+    pub fn record(&self, time: DateTime<Utc>, term: u64, from: &NodeState, to: &NodeState, cause: Cause) {
+        let peer = cause.peer();
+        let transition = Transition {
+            unix_ms: time.timestamp_millis() as u64,
+            term,
+            from: format!("{:?}", from),
+            to: format!("{:?}", to),
+            cause: cause.to_string(),
+            peer_known: peer.is_some(),
+            peer_id: peer.unwrap_or_default(),
+        };
+        for sink in &self.sinks {
+            sink.record(self.node, &transition);
+        }
+        // nobody may be watching
+        let _ = self.feed.send(transition.clone());
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == AUDIT_LIMIT {
+            recent.pop_front();
+        }
+        recent.push_back(transition);
+        self.total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_call(&self, call: AdminCall) {
+        for sink in &self.sinks {
+            sink.record_call(self.node, &call);
+        }
+        let mut calls = self.calls.lock().unwrap();
+        if calls.len() == AUDIT_LIMIT {
+            calls.pop_front();
+        }
+        calls.push_back(call);
+        self.calls_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The latest transitions, oldest first.
+    pub fn recent(&self) -> Vec<Transition> {
+        self.recent.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    /// The latest admin calls, oldest first.
+    pub fn recent_calls(&self) -> Vec<AdminCall> {
+        self.calls.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn calls_total(&self) -> u64 {
+        self.calls_total.load(Ordering::Relaxed)
+    }
+
+    /// Follows the transitions made from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Transition> {
+        self.feed.subscribe()
+    }
+}
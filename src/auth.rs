@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::{Buf, Bytes};
+use http::HeaderValue;
+use http_body::{Body, Full};
+use prost::Message;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use tonic::body::BoxBody;
+use tonic::Status;
+use tower::{Layer, Service};
+use tracing::warn;
+
+use crate::config::{Config, Grant, Permission};
+use crate::error::ElectionError;
+use crate::leader_election_service::PeerMessage;
+use crate::spiffe;
+
+/// The header a call that changes the election or the ring carries its
+/// signature in, as `<unix ms>.<hex nonce>.<hex tag>`.
+const SIGNATURE_HEADER: &str = "le-signature";
+/// How far the clock that signed a call may be off the node's before the
+/// node refuses the call, and so how long the node remembers the nonces of
+/// the calls it took, to refuse them if they are replayed.
+const MAX_SKEW: Duration = Duration::from_secs(300);
+/// How many random bytes make the nonce of a signed call.
+const NONCE_LEN: usize = 16;
+/// The largest request a signed call may carry. The calls that have to be
+/// signed take a single small message, which the node reads whole to check
+/// the signature over it.
+const MAX_SIGNED_BODY: usize = 64 * 1024;
+/// The calls of the election service that change the election or the ring,
+/// all of which have to be signed along with those of the admin service.
+const CONTROL_CALLS: [&str; 7] = ["Reconfigure", "TakeOver", "Join", "Leave", "Reelect", "Resize", "Merge"];
+/// The calls of the election and admin services that only ask about the
+/// election or the node.
+const READ_CALLS: [&str; 11] = [
+    "LeaderElectionService/GetState", "LeaderElectionService/GetLeader", "LeaderElectionService/WatchLeader",
+    "LeaderElectionService/GetMetrics", "LeaderElectionService/GetStats", "LeaderElectionService/GetAnomalies",
+    "LeaderElectionService/GetRanking", "AdminService/DumpState", "AdminService/GetElectionHistory",
+    "AdminService/GetAuditLog", "AdminService/WatchAuditLog",
+];
+
+/// Authenticates the messages the nodes relay to each other with a secret
+/// all nodes of the ring share: each node signs every message it sends with
+/// an HMAC-SHA256 tag over the rest of the message, and only handles those
+/// whose tags check out. So only a node holding the secret can claim to be
+/// a candidate or the leader, not any client that reaches a node.
+///
+/// Calls that change the election or the ring are signed too, over their
+/// path, the request message, the time and a nonce the node takes each
+/// call with only once.
+#[derive(Debug)]
+pub struct Auth {
+    key: hmac::Key,
+    /// The nonces of the signed calls taken, until they could no longer
+    /// pass for recent ones, with when that is, in unix ms.
+    nonces: Mutex<HashMap<Vec<u8>, u64>>,
+}
+
+impl Auth {
+    /// Reads the secret `config` names, if it enables authentication. A
+    /// trailing newline is not part of the secret.
+    pub fn load(config: &Config) -> io::Result<Option<Self>> {
+        config.auth_key.as_deref().map(Auth::read).transpose()
+    }
+
+    /// Reads the secret in the file at `path`.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let secret = std::fs::read(path).map_err(|e| io::Error::new(e.kind(), format!("cannot read {}: {}", path.display(), e)))?;
+        let secret = secret.strip_suffix(b"\n").unwrap_or(&secret);
+        if secret.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} holds no secret", path.display())))
+        }
+        Ok(Auth { key: hmac::Key::new(hmac::HMAC_SHA256, secret), nonces: Mutex::default() })
+    }
+
+    /// Replaces the signature of `message` with one over the rest of it.
+    pub fn sign(&self, message: &mut PeerMessage) {
+        message.signature.clear();
+        message.signature = hmac::sign(&self.key, &message.encode_to_vec()).as_ref().to_vec();
+    }
+
+    /// Whether `message` was signed with the shared secret, as it is.
+    pub fn verify(&self, message: &PeerMessage) -> bool {
+        let unsigned = PeerMessage { signature: vec![], ..message.clone() };
+        hmac::verify(&self.key, &unsigned.encode_to_vec(), &message.signature).is_ok()
+    }
+
+    /// What the signature of a call of `path` made at `unix_ms` with `nonce`
+    /// and request `body` is over.
+    fn signed(path: &str, unix_ms: u64, nonce: &[u8], body: &[u8]) -> Vec<u8> {
+        [format!("{} {} {}\n", unix_ms, hex(nonce), path).as_bytes(), body].concat()
+    }
+
+    /// The signature of a call of `path` made at `unix_ms` with request
+    /// `body`, under a fresh nonce.
+    fn sign_call(&self, path: &str, unix_ms: u64, body: &[u8]) -> Result<String, ring::error::Unspecified> {
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new().fill(&mut nonce)?;
+        let tag = hmac::sign(&self.key, &Auth::signed(path, unix_ms, &nonce, body));
+        Ok(format!("{}.{}.{}", unix_ms, hex(&nonce), hex(tag.as_ref())))
+    }
+
+    /// Whether `signature` signs a call of `path` with request `body` made
+    /// no more than `MAX_SKEW` away from `now_ms`, under a nonce not taken
+    /// before. Takes the nonce if so.
+    fn verify_call(&self, path: &str, signature: &str, body: &[u8], now_ms: u64) -> bool {
+        let (unix_ms, nonce, tag) = match signature.splitn(3, '.').collect::<Vec<_>>()[..] {
+            [unix_ms, nonce, tag] => (unix_ms, nonce, tag),
+            _ => return false,
+        };
+        let (unix_ms, nonce, tag) = match (unix_ms.parse::<u64>(), unhex(nonce), unhex(tag)) {
+            (Ok(unix_ms), Some(nonce), Some(tag)) if nonce.len() == NONCE_LEN => (unix_ms, nonce, tag),
+            _ => return false,
+        };
+        let max_skew = MAX_SKEW.as_millis() as u64;
+        if unix_ms.abs_diff(now_ms) > max_skew || hmac::verify(&self.key, &Auth::signed(path, unix_ms, &nonce, body), &tag).is_err() {
+            return false
+        }
+        let mut nonces = self.nonces.lock().unwrap();
+        nonces.retain(|_, &mut until| until >= now_ms);
+        nonces.insert(nonce, unix_ms + max_skew).is_none()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok()).collect()
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64)
+}
+
+/// Whether calls of `path` change the election or the ring, and so have to
+/// be signed. Calls that only ask about it need not be, nor do relay
+/// streams, the messages of which are signed one by one.
+pub fn guarded(path: &str) -> bool {
+    match path.strip_prefix("/me.viluon.le.") {
+        Some(call) => call.starts_with("AdminService/")
+            || call.strip_prefix("LeaderElectionService/").is_some_and(|call| CONTROL_CALLS.contains(&call)),
+        None => false,
+    }
+}
+
+/// What the caller of `path` needs to be granted to make the call. Calls of
+/// services other than the election and admin services, like health
+/// checks, only ask about the node.
+pub fn permission(path: &str) -> Permission {
+    match path.strip_prefix("/me.viluon.le.") {
+        Some(call) if READ_CALLS.contains(&call) => Permission::Read,
+        Some(call) if call.starts_with("AdminService/") => Permission::Admin,
+        Some(call) if call.starts_with("LeaderElectionService/") => Permission::Elect,
+        _ => Permission::Read,
+    }
+}
+
+/// Signs the calls a client makes that have to be signed, if it holds the
+/// secret of the ring. Nodes sign theirs to each other, as does `le-admin`.
+#[derive(Debug, Clone)]
+pub struct SigningLayer {
+    auth: Option<Arc<Auth>>,
+}
+
+impl SigningLayer {
+    pub fn new(auth: Option<Arc<Auth>>) -> Self {
+        SigningLayer { auth }
+    }
+}
+
+impl<S> Layer<S> for SigningLayer {
+    type Service = Signed<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Signed { inner, auth: self.auth.clone() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Signed<S> {
+    inner: S,
+    auth: Option<Arc<Auth>>,
+}
+
+/// Reads the whole of `body`, unless it holds more than `MAX_SIGNED_BODY`
+/// bytes.
+async fn read_body<B: Body + Unpin>(mut body: B) -> Result<Bytes, Box<dyn Error + Send + Sync>>
+where B::Error: Into<Box<dyn Error + Send + Sync>> {
+    let mut read = Vec::new();
+    while let Some(data) = body.data().await {
+        let mut data = data.map_err(Into::into)?;
+        if read.len() + data.remaining() > MAX_SIGNED_BODY {
+            return Err(format!("the request exceeds the {} bytes a signed call may carry", MAX_SIGNED_BODY).into())
+        }
+        while data.has_remaining() {
+            let chunk = data.chunk();
+            read.extend_from_slice(chunk);
+            let len = chunk.len();
+            data.advance(len);
+        }
+    }
+    Ok(read.into())
+}
+
+impl<S> Service<http::Request<BoxBody>> for Signed<S>
+where
+    S: Service<http::Request<BoxBody>> + Clone + Send + 'static,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+    S::Future: Send,
+{
+    type Response = S::Response;
+    type Error = Box<dyn Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let auth = match self.auth.clone().filter(|_| guarded(request.uri().path())) {
+            Some(auth) => auth,
+            None => {
+                let response = self.inner.call(request);
+                return Box::pin(async move { response.await.map_err(Into::into) })
+            },
+        };
+        // the service that was made ready takes the call
+        let ready = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, ready);
+        Box::pin(async move {
+            let (mut parts, body) = request.into_parts();
+            let body = read_body(body).await?;
+            let signature = auth.sign_call(parts.uri.path(), unix_ms(), &body).map_err(|_| "cannot draw a nonce to sign the call with")?;
+            parts.headers.insert(SIGNATURE_HEADER, HeaderValue::from_str(&signature).expect("signatures are ASCII"));
+            let body = BoxBody::new(Full::new(body).map_err(|never| match never {}));
+            inner.call(http::Request::from_parts(parts, body)).await.map_err(Into::into)
+        })
+    }
+}
+
+/// Refuses the calls a node serves that have to be signed, unless they are
+/// signed with the secret of the ring, if the node has one, any call from a
+/// peer whose SPIFFE ID matches none of `spiffe_ids`, if there are any, and
+/// any call the `acl` grants its caller no permission for, if it has
+/// entries.
+#[derive(Debug, Clone)]
+pub struct AuthLayer {
+    node: u64,
+    auth: Option<Arc<Auth>>,
+    spiffe_ids: Arc<[String]>,
+    acl: Arc<[Grant]>,
+}
+
+impl AuthLayer {
+    pub fn new(node: u64, auth: Option<Arc<Auth>>, spiffe_ids: Arc<[String]>, acl: Arc<[Grant]>) -> Self {
+        AuthLayer { node, auth, spiffe_ids, acl }
+    }
+
+    /// Whether the peer with SPIFFE ID `peer` may make a call of `path`.
+    fn allows(&self, peer: Option<&str>, path: &str) -> bool {
+        let matching = |pattern: &String| peer.is_some_and(|peer| spiffe::matches(pattern, peer));
+        let needed = permission(path);
+        (self.spiffe_ids.is_empty() || self.spiffe_ids.iter().any(matching))
+            && (self.acl.is_empty() || self.acl.iter().any(|grant| grant.permissions.contains(&needed) && matching(&grant.peers)))
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = Authenticated<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Authenticated { inner, layer: self.clone() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Authenticated<S> {
+    inner: S,
+    layer: AuthLayer,
+}
+
+impl<S> Service<http::Request<hyper::Body>> for Authenticated<S>
+where
+    S: Service<http::Request<hyper::Body>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<hyper::Body>) -> Self::Future {
+        let node = self.layer.node;
+        let path = request.uri().path().to_string();
+        if !self.layer.spiffe_ids.is_empty() || !self.layer.acl.is_empty() {
+            let peer = spiffe::peer_id(request.extensions());
+            if !self.layer.allows(peer.as_deref(), &path) {
+                warn!(node, "refusing a call of {} from {}", path, peer.as_deref().unwrap_or("a peer without a SPIFFE ID"));
+                let refused = Status::from(ElectionError::PermissionDenied { node, peer, call: path }).to_http();
+                return Box::pin(async move { Ok(refused) })
+            }
+        }
+        let auth = match self.layer.auth.clone().filter(|_| guarded(&path)) {
+            Some(auth) => auth,
+            None => return Box::pin(self.inner.call(request)),
+        };
+        // the service that was made ready takes the call
+        let ready = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, ready);
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let signature = parts.headers.get(SIGNATURE_HEADER).and_then(|signature| signature.to_str().ok());
+            let body = match read_body(body).await {
+                Ok(body) if signature.is_some_and(|signature| auth.verify_call(&path, signature, &body, unix_ms())) => body,
+                Ok(_) => {
+                    warn!(node, "refusing a call of {} not signed with the secret of the ring, or replayed", path);
+                    return Ok(Status::from(ElectionError::Unauthenticated { node }).to_http())
+                },
+                Err(e) => {
+                    warn!(node, "refusing a call of {}: {}", path, e);
+                    return Ok(Status::invalid_argument(e.to_string()).to_http())
+                },
+            };
+            inner.call(http::Request::from_parts(parts, hyper::Body::from(body))).await
+        })
+    }
+}
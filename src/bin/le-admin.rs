@@ -0,0 +1,998 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use futures::future;
+use grpc_le::auth::{Auth, Signed, SigningLayer};
+use grpc_le::completions::{self, CommandLine, Shell};
+use grpc_le::config::Output;
+use grpc_le::json;
+use grpc_le::leader_election_service;
+use grpc_le::topology::grpc_url;
+use leader_election_service::leader_election_service_client::LeaderElectionServiceClient;
+use leader_election_service::{state_response::{Kind, Reachability}, AnomaliesRequest, AnomaliesResponse, MetricsRequest, StateRequest, StateResponse};
+use leader_election_service::{MessageCounts, StatsRequest, StatsResponse};
+use leader_election_service::{LeaveRequest, Neighbor, ReconfigureRequest};
+use leader_election_service::admin_service_client::AdminServiceClient;
+use leader_election_service::{AdminCall, AuditLogRequest, DumpStateResponse, Election, NeighborLatency, PauseRequest, ResumeRequest, Transition, UpdateConfigRequest};
+use leader_election_service::{DrainRequest, DumpStateRequest, ElectionHistoryRequest, ForceStateRequest, StepDownRequest, TransferLeadershipRequest, TriggerReelectionRequest};
+use tonic::transport::{Channel, Endpoint};
+use tower::ServiceBuilder;
+
+const USAGE: &str = "usage: le-admin verify --peers ADDR[,ADDR...] [--output json]
+       le-admin status --peers ADDR[,ADDR...] [--output json]
+       le-admin metrics --peers ADDR[,ADDR...]
+       le-admin anomalies --peers ADDR[,ADDR...] [--output json]
+       le-admin stats --peers ADDR[,ADDR...] [--output json]
+       le-admin reconfigure --peer ADDR --epoch N [--left ID=ADDR] [--right ID=ADDR]
+       le-admin leave --peer ADDR --epoch N
+       le-admin dump --peer ADDR [--output json]
+       le-admin force --peer ADDR --state candidate:PHASE|defeated[:LEADER]|leader
+       le-admin reelect --peer ADDR [--epoch N]
+       le-admin drain --peer ADDR [--timeout-ms N]
+       le-admin step-down --peer ADDR
+       le-admin transfer --peer ADDR --to ID[=ADDR]
+       le-admin history --peer ADDR [--output json]
+       le-admin audit --peer ADDR [--output json]
+       le-admin watch-audit --peer ADDR [--output json]
+       le-admin update-config --peer ADDR --set NAME=VALUE [--set NAME=VALUE...]
+       le-admin pause --peer ADDR
+       le-admin resume --peer ADDR
+       le-admin rebalance --peers ADDR[,ADDR...] --add ID=ADDR[,ID=ADDR...] --epoch N [--dry-run]
+       le-admin gen-dashboard [--datasource UID]
+       le-admin export-proto-descriptors --out FILE
+       le-admin completions bash|zsh|fish
+
+Calls that change the election or the ring are signed with the secret of
+--auth-key FILE, if the nodes have one.";
+
+/// The commands, each with the words that may follow it, for completions.
+const COMMANDS: [(&str, &[&str]); 23] = [("verify", &[]), ("status", &[]), ("metrics", &[]), ("anomalies", &[]), ("stats", &[]),
+    ("reconfigure", &[]), ("leave", &[]), ("dump", &[]), ("force", &[]), ("reelect", &[]), ("drain", &[]), ("step-down", &[]),
+    ("transfer", &[]), ("history", &[]), ("audit", &[]), ("watch-audit", &[]), ("update-config", &[]), ("pause", &[]), ("resume", &[]),
+    ("rebalance", &[]), ("gen-dashboard", &[]), ("export-proto-descriptors", &[]), ("completions", &["bash", "zsh", "fish"])];
+/// The flags of all commands.
+const FLAGS: [&str; 15] = ["peers", "peer", "epoch", "left", "right", "state", "timeout-ms", "to", "set", "add", "dry-run", "datasource", "out", "output", "auth-key"];
+
+/// The panels of the generated dashboard: title, unit, PromQL query and legend.
+const PANELS: [(&str, &str, &str, &str); 15] = [
+    ("Time without a leader", "s", "grpc_le_leaderless_seconds", "{{node}}"),
+    ("Leader tenure", "s", "grpc_le_leader_tenure_seconds", "{{node}}"),
+    ("Leader changes", "short", "increase(grpc_le_leader_changes_total[5m])", "{{node}}"),
+    ("Terms served", "short", "grpc_le_terms_served_total", "{{node}}"),
+    ("RPC rate", "reqps", "sum by (method) (rate(grpc_le_rpc_responses_total{side=\"client\"}[1m]))", "{{method}}"),
+    ("RPC latency (p99)", "s",
+        "histogram_quantile(0.99, sum by (le, method) (rate(grpc_le_rpc_latency_seconds_bucket[5m])))", "{{method}}"),
+    ("Outbound queue depth", "short", "grpc_le_outbound_queue_length", "{{node}} to {{neighbor}}"),
+    ("Outbound messages dropped", "short", "increase(grpc_le_outbound_dropped_total[5m])", "{{node}} to {{neighbor}}"),
+    ("Neighbour round trip", "s", "grpc_le_neighbor_rtt_seconds", "{{node}} to {{neighbor}}"),
+    ("Duplicate, missing and reordered messages", "short",
+        "increase({__name__=~\"grpc_le_(duplicate|missing|reordered)_messages_total\"}[5m])", "{{node}} {{__name__}}"),
+    ("Leader anomalies", "short", "increase(grpc_le_leader_anomalies_total[5m])", "{{node}}"),
+    ("Election duration", "s", "grpc_le_election_duration_seconds", "{{node}}"),
+    ("Elections", "short", "increase(grpc_le_elections_total[1h])", "{{node}}"),
+    ("Election phase", "short", "grpc_le_phase", "{{node}}"),
+    ("Probe rate", "short",
+        "rate({__name__=~\"grpc_le_probes_(sent|received|forwarded)_total\"}[1m])", "{{node}} {{__name__}}"),
+];
+
+async fn get_state(addr: String) -> Result<StateResponse, Box<dyn std::error::Error>> {
+    let mut client = LeaderElectionServiceClient::connect(addr).await?;
+    Ok(client.get_state(StateRequest::default()).await?.into_inner())
+}
+
+async fn get_metrics(addr: String) -> Result<String, Box<dyn std::error::Error>> {
+    let mut client = LeaderElectionServiceClient::connect(addr).await?;
+    Ok(client.get_metrics(MetricsRequest::default()).await?.into_inner().text)
+}
+
+/// Prints the metrics of every peer. Returns whether all peers responded.
+async fn metrics(peers: &[String]) -> bool {
+    let texts = future::join_all(peers.iter().cloned().map(get_metrics)).await;
+    let mut all_ok = true;
+    for (peer, text) in peers.iter().zip(texts) {
+        println!("# peer {}", peer);
+        match text {
+            Ok(text) => print!("{}", text),
+            Err(e) => {
+                println!("# unreachable: {}", e);
+                all_ok = false;
+            },
+        }
+    }
+    all_ok
+}
+
+async fn get_anomalies(addr: String) -> Result<AnomaliesResponse, Box<dyn std::error::Error>> {
+    let mut client = LeaderElectionServiceClient::connect(addr).await?;
+    Ok(client.get_anomalies(AnomaliesRequest::default()).await?.into_inner())
+}
+
+/// Prints the anomalies every peer has seen. Returns whether all peers
+/// responded and none has seen any.
+async fn anomalies(peers: &[String], output: Output) -> bool {
+    let responses = future::join_all(peers.iter().cloned().map(get_anomalies)).await;
+    let mut clean = true;
+    let mut reports = vec![];
+    for (peer, response) in peers.iter().zip(responses) {
+        match response {
+            Ok(response) if output == Output::Json => {
+                let anomalies = response.anomalies.iter()
+                    .map(|anomaly| format!(r#"{{"unix_ms": {}, "kind": "{:?}", "detail": {}}}"#, anomaly.unix_ms, anomaly.kind(), json::quote(&anomaly.detail)))
+                    .collect::<Vec<_>>();
+                reports.push(format!(r#"{{"peer": {}, "total": {}, "anomalies": [{}]}}"#, json::quote(peer), response.total, anomalies.join(", ")));
+                clean &= response.total == 0;
+            },
+            Ok(response) => {
+                println!("{}: {} anomalies", peer, response.total);
+                for anomaly in &response.anomalies {
+                    let at = chrono::NaiveDateTime::from_timestamp((anomaly.unix_ms / 1000) as i64, (anomaly.unix_ms % 1000 * 1_000_000) as u32);
+                    println!("  {} {:?}: {}", at.format("%F %T%.3f"), anomaly.kind(), anomaly.detail);
+                }
+                clean &= response.total == 0;
+            },
+            Err(e) => {
+                match output {
+                    Output::Text => println!("{}: unreachable: {}", peer, e),
+                    Output::Json => reports.push(unreachable(peer, &*e)),
+                }
+                clean = false;
+            },
+        }
+    }
+    if output == Output::Json {
+        println!(r#"{{"peers": [{}]}}"#, reports.join(", "));
+    }
+    clean
+}
+
+async fn get_stats(addr: String) -> Result<StatsResponse, Box<dyn std::error::Error>> {
+    let mut client = LeaderElectionServiceClient::connect(addr).await?;
+    Ok(client.get_stats(StatsRequest::default()).await?.into_inner())
+}
+
+fn describe_counts(counts: &MessageCounts) -> String {
+    format!("{} probes, {} notifications, {} digests", counts.probes, counts.notifications, counts.digests)
+}
+
+fn counts_json(counts: &MessageCounts) -> String {
+    format!(r#"{{"probes": {}, "notifications": {}, "digests": {}}}"#, counts.probes, counts.notifications, counts.digests)
+}
+
+/// Prints what every peer counted over each window. Returns whether all
+/// peers responded.
+async fn stats(peers: &[String], output: Output) -> bool {
+    let responses = future::join_all(peers.iter().cloned().map(get_stats)).await;
+    let mut all_ok = true;
+    let mut reports = vec![];
+    let none = MessageCounts::default();
+    for (peer, response) in peers.iter().zip(responses) {
+        match response {
+            Ok(response) if output == Output::Json => {
+                let windows = response.windows.iter().map(|window| format!(
+                    r#"{{"seconds": {}, "received": {}, "sent": {}, "transitions": {}, "reconnects": {}, "errors": {}}}"#, window.seconds,
+                    counts_json(window.received.as_ref().unwrap_or(&none)), counts_json(window.sent.as_ref().unwrap_or(&none)),
+                    window.transitions, window.reconnects, window.errors)).collect::<Vec<_>>();
+                reports.push(format!(r#"{{"peer": {}, "windows": [{}]}}"#, json::quote(peer), windows.join(", ")));
+            },
+            Ok(response) => {
+                println!("{}:", peer);
+                for window in &response.windows {
+                    println!("  last {}m: received {}; sent {}; {} transitions, {} reconnects, {} errors",
+                        window.seconds / 60,
+                        describe_counts(window.received.as_ref().unwrap_or(&none)),
+                        describe_counts(window.sent.as_ref().unwrap_or(&none)),
+                        window.transitions, window.reconnects, window.errors);
+                }
+            },
+            Err(e) => {
+                match output {
+                    Output::Text => println!("{}: unreachable: {}", peer, e),
+                    Output::Json => reports.push(unreachable(peer, &*e)),
+                }
+                all_ok = false;
+            },
+        }
+    }
+    if output == Output::Json {
+        println!(r#"{{"peers": [{}]}}"#, reports.join(", "));
+    }
+    all_ok
+}
+
+fn describe(state: &StateResponse) -> String {
+    match state.kind() {
+        Kind::Candidate => format!("candidate (phase {})", state.phase),
+        Kind::Defeated => "defeated".to_string(),
+        Kind::Leader => "leader".to_string(),
+    }
+}
+
+/// Neighbour `id` of a node, with whether the node could reach it.
+fn describe_neighbor(id: u64, reachability: Reachability) -> String {
+    match reachability {
+        Reachability::Untried => format!("{} (untried)", id),
+        Reachability::Reachable => format!("{} (up)", id),
+        Reachability::Unreachable => format!("{} (DOWN)", id),
+    }
+}
+
+/// A peer that could not be queried, as a JSON object.
+fn unreachable(peer: &str, e: &dyn std::error::Error) -> String {
+    format!(r#"{{"peer": {}, "error": {}}}"#, json::quote(peer), json::quote(&e.to_string()))
+}
+
+/// `value` in JSON, `null` if there is none.
+fn or_null(value: Option<u64>) -> String {
+    value.map_or("null".to_string(), |value| value.to_string())
+}
+
+/// The state of a node as the fields of a JSON object.
+fn state_fields(state: &StateResponse) -> String {
+    let neighbor = |id, reachability| format!(r#"{{"id": {}, "reachability": "{}"}}"#, id, match reachability {
+        Reachability::Untried => "untried",
+        Reachability::Reachable => "up",
+        Reachability::Unreachable => "down",
+    });
+    let timers = state.timers.iter()
+        .map(|timer| format!(r#"{{"name": {}, "remaining_ms": {}}}"#, json::quote(&timer.name), timer.remaining_ms))
+        .collect::<Vec<_>>();
+    format!(concat!(r#""id": {}, "state": "{}", "phase": {}, "term": {}, "leader": {}, "deputy": {}, "ring_size": {}, "committee": {:?}, "#,
+        r#""priority": {}, "group": {}, "lamport": {}, "left": {}, "right": {}, "timers": [{}]"#),
+        state.id, format!("{:?}", state.kind()).to_lowercase(), state.phase, state.term,
+        or_null(state.leader_known.then_some(state.leader_id)), or_null(state.deputy_known.then_some(state.deputy_id)),
+        state.ring_size, state.committee, state.priority, state.group_id, state.lamport,
+        neighbor(state.left_id, state.left_reachability()), neighbor(state.right_id, state.right_reachability()), timers.join(", "))
+}
+
+/// Prints the state of every peer, queried all at once, and whether they
+/// reach their neighbours. Returns whether all peers responded and agree on
+/// the leader.
+async fn status(peers: &[String], output: Output) -> bool {
+    let states = future::join_all(peers.iter().cloned().map(get_state)).await;
+    let mut leaders: BTreeMap<Option<u64>, Vec<u64>> = BTreeMap::new();
+    let mut all_ok = true;
+    let mut reports = vec![];
+    if output == Output::Text {
+        println!("{:<30} {:>6}  {:<10} {:>5} {:>6}  {:<16} right", "peer", "id", "state", "phase", "leader", "left");
+    }
+    for (peer, state) in peers.iter().zip(states) {
+        let state = match state {
+            Ok(state) => state,
+            Err(e) => {
+                match output {
+                    Output::Text => println!("{:<30} unreachable: {}", peer, e),
+                    Output::Json => reports.push(unreachable(peer, &*e)),
+                }
+                all_ok = false;
+                continue
+            },
+        };
+        let leader = state.leader_known.then_some(state.leader_id);
+        leaders.entry(leader).or_default().push(state.id);
+        if output == Output::Json {
+            reports.push(format!(r#"{{"peer": {}, {}}}"#, json::quote(peer), state_fields(&state)));
+            continue
+        }
+        let phase = match state.kind() {
+            Kind::Candidate => state.phase.to_string(),
+            _ => "-".to_string(),
+        };
+        println!("{:<30} {:>6}  {:<10} {:>5} {:>6}  {:<16} {}", peer, state.id, format!("{:?}", state.kind()).to_lowercase(), phase,
+            leader.map_or("?".to_string(), |l| l.to_string()),
+            describe_neighbor(state.left_id, state.left_reachability()), describe_neighbor(state.right_id, state.right_reachability()));
+    }
+    let agreed = leaders.len() <= 1;
+    match output {
+        Output::Text if !agreed => {
+            let views = leaders.iter()
+                .map(|(leader, ids)| format!("{} according to {:?}", leader.map_or("no leader".to_string(), |l| l.to_string()), ids))
+                .collect::<Vec<_>>();
+            println!("leader disagreement: {}", views.join("; "));
+        },
+        Output::Text => (),
+        Output::Json => println!(r#"{{"peers": [{}], "agreed": {}}}"#, reports.join(", "), agreed),
+    }
+    all_ok && agreed
+}
+
+/// Queries every peer and reports whether they agree on the leader, the ring
+/// size, the committee and the deputy, and whether their neighbours agree
+/// on how the ring is wired. Returns the list of human-readable
+/// inconsistencies found, having printed them along with the peers' states
+/// if the output is JSON.
+async fn verify(peers: &[String], output: Output) -> Vec<String> {
+    let states = future::join_all(peers.iter().cloned().map(get_state)).await;
+
+    let mut problems = vec![];
+    let mut leaders: BTreeMap<Option<u64>, Vec<u64>> = BTreeMap::new();
+    let mut ring_sizes: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+    let mut committees: BTreeMap<Vec<u64>, Vec<u64>> = BTreeMap::new();
+    let mut deputies: BTreeMap<Option<u64>, Vec<u64>> = BTreeMap::new();
+    let mut neighbors: BTreeMap<u64, (u64, u64)> = BTreeMap::new();
+    let mut reports = vec![];
+    if output == Output::Text {
+        println!("{:<30} {:>6}  {:<20} {:>6} {:>6} {:>9}  committee", "peer", "id", "state", "leader", "deputy", "ring size");
+    }
+    for (peer, state) in peers.iter().zip(states) {
+        let state = match state {
+            Ok(state) => state,
+            Err(e) => {
+                match output {
+                    Output::Text => println!("{:<30} unreachable: {}", peer, e),
+                    Output::Json => reports.push(unreachable(peer, &*e)),
+                }
+                problems.push(format!("{} is unreachable", peer));
+                continue
+            },
+        };
+        let leader = state.leader_known.then_some(state.leader_id);
+        let deputy = state.deputy_known.then_some(state.deputy_id);
+        match output {
+            Output::Text => println!("{:<30} {:>6}  {:<20} {:>6} {:>6} {:>9}  {:?}", peer, state.id, describe(&state),
+                leader.map_or("?".to_string(), |l| l.to_string()), deputy.map_or("?".to_string(), |d| d.to_string()),
+                state.ring_size, state.committee),
+            Output::Json => reports.push(format!(r#"{{"peer": {}, {}}}"#, json::quote(peer), state_fields(&state))),
+        }
+        leaders.entry(leader).or_default().push(state.id);
+        ring_sizes.entry(state.ring_size).or_default().push(state.id);
+        committees.entry(state.committee).or_default().push(state.id);
+        deputies.entry(deputy).or_default().push(state.id);
+        neighbors.insert(state.id, (state.left_id, state.right_id));
+    }
+
+    if leaders.len() > 1 || leaders.contains_key(&None) {
+        let views = leaders.iter()
+            .map(|(leader, ids)| format!("{} according to {:?}", leader.map_or("no leader".to_string(), |l| l.to_string()), ids))
+            .collect::<Vec<_>>();
+        problems.push(format!("leader disagreement: {}", views.join("; ")));
+    }
+    if ring_sizes.len() > 1 {
+        let views = ring_sizes.iter()
+            .map(|(size, ids)| format!("{} according to {:?}", size, ids))
+            .collect::<Vec<_>>();
+        problems.push(format!("ring size disagreement: {}", views.join("; ")));
+    }
+    if committees.len() > 1 {
+        let views = committees.iter()
+            .map(|(committee, ids)| format!("{:?} according to {:?}", committee, ids))
+            .collect::<Vec<_>>();
+        problems.push(format!("committee disagreement: {}", views.join("; ")));
+    }
+    if deputies.len() > 1 {
+        let views = deputies.iter()
+            .map(|(deputy, ids)| format!("{} according to {:?}", deputy.map_or("no deputy".to_string(), |d| d.to_string()), ids))
+            .collect::<Vec<_>>();
+        problems.push(format!("deputy disagreement: {}", views.join("; ")));
+    }
+    for (id, &(_, right)) in &neighbors {
+        match neighbors.get(&right) {
+            Some(&(left, _)) if left != *id =>
+                problems.push(format!("miswired ring: {} sends right to {}, which sends left to {}", id, right, left)),
+            _ => (),
+        }
+    }
+    if output == Output::Json {
+        let quoted = problems.iter().map(|problem| json::quote(problem)).collect::<Vec<_>>();
+        println!(r#"{{"peers": [{}], "problems": [{}]}}"#, reports.join(", "), quoted.join(", "));
+    }
+    problems
+}
+
+/// A Grafana dashboard charting the metrics nodes export, reading from the
+/// Prometheus data source with the given UID.
+fn dashboard(datasource: &str) -> String {
+    let datasource = format!("{{\"type\": \"prometheus\", \"uid\": {}}}", json::quote(datasource));
+    let panels = PANELS.iter().enumerate().map(|(i, (title, unit, expr, legend))| format!(
+        r#"    {{
+      "id": {id},
+      "type": "timeseries",
+      "title": {title},
+      "datasource": {datasource},
+      "gridPos": {{"h": 8, "w": 12, "x": {x}, "y": {y}}},
+      "fieldConfig": {{"defaults": {{"unit": {unit}}}, "overrides": []}},
+      "targets": [{{"refId": "A", "datasource": {datasource}, "expr": {expr}, "legendFormat": {legend}}}]
+    }}"#,
+        id = i + 1, title = json::quote(title), datasource = datasource, x = i % 2 * 12, y = i / 2 * 8,
+        unit = json::quote(unit), expr = json::quote(expr), legend = json::quote(legend)))
+        .collect::<Vec<_>>();
+    format!(r#"{{
+  "title": "gRPC leader election",
+  "uid": "grpc-le",
+  "tags": ["grpc-le"],
+  "timezone": "browser",
+  "schemaVersion": 36,
+  "refresh": "10s",
+  "time": {{"from": "now-1h", "to": "now"}},
+  "panels": [
+{}
+  ]
+}}
+"#, panels.join(",\n"))
+}
+
+/// The gRPC URL of the node at `addr`, saying what is wrong with it if it
+/// is not an address.
+fn url(addr: &str) -> Option<String> {
+    grpc_url(addr).map_err(|e| eprintln!("invalid address {:?}: {}", addr, e)).ok()
+}
+
+fn parse_peers(args: &[String]) -> Option<Vec<String>> {
+    match args {
+        [flag, peers] if flag == "--peers" => peers
+            .split(',')
+            .filter(|p| !p.is_empty())
+            .map(url)
+            .collect(),
+        _ => None,
+    }
+}
+
+/// Parses `--peer ADDR --epoch N [--left ID=ADDR] [--right ID=ADDR]`.
+fn parse_reconfigure(args: &[String]) -> Option<(String, ReconfigureRequest)> {
+    let neighbor = |value: &str| {
+        let (id, addr) = value.split_once('=')?;
+        Some(Neighbor { id: id.parse().ok()?, addr: url(addr)? })
+    };
+    let (mut peer, mut epoch, mut request) = (None, None, ReconfigureRequest::default());
+    for pair in args.chunks(2) {
+        match pair {
+            [flag, value] if flag == "--peer" => peer = Some(url(value)?),
+            [flag, value] if flag == "--epoch" => epoch = Some(value.parse().ok()?),
+            [flag, value] if flag == "--left" => request.left = Some(neighbor(value)?),
+            [flag, value] if flag == "--right" => request.right = Some(neighbor(value)?),
+            _ => return None,
+        }
+    }
+    request.epoch = epoch?;
+    Some((peer?, request))
+}
+
+/// Connects to `addr`, signing the calls that have to be with `auth`.
+async fn signed(addr: String, auth: &Option<Arc<Auth>>) -> Result<Signed<Channel>, Box<dyn std::error::Error>> {
+    let channel = Endpoint::from_shared(addr)?.connect().await?;
+    Ok(ServiceBuilder::new().layer(SigningLayer::new(auth.clone())).service(channel))
+}
+
+async fn leave(peer: String, epoch: u64, auth: &Option<Arc<Auth>>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = LeaderElectionServiceClient::new(signed(peer, auth).await?);
+    client.leave(LeaveRequest { epoch, ..Default::default() }).await?;
+    Ok(())
+}
+
+async fn reconfigure(peer: String, request: ReconfigureRequest, auth: &Option<Arc<Auth>>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = LeaderElectionServiceClient::new(signed(peer, auth).await?);
+    client.reconfigure(request).await?;
+    Ok(())
+}
+
+/// A request to a node's admin service.
+enum AdminRequest {
+    Dump,
+    Force(ForceStateRequest),
+    Reelect(TriggerReelectionRequest),
+    Drain(DrainRequest),
+    StepDown,
+    Transfer(TransferLeadershipRequest),
+    History,
+    Audit,
+    WatchAudit,
+    UpdateConfig(UpdateConfigRequest),
+    Pause,
+    Resume,
+}
+
+/// Parses `candidate:PHASE`, `defeated[:LEADER]` or `leader`.
+fn parse_forced(value: &str) -> Option<ForceStateRequest> {
+    let (kind, id) = match value.split_once(':') {
+        Some((kind, id)) => (kind, Some(id.parse().ok()?)),
+        None => (value, None),
+    };
+    match (kind, id) {
+        ("candidate", Some(phase)) => Some(ForceStateRequest { kind: Kind::Candidate as i32, phase, ..Default::default() }),
+        ("defeated", leader) => Some(ForceStateRequest {
+            kind: Kind::Defeated as i32, leader_id: leader.unwrap_or_default(), leader_known: leader.is_some(), ..Default::default()
+        }),
+        ("leader", None) => Some(ForceStateRequest { kind: Kind::Leader as i32, ..Default::default() }),
+        _ => None,
+    }
+}
+
+/// Parses `<command> --peer ADDR` and the options of the command, for the
+/// commands of the admin service.
+fn parse_admin(args: &[String]) -> Option<(String, AdminRequest)> {
+    let (command, args) = args.split_first()?;
+    let (mut peer, mut forced, mut epoch, mut timeout_ms, mut target) = (None, None, 0, 0, None);
+    let mut settings = HashMap::new();
+    for pair in args.chunks(2) {
+        match (command.as_str(), pair) {
+            (_, [flag, value]) if flag == "--peer" => peer = Some(url(value)?),
+            ("force", [flag, value]) if flag == "--state" => forced = Some(parse_forced(value)?),
+            ("reelect", [flag, value]) if flag == "--epoch" => epoch = value.parse().ok()?,
+            ("drain", [flag, value]) if flag == "--timeout-ms" => timeout_ms = value.parse().ok()?,
+            ("transfer", [flag, value]) if flag == "--to" => target = Some(match value.split_once('=') {
+                Some((id, addr)) => TransferLeadershipRequest { target_id: id.parse().ok()?, target_addr: url(addr)?, ..Default::default() },
+                None => TransferLeadershipRequest { target_id: value.parse().ok()?, ..Default::default() },
+            }),
+            ("update-config", [flag, value]) if flag == "--set" => {
+                let (name, value) = value.split_once('=')?;
+                settings.insert(name.to_string(), value.to_string());
+            },
+            _ => return None,
+        }
+    }
+    let request = match command.as_str() {
+        "dump" => AdminRequest::Dump,
+        "force" => AdminRequest::Force(forced?),
+        "reelect" => AdminRequest::Reelect(TriggerReelectionRequest { epoch, ..Default::default() }),
+        "drain" => AdminRequest::Drain(DrainRequest { timeout_ms, ..Default::default() }),
+        "step-down" => AdminRequest::StepDown,
+        "transfer" => AdminRequest::Transfer(target?),
+        "history" => AdminRequest::History,
+        "audit" => AdminRequest::Audit,
+        "watch-audit" => AdminRequest::WatchAudit,
+        "update-config" if !settings.is_empty() => AdminRequest::UpdateConfig(UpdateConfigRequest { settings, ..Default::default() }),
+        "pause" => AdminRequest::Pause,
+        "resume" => AdminRequest::Resume,
+        _ => return None,
+    };
+    Some((peer?, request))
+}
+
+/// Sends `request` to the admin service of `peer` and prints its answer,
+/// as JSON if `output` says so and the request asks for information.
+async fn admin(peer: String, request: AdminRequest, output: Output, auth: &Option<Arc<Auth>>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = AdminServiceClient::new(signed(peer, auth).await?);
+    match request {
+        AdminRequest::Dump => match output {
+            Output::Text => println!("{:#?}", client.dump_state(DumpStateRequest::default()).await?.into_inner()),
+            Output::Json => println!("{}", dump_json(&client.dump_state(DumpStateRequest::default()).await?.into_inner())),
+        },
+        AdminRequest::Force(request) => {
+            client.force_state(request).await?;
+        },
+        AdminRequest::Reelect(request) =>
+            println!("restarting the election for epoch {}", client.trigger_reelection(request).await?.into_inner().epoch),
+        AdminRequest::Drain(request) =>
+            println!("shut down with {} messages undelivered", client.drain(request).await?.into_inner().undelivered),
+        AdminRequest::StepDown => match client.step_down(StepDownRequest::default()).await?.into_inner().stepped_down {
+            true => println!("stepped down"),
+            false => println!("not the leader"),
+        },
+        AdminRequest::Transfer(request) => {
+            let target = request.target_id;
+            match client.transfer_leadership(request).await?.into_inner() {
+                response if response.transferred => println!("node {} leads term {}", target, response.term),
+                _ => println!("node {} could not take over, stepped down instead", target),
+            }
+        },
+        AdminRequest::History => {
+            let history = client.get_election_history(ElectionHistoryRequest::default()).await?.into_inner();
+            if output == Output::Json {
+                let elections = history.elections.iter().map(election_json).collect::<Vec<_>>();
+                println!(r#"{{"total": {}, "elections": [{}]}}"#, history.total, elections.join(", "));
+                return Ok(())
+            }
+            println!("{} elections", history.total);
+            for election in &history.elections {
+                let at = chrono::NaiveDateTime::from_timestamp((election.unix_ms / 1000) as i64, (election.unix_ms % 1000 * 1_000_000) as u32);
+                let candidates = election.candidates.iter().map(u64::to_string).collect::<Vec<_>>().join("/");
+                println!("  {} term {}: node {} after {} ms, {} phases, {} messages, {} probes ended here after {} hops at most, {} candidates by phase",
+                    at.format("%F %T%.3f"), election.term, election.leader_id, election.duration_ms, election.phases, election.messages,
+                    election.probes_ended, election.max_probe_hops, candidates);
+            }
+        },
+        AdminRequest::Audit => {
+            let audit = client.get_audit_log(AuditLogRequest::default()).await?.into_inner();
+            if output == Output::Json {
+                let transitions = audit.transitions.iter().map(transition_json).collect::<Vec<_>>();
+                let calls = audit.admin_calls.iter().map(admin_call_json).collect::<Vec<_>>();
+                println!(r#"{{"total": {}, "transitions": [{}], "admin_calls_total": {}, "admin_calls": [{}]}}"#,
+                    audit.total, transitions.join(", "), audit.admin_calls_total, calls.join(", "));
+                return Ok(())
+            }
+            println!("{} transitions", audit.total);
+            for transition in &audit.transitions {
+                println!("  {}", transition_line(transition));
+            }
+            println!("{} admin calls", audit.admin_calls_total);
+            for call in &audit.admin_calls {
+                let at = chrono::NaiveDateTime::from_timestamp((call.unix_ms / 1000) as i64, (call.unix_ms % 1000 * 1_000_000) as u32);
+                let caller = if call.caller.is_empty() { "in-process" } else { &call.caller };
+                println!("  {} {}({}) by {} {}: {}", at.format("%F %T%.3f"), call.call, call.arguments, caller,
+                    if call.ok { "succeeded" } else { "failed" }, call.outcome);
+            }
+        },
+        AdminRequest::WatchAudit => {
+            let mut transitions = client.watch_audit_log(AuditLogRequest::default()).await?.into_inner();
+            while let Some(transition) = transitions.message().await? {
+                match output {
+                    Output::Text => println!("{}", transition_line(&transition)),
+                    Output::Json => println!("{}", transition_json(&transition)),
+                }
+            }
+        },
+        AdminRequest::UpdateConfig(request) => match client.update_config(request).await?.into_inner().reelected {
+            true => println!("updated, restarting the election for the new neighbours"),
+            false => println!("updated"),
+        },
+        AdminRequest::Pause => match client.pause(PauseRequest::default()).await?.into_inner().was_paused {
+            true => println!("already paused"),
+            false => println!("paused"),
+        },
+        AdminRequest::Resume => match client.resume(ResumeRequest::default()).await?.into_inner().was_paused {
+            true => println!("resumed"),
+            false => println!("not paused"),
+        },
+    }
+    Ok(())
+}
+
+/// Describes `transition` on one line.
+fn transition_line(transition: &Transition) -> String {
+    let at = chrono::NaiveDateTime::from_timestamp((transition.unix_ms / 1000) as i64, (transition.unix_ms % 1000 * 1_000_000) as u32);
+    let mut line = format!("{} term {}: {} -> {} on {}", at.format("%F %T%.3f"), transition.term, transition.from, transition.to, transition.cause);
+    if transition.peer_known {
+        line += &format!(", from node {}", transition.peer_id);
+    }
+    line
+}
+
+fn transition_json(transition: &Transition) -> String {
+    format!(r#"{{"unix_ms": {}, "term": {}, "from": {}, "to": {}, "cause": {}, "peer": {}}}"#, transition.unix_ms, transition.term,
+        json::quote(&transition.from), json::quote(&transition.to), json::quote(&transition.cause),
+        or_null(transition.peer_known.then_some(transition.peer_id)))
+}
+
+fn admin_call_json(call: &AdminCall) -> String {
+    format!(r#"{{"unix_ms": {}, "call": {}, "arguments": {}, "caller": {}, "ok": {}, "outcome": {}}}"#, call.unix_ms, json::quote(&call.call),
+        json::quote(&call.arguments), json::quote(&call.caller), call.ok, json::quote(&call.outcome))
+}
+
+fn election_json(election: &Election) -> String {
+    format!(concat!(r#"{{"unix_ms": {}, "term": {}, "leader": {}, "duration_ms": {}, "phases": {}, "messages": {}, "#,
+        r#""probes_ended": {}, "max_probe_hops": {}, "probe_hops": {}, "candidates": {:?}}}"#),
+        election.unix_ms, election.term, election.leader_id, election.duration_ms, election.phases, election.messages,
+        election.probes_ended, election.max_probe_hops, election.probe_hops, election.candidates)
+}
+
+fn latency_json(latency: Option<&NeighborLatency>) -> String {
+    match latency {
+        Some(latency) => format!(r#"{{"smoothed_rtt_us": {}, "last_rtt_us": {}, "samples": {}, "slow": {}, "slow_count": {}}}"#,
+            latency.smoothed_rtt_us, latency.last_rtt_us, latency.samples, latency.slow, latency.slow_count),
+        None => "null".to_string(),
+    }
+}
+
+/// What `le-admin dump` prints with `--output json`.
+fn dump_json(dump: &DumpStateResponse) -> String {
+    let state = dump.state.as_ref().map_or("null".to_string(), |state| format!("{{{}}}", state_fields(state)));
+    let neighbor = |neighbor: Option<&leader_election_service::Neighbor>| neighbor
+        .map_or("null".to_string(), |neighbor| format!(r#"{{"id": {}, "addr": {}}}"#, neighbor.id, json::quote(&neighbor.addr)));
+    let timed_out = dump.timed_out.as_ref().map_or("null".to_string(), |timeout| {
+        let traffic = timeout.neighbors.iter().map(|traffic| format!(r#"{{"id": {}, "sent_ms_ago": {}, "received_ms_ago": {}}}"#, traffic.id,
+            or_null(traffic.sent_known.then_some(traffic.sent_ms_ago)), or_null(traffic.received_known.then_some(traffic.received_ms_ago))))
+            .collect::<Vec<_>>();
+        format!(r#"{{"term": {}, "phase": {}, "unix_ms": {}, "deadline_ms": {}, "neighbors": [{}]}}"#,
+            timeout.term, timeout.phase, timeout.unix_ms, timeout.deadline_ms, traffic.join(", "))
+    });
+    format!(concat!(r#"{{"state": {}, "left": {}, "right": {}, "left_queued": {}, "right_queued": {}, "left_unacknowledged": {}, "#,
+        r#""right_unacknowledged": {}, "topology_epoch": {}, "reelection_epoch": {}, "incarnation": {}, "paused": {}, "timed_out": {}, "#,
+        r#""left_latency": {}, "right_latency": {}}}"#),
+        state, neighbor(dump.left.as_ref()), neighbor(dump.right.as_ref()), dump.left_queued, dump.right_queued, dump.left_unacknowledged,
+        dump.right_unacknowledged, dump.topology_epoch, dump.reelection_epoch, dump.incarnation, dump.paused, timed_out,
+        latency_json(dump.left_latency.as_ref()), latency_json(dump.right_latency.as_ref()))
+}
+
+/// A node of the ring, with the address it is reached at.
+type Member = (u64, String);
+
+/// Walks the ring formed by `peers` rightwards, starting at the lowest ID.
+/// Fails unless every peer is reachable and they form a single ring.
+async fn ring_order(peers: &[String]) -> Result<Vec<Member>, String> {
+    let states = future::join_all(peers.iter().cloned().map(get_state)).await;
+    let mut members = BTreeMap::new();
+    for (peer, state) in peers.iter().zip(states) {
+        let state = state.map_err(|e| format!("{} is unreachable: {}", peer, e))?;
+        members.insert(state.id, (peer.clone(), state.right_id));
+    }
+    let first = *members.keys().next().ok_or("no peers given")?;
+    let mut order = vec![];
+    let mut id = first;
+    loop {
+        let (addr, right) = members.get(&id).ok_or_else(|| format!("node {} is not among the peers", id))?;
+        order.push((id, addr.clone()));
+        id = *right;
+        if id == first {
+            break
+        }
+        if order.len() == members.len() {
+            return Err(format!("the ring does not lead back to node {}", first))
+        }
+    }
+    if order.len() != members.len() {
+        return Err(format!("only {} of {} peers are on node {}'s ring", order.len(), members.len(), first))
+    }
+    Ok(order)
+}
+
+/// Spreads `newcomers` evenly over the links of `ring`, so that as few
+/// existing links as possible are broken.
+fn splice(ring: &[Member], newcomers: &[Member]) -> Vec<Member> {
+    let mut spliced = vec![];
+    for (i, member) in ring.iter().enumerate() {
+        spliced.push(member.clone());
+        spliced.extend(newcomers.iter().enumerate()
+            .filter(|(j, _)| j * ring.len() / newcomers.len() == i)
+            .map(|(_, newcomer)| newcomer.clone()));
+    }
+    spliced
+}
+
+/// The neighbours of every node of `ring`, by ID.
+fn neighbors_in(ring: &[Member]) -> BTreeMap<u64, (&Member, &Member)> {
+    let n = ring.len();
+    (0..n).map(|i| (ring[i].0, (&ring[(i + n - 1) % n], &ring[(i + 1) % n]))).collect()
+}
+
+/// Splices `newcomers` into the ring of `peers`, reconfiguring every node
+/// whose neighbours change, then checks that the ring is wired as planned.
+/// Returns whether it is.
+async fn rebalance(peers: &[String], newcomers: &[Member], epoch: u64, dry_run: bool, auth: &Option<Arc<Auth>>) -> bool {
+    let ring = match ring_order(peers).await {
+        Ok(ring) => ring,
+        Err(e) => {
+            println!("cannot rebalance: {}", e);
+            return false
+        },
+    };
+    let planned = splice(&ring, newcomers);
+    println!("ring {:?} becomes {:?}", ring.iter().map(|m| m.0).collect::<Vec<_>>(), planned.iter().map(|m| m.0).collect::<Vec<_>>());
+
+    let (before, after) = (neighbors_in(&ring), neighbors_in(&planned));
+    let neighbor = |&(id, ref addr): &Member| Neighbor { id, addr: addr.clone() };
+    let mut ok = true;
+    for (id, addr) in &planned {
+        let (left, right) = after[id];
+        let (old_left, old_right) = before.get(id).map_or((None, None), |&(l, r)| (Some(l.0), Some(r.0)));
+        let request = ReconfigureRequest {
+            epoch,
+            left: (old_left != Some(left.0)).then(|| neighbor(left)),
+            right: (old_right != Some(right.0)).then(|| neighbor(right)),
+            ..Default::default()
+        };
+        if request.left.is_none() && request.right.is_none() {
+            continue
+        }
+        println!("node {}: left {} -> {}, right {} -> {}", id,
+            old_left.map_or("-".to_string(), |l| l.to_string()), left.0,
+            old_right.map_or("-".to_string(), |r| r.to_string()), right.0);
+        if dry_run {
+            continue
+        }
+        if let Err(e) = reconfigure(addr.clone(), request, auth).await {
+            println!("cannot reconfigure node {} at {}: {}", id, addr, e);
+            ok = false;
+        }
+    }
+    if dry_run || !ok {
+        return ok
+    }
+
+    let addrs = planned.iter().map(|m| m.1.clone()).collect::<Vec<_>>();
+    match ring_order(&addrs).await {
+        Ok(order) if order == planned => {
+            println!("ring of {} nodes wired as planned", order.len());
+            true
+        },
+        Ok(order) => {
+            println!("ring wired as {:?} instead", order.iter().map(|m| m.0).collect::<Vec<_>>());
+            false
+        },
+        Err(e) => {
+            println!("ring is broken: {}", e);
+            false
+        },
+    }
+}
+
+/// Parses `--peers ADDR[,ADDR...] --add ID=ADDR[,ID=ADDR...] --epoch N [--dry-run]`.
+fn parse_rebalance(args: &[String]) -> Option<(Vec<String>, Vec<Member>, u64, bool)> {
+    let (mut peers, mut newcomers, mut epoch, mut dry_run) = (None, None, None, false);
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--peers" => peers = parse_peers(&[flag.clone(), args.next()?.clone()]),
+            "--add" => newcomers = args.next()?.split(',')
+                .map(|newcomer| {
+                    let (id, addr) = newcomer.split_once('=')?;
+                    Some((id.parse().ok()?, url(addr)?))
+                })
+                .collect(),
+            "--epoch" => epoch = Some(args.next()?.parse().ok()?),
+            "--dry-run" => dry_run = true,
+            _ => return None,
+        }
+    }
+    Some((peers?, newcomers?, epoch?, dry_run))
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    let output = match args.iter().position(|arg| arg == "--output") {
+        Some(i) => match args.get(i + 1).map(|output| output.parse::<Output>()) {
+            Some(Ok(output)) => {
+                args.drain(i..i + 2);
+                output
+            },
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::from(2)
+            },
+        },
+        None => Output::Text,
+    };
+    let auth = match args.iter().position(|arg| arg == "--auth-key") {
+        Some(i) => match args.get(i + 1).map(|path| Auth::read(Path::new(path))) {
+            Some(Ok(auth)) => {
+                args.drain(i..i + 2);
+                Some(Arc::new(auth))
+            },
+            Some(Err(e)) => {
+                eprintln!("{}", e);
+                return ExitCode::from(2)
+            },
+            None => {
+                eprintln!("{}", USAGE);
+                return ExitCode::from(2)
+            },
+        },
+        None => None,
+    };
+    if args.first().map(String::as_str) == Some("completions") {
+        return match &args[1..] {
+            [shell] => match shell.parse::<Shell>() {
+                Ok(shell) => {
+                    print!("{}", completions::script(shell, &CommandLine { program: "le-admin", commands: &COMMANDS, flags: &FLAGS }));
+                    ExitCode::SUCCESS
+                },
+                Err(e) => {
+                    eprintln!("{}", e);
+                    ExitCode::from(2)
+                },
+            },
+            _ => {
+                eprintln!("{}", USAGE);
+                ExitCode::from(2)
+            },
+        }
+    }
+    if args.first().map(String::as_str) == Some("gen-dashboard") {
+        return match &args[1..] {
+            [] => {
+                print!("{}", dashboard("prometheus"));
+                ExitCode::SUCCESS
+            },
+            [flag, uid] if flag == "--datasource" => {
+                print!("{}", dashboard(uid));
+                ExitCode::SUCCESS
+            },
+            _ => {
+                eprintln!("{}", USAGE);
+                ExitCode::from(2)
+            },
+        }
+    }
+    if args.first().map(String::as_str) == Some("export-proto-descriptors") {
+        return match &args[1..] {
+            [flag, path] if flag == "--out" => match std::fs::write(path, leader_election_service::FILE_DESCRIPTOR_SET) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("cannot write {}: {}", path, e);
+                    ExitCode::FAILURE
+                },
+            },
+            _ => {
+                eprintln!("{}", USAGE);
+                ExitCode::from(2)
+            },
+        }
+    }
+    if args.first().map(String::as_str) == Some("reconfigure") {
+        let (peer, request) = match parse_reconfigure(&args[1..]) {
+            Some(parsed) => parsed,
+            None => {
+                eprintln!("{}", USAGE);
+                return ExitCode::from(2)
+            },
+        };
+        return match reconfigure(peer.clone(), request, &auth).await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("cannot reconfigure {}: {}", peer, e);
+                ExitCode::FAILURE
+            },
+        }
+    }
+    if args.first().map(String::as_str) == Some("leave") {
+        let (peer, epoch) = match &args[1..] {
+            [peer_flag, peer, epoch_flag, epoch] if peer_flag == "--peer" && epoch_flag == "--epoch" => match (url(peer), epoch.parse()) {
+                (Some(peer), Ok(epoch)) => (peer, epoch),
+                _ => {
+                    eprintln!("{}", USAGE);
+                    return ExitCode::from(2)
+                },
+            },
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::from(2)
+            },
+        };
+        return match leave(peer.clone(), epoch, &auth).await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("cannot take {} out of the ring: {}", peer, e);
+                ExitCode::FAILURE
+            },
+        }
+    }
+    if let Some("dump" | "force" | "reelect" | "drain" | "step-down" | "transfer" | "history" | "audit" | "watch-audit" | "update-config" | "pause" | "resume") = args.first().map(String::as_str) {
+        let (peer, request) = match parse_admin(&args) {
+            Some(parsed) => parsed,
+            None => {
+                eprintln!("{}", USAGE);
+                return ExitCode::from(2)
+            },
+        };
+        return match admin(peer.clone(), request, output, &auth).await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("cannot {} {}: {}", args[0], peer, e);
+                ExitCode::FAILURE
+            },
+        }
+    }
+    if args.first().map(String::as_str) == Some("rebalance") {
+        return match parse_rebalance(&args[1..]) {
+            Some((peers, newcomers, epoch, dry_run)) if !peers.is_empty() && !newcomers.is_empty() =>
+                if rebalance(&peers, &newcomers, epoch, dry_run, &auth).await { ExitCode::SUCCESS } else { ExitCode::FAILURE },
+            _ => {
+                eprintln!("{}", USAGE);
+                ExitCode::from(2)
+            },
+        }
+    }
+    let (command, peers) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), parse_peers(rest)),
+        None => ("", None),
+    };
+    let peers = match peers {
+        Some(peers) if !peers.is_empty() => peers,
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2)
+        },
+    };
+
+    match command {
+        "verify" => (),
+        "metrics" => return if metrics(&peers).await { ExitCode::SUCCESS } else { ExitCode::FAILURE },
+        "status" => return if status(&peers, output).await { ExitCode::SUCCESS } else { ExitCode::FAILURE },
+        "anomalies" => return if anomalies(&peers, output).await { ExitCode::SUCCESS } else { ExitCode::FAILURE },
+        "stats" => return if stats(&peers, output).await { ExitCode::SUCCESS } else { ExitCode::FAILURE },
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2)
+        },
+    }
+
+    let problems = verify(&peers, output).await;
+    match output {
+        Output::Text if problems.is_empty() => println!("all {} peers agree", peers.len()),
+        Output::Text => problems.iter().for_each(|problem| println!("inconsistent: {}", problem)),
+        Output::Json => (),
+    }
+    if problems.is_empty() { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
@@ -0,0 +1,193 @@
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::clock::{Clock, TokioClock};
+use crate::config::{Config, Middleware, TimingConfig};
+use crate::topology::{grpc_url, Link, NodeSpec};
+use crate::traces::otlp;
+use crate::{run_node, Node};
+
+/// Sets up a node of a ring step by step, checking the settings as it
+/// builds it, e.g.
+///
+/// ```text
+/// let handle = Node::builder()
+///     .id(7)
+///     .listen("0.0.0.0:50007")
+///     .left(6, "http://le-6:50006")
+///     .right(8, "le-8:50008")
+///     .ring_size(9)
+///     .build()?;
+/// ```
+///
+/// Neighbours are given as `host:port`, `ipv4:port`, `[ipv6]:port` or full
+/// URLs, those without a scheme taken to be `http://`. Anything not set here comes from the [`Config`], the defaults unless given one.
+#[derive(Debug, Default)]
+pub struct NodeBuilder {
+    id: Option<u16>,
+    listen: Option<String>,
+    left: Option<(u16, String)>,
+    right: Option<(u16, String)>,
+    ring_size: Option<u64>,
+    priority: u64,
+    zone: Option<String>,
+    config: Config,
+    middleware: Option<Middleware>,
+    finished_spans: Option<mpsc::UnboundedSender<otlp::Span>>,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl NodeBuilder {
+    pub fn id(mut self, id: u16) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    /// The address to serve the node on, by default that of its ID.
+    pub fn listen(mut self, addr: impl Into<String>) -> Self {
+        self.listen = Some(addr.into());
+        self
+    }
+
+    /// Another address to serve the node on, e.g. of another interface or
+    /// the other IP version.
+    pub fn also_listen(mut self, addr: SocketAddr) -> Self {
+        self.config.also_listen.push(addr);
+        self
+    }
+
+    pub fn left(mut self, id: u16, url: impl Into<String>) -> Self {
+        self.left = Some((id, url.into()));
+        self
+    }
+
+    pub fn right(mut self, id: u16, url: impl Into<String>) -> Self {
+        self.right = Some((id, url.into()));
+        self
+    }
+
+    /// How many nodes the whole ring has; two if the neighbours are one
+    /// and the same, and needed otherwise.
+    pub fn ring_size(mut self, ring_size: u64) -> Self {
+        self.ring_size = Some(ring_size);
+        self
+    }
+
+    pub fn priority(mut self, priority: u64) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// The failure domain the node runs in, which the ring prefers its
+    /// leaders in if it is the [`Config::preferred_zone`].
+    pub fn zone(mut self, zone: impl Into<String>) -> Self {
+        self.zone = Some(zone.into());
+        self
+    }
+
+    /// Splices the node into a running ring at topology `epoch`, right of
+    /// its left neighbour, from which it learns its right neighbour and the
+    /// ring size instead. The epoch has to exceed those of the ring.
+    pub fn join(mut self, epoch: u64) -> Self {
+        self.config.join = Some(epoch);
+        self
+    }
+
+    pub fn timing(mut self, timing: TimingConfig) -> Self {
+        self.config.timing = timing;
+        self
+    }
+
+    /// How the node's server limits and sheds the calls it takes, e.g.
+    ///
+    /// ```text
+    /// .middleware(Middleware { concurrency_limit: Some(64), load_shed: true, ..Middleware::default() })
+    /// ```
+    pub fn middleware(mut self, middleware: Middleware) -> Self {
+        self.middleware = Some(middleware);
+        self
+    }
+
+    /// Takes every other setting from `config`.
+    pub fn config(mut self, config: Config) -> Self {
+        let also_listen = [std::mem::take(&mut self.config.also_listen), config.also_listen.clone()].concat();
+        self.config = Config { timing: self.config.timing, also_listen, ..config };
+        self
+    }
+
+    /// Where the node's finished trace spans go, if anywhere.
+    pub fn finished_spans(mut self, finished_spans: mpsc::UnboundedSender<otlp::Span>) -> Self {
+        self.finished_spans = Some(finished_spans);
+        self
+    }
+
+    /// What the node keeps time by, tokio's clock unless set.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Creates the node and starts serving it and taking part in the
+    /// election, on the current tokio runtime.
+    pub fn build(self) -> Result<NodeHandle, String> {
+        let id = self.id.ok_or("missing id")?;
+        let listen = match &self.listen {
+            Some(listen) => listen.parse().map_err(|e| format!("invalid listen address {:?}: {}", listen, e))?,
+            None => crate::topology::default_addr(id),
+        };
+        let link = |side: &str, neighbor: Option<(u16, String)>| -> Result<Link, String> {
+            let (id, url) = neighbor.ok_or_else(|| format!("missing {} neighbour", side))?;
+            let url = grpc_url(&url).map_err(|e| format!("invalid {} neighbour URL {:?}: {}", side, url, e))?;
+            Ok(Link { id, url })
+        };
+        let (left, right) = (link("left", self.left)?, link("right", self.right)?);
+        let ring_size = match self.ring_size {
+            Some(ring_size) if ring_size < 2 => return Err(format!("a ring of {} nodes has no neighbours", ring_size)),
+            Some(ring_size) => ring_size,
+            None if left == right => 2,
+            None => return Err("missing ring size".to_string()),
+        };
+        let spec = NodeSpec { priority: self.priority, zone: self.zone, ..NodeSpec::ring(id, listen, left, right) };
+        let clock = self.clock.unwrap_or_else(|| Arc::new(TokioClock::new()));
+        let node = Node::with_clock(clock, &spec, ring_size, &self.config, self.finished_spans).map_err(|e| e.to_string())?;
+        let config = Config { middleware: self.middleware.unwrap_or(self.config.middleware), ..self.config };
+        let served = node.clone();
+        let task = tokio::spawn(async move { run_node(served, listen, &config).await });
+        Ok(NodeHandle { node, addr: listen, task })
+    }
+}
+
+/// A node started by a [`NodeBuilder`], owning the task that serves it and
+/// takes part in the election.
+#[derive(Debug)]
+pub struct NodeHandle {
+    node: Node,
+    addr: SocketAddr,
+    task: JoinHandle<Result<(), tonic::transport::Error>>,
+}
+
+impl NodeHandle {
+    pub fn node(&self) -> &Node {
+        &self.node
+    }
+
+    /// The address the node is served on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Waits until the node stops, returning why if it failed.
+    pub async fn join(self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(self.task.await??)
+    }
+
+    /// Shuts the node down and waits until it stops.
+    pub async fn shutdown(self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.node.shutdown();
+        self.join().await
+    }
+}
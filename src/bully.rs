@@ -0,0 +1,184 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use futures::future;
+use tokio::sync::{watch, Notify};
+use tokio::time::{timeout, Duration};
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Request, Response, Status};
+use tonic_health::ServingStatus;
+use tracing::{info, warn};
+
+use crate::clock::{Clock, TokioClock};
+use crate::compression::compressed;
+use crate::config::{Compression, Config, Output};
+use crate::health;
+use crate::overload::OverloadLayer;
+use crate::leader_election_service::bully_service_client::BullyServiceClient;
+use crate::leader_election_service::bully_service_server::{BullyService, BullyServiceServer};
+use crate::leader_election_service::{AnswerMessage, CoordinatorMessage, CoordinatorResponse, ElectionMessage};
+use crate::tls::{self, Tls};
+use crate::topology::Member;
+use crate::{preferred_leader, print_message, publish, until_set, ElectionAlgorithm, ElectionResult, DELAY_MODIFIER};
+
+/// How long a node waits for better nodes to answer its call for an election.
+const ANSWER_TIMEOUT: Duration = Duration::from_millis(5 * DELAY_MODIFIER);
+/// How long a node that was answered waits for the leader to announce itself
+/// before it calls another election.
+const COORDINATOR_TIMEOUT: Duration = Duration::from_millis(10 * DELAY_MODIFIER);
+
+type Client = BullyServiceClient<Channel>;
+
+/// A node electing the leader of a cluster in which every node can reach every
+/// other, with the bully algorithm. A node calls an election by asking every
+/// better node to take over; if none answers, it is the best one alive and
+/// announces itself as the leader to the rest. As in the ring election, the
+/// smallest ID wins.
+#[derive(Debug, Clone)]
+pub struct BullyNode {
+    id: u64,
+    /// Every other node of the cluster.
+    peers: Arc<Vec<(u64, Endpoint)>>,
+    clock: Arc<dyn Clock>,
+    /// How long to wait for the other nodes to come up.
+    startup_grace: Duration,
+    /// Woken whenever a worse node calls an election.
+    called: Arc<Notify>,
+    /// Counts the leader announcements received.
+    announcements: Arc<watch::Sender<u64>>,
+    results: Arc<watch::Sender<ElectionResult>>,
+    stopping: Arc<watch::Sender<bool>>,
+    tls: Option<Arc<Tls>>,
+    compression: Option<Compression>,
+    /// How the node prints its message log, if it does.
+    message_log: Option<Output>,
+}
+
+impl BullyNode {
+    /// Creates the node `id` of the cluster of `members`.
+    pub fn new(id: u16, members: &[Member], config: &Config) -> std::io::Result<Self> {
+        let tls = Tls::load(config)?.map(Arc::new);
+        let peers = members.iter().filter(|member| member.id != id).map(|member| {
+            let endpoint = tls::endpoint(member.url(), tls.as_deref(), &config.timing)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+            Ok((member.id.into(), endpoint))
+        }).collect::<std::io::Result<_>>()?;
+        Ok(BullyNode {
+            id: id.into(),
+            peers: Arc::new(peers),
+            clock: Arc::new(TokioClock::new()),
+            startup_grace: config.timing.startup_grace,
+            called: Arc::default(),
+            announcements: Arc::new(watch::channel(0).0),
+            results: Arc::new(watch::channel(ElectionResult::Undecided).0),
+            stopping: Arc::new(watch::channel(false).0),
+            tls,
+            compression: config.compression,
+            message_log: config.message_log.then_some(config.output),
+        })
+    }
+
+    /// Prints an outgoing message to the message log.
+    fn log_message(&self, value: u64, target: u64) {
+        print_message(self.message_log, self.id, self.clock.wall_now(), value, target);
+    }
+
+    /// Holds an election whenever one is called for, starting with one of
+    /// its own once the other nodes had the time to come up.
+    async fn campaign(self) {
+        let peers = self.peers.iter()
+            .map(|(id, endpoint)| (*id, compressed!(Client::new(endpoint.connect_lazy()), self.compression)))
+            .collect::<Vec<_>>();
+        let (better, worse): (Vec<_>, Vec<_>) = peers.into_iter().partition(|&(id, _)| preferred_leader(id, self.id) == id);
+        self.clock.sleep_until(self.clock.now() + self.startup_grace).await;
+        loop {
+            let mut announced = self.announcements.subscribe();
+            let answers = future::join_all(better.iter().map(|(id, client)| {
+                let mut client = client.clone();
+                self.log_message(self.id, *id);
+                async move { matches!(timeout(ANSWER_TIMEOUT, client.election(ElectionMessage { sender_id: self.id })).await, Ok(Ok(_))) }
+            })).await;
+
+            if answers.contains(&true) {
+                // a better node took over, wait for it to win
+                if timeout(COORDINATOR_TIMEOUT, announced.changed()).await.is_err() {
+                    warn!(node = self.id, "heard from no leader, calling another election");
+                    continue
+                }
+            } else {
+                info!(node = self.id, "is the leader");
+                publish(&self.results, ElectionResult::Leader);
+                future::join_all(worse.iter().map(|(id, client)| {
+                    let mut client = client.clone();
+                    self.log_message(self.id, *id);
+                    async move { timeout(ANSWER_TIMEOUT, client.coordinator(CoordinatorMessage { leader_id: self.id })).await }
+                })).await;
+            }
+            self.called.notified().await;
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl BullyService for BullyNode {
+    async fn election(&self, request: Request<ElectionMessage>) -> Result<Response<AnswerMessage>, Status> {
+        let ElectionMessage { sender_id } = request.into_inner();
+        info!(node = self.id, "taking over the election called by {}", sender_id);
+        self.called.notify_one();
+        Ok(Response::new(AnswerMessage { sender_id: self.id }))
+    }
+
+    async fn coordinator(&self, request: Request<CoordinatorMessage>) -> Result<Response<CoordinatorResponse>, Status> {
+        let CoordinatorMessage { leader_id } = request.into_inner();
+        if preferred_leader(leader_id, self.id) == leader_id {
+            info!(node = self.id, "acknowledging {}'s leadership", leader_id);
+            publish(&self.results, ElectionResult::Defeated { leader: leader_id });
+            let count = *self.announcements.borrow();
+            self.announcements.send_replace(count + 1);
+        } else {
+            // a worse node claims to lead, bully it
+            info!(node = self.id, "rejecting the leadership of {}", leader_id);
+            self.called.notify_one();
+        }
+        Ok(Response::new(CoordinatorResponse {}))
+    }
+}
+
+#[tonic::async_trait]
+impl ElectionAlgorithm for BullyNode {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn subscribe(&self) -> watch::Receiver<ElectionResult> {
+        self.results.subscribe()
+    }
+
+    fn shutdown(&self) {
+        info!(node = self.id, "shutting down");
+        self.stopping.send_replace(true);
+    }
+
+    async fn run(self, addr: SocketAddr, config: &Config) -> Result<(), tonic::transport::Error> {
+        let (mut health, health_service) = health::service::<BullyServiceServer<BullyNode>>().await;
+        let mut server = tls::server(&config.timing, &config.middleware);
+        if let Some(tls) = &self.tls {
+            server = server.tls_config(tls.server.clone())?;
+        }
+        let server = server
+            .layer(OverloadLayer::new(&config.middleware))
+            .add_service(compressed!(BullyServiceServer::new(self.clone()), self.compression))
+            .add_service(health_service)
+            .serve_with_shutdown(addr, until_set(&self.stopping));
+        let campaign = async {
+            health::report::<BullyServiceServer<BullyNode>>(&mut health, ServingStatus::Serving).await;
+            tokio::select! {
+                _ = self.clone().campaign() => (),
+                _ = until_set(&self.stopping) => (),
+            }
+            health::report::<BullyServiceServer<BullyNode>>(&mut health, ServingStatus::NotServing).await;
+        };
+        let (served, _) = future::join(server, campaign).await;
+        served
+    }
+}
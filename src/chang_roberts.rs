@@ -0,0 +1,204 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures::future;
+use tokio::sync::{mpsc, watch, Mutex};
+use tonic::transport::Endpoint;
+use tonic::{Request, Response, Status};
+use tonic_health::ServingStatus;
+use tracing::{error, info, warn};
+
+use crate::clock::{Clock, TokioClock};
+use crate::compression::compressed;
+use crate::config::{Compression, Config, Output, TimingConfig};
+use crate::health;
+use crate::overload::OverloadLayer;
+use crate::retry::{retry, RetryPolicy};
+use crate::leader_election_service::chang_roberts_service_client::ChangRobertsServiceClient;
+use crate::leader_election_service::chang_roberts_service_server::{ChangRobertsService, ChangRobertsServiceServer};
+use crate::leader_election_service::{CandidateMessage, CandidateResponse, ElectedMessage, ElectedResponse};
+use crate::tls::{self, Tls};
+use crate::topology::NodeSpec;
+use crate::{deadline, preferred_leader, print_message, publish, seed_of, until_set, ElectionAlgorithm, ElectionResult};
+
+#[derive(Debug, Clone, Copy)]
+enum Message {
+    Candidate(u64),
+    Elected(u64),
+}
+
+/// A node electing the leader of a ring with Chang and Roberts' algorithm.
+/// Every node sends its ID to the right, each node passes on only IDs better
+/// than its own, and the node whose ID makes it all the way around the ring
+/// leads. Simpler than the ring election, but it takes O(n²) messages when
+/// the IDs increase in the direction of travel.
+#[derive(Debug, Clone)]
+pub struct ChangRobertsNode {
+    id: u64,
+    right_id: u64,
+    right: Endpoint,
+    clock: Arc<dyn Clock>,
+    retry: RetryPolicy,
+    /// Seeds the jitter of the node's retries.
+    seed: u64,
+    timing: TimingConfig,
+    /// How the node prints its message log, if it does.
+    message_log: Option<Output>,
+    /// Whether the node sent a candidate on already, its own or a better one.
+    participating: Arc<AtomicBool>,
+    outgoing: mpsc::UnboundedSender<Message>,
+    /// Messages waiting to be sent to the right neighbour, until the node
+    /// runs.
+    queued: Arc<Mutex<Option<mpsc::UnboundedReceiver<Message>>>>,
+    results: Arc<watch::Sender<ElectionResult>>,
+    stopping: Arc<watch::Sender<bool>>,
+    tls: Option<Arc<Tls>>,
+    compression: Option<Compression>,
+}
+
+impl ChangRobertsNode {
+    /// Creates the node `spec` describes. Only its right neighbour is used.
+    pub fn new(spec: &NodeSpec, config: &Config) -> std::io::Result<Self> {
+        let tls = Tls::load(config)?.map(Arc::new);
+        let right = tls::endpoint(spec.right().url.clone(), tls.as_deref(), &config.timing)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let (outgoing, queued) = mpsc::unbounded_channel();
+        let clock = Arc::new(TokioClock::new());
+        Ok(ChangRobertsNode {
+            id: spec.id.into(),
+            right_id: spec.right().id.into(),
+            right,
+            retry: config.retry,
+            seed: seed_of(spec.id.into(), config, clock.wall_now().timestamp_nanos() as u64),
+            clock,
+            timing: config.timing,
+            message_log: config.message_log.then_some(config.output),
+            participating: Arc::default(),
+            outgoing,
+            queued: Arc::new(Mutex::new(Some(queued))),
+            results: Arc::new(watch::channel(ElectionResult::Undecided).0),
+            stopping: Arc::new(watch::channel(false).0),
+            tls,
+            compression: config.compression,
+        })
+    }
+
+    fn send(&self, message: Message) {
+        // the receiver lives as long as the node runs
+        let _ = self.outgoing.send(message);
+    }
+
+    /// Puts the node forward as a candidate, unless it already took part.
+    fn stand(&self) {
+        if !self.participating.swap(true, Ordering::SeqCst) {
+            self.send(Message::Candidate(self.id));
+        }
+    }
+
+    /// Sends the queued messages to the right neighbour in order, retrying
+    /// each as the retry policy says and dropping it if the policy gives up.
+    async fn forward(self, mut queued: mpsc::UnboundedReceiver<Message>) {
+        let client = compressed!(ChangRobertsServiceClient::new(self.right.connect_lazy()), self.compression);
+        while let Some(message) = queued.recv().await {
+            let value = match message {
+                Message::Candidate(id) | Message::Elected(id) => id,
+            };
+            let send = || {
+                let mut client = client.clone();
+                print_message(self.message_log, self.id, self.clock.wall_now(), value, self.right_id);
+                async move {
+                    match message {
+                        Message::Candidate(candidate_id) =>
+                            client.candidate(deadline(CandidateMessage { candidate_id }, self.timing.rpc_deadline)).await.map(drop),
+                        Message::Elected(leader_id) =>
+                            client.elected(deadline(ElectedMessage { leader_id }, self.timing.rpc_deadline)).await.map(drop),
+                    }
+                }
+            };
+            let retrying = |e: &Status, delay| warn!(node = self.id, "cannot reach {}, retrying in {:?}: {}", self.right.uri(), delay, e);
+            if let Err(e) = retry(self.retry.backoff(self.seed), &*self.clock, send, retrying).await {
+                error!(node = self.id, "gave up on sending {:?} to {}: {}", message, self.right.uri(), e);
+            }
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl ChangRobertsService for ChangRobertsNode {
+    async fn candidate(&self, request: Request<CandidateMessage>) -> Result<Response<CandidateResponse>, Status> {
+        let CandidateMessage { candidate_id } = request.into_inner();
+        if candidate_id == self.id {
+            info!(node = self.id, "is the leader");
+            publish(&self.results, ElectionResult::Leader);
+            self.send(Message::Elected(self.id));
+        } else if preferred_leader(candidate_id, self.id) == candidate_id {
+            self.participating.store(true, Ordering::SeqCst);
+            self.send(Message::Candidate(candidate_id));
+        } else {
+            // swallow the worse candidate, but make sure this node's own is on its way
+            self.stand();
+        }
+        Ok(Response::new(CandidateResponse {}))
+    }
+
+    async fn elected(&self, request: Request<ElectedMessage>) -> Result<Response<ElectedResponse>, Status> {
+        let ElectedMessage { leader_id } = request.into_inner();
+        if leader_id != self.id {
+            info!(node = self.id, "acknowledging {}'s leadership", leader_id);
+            publish(&self.results, ElectionResult::Defeated { leader: leader_id });
+            self.send(Message::Elected(leader_id));
+        }
+        Ok(Response::new(ElectedResponse {}))
+    }
+}
+
+#[tonic::async_trait]
+impl ElectionAlgorithm for ChangRobertsNode {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn subscribe(&self) -> watch::Receiver<ElectionResult> {
+        self.results.subscribe()
+    }
+
+    fn shutdown(&self) {
+        info!(node = self.id, "shutting down");
+        self.stopping.send_replace(true);
+    }
+
+    async fn run(self, addr: SocketAddr, config: &Config) -> Result<(), tonic::transport::Error> {
+        let (mut health, health_service) = health::service::<ChangRobertsServiceServer<ChangRobertsNode>>().await;
+        let mut server = tls::server(&config.timing, &config.middleware);
+        if let Some(tls) = &self.tls {
+            server = server.tls_config(tls.server.clone())?;
+        }
+        let server = server
+            .layer(OverloadLayer::new(&config.middleware))
+            .add_service(compressed!(ChangRobertsServiceServer::new(self.clone()), self.compression))
+            .add_service(health_service)
+            .serve_with_shutdown(addr, until_set(&self.stopping));
+        let queued = self.queued.lock().await.take();
+        let mut serving = health.clone();
+        let client = async {
+            // give the other nodes the time to come up
+            self.clock.sleep_until(self.clock.now() + self.timing.startup_grace).await;
+            self.stand();
+            health::report::<ChangRobertsServiceServer<ChangRobertsNode>>(&mut serving, ServingStatus::Serving).await;
+            match queued {
+                Some(queued) => self.clone().forward(queued).await,
+                None => future::pending().await,
+            }
+        };
+        let client = async {
+            tokio::select! {
+                _ = client => (),
+                _ = until_set(&self.stopping) => (),
+            }
+            health::report::<ChangRobertsServiceServer<ChangRobertsNode>>(&mut health, ServingStatus::NotServing).await;
+        };
+        let (served, _) = future::join(server, client).await;
+        served
+    }
+}
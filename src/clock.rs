@@ -0,0 +1,125 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use tokio::sync::watch;
+use tokio::time::{Duration, Instant};
+
+/// The source of time for a node. All sleeps, periodic timers and timestamps
+/// go through it, so tests and simulations can control time centrally.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// The wall-clock time corresponding to `now()`, used for log timestamps.
+    fn wall_now(&self) -> DateTime<Utc>;
+
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()> + Send + '_>>;
+
+    /// Ticks every `period`, starting one period from now. Missed ticks are
+    /// skipped rather than delivered in a burst.
+    fn interval(self: Arc<Self>, period: Duration) -> Pin<Box<dyn Stream<Item = Instant> + Send>> where Self: 'static {
+        Box::pin(async_stream::stream! {
+            let mut deadline = self.now() + period;
+            loop {
+                self.sleep_until(deadline).await;
+                yield deadline;
+                deadline = (deadline + period).max(self.now());
+            }
+        })
+    }
+}
+
+/// The real clock, backed by tokio's timer. `tokio::time::pause` and `advance`
+/// still apply to it. Wall-clock timestamps are derived from the monotonic
+/// clock, so they stay consistent with paused time.
+#[derive(Debug, Clone, Copy)]
+pub struct TokioClock {
+    wall_origin: DateTime<Utc>,
+    origin: Instant,
+}
+
+impl TokioClock {
+    pub fn new() -> Self {
+        TokioClock { wall_origin: Utc::now(), origin: Instant::now() }
+    }
+}
+
+impl Default for TokioClock {
+    fn default() -> Self {
+        TokioClock::new()
+    }
+}
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wall_now(&self) -> DateTime<Utc> {
+        wall_time(self.wall_origin, self.origin, self.now())
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        Box::pin(tokio::time::sleep_until(deadline))
+    }
+}
+
+/// A clock that only moves when told to. Sleepers wake up as soon as
+/// `advance` moves the time past their deadline, so a test can drive the
+/// timers of nodes made [`Node::with_clock`](crate::Node::with_clock) by
+/// hand.
+#[derive(Debug)]
+pub struct MockClock {
+    wall_origin: DateTime<Utc>,
+    origin: Instant,
+    now: watch::Sender<Instant>,
+    // Keeps the channel open so that `advance` never fails for lack of sleepers.
+    observer: watch::Receiver<Instant>,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        let origin = Instant::now();
+        let (now, observer) = watch::channel(origin);
+        MockClock { wall_origin: Utc::now(), origin, now, observer }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let _ = self.now.send(self.now() + duration);
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.observer.borrow()
+    }
+
+    fn wall_now(&self) -> DateTime<Utc> {
+        wall_time(self.wall_origin, self.origin, self.now())
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        let mut now = self.observer.clone();
+        Box::pin(async move {
+            while *now.borrow() < deadline {
+                if now.changed().await.is_err() {
+                    break
+                }
+            }
+        })
+    }
+}
+
+fn wall_time(wall_origin: DateTime<Utc>, origin: Instant, now: Instant) -> DateTime<Utc> {
+    let elapsed = chrono::Duration::from_std(now.saturating_duration_since(origin)).unwrap_or_else(|_| chrono::Duration::max_value());
+    wall_origin + elapsed
+}
@@ -0,0 +1,94 @@
+use std::str::FromStr;
+
+/// The shells [`script`] writes completions for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(format!("unknown shell {:?}, expected bash, zsh or fish", s)),
+        }
+    }
+}
+
+/// What a program takes on its command line.
+#[derive(Debug, Clone, Copy)]
+pub struct CommandLine<'a> {
+    pub program: &'a str,
+    /// The commands its first argument names, each with the words its second
+    /// argument may be after it, if any.
+    pub commands: &'a [(&'a str, &'a [&'a str])],
+    /// The flags of all commands, without their dashes.
+    pub flags: &'a [&'a str],
+}
+
+/// A script that has `shell` complete the commands and flags of `command`,
+/// and paths where neither fits, e.g. for `source <(grpc-le completions bash)`.
+pub fn script(shell: Shell, command: &CommandLine) -> String {
+    let function = format!("_{}", command.program.replace('-', "_"));
+    let names = command.commands.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(" ");
+    let flags = command.flags.iter().map(|flag| format!("--{}", flag)).collect::<Vec<_>>().join(" ");
+    let followers = command.commands.iter().filter(|(_, words)| !words.is_empty());
+    match shell {
+        Shell::Bash => {
+            let cases = followers.map(|(name, words)| format!("            {}) words=\"{}\" ;;\n", name, words.join(" "))).collect::<String>();
+            format!(r#"{function}() {{
+    local cur=${{COMP_WORDS[COMP_CWORD]}} words=
+    if [[ $cur == -* ]]; then
+        words="{flags}"
+    elif [[ $COMP_CWORD -eq 1 ]]; then
+        words="{names}"
+    elif [[ $COMP_CWORD -eq 2 ]]; then
+        case ${{COMP_WORDS[1]}} in
+{cases}        esac
+    fi
+    if [[ -n $words ]]; then
+        COMPREPLY=($(compgen -W "$words" -- "$cur"))
+    fi
+}}
+complete -o default -F {function} {program}
+"#, function = function, flags = flags, names = names, cases = cases, program = command.program)
+        },
+        Shell::Zsh => {
+            let cases = followers.map(|(name, words)| format!("        {}) compadd -- {} ;;\n", name, words.join(" "))).collect::<String>();
+            format!(r#"#compdef {program}
+
+{function}() {{
+    if [[ $PREFIX == -* ]]; then
+        compadd -- {flags}
+    elif (( CURRENT == 2 )); then
+        compadd -- {names}
+    elif (( CURRENT == 3 )); then
+        case $words[2] in
+{cases}        *) _files ;;
+        esac
+    else
+        _files
+    fi
+}}
+
+{function} "$@"
+"#, function = function, flags = flags, names = names, cases = cases, program = command.program)
+        },
+        Shell::Fish => {
+            let mut script = format!("complete -c {} -n __fish_use_subcommand -a '{}'\n", command.program, names);
+            for (name, words) in followers {
+                script += &format!("complete -c {} -n '__fish_seen_subcommand_from {}' -a '{}'\n", command.program, name, words.join(" "));
+            }
+            for flag in command.flags {
+                script += &format!("complete -c {} -l {}\n", command.program, flag);
+            }
+            script
+        },
+    }
+}
@@ -0,0 +1,13 @@
+/// Lets a generated client or server take messages compressed with gzip,
+/// and compresses the ones it sends as the `Option<Compression>` says. A
+/// server only compresses responses for clients that take them compressed.
+macro_rules! compressed {
+    ($service:expr, $compression:expr) => {
+        match $compression {
+            Some($crate::config::Compression::Gzip) => $service.accept_gzip().send_gzip(),
+            None => $service.accept_gzip(),
+        }
+    };
+}
+
+pub(crate) use compressed;
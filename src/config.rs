@@ -0,0 +1,1067 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use tracing_subscriber::EnvFilter;
+
+use crate::mock::Script;
+use crate::outbound::DropPolicy;
+use crate::retry::RetryPolicy;
+use crate::simulation::Chaos;
+use crate::spiffe;
+use crate::topology::{default_addr, grpc_url, Link, NodeSpec};
+use crate::transport::Impairment;
+use crate::DELAY_MODIFIER;
+
+/// Prefix of the environment variables that settings are read from, e.g.
+/// `GRPC_LE_POLL_INTERVAL_MS` for `--poll-interval-ms`.
+const ENV_PREFIX: &str = "GRPC_LE_";
+
+/// Which election algorithm the nodes run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Probes sent around a ring, each node knowing only its neighbours.
+    Ring,
+    /// The bully algorithm, each node knowing the whole cluster.
+    Bully,
+    /// Chang and Roberts' algorithm, in which messages only travel to the
+    /// right around the ring.
+    ChangRoberts,
+    /// Hirschberg and Sinclair's algorithm, in which candidates probe
+    /// further around the ring both ways each phase.
+    ///
+    /// Like bully and Chang-Roberts, it holds a single election with no
+    /// terms, and sends its messages as they come, each retried until the
+    /// retry policy gives up: none of them bound the queues to the
+    /// neighbours, keep an outbox, sign their messages or limit the rate of
+    /// their peers' calls, and they refuse the settings for those.
+    HirschbergSinclair,
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ring" => Ok(Algorithm::Ring),
+            "bully" => Ok(Algorithm::Bully),
+            "chang-roberts" => Ok(Algorithm::ChangRoberts),
+            "hs" => Ok(Algorithm::HirschbergSinclair),
+            _ => Err(format!("unknown algorithm {:?}, expected ring, bully, chang-roberts or hs", s)),
+        }
+    }
+}
+
+/// How diagnostics are written to stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    Pretty,
+    /// One JSON object per event, with the fields of its spans, for log
+    /// aggregation systems.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format {:?}, expected json or pretty", s)),
+        }
+    }
+}
+
+/// How the commands print what they report on stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per report, or per line of a log, for scripts.
+    Json,
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Output::Text),
+            "json" => Ok(Output::Json),
+            _ => Err(format!("unknown output {:?}, expected json or text", s)),
+        }
+    }
+}
+
+/// Changes which diagnostics the process writes while it runs.
+pub trait LogFilter: std::fmt::Debug + Send + Sync {
+    /// Writes the diagnostics `directives` lets through from now on, given
+    /// like `--log-level` takes them.
+    fn set(&self, directives: &str) -> Result<(), String>;
+}
+
+/// Where the nodes write their audit logs, besides serving them through
+/// `WatchAuditLog`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditTarget {
+    /// Among the diagnostics on stderr.
+    Stderr,
+    /// A file per node in the directory, appended to.
+    Dir(PathBuf),
+}
+
+impl FromStr for AuditTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stderr" => Ok(AuditTarget::Stderr),
+            "" => Err("expected stderr or a directory for the audit log".to_string()),
+            dir => Ok(AuditTarget::Dir(dir.into())),
+        }
+    }
+}
+
+/// The kinds of key-value store a leader can register itself in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Store {
+    /// etcd, through its v3 JSON gateway, the key attached to a lease.
+    Etcd,
+    /// Consul, the key held by a session.
+    Consul,
+}
+
+/// Where the leader registers itself for discovery by other systems, e.g.
+/// `etcd://etcd:2379/services/le/leader`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registration {
+    pub store: Store,
+    /// The host and port of the store's HTTP API.
+    pub addr: String,
+    pub key: String,
+}
+
+impl FromStr for Registration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (store, rest) = s.split_once("://").ok_or_else(|| format!("expected etcd://<addr>/<key> or consul://<addr>/<key>, found {:?}", s))?;
+        let store = match store {
+            "etcd" => Store::Etcd,
+            "consul" => Store::Consul,
+            _ => return Err(format!("unknown store {:?}, expected etcd or consul", store)),
+        };
+        match rest.split_once('/') {
+            Some((addr, key)) if !addr.is_empty() && !key.is_empty() => Ok(Registration { store, addr: addr.to_string(), key: key.to_string() }),
+            _ => Err(format!("missing the address or the key in {:?}", s)),
+        }
+    }
+}
+
+/// A role the nodes elect a leader for apart from any other, e.g.
+/// `scheduler`, or `compactor=5` for the node to stand in its elections with
+/// priority 5 instead of its own, so that different nodes win different
+/// roles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Role {
+    pub name: String,
+    pub priority: Option<u64>,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, priority) = match s.split_once('=') {
+            Some((name, priority)) => (name, Some(priority.parse().map_err(|e| format!("invalid priority of role {:?}: {}", name, e))?)),
+            None => (s, None),
+        };
+        match name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == ',') {
+            true => Err(format!("a role must be a single word, e.g. scheduler, found {:?}", name)),
+            false => Ok(Role { name: name.to_string(), priority }),
+        }
+    }
+}
+
+/// What a call lets its caller do, which a peer needs to be granted to make
+/// it once the nodes check what their peers may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// Asking about the election and the node, without changing either.
+    Read,
+    /// Taking part in the election and changing the ring, as nodes do.
+    Elect,
+    /// Making the admin calls that change the node or the election.
+    Admin,
+}
+
+impl FromStr for Permission {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Permission::Read),
+            "elect" => Ok(Permission::Elect),
+            "admin" => Ok(Permission::Admin),
+            _ => Err(format!("unknown permission {:?}, expected read, elect or admin", s)),
+        }
+    }
+}
+
+/// An entry of the nodes' access control list, granting the peers whose
+/// SPIFFE IDs match `peers` the `permissions` it lists, e.g.
+/// `spiffe://example.org/monitor=read` or
+/// `spiffe://example.org/ring/*=elect+read`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grant {
+    pub peers: String,
+    pub permissions: Vec<Permission>,
+}
+
+impl FromStr for Grant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (peers, permissions) = s.split_once('=').ok_or_else(|| format!("an ACL entry must be given as <SPIFFE ID>=<permission>+..., found {:?}", s))?;
+        spiffe::check_pattern(peers)?;
+        let permissions = permissions.split('+').map(str::parse).collect::<Result<_, _>>()?;
+        Ok(Grant { peers: peers.to_string(), permissions })
+    }
+}
+
+/// How the nodes compress the messages they send each other. They take
+/// compressed messages either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gzip" => Ok(Compression::Gzip),
+            _ => Err(format!("unknown compression {:?}, expected gzip or none", s)),
+        }
+    }
+}
+
+/// How long the nodes wait for each other and between their own steps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimingConfig {
+    /// How long a node waits for a connection to another node.
+    pub connect_timeout: Duration,
+    /// How long a node waits for the answer to a call to another node, and
+    /// for a neighbour to take up a stream of the election's messages.
+    pub rpc_deadline: Duration,
+    /// How long a node waits for a neighbour to acknowledge a message it
+    /// relayed before it takes the stream for stuck, closes it and resends
+    /// the messages not acknowledged yet over a new one.
+    pub stream_timeout: Duration,
+    /// How long a neighbour may take on average to acknowledge a message
+    /// before the node warns that it is slow.
+    pub slow_neighbour: Duration,
+    /// How often a candidate checks whether to probe its next phase.
+    pub poll_interval: Duration,
+    /// The fraction of each poll interval that may be cut off at random, so
+    /// that nodes started together do not all probe at once.
+    pub poll_jitter: f64,
+    /// How long a node waits after starting for the others to come up,
+    /// before it takes part in the election.
+    pub startup_grace: Duration,
+    /// Up to how much longer than the grace a node waits, at random, so that
+    /// nodes started together do not all stand as candidates at once.
+    pub startup_jitter: Duration,
+    /// How often a node pings the other end of its connections, as client
+    /// and as server, so that NATs keep idle links open and dead peers are
+    /// noticed. Without an interval it does not ping.
+    pub keepalive_interval: Option<Duration>,
+    /// How long a ping may go unanswered before the connection is taken for
+    /// dead and closed.
+    pub keepalive_timeout: Duration,
+    /// Whether a node pings the connections it has no calls open on, too.
+    pub keepalive_while_idle: bool,
+}
+
+impl Default for TimingConfig {
+    fn default() -> Self {
+        TimingConfig {
+            connect_timeout: Duration::from_millis(10 * DELAY_MODIFIER),
+            rpc_deadline: Duration::from_millis(20 * DELAY_MODIFIER),
+            stream_timeout: Duration::from_millis(50 * DELAY_MODIFIER),
+            slow_neighbour: Duration::from_millis(10 * DELAY_MODIFIER),
+            poll_interval: Duration::from_millis(DELAY_MODIFIER),
+            poll_jitter: 0.2,
+            startup_grace: Duration::from_millis(2 * DELAY_MODIFIER),
+            startup_jitter: Duration::from_millis(DELAY_MODIFIER),
+            keepalive_interval: None,
+            keepalive_timeout: Duration::from_secs(20),
+            keepalive_while_idle: false,
+        }
+    }
+}
+
+/// How a node's server holds up under more calls than it can handle, e.g.
+/// a storm of probes, instead of taking on ever more at once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Middleware {
+    /// How many calls the node handles at once, over all its connections and
+    /// addresses. Calls beyond these wait for one to finish. Without a limit
+    /// the node takes every call as it comes.
+    pub concurrency_limit: Option<usize>,
+    /// How long a call may take to be answered, waiting for the concurrency
+    /// limit included, before it fails with `DEADLINE_EXCEEDED`. Streams
+    /// only have to be opened in time.
+    pub timeout: Option<Duration>,
+    /// Whether calls beyond the concurrency limit fail with `UNAVAILABLE`
+    /// right away instead of waiting.
+    pub load_shed: bool,
+    /// How many streams each connection may have open at once. Without a
+    /// limit it is up to the client.
+    pub streams_per_connection: Option<u32>,
+}
+
+/// The settings a running node takes on without restarting: its timing, the
+/// level of its diagnostics and its neighbours, those not given left as they
+/// are.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reload {
+    pub timing: TimingConfig,
+    pub log_level: Option<String>,
+    pub left: Option<Link>,
+    pub right: Option<Link>,
+}
+
+/// The settings of a running node that [`Reload`] can change, besides its
+/// neighbours.
+const RELOADABLE: [&str; 12] = ["connect-timeout-ms", "rpc-deadline-ms", "keepalive-interval-ms", "keepalive-timeout-ms", "keepalive-while-idle",
+    "stream-timeout-ms", "slow-neighbour-ms", "poll-interval-ms", "poll-jitter", "startup-grace-ms", "startup-jitter-ms", "log-level"];
+
+impl Reload {
+    /// Applies `settings` over `timing`, each named like the argument
+    /// setting it without the dashes, e.g. `("poll-interval-ms", "50")` or
+    /// `("left", "2=http://[::1]:40002")`. Only the timing settings,
+    /// `log-level`, `left` and `right` can be changed.
+    pub fn parse(timing: TimingConfig, settings: impl IntoIterator<Item = (String, String)>) -> Result<Self, String> {
+        let mut config = Config { timing, ..Config::default() };
+        let (mut left, mut right) = (None, None);
+        for (name, value) in settings {
+            match &name[..] {
+                "left" => left = Some(link(&name, &value)?),
+                "right" => right = Some(link(&name, &value)?),
+                name if RELOADABLE.contains(&name) => config.set(name, &value)?,
+                _ => return Err(format!("--{} cannot be changed while the node runs, only {}, left and right", name, RELOADABLE.join(", "))),
+            }
+        }
+        Ok(Reload { timing: config.timing, log_level: config.log_level, left, right })
+    }
+}
+
+/// Parses a neighbour given as `<id>=<url>`.
+fn link(name: &str, value: &str) -> Result<Link, String> {
+    let (id, addr) = value.split_once('=').ok_or_else(|| format!("--{} must be given as <id>=<url>", name))?;
+    Ok(Link { id: parse(name, id)?, url: grpc_url(addr).map_err(|e| format!("invalid --{} {:?}: {}", name, addr, e))? })
+}
+
+/// Settings shared by all nodes the process runs, taken from the environment,
+/// the command line and any config files it names.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The election algorithm to run.
+    pub algorithm: Algorithm,
+    /// How many messages may wait to be sent to each neighbour.
+    pub queue_capacity: usize,
+    /// What to do with a message for a neighbour whose queue is full.
+    pub drop_policy: DropPolicy,
+    /// Directory to keep undelivered leader notifications in, so they are
+    /// sent even if the process restarts. Without one they live in memory.
+    pub outbox_dir: Option<PathBuf>,
+    /// Directory to keep the state of each node in, so that a restarted
+    /// node that knew the leader rejoins the ring as the leader's follower
+    /// rather than as a candidate. Without one nodes always start afresh.
+    pub state_dir: Option<PathBuf>,
+    /// Directory to record the events of each node in, as JSONL, for
+    /// `grpc-le trace merge` to piece together. Without one no events are
+    /// recorded.
+    pub events_dir: Option<PathBuf>,
+    /// Where to write every change of each node's state, and its cause.
+    /// Without one the audit log is only served through the admin API.
+    pub audit_log: Option<AuditTarget>,
+    /// How long a node may go without knowing of a leader before it raises
+    /// an alarm. Without a threshold there are no alarms.
+    pub no_leader_alarm: Option<Duration>,
+    /// Shell command run on every alarm, with `LE_NODE` and `LE_LEADERLESS_MS`
+    /// in its environment. Alarms are only logged without one.
+    pub no_leader_hook: Option<String>,
+    /// How long a defeated node may go without hearing that its leader is
+    /// alive before it starts a new election, or the leader's deputy, once
+    /// its neighbours confirm that the leader is gone, takes over without
+    /// one. Without a timeout the leader is not monitored.
+    pub leader_timeout: Option<Duration>,
+    /// How long the leadership lasts unless the ring confirms it, which a
+    /// healthy ring does several times over. A leader whose lease runs out
+    /// starts a new election, and followers wait out at least the lease
+    /// before they do. Without a lease the leadership never runs out.
+    pub lease: Option<Duration>,
+    /// How long a node that stepped down, or whose lease ran out, sits out
+    /// the elections that start, so that it does not take back the
+    /// leadership it just gave up. Without a cooldown it only sits out the
+    /// election right after it stepped down.
+    pub step_down_cooldown: Option<Duration>,
+    /// How often a node checks that its right neighbour is alive. A node
+    /// whose right neighbour misses three checks in a row splices it out of
+    /// the ring, taking the next live node past it, which the checks told
+    /// it of, for its right neighbour. Only if the leader was among the
+    /// nodes gone does it restart the election. Without an interval failed
+    /// nodes are not spliced out.
+    pub liveness_interval: Option<Duration>,
+    /// How many nodes a segment of a ring split by a partition needs to
+    /// elect a provisional leader. With this or a witness, a node none of
+    /// whose successors answers closes the segment it is in into a ring of
+    /// its own, which elects a leader only if it holds a quorum or the
+    /// witness, so that the segments do not lead apart. Once the nodes past
+    /// it answer again, the segments merge back under the leader of the one
+    /// closed later. A majority of the ring by default. Without either, a
+    /// split ring elects no leader.
+    pub quorum: Option<u64>,
+    /// The node whose segment may elect a provisional leader without a
+    /// quorum, when no other segment holds one either, e.g. the half of an
+    /// evenly split ring it is in.
+    pub witness: Option<u64>,
+    /// OTLP/gRPC collector to export election traces to. Without one no
+    /// traces are recorded.
+    pub otlp_endpoint: Option<String>,
+    /// Fraction of elections phases and notifications to trace.
+    pub trace_sample_ratio: f64,
+    /// How many spans to export at once.
+    pub trace_batch_size: usize,
+    /// The service the exported spans belong to.
+    pub trace_service: String,
+    /// How many of the best nodes to elect as a committee, the leader first.
+    pub committee_size: usize,
+    /// JSON ring definition to run instead of the IDs read from stdin.
+    pub topology: Option<PathBuf>,
+    /// Where to save the definition of the ring being run, as JSON.
+    pub save_topology: Option<PathBuf>,
+    /// The nodes to run as listed by config files, which need not make up
+    /// the whole ring. Without any, the ring comes from the topology or stdin.
+    pub nodes: Vec<NodeSpec>,
+    /// How many nodes the whole ring has, if more than the listed ones.
+    pub ring_size: Option<u64>,
+    /// Address the single node run listens on instead of its own, e.g.
+    /// `0.0.0.0:40001` in a container the other nodes know by name.
+    pub bind: Option<SocketAddr>,
+    /// More addresses the single node run serves on besides its own, e.g.
+    /// `127.0.0.1:40001` besides `[::1]:40001`, or those of other interfaces.
+    pub also_listen: Vec<SocketAddr>,
+    /// Priority the single node run stands in elections with, instead of its
+    /// own. Nodes of a higher priority win the ring algorithm's elections,
+    /// and of the same priority, those with the smaller ID.
+    pub priority: Option<u64>,
+    /// Failure domain of the single node run, e.g. its availability zone or
+    /// rack, instead of its own.
+    pub zone: Option<String>,
+    /// The zone the ring algorithm prefers its leaders in, e.g. that of the
+    /// primary database, among the nodes of the same priority. Every node
+    /// of the ring has to prefer the same zone.
+    pub preferred_zone: Option<String>,
+    /// gRPC URL the single node run tells the ring to reach it by as it joins
+    /// and once it leads, instead of the one its neighbours know it by, e.g.
+    /// from outside a private network or when it listens on `0.0.0.0`.
+    pub advertise: Option<String>,
+    /// Application data the nodes tell the ring about themselves once they
+    /// lead, handed out along with the leader's address.
+    pub leader_metadata: Vec<u8>,
+    /// Whether the single node run only follows the elections, passing the
+    /// messages of the others on and tracking the leader without ever
+    /// standing itself, e.g. as a read replica.
+    pub observer: bool,
+    /// Where the leader registers its ID and address, under a key that
+    /// expires `register_ttl` after the leader last renewed it, in whole
+    /// seconds and, for Consul, no less than ten. Needs the `registry`
+    /// feature.
+    pub register: Option<Registration>,
+    pub register_ttl: Duration,
+    /// Headless Service whose SRV records list the pods of the StatefulSet
+    /// the single node run is a pod of, and thus the ring. Needs the `k8s`
+    /// feature.
+    pub k8s_service: Option<String>,
+    /// Epoch at which the single node run splices itself into a running
+    /// ring, right of its left neighbour. Without one it starts out wired.
+    pub join: Option<u64>,
+    /// How long each node waits for both its neighbours to answer its
+    /// introduction before it starts the election, retrying those it cannot
+    /// reach yet, so that its first probes are not lost to a neighbour still
+    /// starting up. A node that waits in vain starts anyway. Without a wait
+    /// nodes only sit out the startup grace.
+    pub await_neighbours: Option<Duration>,
+    /// How long an election may take before the node gives up on it,
+    /// reporting what it knows instead of probing on. Without a deadline
+    /// the node probes until a leader is found.
+    pub election_deadline: Option<Duration>,
+    /// How far above its gRPC port each node serves its metrics over plain
+    /// HTTP, at `/metrics`, along with the JSON gateway of the `web` feature.
+    /// Without an offset the metrics are only available through the
+    /// `GetMetrics` RPC.
+    pub metrics_port_offset: Option<u16>,
+    /// How far above its gRPC port each node serves a dashboard of its state
+    /// and a live feed of its events, with the `dashboard` feature.
+    pub dashboard_port_offset: Option<u16>,
+    /// Seeds the random parts of the nodes' delays, so that a run can be
+    /// repeated, e.g. on the virtual clock of a simulation. Without a seed
+    /// each node draws one from the operating system.
+    pub seed: Option<u64>,
+    /// How nodes retry calls to their neighbours that failed.
+    pub retry: RetryPolicy,
+    pub timing: TimingConfig,
+    /// Faults to inject into an in-memory simulation of the ring, which is
+    /// run instead of the real nodes if set.
+    pub chaos: Option<Chaos>,
+    /// Latency, jitter and loss the `grpc-le` binary adds to the messages
+    /// between the nodes it runs, to show how the network slows the election
+    /// down. Without one the messages go out as they are.
+    pub impairment: Option<Impairment>,
+    /// What the single node run does to the messages it relays, which makes
+    /// it a mock peer. Without a script it relays them as they are.
+    pub script: Option<Script>,
+    /// How to write diagnostics.
+    pub log_format: LogFormat,
+    /// How to print the message log and what the commands report.
+    pub output: Output,
+    /// Whether the nodes print a line to stdout for every probe and
+    /// notification they send or receive. Off unless turned on, except on
+    /// the command line, see [`Config::from_args`].
+    pub message_log: bool,
+    /// Which diagnostics to write, as a filter like `RUST_LOG` takes, e.g.
+    /// `debug` or `grpc_le=debug`. Without one it is up to `RUST_LOG`, by
+    /// default everything at the info level and above.
+    pub log_level: Option<String>,
+    /// PEM certificate and private key the nodes present to their peers.
+    /// With these and a CA, all traffic between nodes uses mutual TLS.
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    /// PEM certificate of the CA that signed the certificates of all nodes.
+    pub tls_ca: Option<PathBuf>,
+    /// Name the certificates of the neighbours are checked against, instead
+    /// of the host of their URLs. Needed when the URLs use IP addresses.
+    pub tls_domain: Option<String>,
+    /// How often the node looks whether the files of its certificates
+    /// changed, to present renewed ones without restarting. The node also
+    /// looks whenever its settings are reloaded. 10 seconds by default.
+    pub tls_watch_interval: Duration,
+    /// The SPIFFE IDs of the peers allowed to call the nodes, each an ID
+    /// like `spiffe://example.org/ring/node-1` or a trust domain or path
+    /// ending in `/*`. With any, the nodes refuse calls from peers whose
+    /// certificates are not X.509 SVIDs of a matching ID, which needs TLS.
+    /// The SVIDs themselves are read from `tls_cert` and `tls_ca`, where a
+    /// SPIFFE helper keeps them renewed.
+    pub spiffe_ids: Vec<String>,
+    /// What each peer may do, by its SPIFFE ID. With any entries, the nodes
+    /// refuse the calls of peers no entry grants the permission the call
+    /// needs, which needs TLS: nodes need to be granted `elect`, and tools
+    /// like `le-admin` `admin` and `read`. Without any, every peer may make
+    /// every call.
+    pub acl: Vec<Grant>,
+    /// File holding the secret all nodes of the ring sign their messages to
+    /// each other with. Nodes with a secret only handle messages signed with
+    /// it, and only serve the calls that change the election or the ring,
+    /// like those of the admin service, if signed with it too. Without one
+    /// nothing is authenticated. Only the ring algorithm takes a secret.
+    pub auth_key: Option<PathBuf>,
+    /// The election groups each node takes part in, e.g. one per shard, each
+    /// electing a leader of its own around the same ring. Without any, nodes
+    /// only take part in the default group, zero.
+    pub groups: Vec<u64>,
+    /// The roles each node takes part in the elections of besides its
+    /// groups, each electing a leader of its own around the same ring in a
+    /// group named after the role.
+    pub roles: Vec<Role>,
+    /// How many calls each connection to a node may make a second, and how
+    /// many messages it may send over them, in bursts of as many. Calls
+    /// beyond these are refused.
+    pub rate_limit: usize,
+    /// How many times a minute the callers of a node may have it restart
+    /// the election, through `TriggerReelection` or `Reelect`, in bursts of
+    /// as many. Requests beyond these are refused.
+    pub restart_limit: usize,
+    /// How many of those restarts any one caller may ask for.
+    pub restart_limit_per_caller: usize,
+    /// How the nodes compress the requests and responses they send, e.g. to
+    /// save bandwidth over WAN links. Without one they send them as they are.
+    pub compression: Option<Compression>,
+    pub middleware: Middleware,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config { algorithm: Algorithm::Ring, queue_capacity: 64, drop_policy: DropPolicy::Coalesce, outbox_dir: None, state_dir: None, events_dir: None, audit_log: None, no_leader_alarm: None, no_leader_hook: None,
+            leader_timeout: None, lease: None, step_down_cooldown: None, liveness_interval: None, quorum: None, witness: None, otlp_endpoint: None, trace_sample_ratio: 1.0, trace_batch_size: 64, trace_service: "grpc-le".to_string(), committee_size: 1,
+            topology: None, save_topology: None, nodes: Vec::new(), ring_size: None, bind: None, also_listen: Vec::new(), priority: None, zone: None, preferred_zone: None, advertise: None, leader_metadata: Vec::new(), observer: false, register: None, register_ttl: Duration::from_secs(10), k8s_service: None, join: None, await_neighbours: None, election_deadline: None,
+            metrics_port_offset: None, dashboard_port_offset: None, seed: None, retry: RetryPolicy::default(), timing: TimingConfig::default(), chaos: None, impairment: None, script: None, log_format: LogFormat::Pretty, output: Output::Text, message_log: false, log_level: None, tls_cert: None, tls_key: None, tls_ca: None, tls_domain: None, tls_watch_interval: Duration::from_secs(10), spiffe_ids: Vec::new(), acl: Vec::new(), auth_key: None, groups: Vec::new(), roles: Vec::new(), rate_limit: 1000, restart_limit: 60, restart_limit_per_caller: 12, compression: None,
+            middleware: Middleware::default() }
+    }
+}
+
+/// Parses the name of a failure domain, which has to be a single word.
+fn zone(name: &str, value: &str) -> Result<String, String> {
+    match value.is_empty() || value.contains(char::is_whitespace) {
+        true => Err(format!("--{} must be a single word, e.g. eu-west-1a", name)),
+        false => Ok(value.to_string()),
+    }
+}
+
+fn parse<T: FromStr>(name: &str, value: &str) -> Result<T, String> where T::Err: std::fmt::Display {
+    value.parse().map_err(|e| format!("invalid --{}: {}", name, e))
+}
+
+fn probability(name: &str, value: &str) -> Result<f64, String> {
+    match parse(name, value)? {
+        p if (0.0..=1.0).contains(&p) => Ok(p),
+        _ => Err(format!("--{} must be between 0 and 1", name)),
+    }
+}
+
+fn positive(name: &str, value: &str) -> Result<usize, String> {
+    match parse(name, value)? {
+        0 => Err(format!("--{} must be at least 1", name)),
+        n => Ok(n),
+    }
+}
+
+/// The settings [`Config::from_args`] takes in every build, without their
+/// dashes.
+const SETTINGS: [&str; 83] = [
+    "algorithm", "queue-capacity", "drop-policy", "outbox-dir", "state-dir", "events-dir", "audit-log", "no-leader-alarm-ms",
+    "no-leader-hook", "leader-timeout-ms", "lease-ms", "step-down-cooldown-ms", "await-neighbours-ms", "election-deadline-ms", "liveness-interval-ms", "quorum", "witness",
+    "otlp-endpoint", "trace-sample-ratio", "trace-batch-size", "trace-service", "committee-size", "topology", "save-topology",
+    "ring-size", "bind", "also-listen", "priority", "zone", "preferred-zone", "advertise", "leader-metadata", "observer",
+    "metrics-port-offset", "seed", "retry-max-attempts", "retry-initial-delay-ms", "retry-max-delay-ms", "retry-jitter",
+    "connect-timeout-ms", "rpc-deadline-ms", "keepalive-interval-ms", "keepalive-timeout-ms", "keepalive-while-idle",
+    "stream-timeout-ms", "slow-neighbour-ms", "poll-interval-ms", "poll-jitter", "startup-grace-ms", "startup-jitter-ms", "latency-ms",
+    "jitter-ms", "loss", "script", "chaos", "chaos-drop", "chaos-delay", "chaos-duplicate", "chaos-crash", "chaos-seed", "log-format",
+    "output", "message-log", "log-level", "tls-cert", "tls-key", "tls-ca", "tls-domain", "tls-watch-interval-ms", "spiffe-ids", "acl", "auth-key", "groups", "roles", "rate-limit", "restart-limit", "restart-limit-per-caller", "compression",
+    "concurrency-limit", "request-timeout-ms", "load-shed", "max-streams-per-connection", "config",
+];
+
+impl Config {
+    /// The settings [`Config::from_args`] takes, without their dashes, for
+    /// shells to complete.
+    pub fn settings() -> Vec<&'static str> {
+        let mut settings = SETTINGS.to_vec();
+        if cfg!(feature = "registry") {
+            settings.extend(["register", "register-ttl-ms"]);
+        }
+        if cfg!(feature = "k8s") {
+            settings.push("k8s-service");
+        }
+        if cfg!(feature = "dashboard") {
+            settings.push("dashboard-port-offset");
+        }
+        settings
+    }
+
+    /// Parses `--algorithm <ring|bully|chang-roberts|hs>`, `--queue-capacity <n>`,
+    /// `--drop-policy <block|drop-oldest|coalesce>`, `--outbox-dir <path>`, `--state-dir <path>`, `--events-dir <path>`, `--audit-log <stderr|path>`,
+    /// `--no-leader-alarm-ms <n>`, `--no-leader-hook <command>`, `--leader-timeout-ms <n>`, `--lease-ms <n>`, `--step-down-cooldown-ms <n>`, `--liveness-interval-ms <n>`, `--quorum <n>`, `--witness <id>`, `--await-neighbours-ms <n>`,
+    /// `--election-deadline-ms <n>`, `--otlp-endpoint <url>`, `--trace-sample-ratio <0..1>`, `--trace-batch-size <n>`, `--trace-service <name>`,
+    /// `--committee-size <n>`, `--topology <path>`, `--save-topology <path>`,
+    /// `--ring-size <n>`, `--bind <addr>`, `--also-listen <addr>,<addr>...`, `--priority <n>`, `--zone <name>`, `--preferred-zone <name>`, `--advertise <url>`, `--leader-metadata <text>`, `--observer`, `--register <etcd|consul>://<addr>/<key>`, `--register-ttl-ms <n>`, `--k8s-service <name>`, `--metrics-port-offset <n>`,
+    /// `--dashboard-port-offset <n>`, `--seed <n>`, `--log-format <json|pretty>`, `--log-level <filter>`, `--output <json|text>`, `--message-log <bool>`,
+    /// `--retry-max-attempts <n>`, `--retry-initial-delay-ms <n>`, `--retry-max-delay-ms <n>`,
+    /// `--retry-jitter <0..1>`, `--connect-timeout-ms <n>`, `--rpc-deadline-ms <n>`, `--stream-timeout-ms <n>`,
+    /// `--slow-neighbour-ms <n>`, `--keepalive-interval-ms <n>`, `--keepalive-timeout-ms <n>`, `--keepalive-while-idle <bool>`,
+    /// `--poll-interval-ms <n>`, `--poll-jitter <0..1>`, `--startup-grace-ms <n>`, `--startup-jitter-ms <n>`,
+    /// `--tls-cert <path>`, `--tls-key <path>`, `--tls-ca <path>`, `--tls-domain <name>`, `--tls-watch-interval-ms <n>`, `--spiffe-ids <id>,<id>...`, `--acl <id>=<permission>+...,...`, `--auth-key <path>`, `--groups <n>,<n>...`, `--roles <name>[=<n>],...`, `--rate-limit <n>`, `--restart-limit <n>`, `--restart-limit-per-caller <n>`,
+    /// `--compression <gzip|none>`, `--concurrency-limit <n>`, `--request-timeout-ms <n>`, `--load-shed <bool>`, `--max-streams-per-connection <n>`,
+    /// `--latency-ms <n>`, `--jitter-ms <n>`, `--loss <0..1>`, `--script <path>`,
+    /// `--chaos`, `--chaos-drop <0..1>`, `--chaos-delay <0..1>`, `--chaos-duplicate <0..1>`,
+    /// `--chaos-crash <0..1>`, `--chaos-seed <n>` and `--config <path>`, the settings
+    /// of which later arguments override. Each setting can also be given in
+    /// an environment variable, named like the argument in upper case with
+    /// underscores for dashes and prefixed with `GRPC_LE_`, which the
+    /// arguments override. Unlike the library's defaults, the command line
+    /// starts out with the message log on.
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut config = Config::from_env()?;
+        while let Some(arg) = args.next() {
+            if arg == "--chaos" || arg == "--observer" {
+                config.set(&arg[2..], "true")?;
+                continue
+            }
+            let name = arg.strip_prefix("--").ok_or_else(|| format!("unknown argument {:?}", arg))?;
+            let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+            config.set(name, &value)?;
+        }
+        config.check()?;
+        Ok(config)
+    }
+
+    /// Parses the arguments of a single node, `--id <n> [--listen <addr>]
+    /// --left <id>=<url> --right <id>=<url> --ring-size <n>`, along with any
+    /// of the settings [`Config::from_args`] takes. A node joining a running
+    /// ring with `--join <epoch>` learns its right neighbour and the ring
+    /// size from its left one instead. The node's own arguments can be given
+    /// in the environment too, e.g. `GRPC_LE_LEFT=2=http://le-1:40002`.
+    pub fn node_from_args(mut args: impl Iterator<Item = String>) -> Result<(NodeSpec, Self), String> {
+        let mut config = Config::command_line();
+        let mut fields = vec![];
+        for (var, name, value) in env_settings() {
+            config.set_node(&name, value, &mut fields).map_err(|e| format!("{}: {}", var, e))?;
+        }
+        while let Some(arg) = args.next() {
+            if arg == "--observer" {
+                config.observer = true;
+                continue
+            }
+            let name = arg.strip_prefix("--").ok_or_else(|| format!("unknown argument {:?}", arg))?;
+            let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+            config.set_node(name, value, &mut fields)?;
+        }
+        if config.join.is_some() {
+            // until the left neighbour tells the node otherwise
+            if !fields.iter().any(|(key, _)| key == "right") {
+                let left = fields.iter()
+                    .filter(|(key, _)| key.starts_with("left"))
+                    .map(|(key, value)| (key.replacen("left", "right", 1), value.clone()))
+                    .collect::<Vec<_>>();
+                fields.extend(left);
+            }
+            config.ring_size.get_or_insert(2);
+        }
+        for required in ["id", "left", "right"] {
+            if !fields.iter().any(|(key, _)| key == required) {
+                return Err(format!("missing --{}", required));
+            }
+        }
+        if config.ring_size.is_none() {
+            return Err("missing --ring-size".to_string());
+        }
+        config.check()?;
+        let node = node_spec(&fields).map_err(|e| format!("invalid node: {}", e))?;
+        Ok((node, config))
+    }
+
+    /// The defaults of the command line, which logs the messages the nodes
+    /// exchange unless told not to.
+    fn command_line() -> Self {
+        Config { message_log: true, ..Config::default() }
+    }
+
+    /// The defaults of the command line, overridden by those of the
+    /// environment.
+    fn from_env() -> Result<Self, String> {
+        let mut config = Config::command_line();
+        for (var, name, value) in otel_settings()?.into_iter().chain(env_settings()) {
+            config.set(&name, &value).map_err(|e| format!("{}: {}", var, e))?;
+        }
+        Ok(config)
+    }
+
+    /// Applies an argument of a single node, collecting those describing the
+    /// node itself into `fields`.
+    fn set_node(&mut self, name: &str, value: String, fields: &mut Vec<(String, String)>) -> Result<(), String> {
+        match name {
+            "id" | "listen" => fields.push((name.to_string(), value)),
+            "join" => self.join = Some(parse(name, &value)?),
+            "left" | "right" => {
+                let (id, url) = value.split_once('=').ok_or_else(|| format!("--{} must be given as <id>=<url>", name))?;
+                fields.push((format!("{}_id", name), id.to_string()));
+                fields.push((name.to_string(), url.to_string()));
+            },
+            _ => self.set(name, &value)?,
+        }
+        Ok(())
+    }
+
+    /// Refuses settings that the others rule out.
+    fn check(&self) -> Result<(), String> {
+        if self.auth_key.is_some() && self.algorithm != Algorithm::Ring {
+            return Err("--auth-key only applies to the ring algorithm, the others do not authenticate their messages".to_string())
+        }
+        if !self.spiffe_ids.is_empty() && self.tls_cert.is_none() {
+            return Err("--spiffe-ids needs TLS, for the peers to present their SVIDs".to_string())
+        }
+        if !self.acl.is_empty() && self.tls_cert.is_none() {
+            return Err("--acl needs TLS, for the peers to present their SVIDs".to_string())
+        }
+        if (!self.spiffe_ids.is_empty() || !self.acl.is_empty()) && self.algorithm != Algorithm::Ring {
+            return Err("--spiffe-ids and --acl only apply to the ring algorithm, the others do not check their peers".to_string())
+        }
+        let defaults = Config::default();
+        if (self.queue_capacity != defaults.queue_capacity || self.drop_policy != defaults.drop_policy) && self.algorithm != Algorithm::Ring {
+            return Err("--queue-capacity and --drop-policy only apply to the ring algorithm, the others do not queue their messages".to_string())
+        }
+        if self.outbox_dir.is_some() && self.algorithm != Algorithm::Ring {
+            return Err("--outbox-dir only applies to the ring algorithm, the others do not keep undelivered messages".to_string())
+        }
+        if self.rate_limit != defaults.rate_limit && self.algorithm != Algorithm::Ring {
+            return Err("--rate-limit only applies to the ring algorithm, the others do not limit their peers' calls".to_string())
+        }
+        Ok(())
+    }
+
+    fn chaos_mut(&mut self) -> &mut Chaos {
+        self.chaos.get_or_insert_with(Chaos::default)
+    }
+
+    fn impairment_mut(&mut self) -> &mut Impairment {
+        self.impairment.get_or_insert_with(Impairment::default)
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "algorithm" => self.algorithm = value.parse()?,
+            "queue-capacity" => self.queue_capacity = positive(name, value)?,
+            "drop-policy" => self.drop_policy = value.parse()?,
+            "outbox-dir" => self.outbox_dir = Some(value.into()),
+            "state-dir" => self.state_dir = Some(value.into()),
+            "events-dir" => self.events_dir = Some(value.into()),
+            "audit-log" => self.audit_log = Some(value.parse()?),
+            "no-leader-alarm-ms" => self.no_leader_alarm = Some(Duration::from_millis(positive(name, value)? as u64)),
+            "no-leader-hook" => self.no_leader_hook = Some(value.to_string()),
+            "leader-timeout-ms" => self.leader_timeout = Some(Duration::from_millis(positive(name, value)? as u64)),
+            "lease-ms" => self.lease = Some(Duration::from_millis(positive(name, value)? as u64)),
+            "step-down-cooldown-ms" => self.step_down_cooldown = Some(Duration::from_millis(positive(name, value)? as u64)),
+            "await-neighbours-ms" => self.await_neighbours = Some(Duration::from_millis(positive(name, value)? as u64)),
+            "election-deadline-ms" => self.election_deadline = Some(Duration::from_millis(positive(name, value)? as u64)),
+            "liveness-interval-ms" => self.liveness_interval = Some(Duration::from_millis(positive(name, value)? as u64)),
+            "quorum" => self.quorum = Some(positive(name, value)? as u64),
+            "witness" => self.witness = Some(parse(name, value)?),
+            "otlp-endpoint" => self.otlp_endpoint = Some(value.to_string()),
+            "trace-sample-ratio" => self.trace_sample_ratio = probability(name, value)?,
+            "trace-batch-size" => self.trace_batch_size = positive(name, value)?,
+            "trace-service" => self.trace_service = value.to_string(),
+            "committee-size" => self.committee_size = positive(name, value)?,
+            "topology" => self.topology = Some(value.into()),
+            "save-topology" => self.save_topology = Some(value.into()),
+            "ring-size" => self.ring_size = Some(positive(name, value)? as u64),
+            "bind" => self.bind = Some(parse(name, value)?),
+            "also-listen" => self.also_listen = value.split(',').map(|addr| parse(name, addr.trim())).collect::<Result<_, _>>()?,
+            "priority" => self.priority = Some(parse(name, value)?),
+            "zone" => self.zone = Some(zone(name, value)?),
+            "preferred-zone" => self.preferred_zone = Some(zone(name, value)?),
+            "advertise" => self.advertise = Some(grpc_url(value).map_err(|e| format!("invalid --{} {:?}: {}", name, value, e))?),
+            "leader-metadata" => self.leader_metadata = value.as_bytes().to_vec(),
+            "observer" => self.observer = parse(name, value)?,
+            "register" if cfg!(feature = "registry") => self.register = Some(value.parse()?),
+            "register-ttl-ms" if cfg!(feature = "registry") => self.register_ttl = Duration::from_millis(positive(name, value)? as u64),
+            "k8s-service" if cfg!(feature = "k8s") => self.k8s_service = Some(value.to_string()),
+            "metrics-port-offset" => self.metrics_port_offset = Some(parse(name, value)?),
+            "dashboard-port-offset" if cfg!(feature = "dashboard") => self.dashboard_port_offset = Some(parse(name, value)?),
+            "seed" => self.seed = Some(parse(name, value)?),
+            "retry-max-attempts" => self.retry.max_attempts = Some(positive(name, value)? as u32),
+            "retry-initial-delay-ms" => self.retry.initial_delay = Duration::from_millis(positive(name, value)? as u64),
+            "retry-max-delay-ms" => self.retry.max_delay = Duration::from_millis(positive(name, value)? as u64),
+            "retry-jitter" => self.retry.jitter = probability(name, value)?,
+            "connect-timeout-ms" => self.timing.connect_timeout = Duration::from_millis(positive(name, value)? as u64),
+            "rpc-deadline-ms" => self.timing.rpc_deadline = Duration::from_millis(positive(name, value)? as u64),
+            "keepalive-interval-ms" => self.timing.keepalive_interval = Some(Duration::from_millis(positive(name, value)? as u64)),
+            "keepalive-timeout-ms" => self.timing.keepalive_timeout = Duration::from_millis(positive(name, value)? as u64),
+            "keepalive-while-idle" => self.timing.keepalive_while_idle = parse(name, value)?,
+            "stream-timeout-ms" => self.timing.stream_timeout = Duration::from_millis(positive(name, value)? as u64),
+            "slow-neighbour-ms" => self.timing.slow_neighbour = Duration::from_millis(positive(name, value)? as u64),
+            "poll-interval-ms" => self.timing.poll_interval = Duration::from_millis(positive(name, value)? as u64),
+            "poll-jitter" => self.timing.poll_jitter = probability(name, value)?,
+            "startup-grace-ms" => self.timing.startup_grace = Duration::from_millis(parse(name, value)?),
+            "startup-jitter-ms" => self.timing.startup_jitter = Duration::from_millis(parse(name, value)?),
+            "latency-ms" => self.impairment_mut().latency = Duration::from_millis(parse(name, value)?),
+            "jitter-ms" => self.impairment_mut().jitter = Duration::from_millis(parse(name, value)?),
+            "loss" => self.impairment_mut().loss = probability(name, value)?,
+            "script" => self.script = Some(Script::load(Path::new(value))?),
+            "chaos" => self.chaos = parse::<bool>(name, value)?.then(|| self.chaos.unwrap_or_default()),
+            "chaos-drop" => self.chaos_mut().drop = probability(name, value)?,
+            "chaos-delay" => self.chaos_mut().delay = probability(name, value)?,
+            "chaos-duplicate" => self.chaos_mut().duplicate = probability(name, value)?,
+            "chaos-crash" => self.chaos_mut().crash = probability(name, value)?,
+            "chaos-seed" => self.chaos_mut().seed = parse(name, value)?,
+            "log-format" => self.log_format = value.parse()?,
+            "output" => self.output = value.parse()?,
+            "message-log" => self.message_log = parse(name, value)?,
+            "log-level" => {
+                EnvFilter::try_new(value).map_err(|e| format!("invalid --log-level: {}", e))?;
+                self.log_level = Some(value.to_string());
+            },
+            "tls-cert" => self.tls_cert = Some(value.into()),
+            "tls-key" => self.tls_key = Some(value.into()),
+            "tls-ca" => self.tls_ca = Some(value.into()),
+            "tls-domain" => self.tls_domain = Some(value.to_string()),
+            "tls-watch-interval-ms" => self.tls_watch_interval = Duration::from_millis(positive(name, value)? as u64),
+            "spiffe-ids" => {
+                self.spiffe_ids = value.split(',').map(|id| id.trim().to_string()).collect();
+                self.spiffe_ids.iter().try_for_each(|id| spiffe::check_pattern(id))?;
+            },
+            "acl" => self.acl = value.split(',').map(|grant| grant.trim().parse()).collect::<Result<_, _>>()?,
+            "auth-key" => self.auth_key = Some(value.into()),
+            "groups" => self.groups = value.split(',').map(|group| parse(name, group.trim())).collect::<Result<_, _>>()?,
+            "roles" => self.roles = value.split(',').map(|role| role.trim().parse()).collect::<Result<_, _>>()?,
+            "rate-limit" => self.rate_limit = positive(name, value)?,
+            "restart-limit" => self.restart_limit = positive(name, value)?,
+            "restart-limit-per-caller" => self.restart_limit_per_caller = positive(name, value)?,
+            "compression" => self.compression = match value {
+                "none" => None,
+                value => Some(value.parse()?),
+            },
+            "concurrency-limit" => self.middleware.concurrency_limit = Some(positive(name, value)?),
+            "request-timeout-ms" => self.middleware.timeout = Some(Duration::from_millis(positive(name, value)? as u64)),
+            "load-shed" => self.middleware.load_shed = parse(name, value)?,
+            "max-streams-per-connection" => self.middleware.streams_per_connection = Some(positive(name, value)? as u32),
+            "config" => self.load(Path::new(value))?,
+            _ => return Err(format!("unknown argument \"--{}\"", name)),
+        }
+        Ok(())
+    }
+
+    /// Applies a config file written in a small subset of TOML: top-level
+    /// keys named like the command line arguments, with underscores for
+    /// dashes, and a `[[nodes]]` table for each node to run, e.g.
+    ///
+    /// ```toml
+    /// queue_capacity = 32
+    /// ring_size = 3
+    ///
+    /// [[nodes]]
+    /// id = 3
+    /// listen = "[::]:40003"   # optional, port 40000 plus the ID on localhost
+    /// priority = 1            # optional, 0 by default
+    /// zone = "eu-west-1a"     # optional
+    /// left_id = 2
+    /// left = "http://a.example:40002"
+    /// right_id = 4
+    /// right = "http://b.example:40004"
+    /// ```
+    fn load(&mut self, path: &Path) -> Result<(), String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        let mut nodes: Vec<Vec<(String, String)>> = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let located = |e: String| format!("{}:{}: {}", path.display(), i + 1, e);
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue
+            }
+            if line == "[[nodes]]" {
+                nodes.push(vec![]);
+                continue
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| located(format!("expected `key = value`, found {:?}", line)))?;
+            let (key, value) = (key.trim(), toml_value(value.trim()).map_err(located)?);
+            match nodes.last_mut() {
+                Some(node) => node.push((key.to_string(), value)),
+                None => self.set(&key.replace('_', "-"), &value).map_err(located)?,
+            }
+        }
+        for (i, fields) in nodes.iter().enumerate() {
+            let node = node_spec(fields).map_err(|e| format!("{}: node {}: {}", path.display(), i + 1, e))?;
+            self.nodes.push(node);
+        }
+        Ok(())
+    }
+}
+
+/// The settings of the environment: the variable each is in, and the name
+/// and value of the argument it stands for. A config file named in the
+/// environment comes last, for its settings to override the others.
+fn env_settings() -> Vec<(String, String, String)> {
+    let mut settings = std::env::vars()
+        .filter_map(|(var, value)| {
+            let name = var.strip_prefix(ENV_PREFIX)?.to_lowercase().replace('_', "-");
+            Some((var, name, value))
+        })
+        .collect::<Vec<_>>();
+    settings.sort_by_key(|(_, name, _)| name == "config");
+    settings
+}
+
+/// The settings the standard OpenTelemetry environment variables make, like
+/// `OTEL_EXPORTER_OTLP_ENDPOINT` and `OTEL_TRACES_SAMPLER`, which those for
+/// grpc-le override.
+fn otel_settings() -> Result<Vec<(String, String, String)>, String> {
+    let var = |var: &str| std::env::var(var).ok().map(|value| (var.to_string(), value));
+    let disabled = var("OTEL_SDK_DISABLED").is_some_and(|(_, value)| value.eq_ignore_ascii_case("true"));
+    let endpoint = var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").or_else(|| var("OTEL_EXPORTER_OTLP_ENDPOINT")).filter(|_| !disabled);
+    let ratio = match var("OTEL_TRACES_SAMPLER") {
+        None => None,
+        Some((sampler_var, sampler)) => match &sampler[..] {
+            "always_on" | "parentbased_always_on" => Some((sampler_var, "1".to_string())),
+            "always_off" | "parentbased_always_off" => Some((sampler_var, "0".to_string())),
+            "traceidratio" | "parentbased_traceidratio" => var("OTEL_TRACES_SAMPLER_ARG").or(Some((sampler_var, "1".to_string()))),
+            _ => return Err(format!("{}: unsupported sampler {:?}", sampler_var, sampler)),
+        },
+    };
+    let settings = [
+        (endpoint, "otlp-endpoint"),
+        (ratio, "trace-sample-ratio"),
+        (var("OTEL_BSP_MAX_EXPORT_BATCH_SIZE"), "trace-batch-size"),
+        (var("OTEL_SERVICE_NAME"), "trace-service"),
+    ];
+    Ok(settings.into_iter().filter_map(|(setting, name)| setting.map(|(var, value)| (var, name.to_string(), value))).collect())
+}
+
+fn node_spec(fields: &[(String, String)]) -> Result<NodeSpec, String> {
+    const KEYS: [&str; 8] = ["id", "listen", "priority", "zone", "left_id", "left", "right_id", "right"];
+    if let Some((key, _)) = fields.iter().find(|(key, _)| !KEYS.contains(&&key[..])) {
+        return Err(format!("unknown key {}", key));
+    }
+    let field = |name: &str| fields.iter().rev().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+    let required = |name: &str| field(name).ok_or_else(|| format!("missing {}", name));
+    let id = |name: &str| required(name)?.parse::<u16>().map_err(|e| format!("invalid {}: {}", name, e));
+    let url = |name: &str| required(name).and_then(|addr| grpc_url(addr).map_err(|e| format!("invalid {} {:?}: {}", name, addr, e)));
+    let node_id = id("id")?;
+    let listen = match field("listen") {
+        Some(listen) => listen.parse().map_err(|e| format!("invalid listen: {}", e))?,
+        None => default_addr(node_id),
+    };
+    let priority = match field("priority") {
+        Some(priority) => priority.parse().map_err(|e| format!("invalid priority: {}", e))?,
+        None => 0,
+    };
+    let zone = field("zone").map(|value| zone("zone", value)).transpose()?;
+    let left = Link { id: id("left_id")?, url: url("left")? };
+    let right = Link { id: id("right_id")?, url: url("right")? };
+    Ok(NodeSpec { priority, zone, ..NodeSpec::ring(node_id, listen, left, right) })
+}
+
+/// Cuts a `#` comment off a line, unless the `#` is inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+/// Reads a TOML string, number or boolean as the text of the corresponding
+/// command line argument.
+fn toml_value(value: &str) -> Result<String, String> {
+    let quoted = match value.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) {
+        Some(quoted) => quoted,
+        None if value == "true" || value == "false" => return Ok(value.to_string()),
+        None if value.replace('_', "").parse::<f64>().is_ok() => return Ok(value.replace('_', "")),
+        None => return Err(format!("expected a string, number or boolean, found {:?}", value)),
+    };
+    let mut string = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c @ ('"' | '\\')) => string.push(c),
+                Some('n') => string.push('\n'),
+                Some('t') => string.push('\t'),
+                c => return Err(format!("unsupported escape {:?}", c)),
+            },
+            '"' => return Err(format!("unexpected quote in {}", value)),
+            c => string.push(c),
+        }
+    }
+    Ok(string)
+}
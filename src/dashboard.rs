@@ -0,0 +1,117 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use axum::extract::Extension;
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::Html;
+use axum::routing::get;
+use axum::{Json, Router};
+use futures::Stream;
+use serde_json::{json, Value};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, info};
+
+use crate::leader_election_service::leader_election_service_server::LeaderElectionService;
+use crate::leader_election_service::{state_response::Kind, HeartbeatRequest, StateRequest};
+use crate::outbound::NeighborQueue;
+use crate::Node;
+
+/// The page, which polls `/state` and follows `/events`.
+const PAGE: &str = include_str!("dashboard.html");
+
+/// Serves a dashboard of `node` to browsers, `offset` ports above the port
+/// it listens on: at `/` a page showing where the node stands in the
+/// election, how its neighbours keep up with its messages and what it did
+/// lately, at `/state` the same as JSON, and at `/events` the node's events
+/// as server-sent events as they happen, each a line of its event log. Runs
+/// until the node shuts down.
+pub async fn serve(node: Node, listen: SocketAddr, offset: u16) {
+    let addr = match listen.port().checked_add(offset) {
+        Some(port) => SocketAddr::new(listen.ip(), port),
+        None => {
+            error!(node = node.id, "cannot serve the dashboard {} ports above port {}", offset, listen.port());
+            return
+        },
+    };
+    let app = Router::new()
+        .route("/", get(|| async { Html(PAGE) }))
+        .route("/state", get(state))
+        .route("/events", get(events))
+        .layer(Extension(node.clone()));
+    let server = match axum::Server::try_bind(&addr) {
+        Ok(builder) => builder.serve(app.into_make_service()),
+        Err(e) => {
+            error!(node = node.id, "cannot serve the dashboard on {}: {}", addr, e);
+            return
+        },
+    };
+    info!(node = node.id, "serving the dashboard on http://{}/", addr);
+    if let Err(e) = server.with_graceful_shutdown(node.stopped()).await {
+        error!(node = node.id, "stopped serving the dashboard: {}", e);
+    }
+}
+
+async fn state(Extension(node): Extension<Node>) -> Result<Json<Value>, (StatusCode, String)> {
+    let failed = |status: tonic::Status| (StatusCode::INTERNAL_SERVER_ERROR, status.message().to_string());
+    let state = node.get_state(tonic::Request::new(StateRequest { group_id: node.group() })).await.map_err(failed)?.into_inner();
+    let heartbeat = node.heartbeat(tonic::Request::new(HeartbeatRequest { group_id: node.group() })).await.map_err(failed)?.into_inner();
+    let kind = match state.kind() {
+        Kind::Candidate => "candidate",
+        Kind::Defeated => "defeated",
+        Kind::Leader => "leader",
+    };
+    Ok(Json(json!({
+        "id": state.id,
+        "state": kind,
+        "phase": (state.kind() == Kind::Candidate).then_some(state.phase),
+        "term": state.term,
+        "leader": state.leader_known.then_some(state.leader_id),
+        "leader_seen_ms_ago": heartbeat.leader_known.then_some(heartbeat.leader_seen_ms_ago),
+        "deputy": state.deputy_known.then_some(state.deputy_id),
+        "committee": state.committee,
+        "ring_size": state.ring_size,
+        "priority": state.priority,
+        "lamport": state.lamport,
+        "timers": state.timers.iter().map(|timer| json!({ "name": timer.name, "remaining_ms": timer.remaining_ms })).collect::<Vec<_>>(),
+        "neighbors": [neighbor("left", &node.left), neighbor("right", &node.right)],
+    })))
+}
+
+/// How a neighbour keeps up with the messages the node sends it.
+fn neighbor(side: &str, queue: &NeighborQueue) -> Value {
+    let (peer, latency) = (queue.peer(), queue.latency());
+    json!({
+        "side": side,
+        "id": peer.id,
+        "addr": peer.endpoint.uri().to_string(),
+        "queued": queue.len(),
+        "unacknowledged": queue.unacknowledged().len(),
+        "dropped": queue.dropped(),
+        "rtt_ms": latency.smoothed.map(|rtt| rtt.as_secs_f64() * 1000.0),
+        "slow": latency.slow,
+    })
+}
+
+async fn events(Extension(node): Extension<Node>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let feed = node.events.as_ref().map(|events| events.subscribe());
+    let events = async_stream::stream! {
+        let mut feed = match feed {
+            Some(feed) => feed,
+            None => return,
+        };
+        loop {
+            // ends with the node, which would otherwise wait for the browser
+            let next = tokio::select! {
+                next = feed.recv() => next,
+                _ = node.stopped() => break,
+            };
+            match next {
+                Ok(line) => yield Ok(Event::default().data(line)),
+                Err(RecvError::Lagged(missed)) => yield Ok(Event::default().comment(format!("missed {} events", missed))),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    };
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,92 @@
+use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::TryFutureExt;
+use tonic::body::BoxBody;
+use tonic::transport::NamedService;
+use tower::{Layer, Service, ServiceExt};
+
+use crate::compression::compressed;
+use crate::leader_election_service::admin_service_server::AdminServiceServer;
+use crate::leader_election_service::leader_election_service_server::LeaderElectionServiceServer;
+use crate::metrics::Side;
+use crate::Node;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Serves the election and admin services of a node from the application's
+/// own tonic server, alongside the application's services, so that the node
+/// needs no port or process of its own:
+///
+/// ```text
+/// Server::builder()
+///     .layer(LeaderElectionLayer::new(node.clone()))
+///     .add_service(MyServiceServer::new(my_service))
+///     .serve(addr)
+/// ```
+///
+/// Calls to the node's services get the logging, metrics and rate limits
+/// they get from [`run_node`](crate::run_node); all others go on to the
+/// application's services untouched. The node then takes part in the
+/// election through [`take_part`](crate::take_part).
+#[derive(Debug, Clone)]
+pub struct LeaderElectionLayer {
+    node: Node,
+}
+
+impl LeaderElectionLayer {
+    pub fn new(node: Node) -> Self {
+        LeaderElectionLayer { node }
+    }
+}
+
+impl<S> Layer<S> for LeaderElectionLayer {
+    type Service = LeaderElection<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        LeaderElection { inner, node: self.node.clone() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LeaderElection<S> {
+    inner: S,
+    node: Node,
+}
+
+/// Whether the service `S` handles calls to `path`.
+fn serves<S: NamedService>(path: &str) -> bool {
+    path.strip_prefix('/').and_then(|path| path.strip_prefix(S::NAME)).is_some_and(|method| method.starts_with('/'))
+}
+
+impl<S> Service<http::Request<hyper::Body>> for LeaderElection<S>
+where
+    S: Service<http::Request<hyper::Body>, Response = http::Response<BoxBody>>,
+    S::Error: Into<BoxError> + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: http::Request<hyper::Body>) -> Self::Future {
+        let node = &self.node;
+        let layers = node.layers(Side::Server, None).layer(node.auth_layer()).layer(node.limits.clone());
+        let path = request.uri().path();
+        if serves::<LeaderElectionServiceServer<Node>>(path) {
+            let service = layers.service(compressed!(LeaderElectionServiceServer::new(node.clone()), node.compression));
+            return Box::pin(service.oneshot(request).map_ok(|response| response.map(BoxBody::new)).map_err(|e| match e {}))
+        }
+        if serves::<AdminServiceServer<Node>>(path) {
+            let service = layers.service(compressed!(AdminServiceServer::new(node.clone()), node.compression));
+            return Box::pin(service.oneshot(request).map_ok(|response| response.map(BoxBody::new)).map_err(|e| match e {}))
+        }
+        Box::pin(self.inner.call(request).map_err(Into::into))
+    }
+}
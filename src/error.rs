@@ -0,0 +1,151 @@
+use std::fmt;
+
+use prost::Message;
+use tonic::{Code, Status};
+
+use crate::leader_election_service::{error_detail::Reason, ErrorDetail};
+use crate::NodeState;
+
+/// Everything that can go wrong while a node handles election traffic.
+/// Converting into a `Status` is the single place where these are mapped to
+/// gRPC codes; the status details carry an encoded `ErrorDetail`.
+#[derive(Debug)]
+pub enum ElectionError {
+    /// A neighbour could not be reached.
+    Transport { node: u64, error: tonic::transport::Error },
+    /// An inbound message failed validation.
+    InvalidMessage { node: u64, state: Option<NodeState>, reason: String },
+    /// The node is not in a state that allows the requested transition.
+    WrongState { node: u64, state: NodeState, action: &'static str },
+    /// A reconfiguration was not newer than one already applied.
+    StaleEpoch { node: u64, epoch: u64, current: u64 },
+    /// A message was not signed with the secret of the ring.
+    Unauthenticated { node: u64 },
+    /// A peer sent more than the node is willing to handle.
+    RateLimited { node: u64, reason: String },
+    /// A message or request was meant for an election group the node takes
+    /// no part in.
+    UnknownGroup { node: u64, group: u64 },
+    /// A message or request was meant for the election of a role the node
+    /// takes no part in.
+    UnknownRole { node: u64, role: String },
+    /// Another node of the ring has the node's ID.
+    DuplicateId { node: u64 },
+    /// A request for the leader found no node that leads.
+    NoLeader { node: u64 },
+    /// A peer speaks a version of the relay protocol the node does not
+    /// understand, or does not understand the node's.
+    IncompatibleVersion { node: u64, version: u32 },
+    /// The node is in the last term there is, and cannot restart the
+    /// election in a later one.
+    TermsExhausted { node: u64 },
+    /// The node could not close the ring around failed nodes, close the
+    /// segment it is in or merge it back with another.
+    RepairFailed { node: u64, reason: String },
+    /// A peer presented a certificate that does not name it as one of those
+    /// allowed to make the call.
+    PermissionDenied { node: u64, peer: Option<String>, call: String },
+    /// The node could not take on a configuration or certificates read
+    /// again, and kept those it had.
+    ReloadFailed { node: u64, reason: String },
+}
+
+impl ElectionError {
+    fn code(&self) -> Code {
+        match self {
+            ElectionError::Transport { .. } => Code::Unavailable,
+            ElectionError::InvalidMessage { .. } => Code::InvalidArgument,
+            ElectionError::WrongState { .. } => Code::FailedPrecondition,
+            ElectionError::StaleEpoch { .. } => Code::Aborted,
+            ElectionError::Unauthenticated { .. } => Code::Unauthenticated,
+            ElectionError::RateLimited { .. } => Code::ResourceExhausted,
+            ElectionError::UnknownGroup { .. } => Code::NotFound,
+            ElectionError::UnknownRole { .. } => Code::NotFound,
+            ElectionError::DuplicateId { .. } => Code::AlreadyExists,
+            ElectionError::NoLeader { .. } => Code::Unavailable,
+            ElectionError::IncompatibleVersion { .. } => Code::FailedPrecondition,
+            ElectionError::TermsExhausted { .. } => Code::OutOfRange,
+            ElectionError::RepairFailed { .. } => Code::Unavailable,
+            ElectionError::PermissionDenied { .. } => Code::PermissionDenied,
+            ElectionError::ReloadFailed { .. } => Code::InvalidArgument,
+        }
+    }
+
+    fn detail(&self) -> ErrorDetail {
+        let (reason, node, state) = match self {
+            ElectionError::Transport { node, .. } => (Reason::Transport, *node, None),
+            ElectionError::InvalidMessage { node, state, .. } => (Reason::InvalidMessage, *node, state.as_ref()),
+            ElectionError::WrongState { node, state, .. } => (Reason::WrongState, *node, Some(state)),
+            ElectionError::StaleEpoch { node, .. } => (Reason::StaleEpoch, *node, None),
+            ElectionError::Unauthenticated { node } => (Reason::Unauthenticated, *node, None),
+            ElectionError::RateLimited { node, .. } => (Reason::RateLimited, *node, None),
+            ElectionError::UnknownGroup { node, .. } => (Reason::UnknownGroup, *node, None),
+            ElectionError::UnknownRole { node, .. } => (Reason::UnknownRole, *node, None),
+            ElectionError::DuplicateId { node } => (Reason::DuplicateId, *node, None),
+            ElectionError::NoLeader { node } => (Reason::NoLeader, *node, None),
+            ElectionError::IncompatibleVersion { node, .. } => (Reason::IncompatibleVersion, *node, None),
+            ElectionError::TermsExhausted { node } => (Reason::TermsExhausted, *node, None),
+            ElectionError::RepairFailed { node, .. } => (Reason::RepairFailed, *node, None),
+            ElectionError::PermissionDenied { node, .. } => (Reason::PermissionDenied, *node, None),
+            ElectionError::ReloadFailed { node, .. } => (Reason::ReloadFailed, *node, None),
+        };
+        ErrorDetail {
+            reason: reason as i32,
+            node_id: node,
+            state: state.map(|state| format!("{:?}", state)).unwrap_or_default(),
+        }
+    }
+}
+
+impl fmt::Display for ElectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElectionError::Transport { node, error } =>
+                write!(f, "node {} could not reach a neighbour: {}", node, error),
+            ElectionError::InvalidMessage { node, reason, .. } =>
+                write!(f, "node {} rejected a message: {}", node, reason),
+            ElectionError::WrongState { node, state, action } =>
+                write!(f, "node {} cannot {} in state {:?}", node, action, state),
+            ElectionError::StaleEpoch { node, epoch, current } =>
+                write!(f, "node {} is already at epoch {}, not applying epoch {}", node, current, epoch),
+            ElectionError::Unauthenticated { node } =>
+                write!(f, "node {} rejected a message not signed with the secret of the ring", node),
+            ElectionError::RateLimited { node, reason } =>
+                write!(f, "node {} refused {}", node, reason),
+            ElectionError::UnknownGroup { node, group } =>
+                write!(f, "node {} takes no part in election group {}", node, group),
+            ElectionError::UnknownRole { node, role } =>
+                write!(f, "node {} takes no part in the election of role {:?}", node, role),
+            ElectionError::DuplicateId { node } =>
+                write!(f, "another node of the ring has ID {}", node),
+            ElectionError::NoLeader { node } =>
+                write!(f, "node {} found no leader to forward the request to", node),
+            ElectionError::IncompatibleVersion { node, version } =>
+                write!(f, "node {} cannot relay messages with a peer speaking relay protocol version {}", node, version),
+            ElectionError::TermsExhausted { node } =>
+                write!(f, "node {} is in the last term there is", node),
+            ElectionError::RepairFailed { node, reason } =>
+                write!(f, "node {} cannot repair the ring: {}", node, reason),
+            ElectionError::PermissionDenied { node, peer: Some(peer), call } =>
+                write!(f, "node {} does not let {} call {}", node, peer, call),
+            ElectionError::PermissionDenied { node, peer: None, call } =>
+                write!(f, "node {} does not let peers without a SPIFFE ID call {}", node, call),
+            ElectionError::ReloadFailed { node, reason } =>
+                write!(f, "node {} cannot reload its configuration: {}", node, reason),
+        }
+    }
+}
+
+impl std::error::Error for ElectionError {}
+
+/// Why a node returned `status`, as its details say, if they say.
+pub(crate) fn reason(status: &Status) -> Option<Reason> {
+    ErrorDetail::decode(status.details()).ok().and_then(|detail| Reason::from_i32(detail.reason))
+}
+
+impl From<ElectionError> for Status {
+    fn from(error: ElectionError) -> Self {
+        let details = error.detail().encode_to_vec();
+        Status::with_details(error.code(), error.to_string(), details.into())
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use tokio::sync::broadcast;
+use tracing::error;
+
+use crate::json::{self, Json};
+use crate::leader_election_service::{anomaly::Kind, peer_message, Decision, PeerMessage, Sequence};
+use crate::NodeState;
+
+/// How many events the live feed keeps for subscribers that fall behind.
+const FEED_CAPACITY: usize = 256;
+
+/// Appends what happens at a node to a JSONL file, one object per event, for
+/// piecing elections together after the fact, and hands the events to the
+/// subscribers of its live feed as they happen: every change of the node's
+/// state, e.g.
+///
+/// ```json
+/// {"time": "2021-11-21T18:22:03.520114000Z", "lamport": 4, "node": 2, "event": "state", "term": 0, "state": "defeated", "leader": 1}
+/// ```
+///
+/// and every message the node sends or receives, e.g.
+///
+/// ```json
+/// {"time": "2021-11-21T18:22:03.410207000Z", "lamport": 3, "node": 2, "event": "received", "peer": 1, "kind": "probe",
+///  "value": 1, "phase": 1, "priority": 0, "term": 0, "message": "1-1637518923201044000-right-3", "decision": "forwarded"}
+/// ```
+///
+/// and every time a neighbour becomes slow or recovers, e.g.
+///
+/// ```json
+/// {"time": "2021-11-21T18:22:04.102513000Z", "lamport": 5, "node": 2, "event": "slow", "peer": 3, "slow": true, "rtt_ms": 1204.5}
+/// ```
+///
+/// and every repair of the ring around its right neighbour the node makes,
+/// with the nodes it found gone and the leader that survived them, or null
+/// if the node restarted the election instead, e.g.
+///
+/// ```json
+/// {"time": "2021-11-21T18:22:05.204117000Z", "lamport": 7, "node": 2, "event": "repair", "gone": [3], "right": 4, "epoch": 1,
+///  "ring_size": 3, "leader": 1}
+/// ```
+///
+/// and every anomaly, evidence of two leaders at once, the node sees, e.g.
+///
+/// ```json
+/// {"time": "2021-11-21T18:22:05.731002000Z", "lamport": 9, "node": 2, "event": "anomaly", "kind": "conflicting_leaders",
+///  "detail": "notified of leader 3 while following 1, resolved in favour of 1"}
+/// ```
+///
+/// where `lamport` is the node's Lamport clock, `value` the probing
+/// candidate, of `priority`, or announced leader, and `message` identifies a relayed message
+/// across its retransmissions.
+#[derive(Debug)]
+pub struct EventRecorder {
+    node: u64,
+    /// The file the events are appended to, if any.
+    file: Option<(PathBuf, Mutex<File>)>,
+    feed: broadcast::Sender<String>,
+}
+
+impl EventRecorder {
+    pub fn open(node: u64, path: PathBuf) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(EventRecorder { file: Some((path, Mutex::new(file))), ..EventRecorder::new(node) })
+    }
+
+    /// A recorder that only feeds the events to its subscribers.
+    pub fn new(node: u64) -> Self {
+        EventRecorder { node, file: None, feed: broadcast::channel(FEED_CAPACITY).0 }
+    }
+
+    /// Follows the events recorded from now on, one JSON object per line.
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.feed.subscribe()
+    }
+
+    pub fn state(&self, time: DateTime<Utc>, lamport: u64, state: &NodeState, term: u64) {
+        self.record(time, lamport, format!(r#""event": "state", "term": {}, {}"#, term, state_fields(state)));
+    }
+
+    pub fn sent(&self, time: DateTime<Utc>, message: &PeerMessage, peer: u64) {
+        if let Some(fields) = message_fields(message) {
+            self.record(time, message.lamport, format!(r#""event": "sent", "peer": {}, {}"#, peer, fields));
+        }
+    }
+
+    /// Records a message received at `lamport` and what the node decided on
+    /// it. Only relayed messages say which neighbour they came from.
+    pub fn received(&self, time: DateTime<Utc>, lamport: u64, message: &PeerMessage, decision: Decision) {
+        if let Some(fields) = message_fields(message) {
+            let peer = sequence(message).map_or("null".to_string(), |seq| seq.sender.to_string());
+            self.record(time, lamport, format!(r#""event": "received", "peer": {}, {}, "decision": "{}""#, peer, fields, decision.label()));
+        }
+    }
+
+    /// Records that neighbour `peer` became slower than the node allows, or
+    /// recovered, with the round trip it now takes on average.
+    pub fn slow(&self, time: DateTime<Utc>, lamport: u64, peer: u64, slow: bool, rtt: Duration) {
+        self.record(time, lamport, format!(r#""event": "slow", "peer": {}, "slow": {}, "rtt_ms": {}"#, peer, slow, rtt.as_secs_f64() * 1000.0));
+    }
+
+    pub fn repair(&self, time: DateTime<Utc>, lamport: u64, repair: &Repair) {
+        let Repair { gone, right, epoch, ring_size, leader } = repair;
+        let leader = leader.map_or("null".to_string(), |leader| leader.to_string());
+        self.record(time, lamport, format!(r#""event": "repair", "gone": {:?}, "right": {}, "epoch": {}, "ring_size": {}, "leader": {}"#,
+            gone, right, epoch, ring_size, leader));
+    }
+
+    pub fn anomaly(&self, time: DateTime<Utc>, lamport: u64, kind: Kind, detail: &str) {
+        self.record(time, lamport, format!(r#""event": "anomaly", "kind": "{}", "detail": {}"#, kind.label(), json::quote(detail)));
+    }
+
+    fn record(&self, time: DateTime<Utc>, lamport: u64, fields: String) {
+        let time = time.to_rfc3339_opts(SecondsFormat::Nanos, true);
+        let line = format!(r#"{{"time": "{}", "lamport": {}, "node": {}, {}}}"#, time, lamport, self.node, fields);
+        if let Some((path, file)) = &self.file {
+            if let Err(e) = writeln!(file.lock().unwrap(), "{}", line) {
+                error!(node = self.node, "failed to record an event in {}: {}", path.display(), e);
+            }
+        }
+        // nobody may be following
+        let _ = self.feed.send(line);
+    }
+}
+
+/// A repair of the ring around a node's right neighbour.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repair {
+    /// The nodes spliced out of the ring.
+    pub gone: Vec<u64>,
+    /// The node's right neighbour since.
+    pub right: u64,
+    pub epoch: u64,
+    /// How many nodes the ring has left.
+    pub ring_size: u64,
+    /// The leader the node kept following, if it did not restart the
+    /// election instead.
+    pub leader: Option<u64>,
+}
+
+/// The fields of a JSON object describing `state`, as the event logs
+/// record it, e.g. `"state": "defeated", "leader": 1`.
+pub fn state_fields(state: &NodeState) -> String {
+    match state {
+        NodeState::Leader => r#""state": "leader""#.to_string(),
+        NodeState::Defeated { leader: Some(leader) } => format!(r#""state": "defeated", "leader": {}"#, leader),
+        NodeState::Defeated { leader: None } => r#""state": "defeated""#.to_string(),
+        NodeState::Candidate { phase, .. } => format!(r#""state": "candidate", "phase": {}"#, phase),
+    }
+}
+
+fn sequence(message: &PeerMessage) -> Option<&Sequence> {
+    match message.body.as_ref()? {
+        peer_message::Body::Probe(msg) => msg.seq.as_ref(),
+        peer_message::Body::Notify(msg) => msg.seq.as_ref(),
+        peer_message::Body::Digest(msg) => msg.seq.as_ref(),
+    }
+}
+
+fn message_fields(message: &PeerMessage) -> Option<String> {
+    let (kind, value, probe, term) = match message.body.as_ref()? {
+        peer_message::Body::Probe(msg) => ("probe", msg.sender_id, Some((msg.phase, msg.priority)), msg.term),
+        peer_message::Body::Notify(msg) => ("notify", msg.leader_id, None, msg.term),
+        peer_message::Body::Digest(msg) => ("digest", msg.leader_id, None, msg.term),
+    };
+    let mut fields = format!(r#""kind": "{}", "value": {}"#, kind, value);
+    if let Some((phase, priority)) = probe {
+        fields += &format!(r#", "phase": {}, "priority": {}"#, phase, priority);
+    }
+    fields += &format!(r#", "term": {}"#, term);
+    if let Some(seq) = sequence(message) {
+        fields += &format!(r#", "message": "{}""#, message_id(seq));
+    }
+    Some(fields)
+}
+
+fn message_id(seq: &Sequence) -> String {
+    let direction = if seq.leftward { "left" } else { "right" };
+    format!("{}-{}-{}-{}", seq.sender, seq.incarnation, direction, seq.number)
+}
+
+/// One line of an event log.
+struct Event {
+    line: String,
+    time: DateTime<Utc>,
+    /// The relayed message received, which has to come after it was sent.
+    received: Option<String>,
+    sent: Option<String>,
+}
+
+/// Merges the event logs of the nodes of a ring into one timeline, ordered
+/// by time as far as the clocks of the nodes can be trusted, but with the
+/// events of each node in the order it recorded them, and each relayed
+/// message received only after it was sent.
+pub fn merge(paths: &[&Path]) -> io::Result<Vec<String>> {
+    let mut logs = vec![];
+    for path in paths {
+        let invalid = |line: usize, reason: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}: {}", path.display(), line, reason));
+        let mut events = vec![];
+        for (i, line) in std::fs::read_to_string(path)?.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let json = json::parse(line).map_err(|e| invalid(i + 1, e))?;
+            let time = match json.get("time") {
+                Some(Json::String(time)) => DateTime::parse_from_rfc3339(time).map_err(|e| invalid(i + 1, e.to_string()))?.with_timezone(&Utc),
+                _ => return Err(invalid(i + 1, "expected a \"time\"".to_string())),
+            };
+            let message = match json.get("message") {
+                Some(Json::String(message)) => Some(message.clone()),
+                _ => None,
+            };
+            let (sent, received) = match json.get("event") {
+                Some(Json::String(event)) if event == "sent" => (message, None),
+                Some(Json::String(event)) if event == "received" => (None, message),
+                _ => (None, None),
+            };
+            events.push(Event { line: line.to_string(), time, received, sent });
+        }
+        events.reverse();
+        logs.push(events);
+    }
+    let all_sent = logs.iter().flatten().filter_map(|event| event.sent.clone()).collect::<HashSet<_>>();
+    let mut sent = HashSet::new();
+    let mut timeline = vec![];
+    loop {
+        let ready = |event: &Event| event.received.as_ref().is_none_or(|message| sent.contains(message) || !all_sent.contains(message));
+        let earliest = |events: &mut dyn Iterator<Item = (usize, &Event)>| events.min_by_key(|(_, event)| event.time).map(|(i, _)| i);
+        let heads = || logs.iter().enumerate().filter_map(|(i, events)| Some((i, events.last()?)));
+        // only logs of different runs mixed up leave no event ready
+        let next = match earliest(&mut heads().filter(|(_, event)| ready(event))).or_else(|| earliest(&mut heads())) {
+            Some(next) => next,
+            None => return Ok(timeline),
+        };
+        let event = logs[next].pop().unwrap();
+        sent.extend(event.sent);
+        timeline.push(event.line);
+    }
+}
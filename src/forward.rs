@@ -0,0 +1,15 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+
+use tonic::Status;
+
+/// Handling a forwarded request, resolving to the payload to answer it with.
+pub type Handling<'a> = Pin<Box<dyn Future<Output = Result<Vec<u8>, Status>> + Send + 'a>>;
+
+/// What the leader does with the application requests other nodes forward
+/// to it through `ForwardToLeader`. The election never looks into them, so
+/// `route` and `payload` mean whatever the application makes of them.
+pub trait LeaderHandler: Debug + Send + Sync {
+    fn handle<'a>(&'a self, route: &'a str, payload: Vec<u8>) -> Handling<'a>;
+}
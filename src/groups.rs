@@ -0,0 +1,406 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use futures::stream::{self, Chain, Iter};
+use futures::StreamExt;
+use tokio::sync::mpsc;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::compression::compressed;
+use crate::config::{Config, LogFilter, Role};
+use crate::error::ElectionError;
+use crate::leader_election_service::admin_service_server::{AdminService, AdminServiceServer};
+use crate::leader_election_service::leader_election_service_server::{LeaderElectionService, LeaderElectionServiceServer};
+use crate::leader_election_service::{AnomaliesRequest, AnomaliesResponse, AuditLogRequest, AuditLogResponse, DigestMessage, DigestResponse, HeartbeatRequest, HeartbeatResponse};
+use crate::leader_election_service::{ForwardRequest, ForwardResponse, IntroductionRequest, IntroductionResponse, JoinRequest, JoinResponse, LeaderRequest, LeaderResponse, LeaveRequest, LeaveResponse, MetricsRequest, MetricsResponse};
+use crate::leader_election_service::{MergeRequest, MergeResponse};
+use crate::leader_election_service::{NotifyMessage, NotifyResponse, PauseRequest, PauseResponse, PeerAck, PeerMessage, PreVoteRequest, PreVoteResponse, ProbeMessage, ProbeResponse};
+use crate::leader_election_service::{RankingRequest, RankingResponse, ReconfigureRequest, ReconfigureResponse, ReelectRequest, ReelectResponse, ResizeRequest, ResizeResponse, ResumeRequest, ResumeResponse};
+use crate::leader_election_service::{StateRequest, StateResponse, StatsRequest, StatsResponse, TakeOverRequest, TakeOverResponse};
+use crate::leader_election_service::{DrainRequest, DrainResponse, DumpStateRequest, DumpStateResponse, ElectionHistoryRequest, ElectionHistoryResponse};
+use crate::leader_election_service::{ForceStateRequest, ForceStateResponse, StepDownRequest, StepDownResponse};
+use crate::leader_election_service::{Transition, TransferLeadershipRequest, TransferLeadershipResponse, TriggerReelectionRequest, TriggerReelectionResponse};
+use crate::leader_election_service::{UpdateConfigRequest, UpdateConfigResponse};
+use crate::metrics::Side;
+use crate::overload::OverloadLayer;
+use crate::tls;
+use crate::topology::NodeSpec;
+use crate::traces::otlp;
+use crate::{health, participate, serve_tls, stream_context, ElectionResult, Node, Responses};
+
+/// A message of a stream of election messages, which belongs to a group.
+trait Grouped {
+    fn group(&self) -> u64;
+}
+
+impl Grouped for ProbeMessage {
+    fn group(&self) -> u64 {
+        self.group_id
+    }
+}
+
+impl Grouped for NotifyMessage {
+    fn group(&self) -> u64 {
+        self.group_id
+    }
+}
+
+impl Grouped for DigestMessage {
+    fn group(&self) -> u64 {
+        self.group_id
+    }
+}
+
+impl Grouped for PeerMessage {
+    fn group(&self) -> u64 {
+        self.group_id
+    }
+}
+
+/// The election group of `role`, a hash of its name, which every node
+/// taking part in the role's elections agrees on without being told.
+pub fn role_group(role: &str) -> u64 {
+    // FNV-1a
+    role.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3))
+}
+
+/// A stream of messages whose first one was read to route it.
+type Routed<T> = Chain<Iter<std::option::IntoIter<Result<T, Status>>>, Streaming<T>>;
+
+/// A node taking part in the elections of several groups around the same
+/// ring at once, e.g. one per shard, each electing a leader of its own.
+/// Every group has a [`Node`] of its own, with its own state, timers and
+/// files, and the messages and requests of each go to the node of the group
+/// they name. Groups can be named after roles instead, e.g. `scheduler`
+/// and `compactor`, so that one process holds both in a ring, see
+/// [`MultiGroupNode::with_roles`].
+#[derive(Debug, Clone)]
+pub struct MultiGroupNode {
+    groups: Arc<BTreeMap<u64, Node>>,
+}
+
+impl MultiGroupNode {
+    /// Creates the node `spec` describes, one of a ring of `ring_size` nodes,
+    /// for each of `groups`. The finished trace spans of all groups go to
+    /// `finished_spans`, if anywhere.
+    pub fn new(groups: &[u64], spec: &NodeSpec, ring_size: u64, config: &Config, finished_spans: Option<mpsc::UnboundedSender<otlp::Span>>)
+    -> std::io::Result<Self> {
+        Self::with_roles(groups, &[], spec, ring_size, config, finished_spans)
+    }
+
+    /// Creates the node `spec` describes for each of `groups` and, in the
+    /// group [`role_group`] names after it, each of `roles`.
+    pub fn with_roles(groups: &[u64], roles: &[Role], spec: &NodeSpec, ring_size: u64, config: &Config,
+        finished_spans: Option<mpsc::UnboundedSender<otlp::Span>>) -> std::io::Result<Self> {
+        let invalid = |reason: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, reason);
+        if groups.is_empty() && roles.is_empty() {
+            return Err(invalid("a node takes part in at least one group".to_string()))
+        }
+        let mut nodes = BTreeMap::new();
+        for &group in groups {
+            nodes.insert(group, Node::in_group(group, spec, ring_size, config, finished_spans.clone())?);
+        }
+        for role in roles {
+            let node = Node::in_role(role, spec, ring_size, config, finished_spans.clone())?;
+            if let Some(taken) = nodes.insert(node.group(), node) {
+                return Err(invalid(format!("role {:?} would hold its elections in group {}, which {} already does", role.name, taken.group(),
+                    taken.role().map_or_else(|| "the node".to_string(), |role| format!("role {:?}", role)))))
+            }
+        }
+        Ok(MultiGroupNode { groups: Arc::new(nodes) })
+    }
+
+    /// Changes the level of the process's diagnostics through `filter` when
+    /// the settings of any group's node are reloaded.
+    pub fn with_log_filter(self, filter: Arc<dyn LogFilter>) -> Self {
+        let groups = self.groups.iter().map(|(&group, node)| (group, node.clone().with_log_filter(filter.clone()))).collect();
+        MultiGroupNode { groups: Arc::new(groups) }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.first().id()
+    }
+
+    /// The node taking part in the election of `group`, if this one does.
+    pub fn group(&self, group: u64) -> Option<&Node> {
+        self.groups.get(&group)
+    }
+
+    /// The nodes of all groups, by group.
+    pub fn groups(&self) -> impl Iterator<Item = (u64, &Node)> {
+        self.groups.iter().map(|(&group, node)| (group, node))
+    }
+
+    /// The node taking part in the elections of `role`, if this one does.
+    pub fn role(&self, role: &str) -> Option<&Node> {
+        self.group(role_group(role)).filter(|node| node.role() == Some(role))
+    }
+
+    /// The leader of `group` as far as this node knows, or `None` until the
+    /// group elected one or if the node takes no part in it.
+    pub fn leader(&self, group: u64) -> Option<u64> {
+        let node = self.group(group)?;
+        match *node.subscribe().borrow() {
+            ElectionResult::Undecided | ElectionResult::TimedOut => None,
+            ElectionResult::Leader => Some(node.id()),
+            ElectionResult::Defeated { leader } => Some(leader),
+        }
+    }
+
+    /// The leader of `role` as far as this node knows, or `None` until its
+    /// election is over or if the node takes no part in it.
+    pub fn role_leader(&self, role: &str) -> Option<u64> {
+        self.role(role).and_then(|node| self.leader(node.group()))
+    }
+
+    /// The leader of every group as far as this node knows.
+    pub fn leaders(&self) -> BTreeMap<u64, Option<u64>> {
+        self.groups.keys().map(|&group| (group, self.leader(group))).collect()
+    }
+
+    /// Shuts the nodes of all groups down.
+    pub fn shutdown(&self) {
+        self.groups.values().for_each(Node::shutdown);
+    }
+
+    fn first(&self) -> &Node {
+        self.groups.values().next().expect("a node takes part in at least one group")
+    }
+
+    fn node(&self, group: u64) -> Result<&Node, ElectionError> {
+        self.group(group).ok_or(ElectionError::UnknownGroup { node: self.id(), group })
+    }
+
+    /// The node whose leader `request` asks for: that of the role it names,
+    /// if any, or else of its group.
+    fn leader_node(&self, request: &LeaderRequest) -> Result<&Node, ElectionError> {
+        match request.role.is_empty() {
+            true => self.node(request.group_id),
+            false => self.role(&request.role).ok_or_else(|| ElectionError::UnknownRole { node: self.id(), role: request.role.clone() }),
+        }
+    }
+
+    /// The node of the group of the first of `stream`'s messages, along with
+    /// the whole stream. An empty stream goes to any node.
+    async fn route<T: Grouped>(&self, mut stream: Streaming<T>) -> Result<(&Node, Routed<T>), Status> {
+        let first = stream.message().await?;
+        let node = match &first {
+            Some(message) => self.node(message.group())?,
+            None => self.first(),
+        };
+        Ok((node, stream::iter(first.map(Ok)).chain(stream)))
+    }
+
+    /// The responses `answer` makes the node of the group of `stream`'s
+    /// messages give. Routes the stream only once its first message comes,
+    /// as the sender may wait for the call to be answered before it sends
+    /// any.
+    fn answer<T, R, F>(&self, stream: Streaming<T>, answer: F) -> Responses<R>
+    where
+        T: Grouped + Send + 'static,
+        R: Send + 'static,
+        F: FnOnce(&Node, Routed<T>) -> Result<Responses<R>, ElectionError> + Send + 'static,
+    {
+        let this = self.clone();
+        Box::pin(async_stream::try_stream! {
+            let (node, stream) = this.route(stream).await?;
+            let mut responses = answer(node, stream)?;
+            while let Some(response) = responses.next().await {
+                let response = response?;
+                yield response;
+            }
+        })
+    }
+
+    /// Serves the nodes of all groups on `addr` and takes part in the
+    /// election of each, until the server fails or the nodes are shut down.
+    /// The first group reports the health of the node and serves its metrics
+    /// and dashboard, if `config` asks for them.
+    pub async fn run(self, addr: SocketAddr, config: &Config) -> Result<(), tonic::transport::Error> {
+        let first = self.first().clone();
+        let (health, health_service) = health::service::<LeaderElectionServiceServer<MultiGroupNode>>().await;
+        let overload = OverloadLayer::new(&config.middleware);
+        // the first group's certificates are those of the node
+        let server = serve_tls(&first, |tls| {
+            let mut server = tls::server(&config.timing, &config.middleware);
+            if let Some(tls) = tls {
+                server = server.tls_config(tls.server.clone())?;
+            }
+            Ok(server
+                .layer(first.layers(Side::Server, None).layer(first.auth_layer()).layer(overload.clone()).layer(first.limits.clone()))
+                .add_service(compressed!(LeaderElectionServiceServer::new(self.clone()), first.compression))
+                .add_service(compressed!(AdminServiceServer::new(self.clone()), first.compression))
+                .add_service(health_service.clone())
+                .serve_with_shutdown(addr, async {
+                    futures::future::join_all(self.groups.values().map(Node::stopped)).await;
+                }))
+        });
+        let others = Config { metrics_port_offset: None, dashboard_port_offset: None, ..config.clone() };
+        let mut health = Some(health);
+        let participating = self.groups.values().enumerate().map(|(i, node)| match i {
+            0 => participate(node.clone(), addr, config, health.take()),
+            _ => participate(node.clone(), addr, &others, None),
+        });
+        let (served, _) = futures::future::join(server, futures::future::join_all(participating)).await;
+        served
+    }
+}
+
+#[tonic::async_trait]
+impl LeaderElectionService for MultiGroupNode {
+    type NotifyElectedRawStream = Responses<NotifyResponse>;
+    type ProbeRawStream = Responses<ProbeResponse>;
+    type CheckDigestRawStream = Responses<DigestResponse>;
+    type RelayStream = Responses<PeerAck>;
+    type WatchLeaderStream = Responses<LeaderResponse>;
+
+    async fn probe_raw(&self, request: Request<Streaming<ProbeMessage>>) -> Result<Response<Self::ProbeRawStream>, Status> {
+        let context = stream_context(&request);
+        Ok(Response::new(self.answer(request.into_inner(), |node, stream| node.answer_probes(stream, context))))
+    }
+
+    async fn notify_elected_raw(&self, request: Request<Streaming<NotifyMessage>>)
+    -> Result<Response<Self::NotifyElectedRawStream>, Status> {
+        let context = stream_context(&request);
+        Ok(Response::new(self.answer(request.into_inner(), |node, stream| node.answer_notifications(stream, context))))
+    }
+
+    async fn check_digest_raw(&self, request: Request<Streaming<DigestMessage>>)
+    -> Result<Response<Self::CheckDigestRawStream>, Status> {
+        let context = stream_context(&request);
+        Ok(Response::new(self.answer(request.into_inner(), |node, stream| node.answer_digests(stream, context))))
+    }
+
+    async fn relay(&self, request: Request<Streaming<PeerMessage>>) -> Result<Response<Self::RelayStream>, Status> {
+        let context = stream_context(&request);
+        Ok(Response::new(self.answer(request.into_inner(), |node, stream| Ok(node.acknowledge(stream, context)))))
+    }
+
+    async fn get_state(&self, request: Request<StateRequest>) -> Result<Response<StateResponse>, Status> {
+        LeaderElectionService::get_state(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn get_leader(&self, request: Request<LeaderRequest>) -> Result<Response<LeaderResponse>, Status> {
+        LeaderElectionService::get_leader(self.leader_node(request.get_ref())?, request).await
+    }
+
+    async fn watch_leader(&self, request: Request<LeaderRequest>) -> Result<Response<Self::WatchLeaderStream>, Status> {
+        LeaderElectionService::watch_leader(self.leader_node(request.get_ref())?, request).await
+    }
+
+    async fn get_metrics(&self, request: Request<MetricsRequest>) -> Result<Response<MetricsResponse>, Status> {
+        LeaderElectionService::get_metrics(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn get_stats(&self, request: Request<StatsRequest>) -> Result<Response<StatsResponse>, Status> {
+        LeaderElectionService::get_stats(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn get_anomalies(&self, request: Request<AnomaliesRequest>) -> Result<Response<AnomaliesResponse>, Status> {
+        LeaderElectionService::get_anomalies(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn get_ranking(&self, request: Request<RankingRequest>) -> Result<Response<RankingResponse>, Status> {
+        LeaderElectionService::get_ranking(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn reconfigure(&self, request: Request<ReconfigureRequest>) -> Result<Response<ReconfigureResponse>, Status> {
+        LeaderElectionService::reconfigure(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn heartbeat(&self, request: Request<HeartbeatRequest>) -> Result<Response<HeartbeatResponse>, Status> {
+        LeaderElectionService::heartbeat(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn pre_vote(&self, request: Request<PreVoteRequest>) -> Result<Response<PreVoteResponse>, Status> {
+        LeaderElectionService::pre_vote(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn take_over(&self, request: Request<TakeOverRequest>) -> Result<Response<TakeOverResponse>, Status> {
+        LeaderElectionService::take_over(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn join(&self, request: Request<JoinRequest>) -> Result<Response<JoinResponse>, Status> {
+        LeaderElectionService::join(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn leave(&self, request: Request<LeaveRequest>) -> Result<Response<LeaveResponse>, Status> {
+        LeaderElectionService::leave(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn reelect(&self, request: Request<ReelectRequest>) -> Result<Response<ReelectResponse>, Status> {
+        LeaderElectionService::reelect(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn resize(&self, request: Request<ResizeRequest>) -> Result<Response<ResizeResponse>, Status> {
+        LeaderElectionService::resize(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn merge(&self, request: Request<MergeRequest>) -> Result<Response<MergeResponse>, Status> {
+        LeaderElectionService::merge(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn introduce(&self, request: Request<IntroductionRequest>) -> Result<Response<IntroductionResponse>, Status> {
+        LeaderElectionService::introduce(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn forward_to_leader(&self, request: Request<ForwardRequest>) -> Result<Response<ForwardResponse>, Status> {
+        LeaderElectionService::forward_to_leader(self.node(request.get_ref().group_id)?, request).await
+    }
+}
+
+#[tonic::async_trait]
+impl AdminService for MultiGroupNode {
+    type WatchAuditLogStream = Responses<Transition>;
+
+    async fn dump_state(&self, request: Request<DumpStateRequest>) -> Result<Response<DumpStateResponse>, Status> {
+        AdminService::dump_state(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn force_state(&self, request: Request<ForceStateRequest>) -> Result<Response<ForceStateResponse>, Status> {
+        AdminService::force_state(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn trigger_reelection(&self, request: Request<TriggerReelectionRequest>)
+    -> Result<Response<TriggerReelectionResponse>, Status> {
+        AdminService::trigger_reelection(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn drain(&self, request: Request<DrainRequest>) -> Result<Response<DrainResponse>, Status> {
+        AdminService::drain(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn step_down(&self, request: Request<StepDownRequest>) -> Result<Response<StepDownResponse>, Status> {
+        AdminService::step_down(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn transfer_leadership(&self, request: Request<TransferLeadershipRequest>)
+    -> Result<Response<TransferLeadershipResponse>, Status> {
+        AdminService::transfer_leadership(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn get_election_history(&self, request: Request<ElectionHistoryRequest>) -> Result<Response<ElectionHistoryResponse>, Status> {
+        AdminService::get_election_history(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn get_audit_log(&self, request: Request<AuditLogRequest>) -> Result<Response<AuditLogResponse>, Status> {
+        AdminService::get_audit_log(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn watch_audit_log(&self, request: Request<AuditLogRequest>) -> Result<Response<Self::WatchAuditLogStream>, Status> {
+        AdminService::watch_audit_log(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn update_config(&self, request: Request<UpdateConfigRequest>) -> Result<Response<UpdateConfigResponse>, Status> {
+        AdminService::update_config(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn pause(&self, request: Request<PauseRequest>) -> Result<Response<PauseResponse>, Status> {
+        AdminService::pause(self.node(request.get_ref().group_id)?, request).await
+    }
+
+    async fn resume(&self, request: Request<ResumeRequest>) -> Result<Response<ResumeResponse>, Status> {
+        AdminService::resume(self.node(request.get_ref().group_id)?, request).await
+    }
+}
@@ -0,0 +1,20 @@
+use tonic::transport::NamedService;
+use tonic_health::proto::health_server::{Health, HealthServer};
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+
+/// The standard `grpc.health.v1.Health` service of a node whose election
+/// service is `S`, along with what reports its health. Both the node as a
+/// whole and `S` count as not serving until told otherwise.
+pub async fn service<S: NamedService>() -> (HealthReporter, HealthServer<impl Health>) {
+    let (mut reporter, service) = tonic_health::server::health_reporter();
+    report::<S>(&mut reporter, ServingStatus::NotServing).await;
+    (reporter, service)
+}
+
+/// Reports whether the node takes part in the election, as the status of
+/// both the node as a whole and its election service `S`.
+pub async fn report<S: NamedService>(reporter: &mut HealthReporter, status: ServingStatus) {
+    reporter.set_service_status("", status).await;
+    reporter.set_service_status(S::NAME, status).await;
+}
@@ -0,0 +1,270 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use futures::future;
+use tokio::sync::{mpsc, watch, Mutex};
+use tonic::transport::Endpoint;
+use tonic::{Request, Response, Status};
+use tonic_health::ServingStatus;
+use tracing::{debug, error, info, warn};
+
+use crate::clock::{Clock, TokioClock};
+use crate::compression::compressed;
+use crate::config::{Compression, Config, Output, TimingConfig};
+use crate::health;
+use crate::overload::OverloadLayer;
+use crate::retry::{retry, RetryPolicy};
+use crate::leader_election_service::hirschberg_sinclair_service_client::HirschbergSinclairServiceClient;
+use crate::leader_election_service::hirschberg_sinclair_service_server::{HirschbergSinclairService, HirschbergSinclairServiceServer};
+use crate::leader_election_service::{ElectedMessage, ElectedResponse, HsProbeResponse, ProbeMessage, ProbeReply, ProbeReplyResponse};
+use crate::tls::{self, Tls};
+use crate::topology::NodeSpec;
+use crate::{deadline, preferred_leader, print_message, publish, seed_of, until_set, ElectionAlgorithm, ElectionResult};
+
+#[derive(Debug, Clone)]
+enum Message {
+    Probe(ProbeMessage),
+    Reply(ProbeReply),
+    Elected(u64),
+}
+
+/// The phase a candidate probes, and which of its probes of the phase came
+/// back.
+#[derive(Debug, Default)]
+struct Phase {
+    phase: u64,
+    /// The replies to the probes headed left and right.
+    replied: [bool; 2],
+}
+
+/// A node electing the leader of a ring with Hirschberg and Sinclair's
+/// algorithm. In phase `k`, each remaining candidate probes 2^k nodes both
+/// ways around the ring; a probe dies at a better node and turns around
+/// once it went as far as it had to, and a candidate both of whose probes
+/// came back moves on to the next phase. The candidate whose probe makes it
+/// all the way around the ring leads, after O(n log n) messages.
+#[derive(Debug, Clone)]
+pub struct HirschbergSinclairNode {
+    id: u64,
+    /// The IDs of the left and right neighbours.
+    neighbors: [u64; 2],
+    endpoints: [Endpoint; 2],
+    clock: Arc<dyn Clock>,
+    retry: RetryPolicy,
+    /// Seeds the jitter of the node's retries.
+    seed: u64,
+    timing: TimingConfig,
+    /// How the node prints its message log, if it does.
+    message_log: Option<Output>,
+    phase: Arc<std::sync::Mutex<Phase>>,
+    /// Whether the node passed on a better candidate's probe, and so cannot
+    /// win.
+    beaten: Arc<AtomicBool>,
+    /// Whether the node's own probe came back around the ring.
+    elected: Arc<AtomicBool>,
+    /// The messages to the left and right neighbours.
+    outgoing: [mpsc::UnboundedSender<Message>; 2],
+    /// Messages waiting to be sent to the neighbours, until the node runs.
+    queued: Arc<Mutex<Option<[mpsc::UnboundedReceiver<Message>; 2]>>>,
+    /// How many messages the node sent its neighbours, retries aside.
+    sent: Arc<AtomicU64>,
+    results: Arc<watch::Sender<ElectionResult>>,
+    stopping: Arc<watch::Sender<bool>>,
+    tls: Option<Arc<Tls>>,
+    compression: Option<Compression>,
+}
+
+/// Where the messages headed one way go among a node's neighbours.
+fn side(headed_left: bool) -> usize {
+    match headed_left {
+        true => 0,
+        false => 1,
+    }
+}
+
+impl HirschbergSinclairNode {
+    /// Creates the node `spec` describes.
+    pub fn new(spec: &NodeSpec, config: &Config) -> std::io::Result<Self> {
+        let tls = Tls::load(config)?.map(Arc::new);
+        let endpoint = |url: &str| tls::endpoint(url.to_string(), tls.as_deref(), &config.timing)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e));
+        let (left, queued_left) = mpsc::unbounded_channel();
+        let (right, queued_right) = mpsc::unbounded_channel();
+        let clock = Arc::new(TokioClock::new());
+        Ok(HirschbergSinclairNode {
+            id: spec.id.into(),
+            neighbors: [spec.left().id.into(), spec.right().id.into()],
+            endpoints: [endpoint(&spec.left().url)?, endpoint(&spec.right().url)?],
+            retry: config.retry,
+            seed: seed_of(spec.id.into(), config, clock.wall_now().timestamp_nanos() as u64),
+            clock,
+            timing: config.timing,
+            message_log: config.message_log.then_some(config.output),
+            phase: Arc::default(),
+            beaten: Arc::default(),
+            elected: Arc::default(),
+            outgoing: [left, right],
+            queued: Arc::new(Mutex::new(Some([queued_left, queued_right]))),
+            sent: Arc::default(),
+            results: Arc::new(watch::channel(ElectionResult::Undecided).0),
+            stopping: Arc::new(watch::channel(false).0),
+            tls,
+            compression: config.compression,
+        })
+    }
+
+    /// How many probes, replies and announcements the node sent, each
+    /// counted once however often it had to be retried.
+    pub fn messages_sent(&self) -> u64 {
+        self.sent.load(Ordering::SeqCst)
+    }
+
+    fn send(&self, headed_left: bool, message: Message) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+        // the receivers live as long as the node runs
+        let _ = self.outgoing[side(headed_left)].send(message);
+    }
+
+    /// Probes 2^`phase` nodes both ways around the ring.
+    fn probe(&self, phase: u64) {
+        debug!(node = self.id, "probing phase {}", phase);
+        for headed_left in [true, false] {
+            let hops_remaining = 1 << phase.min(63);
+            let probe = ProbeMessage { sender_id: self.id, headed_left, phase, hops_remaining, ..Default::default() };
+            self.send(headed_left, Message::Probe(probe));
+        }
+    }
+
+    /// Sends the queued messages to the neighbour on `side` in order,
+    /// retrying each as the retry policy says and dropping it if the policy
+    /// gives up.
+    async fn forward(self, side: usize, mut queued: mpsc::UnboundedReceiver<Message>) {
+        let (to, endpoint) = (self.neighbors[side], &self.endpoints[side]);
+        let client = compressed!(HirschbergSinclairServiceClient::new(endpoint.connect_lazy()), self.compression);
+        while let Some(message) = queued.recv().await {
+            let value = match &message {
+                Message::Probe(ProbeMessage { sender_id, .. }) | Message::Reply(ProbeReply { sender_id, .. }) => *sender_id,
+                Message::Elected(leader_id) => *leader_id,
+            };
+            let send = || {
+                let (mut client, message) = (client.clone(), message.clone());
+                print_message(self.message_log, self.id, self.clock.wall_now(), value, to);
+                async move {
+                    match message {
+                        Message::Probe(probe) => client.probe(deadline(probe, self.timing.rpc_deadline)).await.map(drop),
+                        Message::Reply(reply) => client.reply(deadline(reply, self.timing.rpc_deadline)).await.map(drop),
+                        Message::Elected(leader_id) =>
+                            client.elected(deadline(ElectedMessage { leader_id }, self.timing.rpc_deadline)).await.map(drop),
+                    }
+                }
+            };
+            let retrying = |e: &Status, delay| warn!(node = self.id, "cannot reach {}, retrying in {:?}: {}", endpoint.uri(), delay, e);
+            if let Err(e) = retry(self.retry.backoff(self.seed), &*self.clock, send, retrying).await {
+                error!(node = self.id, "gave up on sending {:?} to {}: {}", message, endpoint.uri(), e);
+            }
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl HirschbergSinclairService for HirschbergSinclairNode {
+    async fn probe(&self, request: Request<ProbeMessage>) -> Result<Response<HsProbeResponse>, Status> {
+        let probe = request.into_inner();
+        let ProbeMessage { sender_id, headed_left, phase, hops_remaining, .. } = probe;
+        if sender_id == self.id {
+            // the probe went around the whole ring, and may do so both ways
+            if !self.elected.swap(true, Ordering::SeqCst) {
+                info!(node = self.id, "is the leader");
+                publish(&self.results, ElectionResult::Leader);
+                self.send(false, Message::Elected(self.id));
+            }
+        } else if preferred_leader(sender_id, self.id) == sender_id {
+            self.beaten.store(true, Ordering::SeqCst);
+            match hops_remaining > 1 {
+                true => self.send(headed_left, Message::Probe(ProbeMessage { hops: probe.hops + 1, hops_remaining: hops_remaining - 1, ..probe })),
+                false => self.send(!headed_left, Message::Reply(ProbeReply { sender_id, phase, headed_left })),
+            }
+        } else {
+            debug!(node = self.id, "swallowing the probe of {}", sender_id);
+        }
+        Ok(Response::new(HsProbeResponse {}))
+    }
+
+    async fn reply(&self, request: Request<ProbeReply>) -> Result<Response<ProbeReplyResponse>, Status> {
+        let reply = request.into_inner();
+        if reply.sender_id != self.id {
+            self.send(!reply.headed_left, Message::Reply(reply));
+        } else if !self.beaten.load(Ordering::SeqCst) {
+            let mut current = self.phase.lock().unwrap();
+            if current.phase == reply.phase {
+                current.replied[side(reply.headed_left)] = true;
+                if current.replied == [true, true] {
+                    *current = Phase { phase: current.phase + 1, replied: [false, false] };
+                    self.probe(current.phase);
+                }
+            }
+        }
+        Ok(Response::new(ProbeReplyResponse {}))
+    }
+
+    async fn elected(&self, request: Request<ElectedMessage>) -> Result<Response<ElectedResponse>, Status> {
+        let ElectedMessage { leader_id } = request.into_inner();
+        if leader_id != self.id {
+            info!(node = self.id, "acknowledging {}'s leadership", leader_id);
+            publish(&self.results, ElectionResult::Defeated { leader: leader_id });
+            self.send(false, Message::Elected(leader_id));
+        }
+        Ok(Response::new(ElectedResponse {}))
+    }
+}
+
+#[tonic::async_trait]
+impl ElectionAlgorithm for HirschbergSinclairNode {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn subscribe(&self) -> watch::Receiver<ElectionResult> {
+        self.results.subscribe()
+    }
+
+    fn shutdown(&self) {
+        info!(node = self.id, "shutting down");
+        self.stopping.send_replace(true);
+    }
+
+    async fn run(self, addr: SocketAddr, config: &Config) -> Result<(), tonic::transport::Error> {
+        let (mut health, health_service) = health::service::<HirschbergSinclairServiceServer<HirschbergSinclairNode>>().await;
+        let mut server = tls::server(&config.timing, &config.middleware);
+        if let Some(tls) = &self.tls {
+            server = server.tls_config(tls.server.clone())?;
+        }
+        let server = server
+            .layer(OverloadLayer::new(&config.middleware))
+            .add_service(compressed!(HirschbergSinclairServiceServer::new(self.clone()), self.compression))
+            .add_service(health_service)
+            .serve_with_shutdown(addr, until_set(&self.stopping));
+        let queued = self.queued.lock().await.take();
+        let mut serving = health.clone();
+        let client = async {
+            // give the other nodes the time to come up
+            self.clock.sleep_until(self.clock.now() + self.timing.startup_grace).await;
+            self.probe(0);
+            health::report::<HirschbergSinclairServiceServer<HirschbergSinclairNode>>(&mut serving, ServingStatus::Serving).await;
+            match queued {
+                Some([left, right]) => drop(future::join(self.clone().forward(0, left), self.clone().forward(1, right)).await),
+                None => future::pending().await,
+            }
+        };
+        let client = async {
+            tokio::select! {
+                _ = client => (),
+                _ = until_set(&self.stopping) => (),
+            }
+            health::report::<HirschbergSinclairServiceServer<HirschbergSinclairNode>>(&mut health, ServingStatus::NotServing).await;
+        };
+        let (served, _) = future::join(server, client).await;
+        served
+    }
+}
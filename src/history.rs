@@ -0,0 +1,146 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use tokio::time::Duration;
+
+use crate::leader_election_service::Election;
+
+/// How many elections a node keeps around for `GetElectionHistory`.
+const HISTORY_LIMIT: usize = 64;
+
+/// The elections a node saw complete, for quantifying leader churn. Counts
+/// the phases, messages, candidates and probes ending at the node of the
+/// election under way, if there is one.
+#[derive(Debug)]
+pub struct History {
+    elections: Mutex<VecDeque<Election>>,
+    total: AtomicU64,
+    electing: AtomicBool,
+    highest_phase: AtomicU64,
+    messages: AtomicU64,
+    probes_ended: AtomicU64,
+    probe_hops: AtomicU64,
+    max_probe_hops: AtomicU64,
+    /// The candidates seen probing in each phase.
+    candidates: Mutex<BTreeMap<u64, BTreeSet<u64>>>,
+}
+
+impl History {
+    pub fn new(electing: bool) -> Self {
+        History {
+            elections: Mutex::default(),
+            total: AtomicU64::new(0),
+            electing: AtomicBool::new(electing),
+            highest_phase: AtomicU64::new(0),
+            messages: AtomicU64::new(0),
+            probes_ended: AtomicU64::new(0),
+            probe_hops: AtomicU64::new(0),
+            max_probe_hops: AtomicU64::new(0),
+            candidates: Mutex::default(),
+        }
+    }
+
+    /// Starts counting towards a new election, unless one is under way.
+    pub fn begin(&self) {
+        if !self.electing.swap(true, Ordering::Relaxed) {
+            self.highest_phase.store(0, Ordering::Relaxed);
+            for count in [&self.messages, &self.probes_ended, &self.probe_hops, &self.max_probe_hops] {
+                count.store(0, Ordering::Relaxed);
+            }
+            self.candidates.lock().unwrap().clear();
+        }
+    }
+
+    /// Counts a message sent or received during an election.
+    pub fn message(&self) {
+        if self.electing.load(Ordering::Relaxed) {
+            self.messages.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Notes that the election reached `phase`.
+    pub fn phase(&self, phase: u64) {
+        if self.electing.load(Ordering::Relaxed) {
+            self.highest_phase.fetch_max(phase, Ordering::Relaxed);
+        }
+    }
+
+    /// Notes that candidate `id` probed in `phase`, through a probe the node
+    /// sent or passed on.
+    pub fn candidate(&self, id: u64, phase: u64) {
+        if self.electing.load(Ordering::Relaxed) {
+            self.candidates.lock().unwrap().entry(phase).or_default().insert(id);
+        }
+    }
+
+    /// Notes that a probe ended at the node after passing through `hops`
+    /// nodes, this one included.
+    pub fn probe_ended(&self, hops: u64) {
+        if self.electing.load(Ordering::Relaxed) {
+            self.probes_ended.fetch_add(1, Ordering::Relaxed);
+            self.probe_hops.fetch_add(hops, Ordering::Relaxed);
+            self.max_probe_hops.fetch_max(hops, Ordering::Relaxed);
+        }
+    }
+
+    /// Records the end of the election under way, which took `duration` and
+    /// found `leader` at `at`, and returns it.
+    pub fn finish(&self, term: u64, leader: u64, duration: Duration, at: DateTime<Utc>) -> Election {
+        self.electing.store(false, Ordering::Relaxed);
+        let election = Election {
+            term,
+            leader_id: leader,
+            unix_ms: at.timestamp_millis() as u64,
+            duration_ms: duration.as_millis() as u64,
+            phases: self.highest_phase.load(Ordering::Relaxed) + 1,
+            messages: self.messages.load(Ordering::Relaxed),
+            probes_ended: self.probes_ended.load(Ordering::Relaxed),
+            max_probe_hops: self.max_probe_hops.load(Ordering::Relaxed),
+            probe_hops: self.probe_hops.load(Ordering::Relaxed),
+            candidates: {
+                let candidates = self.candidates.lock().unwrap();
+                let last = candidates.keys().last().copied().unwrap_or(0);
+                (1..=last).map(|phase| candidates.get(&phase).map_or(0, |ids| ids.len() as u64)).collect()
+            },
+        };
+        let mut elections = self.elections.lock().unwrap();
+        if elections.len() == HISTORY_LIMIT {
+            elections.pop_front();
+        }
+        elections.push_back(election.clone());
+        self.total.fetch_add(1, Ordering::Relaxed);
+        election
+    }
+
+    /// The most recent elections, oldest first.
+    pub fn recent(&self) -> Vec<Election> {
+        self.elections.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    /// Appends the election metrics to `out` in the Prometheus text format.
+    pub fn render(&self, node: u64, out: &mut String) {
+        let _ = writeln!(out, "# TYPE grpc_le_elections_total counter");
+        let _ = writeln!(out, "grpc_le_elections_total{{node=\"{}\"}} {}", node, self.total());
+        if let Some(last) = self.elections.lock().unwrap().back() {
+            let _ = writeln!(out, "# TYPE grpc_le_election_phases gauge");
+            let _ = writeln!(out, "grpc_le_election_phases{{node=\"{}\"}} {}", node, last.phases);
+            let _ = writeln!(out, "# TYPE grpc_le_election_messages gauge");
+            let _ = writeln!(out, "grpc_le_election_messages{{node=\"{}\"}} {}", node, last.messages);
+            let _ = writeln!(out, "# TYPE grpc_le_election_probes_ended gauge");
+            let _ = writeln!(out, "grpc_le_election_probes_ended{{node=\"{}\"}} {}", node, last.probes_ended);
+            let _ = writeln!(out, "# TYPE grpc_le_election_max_probe_hops gauge");
+            let _ = writeln!(out, "grpc_le_election_max_probe_hops{{node=\"{}\"}} {}", node, last.max_probe_hops);
+            let _ = writeln!(out, "# TYPE grpc_le_election_candidates gauge");
+            for (phase, candidates) in (1..).zip(&last.candidates) {
+                let _ = writeln!(out, "grpc_le_election_candidates{{node=\"{}\",phase=\"{}\"}} {}", node, phase, candidates);
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+
+use tokio::sync::{mpsc, watch};
+use tracing::error;
+
+use crate::ElectionResult;
+
+/// A run of a hook.
+type Running = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Starts a run of a hook if the change in the outcome of the election is
+/// one the hook was registered for.
+pub(crate) type Hook = Arc<dyn Fn(Change) -> Option<Running> + Send + Sync>;
+
+/// A change in the outcome of the election as node `node` knows it.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Change {
+    pub node: u64,
+    pub from: ElectionResult,
+    pub to: ElectionResult,
+}
+
+impl Change {
+    /// The leaders known before and after the change.
+    pub fn leaders(&self) -> (Option<u64>, Option<u64>) {
+        let leader = |result| match result {
+            ElectionResult::Leader => Some(self.node),
+            ElectionResult::Defeated { leader } => Some(leader),
+            ElectionResult::Undecided | ElectionResult::TimedOut => None,
+        };
+        (leader(self.from), leader(self.to))
+    }
+}
+
+/// The hooks of a node, run one at a time on a task of their own, which is
+/// started along with the first hook.
+#[derive(Debug)]
+pub(crate) struct Hooks {
+    node: u64,
+    registered: OnceLock<mpsc::UnboundedSender<Hook>>,
+}
+
+impl Hooks {
+    pub fn new(node: u64) -> Self {
+        Hooks { node, registered: OnceLock::new() }
+    }
+
+    /// Runs `hook` on the changes `results` go through from now on, and on
+    /// the outcome they already hold unless it is undecided.
+    pub fn register(&self, results: &watch::Sender<ElectionResult>, hook: Hook) {
+        let registered = self.registered.get_or_init(|| {
+            let (registered, hooks) = mpsc::unbounded_channel();
+            tokio::spawn(run(self.node, results.subscribe(), hooks));
+            registered
+        });
+        // the task runs for as long as the node is around to register hooks
+        let _ = registered.send(hook);
+    }
+}
+
+/// Runs the hooks of node `node` as `results` change, until the node goes
+/// away.
+async fn run(node: u64, mut results: watch::Receiver<ElectionResult>, mut registered: mpsc::UnboundedReceiver<Hook>) {
+    let mut hooks = vec![];
+    let mut last = *results.borrow_and_update();
+    loop {
+        tokio::select! {
+            hook = registered.recv() => {
+                let Some(hook) = hook else { return };
+                call(&hook, Change { node, from: ElectionResult::Undecided, to: last }).await;
+                hooks.push(hook);
+            },
+            changed = results.changed() => {
+                if changed.is_err() {
+                    return
+                }
+                let change = Change { node, from: last, to: *results.borrow_and_update() };
+                last = change.to;
+                for hook in &hooks {
+                    call(hook, change).await;
+                }
+            },
+        }
+    }
+}
+
+/// Runs `hook` on `change` to completion, if at all, on a task of its own so
+/// that a panicking hook does not take the others down with it.
+async fn call(hook: &Hook, change: Change) {
+    if let Some(running) = hook(change) {
+        if let Err(e) = tokio::spawn(running).await {
+            error!(node = change.node, "an election hook failed on {:?}: {}", change.to, e);
+        }
+    }
+}
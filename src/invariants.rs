@@ -0,0 +1,12 @@
+/// Checks a protocol invariant when the crate is built with the
+/// `strict-invariants` feature, aborting the node with the given diagnostics
+/// if it does not hold. Without the feature the condition is not evaluated.
+macro_rules! invariant {
+    ($node:expr, $cond:expr, $($arg:tt)+) => {
+        if cfg!(feature = "strict-invariants") && !$cond {
+            panic!("node {} violated an invariant ({}): {}", $node, stringify!($cond), format_args!($($arg)+))
+        }
+    };
+}
+
+pub(crate) use invariant;
@@ -0,0 +1,162 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Parses a JSON document.
+pub fn parse(text: &str) -> Result<Json, String> {
+    Parser { chars: text.chars().peekable() }.document()
+}
+
+/// Quotes `s` as a JSON string, escaping what it has to.
+pub fn quote(s: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[derive(Debug)]
+pub enum Json {
+    /// `true`, `false` or `null`, none of which is read.
+    Keyword,
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+/// Just enough of a JSON parser to read topologies and event logs.
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn document(mut self) -> Result<Json, String> {
+        let value = self.value()?;
+        self.skip_whitespace();
+        match self.chars.next() {
+            None => Ok(value),
+            Some(c) => Err(format!("unexpected {:?} after the document", c)),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected {:?}, found {:?}", expected, c)),
+            None => Err(format!("expected {:?}, found the end of the file", expected)),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Json::String),
+            Some('-' | '0'..='9') => self.number(),
+            Some('t' | 'f' | 'n') => self.keyword(),
+            Some(&c) => Err(format!("unexpected {:?}", c)),
+            None => Err("unexpected end of the file".to_string()),
+        }
+    }
+
+    /// Parses the elements between `open` and `close`, separated by commas.
+    fn sequence<T>(&mut self, open: char, close: char, mut element: impl FnMut(&mut Self) -> Result<T, String>)
+    -> Result<Vec<T>, String> {
+        self.expect(open)?;
+        let mut elements = vec![];
+        self.skip_whitespace();
+        if self.chars.next_if_eq(&close).is_some() {
+            return Ok(elements)
+        }
+        loop {
+            elements.push(element(self)?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => (),
+                Some(c) if c == close => return Ok(elements),
+                _ => return Err(format!("expected ',' or {:?}", close)),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.sequence('{', '}', |parser| {
+            parser.skip_whitespace();
+            let key = parser.string()?;
+            parser.expect(':')?;
+            Ok((key, parser.value()?))
+        }).map(Json::Object)
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.sequence('[', ']', Self::value).map(Json::Array)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => match self.chars.next() {
+                    Some(c @ ('"' | '\\' | '/')) => string.push(c),
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some('r') => string.push('\r'),
+                    Some('b') => string.push('\u{8}'),
+                    Some('f') => string.push('\u{c}'),
+                    Some('u') => {
+                        let hex = (0..4).filter_map(|_| self.chars.next()).collect::<String>();
+                        let c = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32)
+                            .ok_or_else(|| format!("invalid escape \\u{}", hex))?;
+                        string.push(c);
+                    },
+                    c => return Err(format!("invalid escape {:?}", c)),
+                },
+                Some(c) => string.push(c),
+                None => return Err("unterminated string".to_string()),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let mut number = String::new();
+        while let Some(c) = self.chars.next_if(|c| matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9')) {
+            number.push(c);
+        }
+        number.parse().map(Json::Number).map_err(|_| format!("invalid number {:?}", number))
+    }
+
+    fn keyword(&mut self) -> Result<Json, String> {
+        let mut word = String::new();
+        while let Some(c) = self.chars.next_if(char::is_ascii_alphabetic) {
+            word.push(c);
+        }
+        match &word[..] {
+            "true" | "false" | "null" => Ok(Json::Keyword),
+            _ => Err(format!("unexpected {:?}", word)),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use tracing::{info, warn};
+
+use crate::clock::TokioClock;
+use crate::config::Config;
+use crate::retry::{retry, RetryPolicy};
+use crate::topology::{Member, NodeSpec, Topology};
+
+/// DNS record type of service locations.
+const SRV: u16 = 33;
+
+/// Discovers the ring of the StatefulSet the process runs a pod of, from the
+/// SRV records of its headless Service `service`, e.g.
+/// `_grpc._tcp.le.default.svc.cluster.local`, which list each pod by name,
+/// e.g. `le-2.le.default.svc.cluster.local`. The pods make up a ring in the
+/// order of their ordinals, pod `le-<n>` being node `n + 1` and listening on
+/// the port of its record. Waits until the records list the pod itself and,
+/// given a ring size, as many pods as that, and returns the node to run and
+/// the size of the ring.
+pub async fn discover(service: &str, config: &Config) -> io::Result<(NodeSpec, u64)> {
+    let hostname = match std::env::var("HOSTNAME") {
+        Ok(hostname) => hostname,
+        Err(_) => std::fs::read_to_string("/proc/sys/kernel/hostname")?.trim().to_string(),
+    };
+    let id = ordinal(&hostname)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not the name of a StatefulSet pod", hostname)))?;
+    let find = || async {
+        let mut members = srv(service, config.timing.rpc_deadline).await?.into_iter()
+            .filter_map(|(target, port)| Some(Member {
+                id: ordinal(target.split('.').next()?)?,
+                addr: SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)),
+                host: Some(target),
+                priority: 0,
+            }))
+            .collect::<Vec<_>>();
+        members.sort_by_key(|member| member.id);
+        members.dedup_by_key(|member| member.id);
+        if !members.iter().any(|member| member.id == id) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} does not list {} yet", service, hostname)))
+        }
+        if let Some(ring_size) = config.ring_size.filter(|&ring_size| (members.len() as u64) < ring_size) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} lists {} of {} pods", service, members.len(), ring_size)))
+        }
+        Ok(members)
+    };
+    let backoff = RetryPolicy { max_attempts: None, ..config.retry }.backoff(id.into());
+    let retrying = |e: &io::Error, delay| warn!(node = id, "cannot discover the ring yet, retrying in {:?}: {}", delay, e);
+    let members = retry(backoff, &TokioClock::new(), find, retrying).await?;
+    info!(node = id, "discovered the ring {:?}", members.iter().map(|member| member.id).collect::<Vec<_>>());
+    let topology = Topology { members, edges: None };
+    let ring_size = config.ring_size.unwrap_or(topology.members.len() as u64);
+    let spec = topology.nodes().into_iter().find(|spec| spec.id == id).expect("the ring has the node");
+    Ok((spec, ring_size))
+}
+
+/// The node of the StatefulSet pod named `name`, one past its ordinal.
+fn ordinal(name: &str) -> Option<u16> {
+    name.rsplit_once('-')?.1.parse::<u16>().ok()?.checked_add(1)
+}
+
+/// The targets and ports of the SRV records of `name`, qualified by the
+/// search domains of /etc/resolv.conf if need be, like the system resolver
+/// does.
+async fn srv(name: &str, timeout: Duration) -> io::Result<Vec<(String, u16)>> {
+    let resolv = std::fs::read_to_string("/etc/resolv.conf")?;
+    let (mut server, mut search) = (None, vec![]);
+    for line in resolv.lines() {
+        match line.split_whitespace().collect::<Vec<_>>()[..] {
+            ["nameserver", addr, ..] if server.is_none() => server = addr.parse::<IpAddr>().ok(),
+            ["search", ref domains @ ..] => search = domains.iter().map(|domain| format!("{}.{}", name, domain)).collect(),
+            _ => (),
+        }
+    }
+    let server = server.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "/etc/resolv.conf names no nameserver"))?;
+    for name in std::iter::once(name.to_string()).chain(search) {
+        let records = query(server, &name, timeout).await?;
+        if !records.is_empty() {
+            return Ok(records)
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, format!("no SRV records for {}", name)))
+}
+
+/// Asks `server` for the SRV records of `name`.
+async fn query(server: IpAddr, name: &str, timeout: Duration) -> io::Result<Vec<(String, u16)>> {
+    let socket = UdpSocket::bind(match server {
+        IpAddr::V4(_) => "0.0.0.0:0",
+        IpAddr::V6(_) => "[::]:0",
+    }).await?;
+    socket.connect((server, 53)).await?;
+    let id = (std::process::id() as u16).to_be_bytes();
+    // recursion desired, one question
+    let mut message = [&id[..], &[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]].concat();
+    for label in name.trim_end_matches('.').split('.') {
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.extend_from_slice(&[0, (SRV >> 8) as u8, SRV as u8, 0, 1]);
+    socket.send(&message).await?;
+    let mut response = [0; 4096];
+    loop {
+        let received = tokio::time::timeout(timeout, socket.recv(&mut response)).await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("{} did not answer in time", server)))??;
+        if response[..received].starts_with(&id) {
+            return records(&response[..received])
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("malformed answer from {}", server)))?
+        }
+    }
+}
+
+/// The targets and ports of the SRV records in a DNS response, none if the
+/// name does not exist.
+fn records(response: &[u8]) -> Option<io::Result<Vec<(String, u16)>>> {
+    let u16_at = |at: usize| Some(u16::from_be_bytes([*response.get(at)?, *response.get(at + 1)?]));
+    match response.get(3)? & 0x0f {
+        0 => (),
+        3 => return Some(Ok(vec![])),
+        code => return Some(Err(io::Error::other(format!("DNS error {}", code)))),
+    }
+    if response[2] & 0x02 != 0 {
+        return Some(Err(io::Error::other("the answer did not fit a datagram")))
+    }
+    let (questions, answers) = (u16_at(4)?, u16_at(6)?);
+    let mut at = 12;
+    for _ in 0..questions {
+        at = domain_name(response, at)?.1 + 4;
+    }
+    let mut records = vec![];
+    for _ in 0..answers {
+        at = domain_name(response, at)?.1;
+        let (kind, length) = (u16_at(at)?, u16_at(at + 8)? as usize);
+        at += 10;
+        if kind == SRV {
+            records.push((domain_name(response, at + 6)?.0, u16_at(at + 4)?));
+        }
+        at += length;
+    }
+    Some(Ok(records))
+}
+
+/// The possibly compressed domain name at `at` in a DNS message, and where
+/// the message goes on after it.
+fn domain_name(message: &[u8], mut at: usize) -> Option<(String, usize)> {
+    let (mut labels, mut end) = (vec![], None);
+    // a name has fewer labels and pointers than the message has bytes, unless
+    // its pointers go round in circles
+    for _ in 0..message.len() {
+        match *message.get(at)? as usize {
+            0 => return Some((labels.join("."), end.unwrap_or(at + 1))),
+            length if length & 0xc0 == 0xc0 => {
+                end.get_or_insert(at + 2);
+                at = (length & 0x3f) << 8 | *message.get(at + 1)? as usize;
+            },
+            length => {
+                labels.push(String::from_utf8_lossy(message.get(at + 1..at + 1 + length)?).into_owned());
+                at += 1 + length;
+            },
+        }
+    }
+    None
+}
@@ -0,0 +1,58 @@
+use std::sync::Mutex;
+
+use tokio::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// When the lease runs out, if the node holds it.
+    expires: Option<Instant>,
+    /// When the oldest digest that has not made it around the ring yet was
+    /// sent.
+    renewing: Option<Instant>,
+}
+
+/// The leader's lease on its leadership, without which it does not consider
+/// itself the leader. The leader takes the lease once elected and renews it
+/// whenever a digest it sent comes back around the ring, counting from when
+/// the digest was sent. The followers hear of the leader no earlier than
+/// that, so the lease runs out on the leader before they give up on it.
+#[derive(Debug)]
+pub struct Lease {
+    pub duration: Duration,
+    inner: Mutex<Inner>,
+}
+
+impl Lease {
+    pub fn new(duration: Duration) -> Self {
+        Lease { duration, inner: Mutex::default() }
+    }
+
+    /// Takes the lease as of `now`.
+    pub fn grant(&self, now: Instant) {
+        *self.inner.lock().unwrap() = Inner { expires: Some(now + self.duration), renewing: None };
+    }
+
+    /// Notes that a digest was sent at `now`.
+    pub fn renewing(&self, now: Instant) {
+        self.inner.lock().unwrap().renewing.get_or_insert(now);
+    }
+
+    /// Renews the lease after a digest came back, and returns when it runs
+    /// out now, if the node still holds it.
+    pub fn renewed(&self) -> Option<Instant> {
+        let mut inner = self.inner.lock().unwrap();
+        let sent = inner.renewing.take()?;
+        let expires = inner.expires.as_mut()?;
+        *expires = (*expires).max(sent + self.duration);
+        Some(*expires)
+    }
+
+    pub fn revoke(&self) {
+        *self.inner.lock().unwrap() = Inner::default();
+    }
+
+    /// When the lease runs out, if the node holds it.
+    pub fn expires(&self) -> Option<Instant> {
+        self.inner.lock().unwrap().expires
+    }
+}
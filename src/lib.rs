@@ -0,0 +1,3317 @@
+#![recursion_limit = "1024"]
+//! Leader election on a ring of gRPC nodes. Each node is a [`Node`] served
+//! by [`run_node`], or set up and started by a [`builder::NodeBuilder`];
+//! the `grpc-le` binary runs a whole ring in one process.
+//! Fully connected clusters can instead elect their leader with the bully
+//! algorithm of [`bully::BullyNode`], and rings with the simpler algorithm of
+//! [`chang_roberts::ChangRobertsNode`] or the O(n log n) messages of
+//! [`hirschberg_sinclair::HirschbergSinclairNode`]. [`simulation`] runs a ring of nodes
+//! in memory on virtual time, for tests, and a [`mock::Script`] makes a
+//! node misbehave towards its neighbours. Nodes relay their messages to
+//! each other over gRPC unless given another [`transport::Transport`].
+//! Applications can serve a node from their own gRPC server instead, through
+//! an [`embed::LeaderElectionLayer`], and have it take part with [`take_part`].
+//! Any node passes the application's requests on to the leader, which hands
+//! them to its [`forward::LeaderHandler`]. Hooks like [`Node::on_elected`]
+//! run the application's code as the node wins or loses, and hand the
+//! followers' code whatever [`LeaderPayload`] the winner attached. The
+//! leader's [`Node::fencing_token`] lets the resources it writes to turn
+//! away the writes of leaders deposed since.
+//! A [`groups::MultiGroupNode`] takes part in the elections of several
+//! groups around the same ring, each with a leader of its own, e.g. one for
+//! each of the roles the ring's nodes share out. The
+//! transitions between the states of a [`Node`] are the pure functions of
+//! [`state_machine`], and what a node does about polls and probes is decided
+//! there by [`state_machine::react`], free of tokio, so that any runtime or
+//! simulator can drive it. [`selftest::run`] checks that a ring of local
+//! nodes converges on a leader, as a smoke test of the environment. With
+//! the `testkit` feature, the `testkit::TestRing` lets the tests of an
+//! application crash and restart the nodes of a local ring and await its
+//! leader.
+use std::collections::BTreeMap;
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use tokio::sync::{mpsc, mpsc::error::TrySendError, oneshot, watch, Mutex, MutexGuard, Notify};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio::time::{Duration, Instant};
+use tonic::{transport::{Channel, Endpoint}, Request, Response, Status};
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+use tower::ServiceBuilder;
+use tracing::{debug, error, info, warn, Instrument};
+use tower::layer::util::{Identity, Stack};
+use futures::{Stream, StreamExt};
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use leader_election_service::admin_service_server::AdminServiceServer;
+use leader_election_service::error_detail::Reason;
+use leader_election_service::leader_election_service_server::{LeaderElectionService, LeaderElectionServiceServer};
+use leader_election_service::leader_election_service_client::LeaderElectionServiceClient;
+use leader_election_service::{Decision, DigestResponse, NotifyResponse, ProbeResponse};
+use leader_election_service::peer_message;
+use leader_election_service::{HeartbeatRequest, HeartbeatResponse, Neighbor, PreVoteRequest, PreVoteResponse, ReconfigureRequest, ReconfigureResponse};
+use leader_election_service::{MergeRequest, MergeResponse, TakeOverRequest, TakeOverResponse};
+use leader_election_service::{ForwardRequest, ForwardResponse, IntroductionRequest, IntroductionResponse, JoinRequest, JoinResponse, LeaveRequest, LeaveResponse, ReelectRequest, ReelectResponse, ResizeRequest, ResizeResponse};
+use leader_election_service::{anomaly::Kind as AnomalyKind, AnomaliesRequest, AnomaliesResponse, LeaderRequest, LeaderResponse, RankingRequest, RankingResponse};
+use leader_election_service::{state_response, ArmedTimer, MetricsRequest, MetricsResponse, StateRequest, StateResponse, StatsRequest, StatsResponse};
+use leader_election_service::{ElectionTimeout, NeighborTraffic};
+
+pub mod leader_election_service {
+    tonic::include_proto!("me.viluon.le");
+
+    /// The compiled `FileDescriptorSet` of le.proto and its imports.
+    pub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/le_descriptor.bin"));
+
+    /// The source of le.proto, for generating clients in other languages.
+    pub const PROTO: &str = include_str!("../proto/le.proto");
+}
+
+/// The messages the nodes relay around the ring. With the `serde` feature
+/// they, and every other message of le.proto, implement `Serialize` and
+/// `Deserialize`.
+pub use leader_election_service::{DigestMessage, NotifyMessage, Payload, PeerAck, PeerMessage, ProbeMessage, Sequence, TraceContext};
+
+mod admin;
+mod anomalies;
+mod audit;
+pub mod auth;
+mod base64;
+pub mod builder;
+pub mod bully;
+pub mod chang_roberts;
+pub mod clock;
+pub mod completions;
+mod compression;
+pub mod config;
+#[cfg(feature = "dashboard")]
+mod dashboard;
+pub mod embed;
+mod error;
+pub mod events;
+pub mod forward;
+pub mod groups;
+mod health;
+pub mod hirschberg_sinclair;
+mod history;
+mod hooks;
+mod invariants;
+pub mod json;
+#[cfg(feature = "k8s")]
+pub mod k8s;
+mod lease;
+mod metrics;
+pub mod mock;
+mod outbound;
+pub mod outbox;
+mod overload;
+pub mod rate_limit;
+mod repair;
+pub mod replay;
+#[cfg(feature = "registry")]
+mod registry;
+mod request_log;
+pub mod retry;
+pub mod selftest;
+pub mod sequence;
+mod state_file;
+pub mod simulation;
+pub mod spiffe;
+pub mod state_machine;
+mod stats;
+mod tenure;
+#[cfg(feature = "testkit")]
+pub mod testkit;
+mod timers;
+mod tls;
+pub mod topology;
+pub mod traces;
+pub mod transport;
+mod validate;
+#[cfg(feature = "web")]
+mod web;
+mod x509;
+
+use anomalies::Anomalies;
+use audit::{AuditFile, AuditLog, AuditSink, Cause};
+use auth::{Auth, AuthLayer, Signed, SigningLayer};
+use clock::{Clock, TokioClock};
+use compression::compressed;
+use config::{AuditTarget, Compression, Config, Grant, LogFilter, Output, Reload, Role, TimingConfig};
+use error::ElectionError;
+use events::EventRecorder;
+use forward::LeaderHandler;
+use hooks::{Change, Hooks};
+use history::History;
+use invariants::invariant;
+use lease::Lease;
+use metrics::{DecisionCounts, Metered, MetricsLayer, ProbeCounts, RpcMetrics, Side};
+use outbound::{Envelope, Message, NeighborQueue, Peer};
+use outbox::Outbox;
+use overload::OverloadLayer;
+use rate_limit::{ConnectionLimit, ProbeLimit, RateLimitLayer, RestartLimit};
+use repair::{Membership, Partition};
+use request_log::{RequestLog, RequestLogLayer};
+use retry::{retry, Backoff, RetryPolicy};
+use sequence::Receipts;
+use state_file::StateFile;
+use state_machine::{standing, Command, Event, Input, Me, Refused};
+use stats::{Stat, Stats};
+use tenure::Tenure;
+use tonic::metadata::AsciiMetadataValue;
+use timers::{TimerKind, Timers};
+use tls::Tls;
+use topology::{Link, NodeSpec};
+use traces::{otlp, Span, Tracer};
+use transport::{fraction, GrpcTransport, Transport};
+
+type Client = LeaderElectionServiceClient<RequestLog<Metered<Signed<Channel>>>>;
+
+const DELAY_MODIFIER: u64 = 100;
+/// How many responses may queue up for a peer that does not read them.
+const RESPONSE_BUFFER: usize = 16;
+/// How many messages may wait to be written to a neighbour's relay stream.
+const RELAY_BUFFER: usize = 16;
+/// The version of the relay protocol the node speaks, bumped whenever a new
+/// kind of message joins the relay stream.
+const RELAY_VERSION: u32 = 1;
+/// The oldest version of the relay protocol the node still understands,
+/// raised whenever the meaning of a field changes.
+const OLDEST_RELAY_VERSION: u32 = 1;
+const DIGEST_INTERVAL: u64 = 20 * DELAY_MODIFIER;
+/// The longest a node waits between attempts to reach a neighbour.
+const MAX_RETRY_DELAY: Duration = Duration::from_millis(16 * DELAY_MODIFIER);
+/// How far past its own term a node follows a message or request. Every
+/// restart moves the ring on by one term, so a longer leap comes from a
+/// broken or hostile peer, and following it would use up the terms there
+/// are.
+const MAX_TERM_LEAP: u64 = 1 << 10;
+/// How many other nodes a node keeps the priorities, zones and message
+/// numbering of. Far more than any ring has, so only a peer making up sender
+/// IDs runs into it.
+const MAX_PEERS: usize = 1 << 12;
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    id: u64,
+    /// The election group the node takes part in, of those the ring holds
+    /// independent elections for.
+    group: u64,
+    /// The role the group elects a leader for, if it elects one for a role.
+    role: Arc<str>,
+    left: Arc<NeighborQueue>,
+    right: Arc<NeighborQueue>,
+    /// How many nodes the ring has, as of the last change to it.
+    ring_size: Arc<AtomicU64>,
+    /// How many of the best nodes make up the elected committee.
+    committee_size: usize,
+    /// Every node of the ring as last ranked by the leader, best first.
+    ranking: Arc<std::sync::Mutex<Vec<u64>>>,
+    /// How much the node is preferred as the leader, before its ID.
+    priority: u64,
+    /// The priorities of the other nodes, as last seen in their probes and
+    /// the notifications ranking them.
+    priorities: Arc<std::sync::Mutex<BTreeMap<u64, u64>>>,
+    /// The failure domain of the node, empty without one.
+    zone: String,
+    /// The zone the ring prefers its leaders in, if any.
+    preferred_zone: Option<String>,
+    /// The zones of the other nodes, as last seen in their probes.
+    zones: Arc<std::sync::Mutex<BTreeMap<u64, String>>>,
+    /// Tells this run of the node apart from earlier ones with the same ID.
+    incarnation: u64,
+    /// Seeds the random parts of the node's delays and its trace IDs.
+    seed: u64,
+    /// The generator jittering the node's poll intervals.
+    rng: Arc<AtomicU64>,
+    clock: Arc<dyn Clock>,
+    /// How the node prints its message log, if it does.
+    message_log: Option<Output>,
+    /// How the node retries calls to its neighbours that failed.
+    retry: RetryPolicy,
+    /// How long the node waits for others and between its own steps, as last
+    /// reloaded.
+    timing: Arc<std::sync::Mutex<TimingConfig>>,
+    timers: Arc<Timers>,
+    /// Probes dropped for carrying a phase this ring can never reach.
+    implausible_probes: Arc<AtomicU64>,
+    probes: Arc<ProbeCounts>,
+    /// What the neighbours made of the messages this node sent them.
+    decisions: Arc<DecisionCounts>,
+    /// Responses that had to wait for a peer to drain its response stream.
+    slow_peer_responses: Arc<AtomicU64>,
+    rpc_metrics: Arc<RpcMetrics>,
+    receipts: Arc<Receipts>,
+    anomalies: Arc<Anomalies>,
+    tenure: Arc<Tenure>,
+    stats: Arc<Stats>,
+    tracer: Arc<Tracer>,
+    /// The span of the phase this node is probing, if traced.
+    phase_span: Arc<std::sync::Mutex<Span>>,
+    /// Where to reach the last leader the node learned of, and its metadata.
+    leader_info: Arc<std::sync::Mutex<Option<LeaderInfo>>>,
+    /// Woken whenever the node learns where to reach a leader, or its
+    /// metadata.
+    leader_learned: Arc<Notify>,
+    /// The URL the node tells the ring to reach it by as it joins and once
+    /// it leads, if not the one its neighbours know it by.
+    advertised: Option<String>,
+    /// What the node tells the ring about itself once it leads.
+    metadata: Arc<[u8]>,
+    /// What the node attaches to each of its wins, if anything.
+    payload: Option<Arc<dyn LeaderPayload>>,
+    /// When the node last heard that the leader it follows was alive.
+    leader_seen: Arc<std::sync::Mutex<Option<Instant>>>,
+    /// The epoch of the last reconfiguration applied.
+    topology_epoch: Arc<AtomicU64>,
+    /// The epoch of the last ring change the node restarted the election for.
+    reelection_epoch: Arc<AtomicU64>,
+    /// The election the node takes part in, counting restarts. Only changes
+    /// with the state locked.
+    term: Arc<AtomicU64>,
+    /// The term the node sits out after stepping down, or zero.
+    abstaining: Arc<AtomicU64>,
+    /// How long the node sits out the elections after stepping down, if
+    /// longer than the next one.
+    step_down_cooldown: Option<Duration>,
+    /// Until when the node sits out the elections after it last stepped
+    /// down, if it has a cooldown.
+    cooling_down: Arc<std::sync::Mutex<Option<Instant>>>,
+    /// Whether the node sits out every election, only following them.
+    observer: bool,
+    /// The leader's lease on its leadership, if leadership is leased.
+    lease: Option<Arc<Lease>>,
+    /// How long the node's elections may take, if they have a deadline.
+    election_deadline: Option<Duration>,
+    /// What the node knew as its last election timed out.
+    timed_out: Arc<std::sync::Mutex<Option<ElectionTimeout>>>,
+    /// The node's Lamport clock, ticking with every message the node sends
+    /// and moving past the timestamp of every one it receives.
+    lamport: Arc<AtomicU64>,
+    /// Messages dropped for belonging to an older term.
+    stale_messages: Arc<AtomicU64>,
+    /// Source of correlation IDs for the requests this node originates.
+    request_ids: Arc<AtomicU64>,
+    /// The outcome of the election, for subscribers.
+    results: Arc<watch::Sender<ElectionResult>>,
+    /// The term and leader the whole ring last acknowledged, as far as the
+    /// node knows.
+    acknowledged: Arc<watch::Sender<Option<(u64, u64)>>>,
+    /// Set once the node is asked to shut down.
+    stopping: Arc<watch::Sender<bool>>,
+    /// Set while the node is paused, holding its client loop and the
+    /// messages it receives until resumed.
+    paused: Arc<watch::Sender<bool>>,
+    /// How the node secures its connections, if it does, replaced whenever
+    /// its certificates are reloaded.
+    tls: Arc<watch::Sender<Option<Arc<Tls>>>>,
+    /// How the node compresses the messages it sends, if it does.
+    compression: Option<Compression>,
+    /// How the node authenticates the messages of its neighbours, if it does.
+    auth: Option<Arc<Auth>>,
+    /// The SPIFFE IDs of the peers allowed to call the node, if it checks.
+    spiffe_ids: Arc<[String]>,
+    /// What each peer may call the node for, if it checks.
+    acl: Arc<[Grant]>,
+    /// Messages rejected for not being signed with the secret of the ring.
+    unauthenticated_messages: Arc<AtomicU64>,
+    /// How often each sender's probes reached the node this term.
+    probe_limit: Arc<ProbeLimit>,
+    /// Throttles the restarts of the election callers ask for.
+    restart_limit: Arc<RestartLimit>,
+    /// Relay streams closed for a neighbour not acknowledging messages.
+    stuck_streams: Arc<AtomicU64>,
+    /// Calls and messages refused for coming too many or too fast.
+    rate_limited: Arc<AtomicU64>,
+    /// How many calls and messages the node takes from each connection.
+    limits: RateLimitLayer,
+    /// How the node relays its messages to its neighbours.
+    transport: Arc<dyn Transport>,
+    /// What the node does with the requests forwarded to it as the leader,
+    /// if it takes any.
+    handler: Option<Arc<dyn LeaderHandler>>,
+    /// Where the node changes which diagnostics the process writes when its
+    /// settings are reloaded, if it can.
+    log_filter: Option<Arc<dyn LogFilter>>,
+    /// The application's code to run as the outcome of the election changes.
+    hooks: Arc<Hooks>,
+    state: Arc<Mutex<NodeState>>,
+    /// Woken whenever the state changes, for the probes that wait for the
+    /// node to probe its own phase first.
+    state_changed: Arc<Notify>,
+    /// Where the node keeps its state across restarts, if it does.
+    state_file: Option<Arc<StateFile>>,
+    /// Where the node records its events, if it does.
+    events: Option<Arc<EventRecorder>>,
+    /// Every change of the node's state, and its cause.
+    audit: Arc<AuditLog>,
+    /// The elections the node saw complete lately.
+    history: Arc<History>,
+    /// The ring beyond the node's neighbours, as far as it knows.
+    membership: Arc<std::sync::Mutex<Membership>>,
+    /// Failed right neighbours the node spliced out of the ring.
+    repairs: Arc<AtomicU64>,
+    /// How many nodes a segment of a split ring needs to elect a leader, if
+    /// the node closes the segment it is in should the ring split.
+    quorum: Option<u64>,
+    /// The node whose segment may elect a leader without a quorum.
+    witness: Option<u64>,
+    /// How the ring stands if it split, as far as the segment the node is
+    /// in knows.
+    partition: Arc<std::sync::Mutex<Option<Partition>>>,
+}
+
+/// Where to reach a leader, and what it told the ring about itself; each
+/// empty until the node learns it.
+#[derive(Debug, Clone, Default)]
+struct LeaderInfo {
+    leader: u64,
+    addr: String,
+    metadata: Vec<u8>,
+    payload: Option<Payload>,
+}
+
+/// What a node attaches to the notification announcing its win, for the
+/// [`Node::on_leader_changed`] hooks of the followers, e.g. an epoch number
+/// or the endpoints of the leader's services.
+pub trait LeaderPayload: std::fmt::Debug + Send + Sync {
+    /// The payload of the node's win of `term`.
+    fn payload(&self, term: u64) -> Payload;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeState {
+    Candidate { phase: u64, last_phase_probed: u64 },
+    Defeated { leader: Option<u64> },
+    Leader,
+}
+
+/// The outcome of the election as far as one node knows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElectionResult {
+    /// No leader is known yet.
+    Undecided,
+    /// The node itself leads the ring.
+    Leader,
+    /// Another node leads the ring.
+    Defeated { leader: u64 },
+    /// The election found no leader within its deadline and the node gave
+    /// up on it, see [`Node::election_timeout`].
+    TimedOut,
+}
+
+impl Default for NodeState {
+    fn default() -> Self {
+        NodeState::Candidate { phase: 1, last_phase_probed: 0 }
+    }
+}
+
+/// Whether the node can relay messages to and from a peer speaking relay
+/// protocol `version` and understanding it back to `oldest`. Nodes from
+/// before versioning say neither and speak version 1.
+fn compatible(version: u32, oldest: u32) -> bool {
+    version.max(1) >= OLDEST_RELAY_VERSION && oldest <= RELAY_VERSION
+}
+
+/// Resolves once `flag` is set.
+async fn until_set(flag: &watch::Sender<bool>) {
+    let mut flag = flag.subscribe();
+    while !*flag.borrow_and_update() {
+        if flag.changed().await.is_err() {
+            return
+        }
+    }
+}
+
+/// Records `value` for node `id` in `peers`, unless `peers` already holds
+/// [`MAX_PEERS`] other nodes.
+fn remember<V>(peers: &std::sync::Mutex<BTreeMap<u64, V>>, id: u64, value: V) {
+    let mut peers = peers.lock().unwrap();
+    if peers.len() < MAX_PEERS || peers.contains_key(&id) {
+        peers.insert(id, value);
+    }
+}
+
+/// Tells the subscribers of `results` about `result`, unless it is what they
+/// already know.
+fn publish(results: &watch::Sender<ElectionResult>, result: ElectionResult) {
+    if *results.borrow() != result {
+        results.send_replace(result);
+    }
+}
+
+/// A call of `message` to another node, failing unless answered within
+/// `deadline`.
+fn deadline<T>(message: T, deadline: Duration) -> Request<T> {
+    let mut request = Request::new(message);
+    request.set_timeout(deadline);
+    request
+}
+
+/// A node taking part in one of the election algorithms.
+#[tonic::async_trait]
+pub trait ElectionAlgorithm: Clone + Send + Sync + 'static {
+    fn id(&self) -> u64;
+
+    /// Watches the outcome of the election as far as the node knows.
+    fn subscribe(&self) -> watch::Receiver<ElectionResult>;
+
+    /// Makes [`ElectionAlgorithm::run`] return.
+    fn shutdown(&self);
+
+    /// Serves the node on `addr` and takes part in elections until the
+    /// server fails or the node is shut down.
+    async fn run(self, addr: SocketAddr, config: &Config) -> Result<(), tonic::transport::Error>;
+
+    /// Runs `task` once the node leads, for as long as it does. See
+    /// [`run_when_leader`].
+    fn run_when_leader<F: Future>(&self, task: F) -> impl Future<Output = Option<F::Output>> {
+        run_when_leader(self.subscribe(), task)
+    }
+}
+
+/// Runs `task` once `results` say the node leads, for as long as they do:
+/// the task is cancelled as soon as the node stops leading, whether it lost
+/// its lease, stepped down or learned of a better leader. Returns what the
+/// task returned, or `None` if it was cancelled or the node went away.
+/// Meant for work only one node of the ring may do at a time.
+pub async fn run_when_leader<F: Future>(mut results: watch::Receiver<ElectionResult>, task: F) -> Option<F::Output> {
+    while *results.borrow_and_update() != ElectionResult::Leader {
+        results.changed().await.ok()?;
+    }
+    tokio::pin!(task);
+    loop {
+        tokio::select! {
+            // stop before the task gets any further
+            biased;
+            changed = results.changed() => {
+                changed.ok()?;
+                if *results.borrow() != ElectionResult::Leader {
+                    return None
+                }
+            },
+            output = &mut task => return Some(output),
+        }
+    }
+}
+
+/// The sending half of a relay stream to a neighbour.
+struct Relay {
+    messages: mpsc::Sender<PeerMessage>,
+    /// Resolves once the neighbour stops acknowledging messages, i.e. when
+    /// the stream broke.
+    broken: oneshot::Receiver<()>,
+}
+
+/// Seeds the random parts of the delays of node `id`: the seed of the run,
+/// if there is one, or one drawn from the operating system, as `incarnation`
+/// hashes, told apart by the node's ID. Logs it, so that the run can be
+/// repeated with `--seed`.
+fn seed_of(id: u64, config: &Config, incarnation: u64) -> u64 {
+    let seed = config.seed.unwrap_or_else(|| RandomState::new().hash_one(incarnation)) ^ id;
+    info!(node = id, "seeded with {}, as by --seed {}", seed, seed ^ id);
+    seed
+}
+
+/// Picks the node that wins the comparison a probe would make between `a` and
+/// `b` when neither has a priority, the way the algorithms without
+/// priorities rank nodes.
+fn preferred_leader(a: u64, b: u64) -> u64 {
+    a.min(b)
+}
+
+/// Prints a line of the message log to stdout, if the node keeps one: node
+/// `id` sending `value` to `target` at `time`, or receiving it if `target` is
+/// `id` itself.
+fn print_message(message_log: Option<Output>, id: u64, time: DateTime<Utc>, value: u64, target: u64) {
+    match message_log {
+        None => {},
+        Some(Output::Text) => println!("<{}, {}, {}, {}>", id, time.format("%T"), value, target),
+        Some(Output::Json) => println!(r#"{{"node": {}, "time": "{}", "value": {}, "target": {}}}"#,
+            id, time.to_rfc3339_opts(SecondsFormat::Nanos, true), value, target),
+    }
+}
+
+/// The kind of state `state` is, as `GetState` reports it, and the phase
+/// of a candidate.
+fn kind_of(state: &NodeState) -> (state_response::Kind, u64) {
+    match *state {
+        NodeState::Candidate { phase, .. } => (state_response::Kind::Candidate, phase),
+        NodeState::Defeated { .. } => (state_response::Kind::Defeated, 0),
+        NodeState::Leader => (state_response::Kind::Leader, 0),
+    }
+}
+
+fn reachability(reachable: Option<bool>) -> state_response::Reachability {
+    match reachable {
+        None => state_response::Reachability::Untried,
+        Some(true) => state_response::Reachability::Reachable,
+        Some(false) => state_response::Reachability::Unreachable,
+    }
+}
+
+impl Node {
+    /// Sets up a node step by step, see [`builder::NodeBuilder`].
+    pub fn builder() -> builder::NodeBuilder {
+        builder::NodeBuilder::default()
+    }
+
+    /// Creates the node `spec` describes, one of a ring of `ring_size` nodes.
+    /// Its finished trace spans go to `finished_spans`, if anywhere.
+    pub fn new(spec: &NodeSpec, ring_size: u64, config: &Config, finished_spans: Option<mpsc::UnboundedSender<otlp::Span>>)
+    -> std::io::Result<Self> {
+        Node::in_group(0, spec, ring_size, config, finished_spans)
+    }
+
+    /// Creates the node `spec` describes like [`Node::new`], keeping time by
+    /// `clock` rather than tokio's, e.g. by a [`clock::MockClock`] a test
+    /// moves on by hand.
+    pub fn with_clock(clock: Arc<dyn Clock>, spec: &NodeSpec, ring_size: u64, config: &Config, finished_spans: Option<mpsc::UnboundedSender<otlp::Span>>)
+    -> std::io::Result<Self> {
+        Node::create(0, "", spec, ring_size, config, finished_spans, clock)
+    }
+
+    /// Creates the node `spec` describes for the election of `group`, which
+    /// it keeps apart from the elections of any other groups around the same
+    /// ring, with files of its own.
+    pub fn in_group(group: u64, spec: &NodeSpec, ring_size: u64, config: &Config, finished_spans: Option<mpsc::UnboundedSender<otlp::Span>>)
+    -> std::io::Result<Self> {
+        Node::create(group, "", spec, ring_size, config, finished_spans, Arc::new(TokioClock::new()))
+    }
+
+    /// Creates the node `spec` describes for the election of a leader for
+    /// `role`, held in the group [`groups::role_group`] names after it, with
+    /// the role's priority if it has one.
+    pub fn in_role(role: &Role, spec: &NodeSpec, ring_size: u64, config: &Config, finished_spans: Option<mpsc::UnboundedSender<otlp::Span>>)
+    -> std::io::Result<Self> {
+        let spec = NodeSpec { priority: role.priority.unwrap_or(spec.priority), ..spec.clone() };
+        Node::create(groups::role_group(&role.name), &role.name, &spec, ring_size, config, finished_spans, Arc::new(TokioClock::new()))
+    }
+
+    fn create(group: u64, role: &str, spec: &NodeSpec, ring_size: u64, config: &Config, finished_spans: Option<mpsc::UnboundedSender<otlp::Span>>,
+        clock: Arc<dyn Clock>)
+    -> std::io::Result<Self> {
+        let node_id = spec.id;
+        // the default group keeps the names files had before there were groups
+        let stem = match (group, role) {
+            (0, _) => node_id.to_string(),
+            (group, "") => format!("{}.{}", node_id, group),
+            (_, role) => format!("{}.{}", node_id, role),
+        };
+        let tls = Tls::load(config)?.map(Arc::new);
+        let auth = Auth::load(config)?.map(Arc::new);
+        let neighbor = |neighbor: &Link| -> std::io::Result<_> {
+            let outbox = config.outbox_dir.as_ref()
+                .map(|dir| Outbox::open(dir.join(format!("{}-to-{}.outbox", stem, neighbor.id))))
+                .transpose()?;
+            let endpoint = tls::endpoint(neighbor.url.clone(), tls.as_deref(), &config.timing)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+            let peer = Peer { id: neighbor.id.into(), endpoint };
+            Ok(Arc::new(NeighborQueue::new(peer, config.queue_capacity, config.drop_policy, outbox)))
+        };
+        let incarnation = clock.wall_now().timestamp_nanos() as u64;
+        let seed = seed_of(node_id.into(), config, incarnation);
+        let state_file = config.state_dir.as_ref().map(|dir| StateFile::new(dir.join(format!("{}.state", stem))));
+        let (state, term) = state_file.as_ref().map(StateFile::load).transpose()?.unwrap_or_default();
+        let state = match state.unwrap_or_default() {
+            // an observer may have stood in elections before
+            state @ NodeState::Defeated { .. } => state,
+            _ if config.observer => NodeState::Defeated { leader: None },
+            state => state,
+        };
+        let events = match &config.events_dir {
+            Some(dir) => Some(EventRecorder::open(node_id.into(), dir.join(format!("{}.events.jsonl", stem)))?),
+            // the dashboard follows the events as they happen
+            None if config.dashboard_port_offset.is_some() => Some(EventRecorder::new(node_id.into())),
+            None => None,
+        };
+        let audit_sinks: Vec<Box<dyn AuditSink>> = match &config.audit_log {
+            Some(AuditTarget::Stderr) => vec![Box::new(audit::Stderr)],
+            Some(AuditTarget::Dir(dir)) => vec![Box::new(AuditFile::open(dir.join(format!("{}.audit.log", stem)))?)],
+            None => vec![],
+        };
+        let tenure = Tenure::new(node_id.into(), clock.now());
+        let result = match state {
+            NodeState::Leader => ElectionResult::Leader,
+            NodeState::Defeated { leader: Some(leader) } => ElectionResult::Defeated { leader },
+            _ => ElectionResult::Undecided,
+        };
+        if result != ElectionResult::Undecided {
+            info!(node = node_id, "restored state {:?}", state);
+            let leader = match result {
+                ElectionResult::Defeated { leader } => leader,
+                _ => node_id.into(),
+            };
+            tenure.observe(Some(leader), clock.now());
+        }
+        let (request_ids, rpc_metrics) = (Arc::<AtomicU64>::default(), Arc::<RpcMetrics>::default());
+        let rate_limited = Arc::<AtomicU64>::default();
+        Ok(Node {
+            id: node_id.into(),
+            group,
+            role: role.into(),
+            left: neighbor(spec.left())?,
+            right: neighbor(spec.right())?,
+            ring_size: Arc::new(AtomicU64::new(ring_size)),
+            committee_size: config.committee_size,
+            ranking: Arc::default(),
+            priority: spec.priority,
+            priorities: Arc::default(),
+            zone: spec.zone.clone().unwrap_or_default(),
+            preferred_zone: config.preferred_zone.clone(),
+            zones: Arc::default(),
+            incarnation,
+            seed,
+            rng: Arc::new(AtomicU64::new(seed)),
+            clock: clock.clone(),
+            message_log: config.message_log.then_some(config.output),
+            retry: config.retry,
+            timing: Arc::new(std::sync::Mutex::new(config.timing)),
+            timers: Arc::new(Timers::new(clock.clone())),
+            implausible_probes: Arc::default(),
+            probes: Arc::default(),
+            decisions: Arc::default(),
+            slow_peer_responses: Arc::default(),
+            rpc_metrics: rpc_metrics.clone(),
+            receipts: Arc::default(),
+            anomalies: Arc::default(),
+            tenure: Arc::new(tenure),
+            stats: Arc::new(Stats::new(clock.now())),
+            tracer: Arc::new(Tracer::new(node_id.into(), clock.clone(), seed,
+                config.trace_sample_ratio, finished_spans)),
+            phase_span: Arc::default(),
+            leader_info: Arc::default(),
+            leader_learned: Arc::default(),
+            advertised: config.advertise.clone(),
+            metadata: config.leader_metadata.as_slice().into(),
+            payload: None,
+            leader_seen: Arc::default(),
+            topology_epoch: Arc::default(),
+            reelection_epoch: Arc::default(),
+            term: Arc::new(AtomicU64::new(term)),
+            abstaining: Arc::default(),
+            step_down_cooldown: config.step_down_cooldown,
+            cooling_down: Arc::default(),
+            observer: config.observer,
+            lease: config.lease.map(|duration| Arc::new(Lease::new(duration))),
+            election_deadline: config.election_deadline,
+            timed_out: Arc::default(),
+            lamport: Arc::default(),
+            stale_messages: Arc::default(),
+            request_ids: request_ids.clone(),
+            results: Arc::new(watch::channel(result).0),
+            acknowledged: Arc::new(watch::channel(None).0),
+            stopping: Arc::new(watch::channel(false).0),
+            paused: Arc::new(watch::channel(false).0),
+            tls: Arc::new(watch::channel(tls).0),
+            compression: config.compression,
+            auth,
+            spiffe_ids: config.spiffe_ids.clone().into(),
+            acl: config.acl.clone().into(),
+            unauthenticated_messages: Arc::default(),
+            probe_limit: Arc::default(),
+            restart_limit: Arc::new(RestartLimit::new(config.restart_limit, config.restart_limit_per_caller, clock.now())),
+            stuck_streams: Arc::default(),
+            rate_limited: rate_limited.clone(),
+            limits: RateLimitLayer::new(node_id.into(), config.rate_limit, clock.clone(), rate_limited),
+            transport: Arc::new(GrpcTransport::new(node_id.into(), request_ids, rpc_metrics, config.compression)),
+            handler: None,
+            log_filter: None,
+            hooks: Arc::new(Hooks::new(node_id.into())),
+            state: Arc::new(Mutex::new(state)),
+            state_changed: Arc::default(),
+            state_file: state_file.map(Arc::new),
+            events: events.map(Arc::new),
+            audit: Arc::new(AuditLog::new(node_id.into(), audit_sinks)),
+            history: Arc::new(History::new(result == ElectionResult::Undecided)),
+            membership: Arc::default(),
+            repairs: Arc::default(),
+            quorum: config.quorum,
+            witness: config.witness,
+            partition: Arc::default(),
+        })
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn group(&self) -> u64 {
+        self.group
+    }
+
+    /// The role the node's group elects a leader for, if any.
+    pub fn role(&self) -> Option<&str> {
+        Some(&*self.role).filter(|role| !role.is_empty())
+    }
+
+    /// How the node relays its messages, e.g. for another transport to wrap.
+    pub fn transport(&self) -> Arc<dyn Transport> {
+        self.transport.clone()
+    }
+
+    /// Relays the node's messages over `transport` instead of gRPC.
+    pub fn with_transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Hands the application requests forwarded to the node while it leads
+    /// to `handler`. Without one the node refuses them.
+    pub fn with_handler(mut self, handler: Arc<dyn LeaderHandler>) -> Self {
+        self.handler = Some(handler);
+        self
+    }
+
+    /// Changes the level of the process's diagnostics through `filter` when
+    /// the node's settings are reloaded. Without one the node refuses to.
+    pub fn with_log_filter(mut self, filter: Arc<dyn LogFilter>) -> Self {
+        self.log_filter = Some(filter);
+        self
+    }
+
+    /// Tells the ring `metadata` about the node once it leads, instead of
+    /// the metadata it was configured with.
+    pub fn with_metadata(mut self, metadata: Vec<u8>) -> Self {
+        self.metadata = metadata.into();
+        self
+    }
+
+    /// Attaches what `payload` makes of each election the node wins to the
+    /// notification announcing it. Without one the notification carries no
+    /// payload.
+    pub fn with_payload(mut self, payload: Arc<dyn LeaderPayload>) -> Self {
+        self.payload = Some(payload);
+        self
+    }
+
+    /// Where the node stands in the election.
+    pub async fn state(&self) -> NodeState {
+        self.state.lock().await.clone()
+    }
+
+    /// Watches the outcome of the election, which changes when the node
+    /// becomes the leader, learns who is, or learns of a different leader.
+    pub fn subscribe(&self) -> watch::Receiver<ElectionResult> {
+        self.results.subscribe()
+    }
+
+    /// What the node knew as it gave up on an election that found no leader
+    /// in time, while the outcome is [`ElectionResult::TimedOut`].
+    pub fn election_timeout(&self) -> Option<ElectionTimeout> {
+        match *self.results.borrow() {
+            ElectionResult::TimedOut => self.timed_out.lock().unwrap().clone(),
+            _ => None,
+        }
+    }
+
+    /// Runs `hook` whenever the node becomes the leader, e.g. to start work
+    /// only the leader may do, and right away if it already leads.
+    ///
+    /// The hooks of a node run one at a time, in the order of the changes
+    /// they are for, on a task of their own started on the current tokio
+    /// runtime, so a hook that keeps working while the node leads holds up
+    /// the others; it had better spawn that work, or use
+    /// [`run_when_leader`]. A hook that panics is logged and does not stop
+    /// the other hooks, unless panics abort the process.
+    pub fn on_elected<F: Future<Output = ()> + Send + 'static>(&self, hook: impl Fn() -> F + Send + Sync + 'static) {
+        self.hooks.register(&self.results, Arc::new(move |change: Change| {
+            (change.to == ElectionResult::Leader && change.from != change.to).then(|| Box::pin(hook()) as _)
+        }));
+    }
+
+    /// Runs `hook` with the winner whenever the node learns that it lost an
+    /// election, e.g. to stop work only the leader may do, and right away if
+    /// it already knows. See [`Node::on_elected`].
+    pub fn on_defeated<F: Future<Output = ()> + Send + 'static>(&self, hook: impl Fn(u64) -> F + Send + Sync + 'static) {
+        self.hooks.register(&self.results, Arc::new(move |change: Change| match (change.from, change.to) {
+            (ElectionResult::Defeated { .. }, _) => None,
+            (_, ElectionResult::Defeated { leader }) => Some(Box::pin(hook(leader)) as _),
+            _ => None,
+        }));
+    }
+
+    /// Runs `hook` with the leader the node knows of, if any, whenever that
+    /// changes, this node included, and right away if it knows of one,
+    /// along with the payload the leader attached to its win, if any. See
+    /// [`Node::on_elected`] and [`Node::with_payload`].
+    pub fn on_leader_changed<F: Future<Output = ()> + Send + 'static>(&self, hook: impl Fn(Option<u64>, Option<Payload>) -> F + Send + Sync + 'static) {
+        let leader_info = self.leader_info.clone();
+        self.hooks.register(&self.results, Arc::new(move |change: Change| {
+            let (from, to) = change.leaders();
+            // the node learns of the payload before it publishes the leader
+            let payload = || leader_info.lock().unwrap().clone().filter(|info| Some(info.leader) == to).and_then(|info| info.payload);
+            (from != to).then(|| Box::pin(hook(to, payload())) as _)
+        }));
+    }
+
+    /// Waits until the whole ring acknowledged the leader of the current
+    /// election, and returns the leader. The leader knows once its
+    /// notification made it around the ring, the other nodes once the
+    /// leader's next digest tells them.
+    pub async fn await_ring_acknowledged(&self) -> u64 {
+        let mut acknowledged = self.acknowledged.subscribe();
+        let mut results = self.subscribe();
+        loop {
+            let leader = match *results.borrow_and_update() {
+                ElectionResult::Leader => Some(self.id),
+                ElectionResult::Defeated { leader } => Some(leader),
+                ElectionResult::Undecided | ElectionResult::TimedOut => None,
+            };
+            match *acknowledged.borrow_and_update() {
+                Some((term, acknowledged)) if term == self.term() && Some(acknowledged) == leader => return acknowledged,
+                _ => (),
+            }
+            // both senders live as long as the node
+            tokio::select! {
+                _ = acknowledged.changed() => (),
+                _ = results.changed() => (),
+            }
+        }
+    }
+
+    /// Starts a new election from the first phase, in the term after the
+    /// current one, forgetting the outcome of the last. For the election to
+    /// complete, every node of the ring has to start it, which the nodes
+    /// that learn of the new term from this one do.
+    /// Returns whether it did, which it does not once the node is in the
+    /// last term there is.
+    pub async fn start_election(&self) -> bool {
+        match self.next_term() {
+            Ok(term) => self.enter_term(term, Cause::Restart).await,
+            Err(e) => {
+                error!(node = self.id, "cannot restart the election: {}", e);
+                false
+            },
+        }
+    }
+
+    /// Gives up the leadership, if the node leads, and restarts the election
+    /// around the ring in the next term, which the node sits out so that
+    /// another node leads. Returns whether the node stepped down, which it
+    /// cannot unless it leads in a term before the last.
+    pub async fn step_down(&self) -> bool {
+        if *self.state.lock().await != NodeState::Leader {
+            return false
+        }
+        let term = match self.next_term() {
+            Ok(term) => term,
+            Err(e) => {
+                error!(node = self.id, "cannot step down: {}", e);
+                return false
+            },
+        };
+        info!(node = self.id, "stepping down, sitting out the election of term {}", term);
+        self.abstaining.store(term, AtomicOrdering::SeqCst);
+        self.cool_down();
+        self.reelect(self.reelection_epoch.load(AtomicOrdering::SeqCst) + 1, self.ring_size(), term);
+        true
+    }
+
+    /// Has the node sit out the elections that start within its cooldown
+    /// from now, if it has one.
+    fn cool_down(&self) {
+        if let Some(cooldown) = self.step_down_cooldown {
+            *self.cooling_down.lock().unwrap() = Some(self.clock.now() + cooldown);
+        }
+    }
+
+    /// Whether the node sits out the election of the term it is in, as an
+    /// observer does every election, as the node does after it stepped down
+    /// until its cooldown ends, and as all nodes of a segment of a split
+    /// ring do unless it is quorate.
+    fn abstains(&self) -> bool {
+        let abstaining = self.abstaining.load(AtomicOrdering::SeqCst);
+        let cooling_down = self.cooling_down.lock().unwrap().is_some_and(|until| self.clock.now() < until);
+        let minority = self.partition().is_some_and(|partition| !partition.quorate);
+        self.observer || cooling_down || minority || abstaining != 0 && abstaining == self.term()
+    }
+
+    /// The kind of state the node is in, and its phase if a candidate, to
+    /// report back to the neighbours on the messages they sent.
+    async fn current_kind(&self) -> (state_response::Kind, u64) {
+        kind_of(&*self.state.lock().await)
+    }
+
+    /// The election term the node is in.
+    pub fn term(&self) -> u64 {
+        self.term.load(AtomicOrdering::SeqCst)
+    }
+
+    /// The term after the one the node is in, to restart the election in.
+    fn next_term(&self) -> Result<u64, ElectionError> {
+        self.term().checked_add(1).ok_or(ElectionError::TermsExhausted { node: self.id })
+    }
+
+    /// Refuses `term` if it is more than `MAX_TERM_LEAP` past the node's.
+    fn check_term(&self, term: u64) -> Result<(), ElectionError> {
+        let current = self.term();
+        match term > current.saturating_add(MAX_TERM_LEAP) {
+            true => Err(ElectionError::InvalidMessage {
+                node: self.id, state: None, reason: format!("term {} is more than {} past term {}", term, MAX_TERM_LEAP, current),
+            }),
+            false => Ok(()),
+        }
+    }
+
+    /// Starts the election of `term` from the first phase, or sits it out if
+    /// the node stepped down for it, unless the node is in that term or past
+    /// it already. Returns whether it did.
+    async fn enter_term(&self, term: u64, cause: Cause) -> bool {
+        let mut state = self.state.lock().await;
+        if self.term.fetch_max(term, AtomicOrdering::SeqCst) >= term {
+            return false
+        }
+        info!(node = self.id, "starting the election of term {} (was {:?})", term, *state);
+        let entered = match self.abstains() {
+            true => NodeState::Defeated { leader: None },
+            false => NodeState::default(),
+        };
+        let from = std::mem::replace(&mut *state, entered);
+        self.changed_state(&from, &state, cause);
+        self.end_phase_span();
+        self.ranking.lock().unwrap().clear();
+        *self.leader_seen.lock().unwrap() = None;
+        self.observe_leader(None);
+        self.publish(ElectionResult::Undecided);
+        self.timers.cancel(TimerKind::Digest);
+        self.timers.set(TimerKind::Poll, self.poll_interval());
+        self.arm_election_deadline();
+        true
+    }
+
+    /// Gives the election that just started its deadline, if it has one.
+    fn arm_election_deadline(&self) {
+        if let Some(deadline) = self.election_deadline {
+            self.timers.set(TimerKind::ElectionDeadline, deadline);
+        }
+    }
+
+    /// Gives up on the election if it found no leader by its deadline: stops
+    /// probing and tells the subscribers, recording the node's phase in
+    /// `state` and when it last exchanged messages with each neighbour.
+    fn time_out(&self, state: &NodeState) {
+        let deadline = match self.election_deadline {
+            Some(deadline) if *self.results.borrow() == ElectionResult::Undecided => deadline,
+            _ => return,
+        };
+        let (_, phase) = kind_of(state);
+        let now = self.clock.now();
+        let ago = |at: Option<Instant>| at.map(|at| now.saturating_duration_since(at).as_millis() as u64);
+        let neighbors = [&self.left, &self.right].iter().map(|neighbor| {
+            let (sent, received) = neighbor.traffic();
+            let (sent, received) = (ago(sent), ago(received));
+            NeighborTraffic {
+                id: neighbor.peer().id,
+                sent_ms_ago: sent.unwrap_or_default(), sent_known: sent.is_some(),
+                received_ms_ago: received.unwrap_or_default(), received_known: received.is_some(),
+            }
+        }).collect::<Vec<_>>();
+        let describe = |ms: u64, known| match known {
+            true => format!("{} ms ago", ms),
+            false => "never".to_string(),
+        };
+        let traffic = neighbors.iter()
+            .map(|n| format!("node {} sent to {}, heard from {}", n.id, describe(n.sent_ms_ago, n.sent_known), describe(n.received_ms_ago, n.received_known)))
+            .collect::<Vec<_>>()
+            .join("; ");
+        warn!(node = self.id, "no leader within {:?} in term {}, giving up in phase {} ({})", deadline, self.term(), phase, traffic);
+        *self.timed_out.lock().unwrap() = Some(ElectionTimeout {
+            term: self.term(), phase, unix_ms: self.clock.wall_now().timestamp_millis() as u64, deadline_ms: deadline.as_millis() as u64, neighbors,
+        });
+        self.timers.cancel(TimerKind::Poll);
+        self.publish(ElectionResult::TimedOut);
+    }
+
+    /// Checks the term of an incoming message against the node's. Messages
+    /// of older terms are stale, while a newer term means that the node
+    /// missed the restart of the election, which it catches up on, unless
+    /// the term is too far ahead to be believed. Returns whether to handle
+    /// the message.
+    async fn admit_term(&self, term: u64, kind: &'static str, peer: Option<u64>) -> bool {
+        if let Err(e) = self.check_term(term) {
+            warn!(node = self.id, "dropping a {}: {}", kind, e);
+            return false
+        }
+        let current = self.term();
+        if term < current {
+            let stale = self.stale_messages.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+            debug!(node = self.id, "dropping a {} of term {} in term {} ({} so far)", kind, term, current, stale);
+            return false
+        }
+        if term > current && self.enter_term(term, Cause::NewerTerm { kind, peer }).await {
+            info!(node = self.id, "caught up with term {} on a {}", term, kind);
+        }
+        true
+    }
+
+    /// The node's metrics in the Prometheus text format.
+    async fn render_metrics(&self) -> String {
+        use std::fmt::Write;
+        let mut text = String::new();
+        let counters = [
+            ("grpc_le_implausible_probes_total", &*self.implausible_probes),
+            ("grpc_le_probes_sent_total", &self.probes.sent),
+            ("grpc_le_probes_received_total", &self.probes.received),
+            ("grpc_le_probes_forwarded_total", &self.probes.forwarded),
+            ("grpc_le_conflicting_leaders_total", &self.anomalies.conflicting_leaders),
+            ("grpc_le_diverged_digests_total", &self.anomalies.diverged_digests),
+            ("grpc_le_rival_leader_digests_total", &self.anomalies.rival_digests),
+            ("grpc_le_slow_peer_responses_total", &*self.slow_peer_responses),
+            ("grpc_le_duplicate_messages_total", &self.receipts.duplicates),
+            ("grpc_le_missing_messages_total", &self.receipts.gaps),
+            ("grpc_le_reordered_messages_total", &self.receipts.reordered),
+            ("grpc_le_stale_term_messages_total", &*self.stale_messages),
+            ("grpc_le_unauthenticated_messages_total", &*self.unauthenticated_messages),
+            ("grpc_le_rate_limited_total", &*self.rate_limited),
+            ("grpc_le_stuck_streams_total", &*self.stuck_streams),
+            ("grpc_le_ring_repairs_total", &*self.repairs),
+        ];
+        for (name, counter) in counters {
+            let _ = writeln!(text, "# TYPE {} counter", name);
+            let _ = writeln!(text, "{}{{node=\"{}\"}} {}", name, self.id, counter.load(AtomicOrdering::Relaxed));
+        }
+        let _ = writeln!(text, "# TYPE grpc_le_outbound_queue_length gauge");
+        for neighbor in [&self.left, &self.right] {
+            let _ = writeln!(text, "grpc_le_outbound_queue_length{{node=\"{}\",neighbor=\"{}\"}} {}", self.id, neighbor.peer().id, neighbor.len());
+        }
+        let _ = writeln!(text, "# TYPE grpc_le_outbound_dropped_total counter");
+        for neighbor in [&self.left, &self.right] {
+            let _ = writeln!(text, "grpc_le_outbound_dropped_total{{node=\"{}\",neighbor=\"{}\"}} {}", self.id, neighbor.peer().id, neighbor.dropped());
+        }
+        let _ = writeln!(text, "# TYPE grpc_le_neighbor_rtt_seconds gauge");
+        for neighbor in [&self.left, &self.right] {
+            if let Some(rtt) = neighbor.latency().smoothed {
+                let _ = writeln!(text, "grpc_le_neighbor_rtt_seconds{{node=\"{}\",neighbor=\"{}\"}} {}", self.id, neighbor.peer().id, rtt.as_secs_f64());
+            }
+        }
+        let _ = writeln!(text, "# TYPE grpc_le_neighbor_slow gauge");
+        for neighbor in [&self.left, &self.right] {
+            let _ = writeln!(text, "grpc_le_neighbor_slow{{node=\"{}\",neighbor=\"{}\"}} {}", self.id, neighbor.peer().id, neighbor.latency().slow as u8);
+        }
+        let _ = writeln!(text, "# TYPE grpc_le_neighbor_slow_total counter");
+        for neighbor in [&self.left, &self.right] {
+            let _ = writeln!(text, "grpc_le_neighbor_slow_total{{node=\"{}\",neighbor=\"{}\"}} {}", self.id, neighbor.peer().id, neighbor.latency().episodes);
+        }
+        self.tenure.render(self.clock.now(), &mut text);
+        self.anomalies.render(self.id, &mut text);
+        self.history.render(self.id, &mut text);
+        self.decisions.render(self.id, &mut text);
+        self.rpc_metrics.render(&mut text);
+        let (current, phase) = match *self.state.lock().await {
+            NodeState::Candidate { phase, .. } => ("candidate", phase),
+            NodeState::Defeated { .. } => ("defeated", 0),
+            NodeState::Leader => ("leader", 0),
+        };
+        let _ = writeln!(text, "# TYPE grpc_le_term gauge");
+        let _ = writeln!(text, "grpc_le_term{{node=\"{}\"}} {}", self.id, self.term());
+        let _ = writeln!(text, "# TYPE grpc_le_phase gauge");
+        let _ = writeln!(text, "grpc_le_phase{{node=\"{}\"}} {}", self.id, phase);
+        let _ = writeln!(text, "# TYPE grpc_le_state gauge");
+        for state in ["candidate", "defeated", "leader"] {
+            let _ = writeln!(text, "grpc_le_state{{node=\"{}\",state=\"{}\"}} {}", self.id, state, (state == current) as u8);
+        }
+        text
+    }
+
+    /// Restarts the election in `term` for the ring change of `epoch`, which
+    /// left the ring with `ring_size` nodes, and passes the restart on to the
+    /// right neighbour. Does nothing if the node restarted for `epoch`
+    /// already, which is how the restart stops once it went around the ring.
+    fn reelect(&self, epoch: u64, ring_size: u64, term: u64) {
+        if self.reelection_epoch.fetch_max(epoch, AtomicOrdering::SeqCst) >= epoch {
+            return
+        }
+        self.ring_size.store(ring_size, AtomicOrdering::SeqCst);
+        let this = self.clone();
+        tokio::spawn(async move {
+            // the election's messages may have got here first
+            this.enter_term(term, Cause::Restart).await;
+            let right = this.right.peer();
+            let pass = || async {
+                match this.connect(&right.endpoint).await {
+                    Ok(mut client) => client.reelect(this.deadline(this.reelect_request(epoch, ring_size, term))).await.map(drop).map_err(|e| e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                }
+            };
+            let retrying = |e: &String, delay| warn!(node = this.id, "cannot reach {}, retrying in {:?}: {}", right.endpoint.uri(), delay, e);
+            if let Err(e) = retry(this.backoff(), &*this.clock, pass, retrying).await {
+                warn!(node = this.id, "cannot pass the election for epoch {} on to node {}: {}", epoch, right.id, e);
+            }
+        });
+    }
+
+    /// Takes `ring_size` for the size of the ring after the repair of
+    /// `epoch`, which the leader survived, and passes it on to the right
+    /// neighbour, leaving the election as it is. Does nothing if the node
+    /// heard of `epoch` already, like [`Node::reelect`].
+    fn resize(&self, epoch: u64, ring_size: u64) {
+        if self.reelection_epoch.fetch_max(epoch, AtomicOrdering::SeqCst) >= epoch {
+            return
+        }
+        self.ring_size.store(ring_size, AtomicOrdering::SeqCst);
+        let this = self.clone();
+        tokio::spawn(async move {
+            let right = this.right.peer();
+            let pass = || async {
+                match this.connect(&right.endpoint).await {
+                    Ok(mut client) => client.resize(this.deadline(ResizeRequest { epoch, ring_size, group_id: this.group })).await.map(drop).map_err(|e| e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                }
+            };
+            let retrying = |e: &String, delay| warn!(node = this.id, "cannot reach {}, retrying in {:?}: {}", right.endpoint.uri(), delay, e);
+            if let Err(e) = retry(this.backoff(), &*this.clock, pass, retrying).await {
+                warn!(node = this.id, "cannot pass the size of the ring for epoch {} on to node {}: {}", epoch, right.id, e);
+            }
+        });
+    }
+
+    /// Follows the leader of `merge` from its term on, in the ring its
+    /// segments were merged back into, and passes the merge on to the right
+    /// neighbour. The node goes on leading if it is that leader, and steps
+    /// down if it led another segment. Does nothing if the node heard of the
+    /// merge's epoch already, like [`Node::reelect`].
+    fn merge(&self, merge: MergeRequest) {
+        let MergeRequest { epoch, ring_size, whole_size, quorate, .. } = merge;
+        if self.reelection_epoch.fetch_max(epoch, AtomicOrdering::SeqCst) >= epoch {
+            return
+        }
+        self.ring_size.store(ring_size, AtomicOrdering::SeqCst);
+        *self.partition.lock().unwrap() = (whole_size != 0).then_some(Partition { whole: whole_size, quorate });
+        self.healed();
+        let this = self.clone();
+        tokio::spawn(async move {
+            this.follow_merge(&merge).await;
+            let right = this.right.peer();
+            let pass = || async {
+                match this.connect(&right.endpoint).await {
+                    Ok(mut client) => client.merge(this.deadline(MergeRequest { group_id: this.group, ..merge.clone() })).await.map(drop).map_err(|e| e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                }
+            };
+            let retrying = |e: &String, delay| warn!(node = this.id, "cannot reach {}, retrying in {:?}: {}", right.endpoint.uri(), delay, e);
+            if let Err(e) = retry(this.backoff(), &*this.clock, pass, retrying).await {
+                warn!(node = this.id, "cannot pass the merge of epoch {} on to node {}: {}", epoch, right.id, e);
+            }
+        });
+    }
+
+    /// Takes the leader of `merge` for the node's own, unless the node
+    /// already moved on to the merge's term.
+    async fn follow_merge(&self, merge: &MergeRequest) {
+        let MergeRequest { epoch, leader_id: leader, term, .. } = *merge;
+        let mut state = self.state.lock().await;
+        if self.term.fetch_max(term, AtomicOrdering::SeqCst) >= term {
+            return
+        }
+        let merged = match leader == self.id {
+            true => NodeState::Leader,
+            false => NodeState::Defeated { leader: Some(leader) },
+        };
+        info!(node = self.id, "following leader {} of the merged ring from term {} (was {:?})", leader, term, *state);
+        let from = std::mem::replace(&mut *state, merged);
+        self.changed_state(&from, &state, Cause::Merge { leader, epoch });
+        self.ranking.lock().unwrap().clear();
+        self.saw_leader(self.clock.now());
+        self.observe_leader(Some(leader));
+        match leader == self.id {
+            true => {
+                self.publish(ElectionResult::Leader);
+                // the leader announces itself to the nodes of the other segment as it polls
+                self.timers.set(TimerKind::Poll, self.poll_interval());
+            },
+            false => {
+                self.learn_leader(LeaderInfo { leader, addr: merge.leader_addr.clone(), ..LeaderInfo::default() });
+                self.publish(ElectionResult::Defeated { leader });
+            },
+        }
+    }
+
+    /// Ticks the Lamport clock for a message about to be sent, and returns
+    /// the message's timestamp.
+    fn tick(&self) -> u64 {
+        self.witness(0)
+    }
+
+    /// Moves the Lamport clock past the `timestamp` of a message received,
+    /// and returns the time of its receipt. A timestamp at the end of the
+    /// clock's range, which only a broken or hostile node sends, stops it
+    /// there.
+    fn witness(&self, timestamp: u64) -> u64 {
+        let advance = |time: u64| Some(time.max(timestamp).saturating_add(1));
+        let previous = self.lamport.fetch_update(AtomicOrdering::SeqCst, AtomicOrdering::SeqCst, advance).unwrap();
+        previous.max(timestamp).saturating_add(1)
+    }
+
+    /// A call of `message` to another node, failing unless answered in time.
+    fn deadline<T>(&self, message: T) -> Request<T> {
+        deadline(message, self.timing().rpc_deadline)
+    }
+
+    fn timing(&self) -> TimingConfig {
+        *self.timing.lock().unwrap()
+    }
+
+    /// Takes on `reload` while the node runs: its timing from the next wait
+    /// on, the level of the process's diagnostics, any neighbours it names
+    /// and the node's certificates if their files changed. New neighbours
+    /// get the messages the old ones did not acknowledge, and the election
+    /// is restarted around the ring for them; returns whether it was, which
+    /// it is not for neighbours the node already had. Takes on none of it
+    /// if any of it cannot be.
+    pub fn reload(&self, reload: Reload) -> Result<bool, ElectionError> {
+        let Reload { timing, log_level, left, right } = reload;
+        let failed = |reason| ElectionError::ReloadFailed { node: self.id, reason };
+        let filter = match log_level {
+            Some(directives) => Some((self.log_filter.as_ref().ok_or_else(|| failed("the node cannot change the log level".to_string()))?, directives)),
+            None => None,
+        };
+        let tls = self.reread_tls().map_err(failed)?;
+        let presented = tls.clone().or_else(|| self.tls.borrow().clone());
+        let mut neighbors = vec![];
+        for (side, queue, link) in [("left", &self.left, left), ("right", &self.right, right)] {
+            let current = queue.peer();
+            let (id, url) = match link {
+                Some(Link { id, url }) => (id.into(), topology::grpc_url(&url).map_err(|e| failed(format!("invalid address {:?}: {}", url, e)))?),
+                None => (current.id, current.endpoint.uri().to_string()),
+            };
+            let endpoint = tls::endpoint(url, presented.as_deref(), &timing).map_err(failed)?;
+            let moved = id != current.id || endpoint.uri() != current.endpoint.uri();
+            neighbors.push((side, queue, moved, Peer { id, endpoint }));
+        }
+        let changed = neighbors.iter().any(|&(_, _, moved, _)| moved);
+        let term = changed.then(|| self.next_term()).transpose()?;
+
+        // the one change that can still fail comes first
+        if let Some((filter, directives)) = filter {
+            filter.set(&directives).map_err(failed)?;
+            info!(node = self.id, "now logs {}", directives);
+        }
+        if timing != self.timing() {
+            info!(node = self.id, "now times its steps as {:?}", timing);
+            *self.timing.lock().unwrap() = timing;
+        }
+        for (side, queue, moved, peer) in neighbors {
+            if moved {
+                info!(node = self.id, "now has node {} at {} as its {} neighbour", peer.id, peer.endpoint.uri(), side);
+            }
+            if moved || tls.is_some() {
+                queue.retarget(peer);
+            }
+        }
+        if let Some(tls) = tls {
+            info!(node = self.id, "now presents its reloaded certificates");
+            self.tls.send_replace(Some(tls));
+        }
+        if let Some(term) = term {
+            let epoch = self.reelection_epoch.load(AtomicOrdering::SeqCst) + 1;
+            info!(node = self.id, "restarting the election for epoch {} around its new neighbours", epoch);
+            self.reelect(epoch, self.ring_size(), term);
+        }
+        Ok(changed)
+    }
+
+    /// Reads the node's certificates again if their files changed, for its
+    /// server and its connections to its neighbours to present them from
+    /// then on, without restarting the election. Returns whether they
+    /// changed.
+    pub fn reload_tls(&self) -> Result<bool, ElectionError> {
+        let failed = |reason| ElectionError::ReloadFailed { node: self.id, reason };
+        let tls = match self.reread_tls().map_err(failed)? {
+            Some(tls) => tls,
+            None => return Ok(false),
+        };
+        let [left, right] = [&self.left, &self.right].map(|queue| {
+            let Peer { id, endpoint } = queue.peer();
+            tls::endpoint(endpoint.uri().to_string(), Some(&tls), &self.timing()).map(|endpoint| Peer { id, endpoint })
+        });
+        let (left, right) = (left.map_err(failed)?, right.map_err(failed)?);
+        self.left.retarget(left);
+        self.right.retarget(right);
+        info!(node = self.id, "now presents its reloaded certificates");
+        self.tls.send_replace(Some(tls));
+        Ok(true)
+    }
+
+    /// The node's certificates read again, if it has any and their files
+    /// changed.
+    fn reread_tls(&self) -> Result<Option<Arc<Tls>>, String> {
+        let current = self.tls.borrow().clone();
+        let reread = current.map(|tls| tls.reread()).transpose().map_err(|e| e.to_string())?;
+        Ok(reread.flatten().map(Arc::new))
+    }
+
+    /// How often the leader sends its digest around the ring, often enough
+    /// to renew its lease in time.
+    fn digest_interval(&self) -> Duration {
+        let interval = Duration::from_millis(DIGEST_INTERVAL);
+        self.lease.as_ref().map_or(interval, |lease| interval.min(lease.duration / 4))
+    }
+
+    /// How long until the node next polls, with a random part of the
+    /// interval cut off.
+    fn poll_interval(&self) -> Duration {
+        let timing = self.timing();
+        timing.poll_interval.mul_f64(1.0 - timing.poll_jitter * fraction(&self.rng))
+    }
+
+    /// How long the node waits after starting before it stands as a
+    /// candidate.
+    fn startup_delay(&self) -> Duration {
+        let timing = self.timing();
+        timing.startup_grace + timing.startup_jitter.mul_f64(fraction(&self.rng))
+    }
+
+    /// The delays between the attempts at a call to a neighbour.
+    fn backoff(&self) -> Backoff {
+        self.retry.backoff(self.seed)
+    }
+
+    fn ring_size(&self) -> u64 {
+        self.ring_size.load(AtomicOrdering::SeqCst)
+    }
+
+    /// Moves the node to topology `epoch`, unless it is there or past it.
+    fn advance_epoch(&self, epoch: u64) -> Result<(), ElectionError> {
+        let current = self.topology_epoch.fetch_max(epoch, AtomicOrdering::SeqCst);
+        if current >= epoch {
+            return Err(ElectionError::StaleEpoch { node: self.id, epoch, current })
+        }
+        Ok(())
+    }
+
+    fn peer_of(&self, Neighbor { id, addr }: Neighbor) -> Result<Peer, ElectionError> {
+        let invalid = |e| ElectionError::InvalidMessage { node: self.id, state: None, reason: format!("invalid address {:?}: {}", addr, e) };
+        let url = topology::grpc_url(&addr).map_err(invalid)?;
+        match tls::endpoint(url, self.tls.borrow().as_deref(), &self.timing()) {
+            Ok(endpoint) => Ok(Peer { id, endpoint }),
+            Err(e) => Err(invalid(e)),
+        }
+    }
+
+    /// Hands an application request to the leader: to the node's handler if
+    /// it leads, straight to the leader if it knows where that is, and on to
+    /// its right neighbour otherwise, until the request went around the ring.
+    /// Only a leader that cannot be reached is passed by, so the request is
+    /// never handled twice.
+    async fn forward(&self, route: String, payload: Vec<u8>, hops: u64) -> Result<ForwardResponse, Status> {
+        let leader = match self.state().await {
+            NodeState::Leader => {
+                let handler = self.handler.as_ref()
+                    .ok_or_else(|| Status::unimplemented(format!("node {} takes no forwarded requests", self.id)))?;
+                let payload = handler.handle(&route, payload).await?;
+                return Ok(ForwardResponse { payload, leader_id: self.id })
+            },
+            NodeState::Defeated { leader } => leader,
+            NodeState::Candidate { .. } => None,
+        };
+        if hops >= self.ring_size() {
+            return Err(ElectionError::NoLeader { node: self.id }.into())
+        }
+        let request = ForwardRequest { route, payload, hops: hops + 1, group_id: self.group };
+        let direct = match (self.leader_info(leader), leader) {
+            (Some(LeaderInfo { addr, .. }), Some(leader)) if !addr.is_empty() => self.peer_of(Neighbor { id: leader, addr }).ok(),
+            _ => None,
+        };
+        if let Some(peer) = direct {
+            match self.connect(&peer.endpoint).await {
+                Ok(mut client) => return Ok(client.forward_to_leader(self.deadline(request)).await?.into_inner()),
+                Err(e) => debug!(node = self.id, "cannot reach leader {}, forwarding around the ring: {}", peer.id, e),
+            }
+        }
+        let mut client = self.connect(&self.right.peer().endpoint).await?;
+        Ok(client.forward_to_leader(self.deadline(request)).await?.into_inner())
+    }
+
+    /// Stops serving the node and taking part in the election, closing its
+    /// connections to the neighbours. [`run_node`] returns once it is done.
+    pub fn shutdown(&self) {
+        info!(node = self.id, "shutting down");
+        self.stopping.send_replace(true);
+    }
+
+    /// Resolves once the node is asked to shut down, e.g. for the server of
+    /// an application the node is embedded in to stop too.
+    pub async fn stopped(&self) {
+        until_set(&self.stopping).await
+    }
+
+    /// Freezes the node, e.g. to step through an election one node at a
+    /// time: its client loop stops once the next timer fires, and the
+    /// messages its neighbours send wait in their streams unhandled until
+    /// [`Node::resume`]. Returns whether the node was paused already.
+    pub fn pause(&self) -> bool {
+        let was_paused = self.paused.send_replace(true);
+        if !was_paused {
+            info!(node = self.id, "paused");
+        }
+        was_paused
+    }
+
+    /// Lets a paused node carry on where it stopped, handling the messages
+    /// it held first. Returns whether the node was paused.
+    pub fn resume(&self) -> bool {
+        let was_paused = self.paused.send_replace(false);
+        if was_paused {
+            info!(node = self.id, "resumed");
+        }
+        was_paused
+    }
+
+    /// Whether the node is paused, see [`Node::pause`].
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Resolves at once unless the node is paused, and otherwise once it is
+    /// resumed or asked to shut down.
+    async fn until_resumed(&self) {
+        let mut paused = self.paused.subscribe();
+        while *paused.borrow_and_update() {
+            tokio::select! {
+                changed = paused.changed() => if changed.is_err() { return },
+                _ = self.stopped() => return,
+            }
+        }
+    }
+
+    /// Follows up on the node changing from `from` into `state` because of
+    /// `cause`: wakes the probes waiting for the node to move on, gives up
+    /// the lease of a node that no longer leads and records where the node
+    /// moved, if it keeps its state.
+    fn changed_state(&self, from: &NodeState, state: &NodeState, cause: Cause) {
+        self.state_changed.notify_waiters();
+        self.stats.record(Stat::Transitions, self.clock.now());
+        self.audit.record(self.clock.wall_now(), self.term(), from, state, cause);
+        if let Some(lease) = self.lease.as_ref().filter(|_| *state != NodeState::Leader) {
+            lease.revoke();
+            self.timers.cancel(TimerKind::Lease);
+        }
+        if let Some(file) = &self.state_file {
+            if let Err(e) = file.save(state, self.term()) {
+                error!(node = self.id, "failed to save state {:?}: {}", state, e);
+            }
+        }
+        if let Some(events) = &self.events {
+            events.state(self.clock.wall_now(), self.lamport.load(AtomicOrdering::SeqCst), state, self.term());
+        }
+    }
+
+    fn publish(&self, result: ElectionResult) {
+        publish(&self.results, result)
+    }
+
+    /// Takes a restart of the election asked for by `caller`, if known,
+    /// from the allowance of restarts, failing if it is used up.
+    fn admit_restart(&self, caller: Option<SocketAddr>) -> Result<(), ElectionError> {
+        self.restart_limit.admit(self.id, caller.map(|caller| caller.ip()), self.clock.now()).map_err(|e| {
+            let limited = self.rate_limited.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+            warn!(node = self.id, "{} ({} refused so far)", e, limited);
+            e
+        })
+    }
+
+    /// Records an anomaly for `GetAnomalies` and the metrics, and as an
+    /// event, and returns how many of its kind the node saw so far.
+    fn record_anomaly(&self, kind: AnomalyKind, detail: String) -> u64 {
+        let now = self.clock.wall_now();
+        if let Some(events) = &self.events {
+            events.anomaly(now, self.lamport.load(AtomicOrdering::SeqCst), kind, &detail);
+        }
+        self.anomalies.record(kind, detail, now)
+    }
+
+    /// The priority of node `id`, as far as the node knows.
+    fn priority_of(&self, id: u64) -> u64 {
+        match id == self.id {
+            true => self.priority,
+            false => self.priorities.lock().unwrap().get(&id).copied().unwrap_or_default(),
+        }
+    }
+
+    /// Whether `zone` is the one the ring prefers its leaders in.
+    fn in_preferred_zone(&self, zone: &str) -> bool {
+        self.preferred_zone.as_deref() == Some(zone)
+    }
+
+    /// Where node `id` stands in elections, as far as the node knows its
+    /// priority and zone.
+    fn standing_of(&self, id: u64) -> (std::cmp::Reverse<u64>, bool, u64) {
+        let preferred = match id == self.id {
+            true => self.in_preferred_zone(&self.zone),
+            false => self.zones.lock().unwrap().get(&id).is_some_and(|zone| self.in_preferred_zone(zone)),
+        };
+        standing(self.priority_of(id), preferred, id)
+    }
+
+    /// Picks the node that wins the comparison a probe would make between
+    /// `a` and `b`, as far as the node knows their priorities and zones.
+    fn preferred_leader(&self, a: u64, b: u64) -> u64 {
+        match self.standing_of(a) <= self.standing_of(b) {
+            true => a,
+            false => b,
+        }
+    }
+
+    /// Adds the node to `ranking`, keeping it ordered by where the nodes
+    /// stand in elections.
+    fn join_ranking(&self, mut ranking: Vec<u64>) -> Vec<u64> {
+        let key = |&id: &u64| self.standing_of(id);
+        if let Err(at) = ranking.binary_search_by_key(&key(&self.id), key) {
+            ranking.insert(at, self.id);
+        }
+        ranking
+    }
+
+    fn committee(&self) -> Vec<u64> {
+        self.ranking.lock().unwrap().iter().take(self.committee_size).copied().collect()
+    }
+
+    /// The runner-up of the election, if known, which takes over once the
+    /// leader falls silent and its neighbours confirm it, see
+    /// [`monitor_leader`].
+    fn deputy(&self) -> Option<u64> {
+        self.ranking.lock().unwrap().get(1).copied()
+    }
+
+    /// Refuses the calls the node serves that change the election or the
+    /// ring unless signed with the secret of the ring, if it has one.
+    fn auth_layer(&self) -> AuthLayer {
+        AuthLayer::new(self.id, self.auth.clone(), self.spiffe_ids.clone(), self.acl.clone())
+    }
+
+    /// The middleware wrapped around both ends of every RPC the node takes part in.
+    fn layers(&self, side: Side, peer: Option<&str>) -> ServiceBuilder<Stack<MetricsLayer, Stack<RequestLogLayer, Identity>>> {
+        ServiceBuilder::new()
+            .layer(RequestLogLayer::new(self.id, side, self.request_ids.clone(), peer))
+            .layer(MetricsLayer::new(self.rpc_metrics.clone(), side))
+    }
+
+    /// Drives a response pipe on its own task, buffering at most
+    /// `RESPONSE_BUFFER` responses for the peer. Once the buffer is full the
+    /// pipe waits for the peer to catch up, so it stops consuming requests
+    /// and HTTP/2 flow control pushes back on the sender. The pipe is dropped
+    /// when the node shuts down, ending the response stream.
+    fn respond<T: Send + 'static>(&self, pipe: impl Stream<Item = Result<T, Status>> + Send + 'static)
+    -> ReceiverStream<Result<T, Status>> {
+        let (tx, rx) = mpsc::channel(RESPONSE_BUFFER);
+        let this = self.clone();
+        tokio::spawn(async move {
+            futures::pin_mut!(pipe);
+            let stopped = this.stopped();
+            futures::pin_mut!(stopped);
+            while let Some(response) = tokio::select! {
+                response = pipe.next() => response,
+                _ = &mut stopped => None,
+            } {
+                let response = match tx.try_send(response) {
+                    Ok(()) => continue,
+                    Err(TrySendError::Closed(_)) => break,
+                    Err(TrySendError::Full(response)) => response,
+                };
+                let waits = this.slow_peer_responses.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+                warn!(node = this.id, "waiting for a slow peer to read its responses ({} waits so far)", waits);
+                if tx.send(response).await.is_err() {
+                    break
+                }
+            }
+        });
+        ReceiverStream::new(rx)
+    }
+
+    async fn connect(&self, endpoint: &Endpoint) -> Result<Client, ElectionError> {
+        let channel = endpoint.connect().await.map_err(|error| ElectionError::Transport { node: self.id, error })?;
+        let peer = endpoint.uri().to_string();
+        let layers = self.layers(Side::Client, Some(&peer)).layer(SigningLayer::new(self.auth.clone()));
+        Ok(compressed!(LeaderElectionServiceClient::new(layers.service(channel)), self.compression))
+    }
+
+    fn neighbor(&self, headed_left: bool) -> &Arc<NeighborQueue> {
+        if headed_left { &self.left } else { &self.right }
+    }
+
+    /// Warns that `neighbor` became slower to acknowledge messages than the
+    /// node allows, or notes that it recovered, recording it as an event.
+    fn slow_neighbour(&self, neighbor: &NeighborQueue, slow: bool) {
+        let (peer, latency) = (neighbor.peer().id, neighbor.latency());
+        let rtt = latency.smoothed.unwrap_or_default();
+        if slow {
+            warn!(node = self.id, "node {} takes {:?} on average to acknowledge messages, over the {:?} allowed ({} times slow so far)",
+                peer, rtt, self.timing().slow_neighbour, latency.episodes);
+        } else {
+            info!(node = self.id, "node {} is no longer slow, taking {:?} on average to acknowledge messages", peer, rtt);
+        }
+        if let Some(events) = &self.events {
+            events.slow(self.clock.wall_now(), self.lamport.load(AtomicOrdering::SeqCst), peer, slow, rtt);
+        }
+    }
+
+    /// Sends the messages queued for `neighbor` in order over a single relay
+    /// stream, connecting once the first one arrives. When the stream breaks,
+    /// a new one is opened and every message not acknowledged on the old one
+    /// is sent again, the neighbour skipping the ones it did process. The same
+    /// happens when the queue is pointed at another neighbour, which gets the
+    /// messages the old one did not acknowledge. Runs as long as the node does.
+    async fn drain(self, neighbor: Arc<NeighborQueue>) {
+        let mut relay: Option<Relay> = None;
+        let mut number = 0;
+        loop {
+            let next = match &mut relay {
+                Some(relay) => tokio::select! {
+                    envelope = neighbor.pop() => Some(envelope),
+                    _ = &mut relay.broken => None,
+                    _ = neighbor.retargeted() => None,
+                },
+                None => Some(neighbor.pop().await),
+            };
+            if let Some(Envelope { message, request_id, seq, trace }) = next {
+                number += 1;
+                let message = message.sequenced(Some(Sequence {
+                    sender: self.id,
+                    incarnation: self.incarnation,
+                    number,
+                    leftward: Arc::ptr_eq(&neighbor, &self.left),
+                }));
+                let request_id = match request_id {
+                    Some(id) => id.to_str().unwrap_or_default().to_string(),
+                    None => format!("{}-{}", self.id, self.request_ids.fetch_add(1, AtomicOrdering::Relaxed)),
+                };
+                let mut message = PeerMessage {
+                    body: Some(message.into()), request_id, trace, lamport: self.tick(), signature: vec![], group_id: self.group, version: RELAY_VERSION, number,
+                    role: self.role.to_string(),
+                };
+                if let Some(auth) = &self.auth {
+                    auth.sign(&mut message);
+                }
+                neighbor.sent(number, message.clone(), seq, self.clock.now());
+                self.history.message();
+                if let Some(relay) = &relay {
+                    self.log_message(&message, neighbor.peer().id);
+                    if relay.messages.send(message).await.is_ok() {
+                        continue
+                    }
+                }
+            }
+
+            // the stream broke, was never opened or goes to the wrong neighbour
+            if relay.is_some() {
+                self.stats.record(Stat::Reconnects, self.clock.now());
+            }
+            relay = self.resume_relay(&neighbor).await;
+            if relay.is_none() {
+                let abandoned = neighbor.abandon();
+                error!(node = self.id, "gave up on reaching node {}, dropping {} messages", neighbor.peer().id, abandoned);
+            }
+        }
+    }
+
+    /// Opens a relay stream to `neighbor` and sends it every message that was
+    /// not acknowledged yet, retrying as the node's retry policy says. Gives
+    /// up once the policy does.
+    async fn resume_relay(&self, neighbor: &Arc<NeighborQueue>) -> Option<Relay> {
+        let mut backoff = self.backoff();
+        let mut delay = None;
+        loop {
+            if let Some(delay) = delay.take() {
+                self.clock.sleep_until(self.clock.now() + delay).await;
+            }
+            let peer = neighbor.peer();
+            let (tx, rx) = mpsc::channel(RELAY_BUFFER);
+            let rpc_deadline = self.timing().rpc_deadline;
+            let opened = tokio::select! {
+                opened = self.transport.relay(&peer, rx) => opened,
+                () = self.clock.sleep_until(self.clock.now() + rpc_deadline) => Err(format!("no answer within {:?}", rpc_deadline).into()),
+            };
+            neighbor.reached(opened.is_ok());
+            let mut acks = match opened {
+                Ok(acks) => acks,
+                Err(e) => {
+                    self.stats.record(Stat::Errors, self.clock.now());
+                    let next = backoff.next()?;
+                    warn!(node = self.id, "cannot reach {}, retrying in {:?}: {}", peer.endpoint.uri(), next, e);
+                    delay = Some(next);
+                    continue
+                },
+            };
+            let (broken_tx, broken) = oneshot::channel::<()>();
+            let (acknowledging, decisions, id) = (neighbor.clone(), self.decisions.clone(), self.id);
+            let (clock, timeout, stuck_streams) = (self.clock.clone(), self.timing().stream_timeout, self.stuck_streams.clone());
+            let this = self.clone();
+            tokio::spawn(async move {
+                // dropped when the stream ends, telling the sender that it broke
+                let _broken = broken_tx;
+                // the oldest message still not acknowledged when the timeout last ran out
+                let mut waiting_on = None;
+                loop {
+                    let ack = tokio::select! {
+                        ack = acks.next() => ack,
+                        () = clock.sleep_until(clock.now() + timeout) => {
+                            let oldest = acknowledging.oldest_unacknowledged();
+                            if oldest.is_some() && oldest == waiting_on {
+                                let stuck = stuck_streams.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+                                warn!(node = id, "node {} left message {} unacknowledged for over {:?}, reopening the stream ({} stuck so far)",
+                                    acknowledging.peer().id, oldest.unwrap_or_default(), timeout, stuck);
+                                break
+                            }
+                            waiting_on = oldest;
+                            continue
+                        },
+                    };
+                    let ack = match ack {
+                        Some(ack) => ack,
+                        None => {
+                            acknowledging.reached(false);
+                            break
+                        },
+                    };
+                    if let Some(slow) = acknowledging.acknowledged(ack.number, clock.now(), this.timing().slow_neighbour) {
+                        this.slow_neighbour(&acknowledging, slow);
+                    }
+                    debug!(node = id, "node {} decided {:?} on message {}, standing as {:?} in phase {}",
+                        ack.responder_id, ack.decision(), ack.number, ack.responder_state(), ack.responder_phase);
+                    decisions.record(ack.decision());
+                }
+            });
+
+            let mut resent = true;
+            for message in neighbor.unacknowledged() {
+                self.log_message(&message, peer.id);
+                if tx.send(message).await.is_err() {
+                    resent = false;
+                    break
+                }
+            }
+            if resent {
+                return Some(Relay { messages: tx, broken })
+            }
+            delay = Some(backoff.next()?);
+        }
+    }
+
+    /// Prints an outgoing probe or notification to the message log, and
+    /// records any message sent.
+    fn log_message(&self, message: &PeerMessage, target: u64) {
+        if let Some(body) = &message.body {
+            self.stats.record(Stat::message(body, true), self.clock.now());
+        }
+        if let Some(events) = &self.events {
+            events.sent(self.clock.wall_now(), message, target);
+        }
+        let value = match &message.body {
+            Some(peer_message::Body::Probe(msg)) => msg.sender_id,
+            Some(peer_message::Body::Notify(msg)) => msg.leader_id,
+            _ => return,
+        };
+        print_message(self.message_log, self.id, self.clock.wall_now(), value, target);
+    }
+
+    /// Refuses streams of bare probes, notifications or digests, which carry
+    /// no signatures, if the node authenticates messages.
+    fn refuse_unsigned(&self) -> Result<(), ElectionError> {
+        match self.auth {
+            Some(_) => Err(ElectionError::Unauthenticated { node: self.id }),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails unless a message or request of `group` is meant for this node.
+    fn check_group(&self, group: u64) -> Result<(), ElectionError> {
+        match group == self.group {
+            true => Ok(()),
+            false => Err(ElectionError::UnknownGroup { node: self.id, group }),
+        }
+    }
+
+    /// Fails unless a message of `role`, or of none, is meant for this node,
+    /// so that a role and a group of the same number, or two roles named
+    /// into the same group, do not mix their elections up.
+    fn check_role(&self, role: &str) -> Result<(), ElectionError> {
+        match role == &*self.role {
+            true => Ok(()),
+            false => Err(ElectionError::UnknownRole { node: self.id, role: role.to_string() }),
+        }
+    }
+
+    /// Fails unless the leader `request` asks for is this node's: that of
+    /// its role if the request names one, or else of its group.
+    fn check_leader_request(&self, request: &LeaderRequest) -> Result<(), ElectionError> {
+        match request.role.is_empty() {
+            true => self.check_group(request.group_id),
+            false => self.check_role(&request.role),
+        }
+    }
+
+    /// Takes a message's share of the allowance of the connection it came
+    /// over, if the connection has one.
+    fn throttle(&self, limit: Option<&ConnectionLimit>, what: &str) -> Result<(), ElectionError> {
+        limit.map_or(Ok(()), |limit| limit.take(self.id, what))
+    }
+
+    /// Handles a message from a neighbour and returns the acknowledgement
+    /// to answer it with.
+    async fn receive(&self, message: PeerMessage) -> Result<PeerAck, ElectionError> {
+        self.until_resumed().await;
+        if let Some(body) = &message.body {
+            self.stats.record(Stat::message(body, false), self.clock.now());
+        }
+        let acknowledged = self.handle_message(message).await;
+        if acknowledged.is_err() {
+            self.stats.record(Stat::Errors, self.clock.now());
+        }
+        acknowledged
+    }
+
+    async fn handle_message(&self, message: PeerMessage) -> Result<PeerAck, ElectionError> {
+        if self.auth.as_ref().is_some_and(|auth| !auth.verify(&message)) {
+            let rejected = self.unauthenticated_messages.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+            warn!(node = self.id, "rejecting a message not signed with the secret of the ring ({} so far)", rejected);
+            return Err(ElectionError::Unauthenticated { node: self.id })
+        }
+        self.check_group(message.group_id)?;
+        self.check_role(&message.role)?;
+        let lamport = self.witness(message.lamport);
+        let recorded = self.events.as_ref().map(|events| (events.clone(), message.clone()));
+        // a neighbour whose version this node does not understand was refused as it introduced itself
+        let PeerMessage { body, request_id, trace, version, number: frame, .. } = message;
+        let request_id = request_id.parse().ok();
+        let (number, decision) = match body {
+            Some(peer_message::Body::Probe(msg)) => {
+                let number = msg.seq.as_ref().map_or(0, |seq| seq.number);
+                (number, self.on_probe(msg, request_id, trace).await?)
+            },
+            Some(peer_message::Body::Notify(msg)) => {
+                let number = msg.seq.as_ref().map_or(0, |seq| seq.number);
+                (number, self.on_notify(msg, request_id, trace).await?.0)
+            },
+            Some(peer_message::Body::Digest(msg)) => {
+                let number = msg.seq.as_ref().map_or(0, |seq| seq.number);
+                (number, self.on_digest(msg, request_id).await?)
+            },
+            // a newer node relays kinds of messages this one cannot tell apart from none
+            None if version > RELAY_VERSION => {
+                debug!(node = self.id, "ignoring message {} of a kind from relay protocol version {}", frame, version);
+                (frame, Decision::Ignored)
+            },
+            None => return Err(ElectionError::InvalidMessage { node: self.id, state: None, reason: "empty relayed message".to_string() }),
+        };
+        if let Some((events, message)) = recorded {
+            events.received(self.clock.wall_now(), lamport, &message, decision);
+        }
+        let (kind, phase) = self.current_kind().await;
+        Ok(PeerAck { number, decision: decision as i32, version: RELAY_VERSION, responder_id: self.id, responder_state: kind as i32, responder_phase: phase })
+    }
+
+    /// Records the arrival of the message `seq` from a neighbour, and returns
+    /// whether to apply it, see [`Receipts::accept`].
+    fn accept(&self, seq: Option<&Sequence>) -> bool {
+        if let Some(seq) = seq {
+            let now = self.clock.now();
+            for neighbor in [&self.left, &self.right].into_iter().filter(|neighbor| neighbor.peer().id == seq.sender) {
+                neighbor.received(now);
+            }
+        }
+        self.receipts.accept(self.id, seq)
+    }
+
+    /// Handles a probe and returns what became of it.
+    async fn on_probe(&self, msg: ProbeMessage, request_id: Option<AsciiMetadataValue>, trace: Option<TraceContext>)
+    -> Result<Decision, ElectionError> {
+        validate::probe(&msg).map_err(|reason| ElectionError::InvalidMessage { node: self.id, state: None, reason })?;
+        if !self.accept(msg.seq.as_ref()) {
+            return Ok(Decision::Ignored)
+        }
+        if !self.admit_term(msg.term, "probe", msg.seq.as_ref().map(|seq| seq.sender)).await {
+            return Ok(Decision::Ignored)
+        }
+        if let Err(e) = self.probe_limit.admit(self.id, msg.term, msg.sender_id, msg.phase, msg.headed_left) {
+            let limited = self.rate_limited.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+            warn!(node = self.id, "{} ({} refused so far)", e, limited);
+            return Err(e)
+        }
+        self.probes.received.fetch_add(1, AtomicOrdering::Relaxed);
+        self.history.message();
+        if msg.phase > validate::max_phase_for(self.ring_size()) {
+            let dropped = self.implausible_probes.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+            warn!(node = self.id, "server dropping probe from {} with implausible phase {} ({} dropped so far)", msg.sender_id, msg.phase, dropped);
+            return Ok(Decision::Ignored)
+        }
+        self.history.phase(msg.phase);
+        self.history.candidate(msg.sender_id, msg.phase);
+        let mut span = self.tracer.child("probe hop", trace.as_ref());
+        span.attribute("sender", msg.sender_id);
+        span.attribute("phase", msg.phase);
+        let (sender_id, term) = (msg.sender_id, msg.term);
+        print_message(self.message_log, self.id, self.clock.wall_now(), sender_id, self.id);
+        if sender_id != self.id {
+            remember(&self.priorities, sender_id, msg.priority);
+            remember(&self.zones, sender_id, msg.zone.clone());
+        }
+        let (me, cause) = (self.me(), Cause::Probe { sender: sender_id, phase: msg.phase, peer: msg.seq.as_ref().map(|seq| seq.sender) });
+        let (mut input, mut probe, mut decision) = (Input::Probe { sender: sender_id, priority: msg.priority, preferred: self.in_preferred_zone(&msg.zone) }, Some(msg), Decision::Ignored);
+        loop {
+            // catches the changes made from here on, before the lock is taken
+            let changed = self.state_changed.notified();
+            let mut state: MutexGuard<NodeState> = self.state.lock().await;
+            // the election the probe was part of may be over
+            let current = self.term() == term;
+            if !current && matches!(input, Input::Settle(_)) {
+                break
+            }
+            let (mut forward, mut deferred) = (false, None);
+            for command in state_machine::react(&state, me, input) {
+                match command {
+                    Command::Take(event) if current => self.take(&mut state, event, cause)?,
+                    Command::Defer(event) if current => deferred = Some(event),
+                    Command::Take(_) | Command::Defer(_) => (),
+                    Command::ForwardProbe => forward = true,
+                    Command::Decide(decided) => decision = decided,
+                    command => warn!(node = self.id, "skipping {:?}, which does not follow a probe", command),
+                }
+            }
+            drop(state);
+            if let Some(msg) = probe.take() {
+                if forward {
+                    let target = self.neighbor(msg.headed_left);
+                    debug!(node = self.id, "server forwarding probe to {}", target.peer().endpoint.uri());
+                    self.probes.forwarded.fetch_add(1, AtomicOrdering::Relaxed);
+                    target.push(Message::Probe(ProbeMessage { hops: msg.hops.saturating_add(1), ..msg }), request_id.clone(), span.context()).await;
+                } else {
+                    self.history.probe_ended(msg.hops.saturating_add(1));
+                }
+            }
+            match deferred {
+                Some(event) => {
+                    // wait for the client to probe the current phase first
+                    debug!(node = self.id, "server waiting for the phase to be probed");
+                    input = Input::Settle(event);
+                    changed.await;
+                },
+                None => break,
+            }
+        }
+        Ok(decision)
+    }
+
+    /// Handles a notification and returns what became of it, along with the
+    /// leader it was passed on with.
+    async fn on_notify(&self, msg: NotifyMessage, request_id: Option<AsciiMetadataValue>, trace: Option<TraceContext>)
+    -> Result<(Decision, u64), ElectionError> {
+        let NotifyMessage { leader_id, headed_left, seq, ranking, mut leader_addr, term, priorities, mut leader_metadata, mut payload, .. } = msg;
+        let peer = seq.as_ref().map(|seq| seq.sender);
+        if !self.accept(seq.as_ref()) || !self.admit_term(term, "notification", peer).await {
+            return Ok((Decision::Ignored, leader_id))
+        }
+        for (id, priority) in ranking.iter().copied().zip(priorities).filter(|&(id, _)| id != self.id) {
+            remember(&self.priorities, id, priority);
+        }
+        self.history.message();
+        let state = self.state.lock().await.clone();
+        if leader_id == self.id && state != NodeState::Leader {
+            let reason = format!("node {} is not the leader", leader_id);
+            return Err(ElectionError::InvalidMessage { node: self.id, state: Some(state), reason });
+        }
+        // only a leader that is not in the ring lets its notification come back
+        if leader_id != self.id && ranking.contains(&self.id) {
+            warn!(node = self.id, "dropping a notification of node {} that went around the ring without reaching it", leader_id);
+            return Ok((Decision::Ignored, leader_id))
+        }
+        let mut span = self.tracer.child("notification hop", trace.as_ref());
+        span.attribute("leader", leader_id);
+        print_message(self.message_log, self.id, self.clock.wall_now(), leader_id, self.id);
+        let sender = self.neighbor(!headed_left).peer();
+        if leader_addr.is_empty() && sender.id == leader_id {
+            leader_addr = sender.endpoint.uri().to_string();
+        }
+        if self.id != leader_id {
+            info!(node = self.id, "acknowledging {}'s leadership", leader_id);
+            let announced = LeaderInfo { leader: leader_id, addr: leader_addr.clone(), metadata: leader_metadata.clone(), payload: payload.clone() };
+            let winner = self.defeat_with_leader(leader_id, Cause::Notification { leader: leader_id, peer }, announced).await;
+            if winner != leader_id {
+                // the address, metadata and payload are those of the loser
+                leader_addr.clear();
+                leader_metadata.clear();
+                payload = None;
+            }
+            let leader_id = winner;
+
+            // forward the message
+            let target = self.neighbor(headed_left);
+            debug!(node = self.id, "forwarding election notification to {}", target.peer().endpoint.uri());
+            // a node sitting out the election is not ranked in it
+            let ranking = match self.abstains() {
+                true => ranking,
+                false => self.join_ranking(ranking),
+            };
+            let priorities = ranking.iter().map(|&id| self.priority_of(id)).collect();
+            let notification = NotifyMessage { leader_id, headed_left, seq: None, ranking, leader_addr, term, priorities, group_id: self.group, leader_metadata, payload };
+            target.push(Message::Notify(notification), request_id, span.context()).await;
+            Ok((Decision::Forwarded, leader_id))
+        } else {
+            // the notification made it around the ring, past every node
+            *self.ranking.lock().unwrap() = ranking;
+            self.learn_leader(LeaderInfo { leader: leader_id, addr: leader_addr, metadata: leader_metadata, payload });
+            info!(node = self.id, "elected committee {:?} with deputy {:?}", self.committee(), self.deputy());
+            self.acknowledged.send_replace(Some((term, leader_id)));
+            Ok((Decision::YouWin, leader_id))
+        }
+    }
+
+    /// Handles a digest and returns what became of it.
+    async fn on_digest(&self, msg: DigestMessage, request_id: Option<AsciiMetadataValue>) -> Result<Decision, ElectionError> {
+        let DigestMessage { leader_id, ring_size, seq, ranking, term, hops, .. } = msg;
+        if !self.accept(seq.as_ref()) || !self.admit_term(term, "digest", seq.as_ref().map(|seq| seq.sender)).await {
+            return Ok(Decision::Ignored)
+        }
+        // only a leader that is not in the ring lets its digest go around it again
+        if hops > self.ring_size() {
+            warn!(node = self.id, "dropping a digest of node {} that went around the ring without reaching it", leader_id);
+            return Ok(Decision::Ignored)
+        }
+        if self.id == leader_id {
+            // the ring still follows the leader
+            if let Some(expires) = self.lease.as_ref().and_then(|lease| lease.renewed()) {
+                self.timers.set(TimerKind::Lease, expires.saturating_duration_since(self.clock.now()));
+            }
+            return Ok(Decision::YouWin)
+        }
+        let state = self.state.lock().await.clone();
+        invariant!(self.id, state != NodeState::Leader,
+            "received a digest from leader {} while leading the ring of {} nodes", leader_id, self.ring_size());
+        if state == NodeState::Leader {
+            let detail = format!("leader {} received a digest from leader {}", self.id, leader_id);
+            let rivals = self.record_anomaly(AnomalyKind::RivalDigest, detail);
+            warn!(node = self.id, "received a digest from rival leader {} ({} so far)", leader_id, rivals);
+        } else if state != (NodeState::Defeated { leader: Some(leader_id) }) || ring_size != self.ring_size() {
+            let detail = format!("digest (leader {}, ring size {}) diverges from local view ({:?}, ring size {})",
+                leader_id, ring_size, state, self.ring_size());
+            let diverged = self.record_anomaly(AnomalyKind::DivergedDigest, detail.clone());
+            warn!(node = self.id, "{}, {} divergences so far", detail, diverged);
+        }
+
+        if state == (NodeState::Defeated { leader: Some(leader_id) }) {
+            self.saw_leader(self.clock.now());
+        }
+        if !ranking.is_empty() {
+            *self.ranking.lock().unwrap() = ranking.clone();
+            // the leader's notification came back to it
+            if state == (NodeState::Defeated { leader: Some(leader_id) }) {
+                self.acknowledged.send_replace(Some((term, leader_id)));
+            }
+        }
+        let digest = DigestMessage { leader_id, ring_size, seq: None, ranking, term, group_id: self.group, hops: hops.saturating_add(1) };
+        self.left.push(Message::Digest(digest), request_id, None).await;
+        Ok(Decision::Forwarded)
+    }
+
+    fn end_phase_span(&self) {
+        *self.phase_span.lock().unwrap() = Span::default();
+    }
+
+    fn wrong_state(&self, state: &NodeState, action: &'static str) -> ElectionError {
+        ElectionError::WrongState { node: self.id, state: state.clone(), action }
+    }
+
+    /// Who the node is to [`state_machine::react`].
+    fn me(&self) -> Me {
+        Me { id: self.id, priority: self.priority, preferred: self.in_preferred_zone(&self.zone), abstains: self.abstains() }
+    }
+
+    /// Takes `event`, which [`state_machine::react`] called for, with all
+    /// that comes of it.
+    fn take(&self, state: &mut MutexGuard<NodeState>, event: Event, cause: Cause) -> Result<(), ElectionError> {
+        match event {
+            Event::Probe => {
+                if let NodeState::Candidate { phase, last_phase_probed } = **state {
+                    invariant!(self.id, phase > last_phase_probed,
+                        "probing phase {} after already probing phase {}", phase, last_phase_probed);
+                }
+                let probed = state_machine::probe(state).map_err(|Refused(action)| self.wrong_state(state, action))?;
+                let from = std::mem::replace(&mut **state, probed);
+                self.changed_state(&from, state, cause);
+                Ok(())
+            },
+            Event::NextPhase => self.next_phase(state, cause),
+            Event::Defeat => self.defeat(state, cause),
+            Event::Lead => self.lead(state, cause),
+            // notifications are handled as they come, not reacted to
+            Event::Notified { .. } => Err(self.wrong_state(state, "react to a notification")),
+        }
+    }
+
+    /// Moves a candidate that probed its current phase on to the next one.
+    fn next_phase(&self, state: &mut MutexGuard<NodeState>, cause: Cause) -> Result<(), ElectionError> {
+        let next = state_machine::next_phase(state).map_err(|Refused(action)| self.wrong_state(state, action))?;
+        let from = std::mem::replace(&mut **state, next);
+        self.changed_state(&from, state, cause);
+        if let NodeState::Candidate { phase, .. } = **state {
+            self.history.phase(phase);
+        }
+        self.end_phase_span();
+        Ok(())
+    }
+
+    fn defeat(&self, state: &mut MutexGuard<NodeState>, cause: Cause) -> Result<(), ElectionError> {
+        let defeated = state_machine::defeat(state).map_err(|Refused(action)| self.wrong_state(state, action))?;
+        if defeated != **state {
+            let from = std::mem::replace(&mut **state, defeated);
+            self.changed_state(&from, state, cause);
+            self.end_phase_span();
+        }
+        Ok(())
+    }
+
+    /// Records `leader` as the elected leader and returns the leader whose
+    /// notification should keep circulating. Normally that is `leader` itself,
+    /// but if this node already knows of a different leader (or is one), the
+    /// conflict is reported and resolved in favour of the node that would have
+    /// won the election, whose notification is then sent around again. If
+    /// `leader` wins, the address, metadata and payload it `announced` are
+    /// taken on with it.
+    async fn defeat_with_leader(&self, leader: u64, cause: Cause, announced: LeaderInfo) -> u64 {
+        let mut state = self.state.lock().await;
+        let notified = state_machine::defeat_with_leader(&state, self.id, leader, |a, b| self.preferred_leader(a, b));
+        let winner = notified.winner;
+        if let Some(known) = notified.conflict {
+            invariant!(self.id, known == leader,
+                "notified of leader {} while already following {} ({:?})", leader, known, *state);
+            let detail = format!("notified of leader {} while following {}, resolved in favour of {}", leader, known, winner);
+            let conflicts = self.record_anomaly(AnomalyKind::ConflictingLeaders, detail);
+            warn!(node = self.id, "conflicting leaders {} and {}, resolving in favour of {} ({} conflicts so far)", known, leader, winner, conflicts);
+        }
+        self.learn_leader(match winner == leader {
+            true => announced,
+            false => LeaderInfo { leader: winner, ..LeaderInfo::default() },
+        });
+        if winner != self.id {
+            let from = std::mem::replace(&mut *state, notified.state);
+            self.changed_state(&from, &state, cause);
+            self.saw_leader(self.clock.now());
+            self.observe_leader(Some(winner));
+            self.publish(ElectionResult::Defeated { leader: winner });
+        }
+        winner
+    }
+
+    /// Records what the node `learned` of a leader, keeping what it already
+    /// knew of the same leader in place of anything missing.
+    fn learn_leader(&self, learned: LeaderInfo) {
+        let mut info = self.leader_info.lock().unwrap();
+        let known = info.take().filter(|info| info.leader == learned.leader).unwrap_or_default();
+        *info = Some(LeaderInfo {
+            leader: learned.leader,
+            addr: if learned.addr.is_empty() { known.addr } else { learned.addr },
+            metadata: if learned.metadata.is_empty() { known.metadata } else { learned.metadata },
+            payload: learned.payload.or(known.payload),
+        });
+        self.leader_learned.notify_waiters();
+    }
+
+    /// Who leads the ring as far as the node knows, as `GetLeader` answers.
+    async fn leader_response(&self) -> LeaderResponse {
+        let (leader, fencing_token) = self.leader_and_token().await.unzip();
+        let LeaderInfo { addr: leader_addr, metadata: leader_metadata, payload, .. } = self.leader_info(leader).unwrap_or_default();
+        LeaderResponse {
+            leader_id: leader.unwrap_or_default(),
+            leader_known: leader.is_some(),
+            leader_addr,
+            leader_metadata,
+            payload,
+            fencing_token: fencing_token.unwrap_or_default(),
+            provisional: leader.is_some() && self.partition().is_some(),
+        }
+    }
+
+    /// The leader the node knows, if any, along with its fencing token.
+    async fn leader_and_token(&self) -> Option<(u64, u64)> {
+        let state = self.state.lock().await;
+        let leader = match *state {
+            NodeState::Leader => Some(self.id),
+            NodeState::Defeated { leader } => leader,
+            NodeState::Candidate { .. } => None,
+        };
+        // the term only changes along with the state, and while the node
+        // knows a leader, it is the one the leader was elected in
+        leader.map(|leader| (leader, self.term()))
+    }
+
+    /// The fencing token of the leader the node knows, if any: the term the
+    /// leader was elected in, which grows with every leadership grant. The
+    /// leader attaches it to its writes to resources that remember the
+    /// largest token they saw, so that they reject the writes of a deposed
+    /// leader, which carry a smaller one.
+    pub async fn fencing_token(&self) -> Option<u64> {
+        self.leader_and_token().await.map(|(_, token)| token)
+    }
+
+    /// What the node knows of `leader`, if anything.
+    fn leader_info(&self, leader: Option<u64>) -> Option<LeaderInfo> {
+        self.leader_info.lock().unwrap().clone().filter(|info| Some(info.leader) == leader)
+    }
+
+    /// Follows the leader of `vouched`, which a neighbour vouched for as the
+    /// live leader of `term`, unless the node has left the candidacy it
+    /// started out with or moved past the term meanwhile. Returns whether it
+    /// did.
+    async fn follow(&self, vouched: LeaderInfo, term: u64, cause: Cause) -> bool {
+        let leader = vouched.leader;
+        let mut state = self.state.lock().await;
+        if !matches!(*state, NodeState::Candidate { .. }) || self.term.fetch_max(term, AtomicOrdering::SeqCst) > term {
+            return false
+        }
+        let from = std::mem::replace(&mut *state, NodeState::Defeated { leader: Some(leader) });
+        self.changed_state(&from, &state, cause);
+        self.end_phase_span();
+        self.saw_leader(self.clock.now());
+        self.observe_leader(Some(leader));
+        self.learn_leader(vouched);
+        self.publish(ElectionResult::Defeated { leader });
+        true
+    }
+
+    /// Leads the ring in `term` in place of `leader`, which hands its
+    /// leadership over or fell silent, as `cause` says, announcing it like an
+    /// elected leader would. Only a node that follows `leader` in an older
+    /// term, and does not merely observe, takes over. Returns whether it did.
+    async fn take_over(&self, leader: u64, term: u64, cause: Cause) -> bool {
+        let mut state = self.state.lock().await;
+        if self.observer || *state != (NodeState::Defeated { leader: Some(leader) }) || self.term.fetch_max(term, AtomicOrdering::SeqCst) >= term {
+            return false
+        }
+        info!(node = self.id, "taking over the leadership of term {} from node {}", term, leader);
+        let from = std::mem::replace(&mut *state, NodeState::Leader);
+        self.changed_state(&from, &state, cause);
+        self.ranking.lock().unwrap().clear();
+        self.observe_leader(Some(self.id));
+        self.publish(ElectionResult::Leader);
+        // the leader announces itself as it polls
+        self.timers.set(TimerKind::Poll, self.poll_interval());
+        true
+    }
+
+    /// Follows `successor`, which took the leadership over from the node for
+    /// `term`, unless the node already moved on to that term.
+    async fn follow_successor(&self, successor: u64, term: u64, addr: &str) {
+        let mut state = self.state.lock().await;
+        if self.term.fetch_max(term, AtomicOrdering::SeqCst) >= term {
+            return
+        }
+        let from = std::mem::replace(&mut *state, NodeState::Defeated { leader: Some(successor) });
+        self.changed_state(&from, &state, Cause::Handover { successor });
+        self.ranking.lock().unwrap().clear();
+        self.saw_leader(self.clock.now());
+        self.observe_leader(Some(successor));
+        // the successor tells the ring its metadata as it announces itself
+        self.learn_leader(LeaderInfo { leader: successor, addr: addr.to_string(), ..LeaderInfo::default() });
+        self.publish(ElectionResult::Defeated { leader: successor });
+    }
+
+    /// Records the leader the node knows of after a state change, and the
+    /// election that found it, if one just did.
+    fn observe_leader(&self, leader: Option<u64>) {
+        let leaderless = self.tenure.observe(leader, self.clock.now());
+        match (leader, leaderless) {
+            (None, _) => self.history.begin(),
+            (Some(leader), Some(duration)) => {
+                let election = self.history.finish(self.term(), leader, duration, self.clock.wall_now());
+                debug!(node = self.id, "the election of term {} took {:?}, {} phases and {} messages",
+                    election.term, duration, election.phases, election.messages);
+            },
+            (Some(_), None) => (),
+        }
+    }
+
+    /// Records that the leader was known to be alive at `at`.
+    fn saw_leader(&self, at: Instant) {
+        let mut seen = self.leader_seen.lock().unwrap();
+        *seen = Some(seen.map_or(at, |seen| seen.max(at)));
+    }
+
+    fn lead(&self, state: &mut MutexGuard<NodeState>, cause: Cause) -> Result<(), ElectionError> {
+        let leading = state_machine::lead(state).map_err(|Refused(action)| self.wrong_state(state, action))?;
+        if leading != **state {
+            let from = std::mem::replace(&mut **state, leading);
+            self.changed_state(&from, state, cause);
+            self.end_phase_span();
+            self.observe_leader(Some(self.id));
+            let payload = self.payload.as_ref().map(|payload| payload.payload(self.term()));
+            self.learn_leader(LeaderInfo { leader: self.id, metadata: self.metadata.to_vec(), payload, ..LeaderInfo::default() });
+            self.publish(ElectionResult::Leader);
+        }
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl ElectionAlgorithm for Node {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn subscribe(&self) -> watch::Receiver<ElectionResult> {
+        Node::subscribe(self)
+    }
+
+    fn shutdown(&self) {
+        Node::shutdown(self)
+    }
+
+    async fn run(self, addr: SocketAddr, config: &Config) -> Result<(), tonic::transport::Error> {
+        run_node(self, addr, config).await
+    }
+}
+
+/// The responses to a stream of election messages.
+pub(crate) type Responses<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// What a stream of messages comes with, besides the messages.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StreamContext {
+    /// The correlation ID of the stream.
+    request_id: Option<AsciiMetadataValue>,
+    /// The trace the client streams the messages in, if it gave one.
+    trace: Option<TraceContext>,
+    /// The allowance of the connection the stream came over.
+    limit: Option<ConnectionLimit>,
+}
+
+/// The context of the stream of messages `request` opens.
+pub(crate) fn stream_context<T>(request: &Request<T>) -> StreamContext {
+    StreamContext {
+        request_id: request_log::request_id(request.metadata()),
+        trace: request.metadata().get(traces::TRACEPARENT)
+            .and_then(|value| value.to_str().ok())
+            .and_then(traces::parse_traceparent),
+        limit: request.extensions().get::<ConnectionLimit>().cloned(),
+    }
+}
+
+impl Node {
+    /// Answers each of a stream of probes with the decision on it.
+    fn answer_probes<S>(&self, mut stream: S, StreamContext { request_id, trace, limit }: StreamContext)
+    -> Result<Responses<ProbeResponse>, ElectionError>
+    where S: Stream<Item = Result<ProbeMessage, Status>> + Send + Unpin + 'static {
+        self.refuse_unsigned()?;
+        let this = self.clone();
+        let pipe: async_stream::AsyncStream<Result<ProbeResponse, Status>, _> = async_stream::try_stream!{
+            debug!(node = this.id, "server waiting for probes");
+            while let Some(req) = stream.next().await {
+                this.throttle(limit.as_ref(), "a probe")?;
+                let req = req?;
+                this.until_resumed().await;
+                this.check_group(req.group_id)?;
+                let decision = this.on_probe(req, request_id.clone(), trace.clone()).await?;
+                let (kind, phase) = this.current_kind().await;
+                yield ProbeResponse { decision: decision as i32, responder_id: this.id, responder_state: kind as i32, responder_phase: phase };
+                debug!(node = this.id, "server finished processing a probe!");
+            }
+            debug!(node = this.id, "server closing connection");
+        };
+        Ok(Box::pin(self.respond(pipe)))
+    }
+
+    /// Answers each of a stream of notifications with the decision on it.
+    fn answer_notifications<S>(&self, mut stream: S, StreamContext { request_id, trace, limit }: StreamContext)
+    -> Result<Responses<NotifyResponse>, ElectionError>
+    where S: Stream<Item = Result<NotifyMessage, Status>> + Send + Unpin + 'static {
+        self.refuse_unsigned()?;
+        let this = self.clone();
+        let pipe: async_stream::AsyncStream<Result<NotifyResponse, Status>, _> = async_stream::try_stream!{
+            while let Some(req) = stream.next().await {
+                this.throttle(limit.as_ref(), "a notification")?;
+                let req = req?;
+                this.until_resumed().await;
+                this.check_group(req.group_id)?;
+                let (decision, leader_id) = this.on_notify(req, request_id.clone(), trace.clone()).await?;
+                yield NotifyResponse { decision: decision as i32, leader_id };
+            }
+        };
+        Ok(Box::pin(self.respond(pipe)))
+    }
+
+    /// Answers each of a stream of digests with the decision on it.
+    fn answer_digests<S>(&self, mut stream: S, StreamContext { request_id, limit, .. }: StreamContext)
+    -> Result<Responses<DigestResponse>, ElectionError>
+    where S: Stream<Item = Result<DigestMessage, Status>> + Send + Unpin + 'static {
+        self.refuse_unsigned()?;
+        let this = self.clone();
+        let pipe: async_stream::AsyncStream<Result<DigestResponse, Status>, _> = async_stream::try_stream!{
+            while let Some(req) = stream.next().await {
+                this.throttle(limit.as_ref(), "a digest")?;
+                let req = req?;
+                this.until_resumed().await;
+                this.check_group(req.group_id)?;
+                let decision = this.on_digest(req, request_id.clone()).await?;
+                yield DigestResponse { decision: decision as i32 };
+            }
+        };
+        Ok(Box::pin(self.respond(pipe)))
+    }
+
+    /// Acknowledges each of a stream of relayed messages once handled.
+    /// Messages without a trace of their own belong to that of the stream.
+    fn acknowledge<S>(&self, mut stream: S, StreamContext { trace, limit, .. }: StreamContext) -> Responses<PeerAck>
+    where S: Stream<Item = Result<PeerMessage, Status>> + Send + Unpin + 'static {
+        let this = self.clone();
+        let pipe: async_stream::AsyncStream<Result<PeerAck, Status>, _> = async_stream::try_stream!{
+            while let Some(req) = stream.next().await {
+                this.throttle(limit.as_ref(), "a relayed message")?;
+                let mut req = req?;
+                req.trace = req.trace.or_else(|| trace.clone());
+                let ack = this.receive(req).await?;
+                yield ack;
+            }
+        };
+        Box::pin(self.respond(pipe))
+    }
+}
+
+#[tonic::async_trait]
+impl LeaderElectionService for Node {
+    type NotifyElectedRawStream = Responses<NotifyResponse>;
+    type ProbeRawStream = Responses<ProbeResponse>;
+    type CheckDigestRawStream = Responses<DigestResponse>;
+    type RelayStream = Responses<PeerAck>;
+    type WatchLeaderStream = Responses<LeaderResponse>;
+
+    async fn probe_raw(&self, request: Request<tonic::Streaming<ProbeMessage>>)
+    -> Result<Response<Self::ProbeRawStream>, Status> {
+        let context = stream_context(&request);
+        Ok(Response::new(self.answer_probes(request.into_inner(), context)?))
+    }
+
+    async fn notify_elected_raw(&self, request: Request<tonic::Streaming<NotifyMessage>>)
+    -> Result<Response<Self::NotifyElectedRawStream>, Status> {
+        let context = stream_context(&request);
+        Ok(Response::new(self.answer_notifications(request.into_inner(), context)?))
+    }
+
+    async fn check_digest_raw(&self, request: Request<tonic::Streaming<DigestMessage>>)
+    -> Result<Response<Self::CheckDigestRawStream>, Status> {
+        let context = stream_context(&request);
+        Ok(Response::new(self.answer_digests(request.into_inner(), context)?))
+    }
+
+    async fn relay(&self, request: Request<tonic::Streaming<PeerMessage>>)
+    -> Result<Response<Self::RelayStream>, Status> {
+        let context = stream_context(&request);
+        Ok(Response::new(self.acknowledge(request.into_inner(), context)))
+    }
+
+    async fn get_metrics(&self, request: Request<MetricsRequest>) -> Result<Response<MetricsResponse>, Status> {
+        self.check_group(request.get_ref().group_id)?;
+        Ok(Response::new(MetricsResponse { text: self.render_metrics().await }))
+    }
+
+    async fn get_stats(&self, request: Request<StatsRequest>) -> Result<Response<StatsResponse>, Status> {
+        self.check_group(request.get_ref().group_id)?;
+        Ok(Response::new(StatsResponse { windows: self.stats.windows(self.clock.now()) }))
+    }
+
+    async fn get_leader(&self, request: Request<LeaderRequest>) -> Result<Response<LeaderResponse>, Status> {
+        self.check_leader_request(request.get_ref())?;
+        Ok(Response::new(self.leader_response().await))
+    }
+
+    async fn watch_leader(&self, request: Request<LeaderRequest>) -> Result<Response<Self::WatchLeaderStream>, Status> {
+        self.check_leader_request(request.get_ref())?;
+        let (this, mut results) = (self.clone(), self.results.subscribe());
+        let pipe: async_stream::AsyncStream<Result<LeaderResponse, Status>, _> = async_stream::try_stream!{
+            let mut last = None;
+            loop {
+                let learned = this.leader_learned.notified();
+                let leader = this.leader_response().await;
+                if last.as_ref() != Some(&leader) {
+                    last = Some(leader.clone());
+                    yield leader;
+                }
+                tokio::select! {
+                    changed = results.changed() => if changed.is_err() { break },
+                    _ = learned => (),
+                }
+            }
+        };
+        Ok(Response::new(Box::pin(self.respond(pipe))))
+    }
+
+    async fn get_anomalies(&self, request: Request<AnomaliesRequest>) -> Result<Response<AnomaliesResponse>, Status> {
+        self.check_group(request.get_ref().group_id)?;
+        Ok(Response::new(AnomaliesResponse { anomalies: self.anomalies.recent(), total: self.anomalies.total() }))
+    }
+
+    async fn get_ranking(&self, request: Request<RankingRequest>) -> Result<Response<RankingResponse>, Status> {
+        self.check_group(request.get_ref().group_id)?;
+        Ok(Response::new(RankingResponse { ranking: self.ranking.lock().unwrap().clone() }))
+    }
+
+    async fn reconfigure(&self, request: Request<ReconfigureRequest>) -> Result<Response<ReconfigureResponse>, Status> {
+        let ReconfigureRequest { epoch, left, right, group_id } = request.into_inner();
+        self.check_group(group_id)?;
+        let (left, right) = (left.map(|left| self.peer_of(left)).transpose()?, right.map(|right| self.peer_of(right)).transpose()?);
+        self.advance_epoch(epoch)?;
+        for (side, queue, peer) in [("left", &self.left, left), ("right", &self.right, right)] {
+            if let Some(peer) = peer {
+                info!(node = self.id, "now has node {} at {} as its {} neighbour (epoch {})", peer.id, peer.endpoint.uri(), side, epoch);
+                queue.retarget(peer);
+            }
+        }
+        Ok(Response::new(ReconfigureResponse {}))
+    }
+
+    async fn heartbeat(&self, request: Request<HeartbeatRequest>) -> Result<Response<HeartbeatResponse>, Status> {
+        self.check_group(request.get_ref().group_id)?;
+        let now = self.clock.now();
+        let (leader, seen) = match *self.state.lock().await {
+            NodeState::Leader => (Some(self.id), Some(now)),
+            NodeState::Defeated { leader } => (leader, *self.leader_seen.lock().unwrap()),
+            NodeState::Candidate { .. } => (None, None),
+        };
+        let (left, successors, epoch) = self.membership();
+        let Partition { whole, quorate } = self.partition().unwrap_or(Partition { whole: 0, quorate: false });
+        Ok(Response::new(HeartbeatResponse {
+            leader_id: leader.unwrap_or_default(),
+            leader_known: leader.is_some() && seen.is_some(),
+            leader_seen_ms_ago: seen.map_or(0, |seen| now.saturating_duration_since(seen).as_millis() as u64),
+            left: Some(left),
+            successors,
+            epoch,
+            term: self.term(),
+            ring_size: self.ring_size(),
+            whole_size: whole,
+            quorate,
+        }))
+    }
+
+    async fn pre_vote(&self, request: Request<PreVoteRequest>) -> Result<Response<PreVoteResponse>, Status> {
+        let PreVoteRequest { candidate_id, group_id } = request.into_inner();
+        self.check_group(group_id)?;
+        let now = self.clock.now();
+        let leader = match *self.state.lock().await {
+            NodeState::Leader => Some(self.id),
+            // the digests of a live leader come by every interval
+            NodeState::Defeated { leader: Some(leader) }
+                if self.leader_seen.lock().unwrap().is_some_and(|seen| now.saturating_duration_since(seen) < 2 * self.digest_interval()) => Some(leader),
+            _ => None,
+        }.filter(|&leader| leader != candidate_id);
+        let LeaderInfo { addr: leader_addr, metadata: leader_metadata, payload, .. } = self.leader_info(leader).unwrap_or_default();
+        if let Some(leader) = leader {
+            info!(node = self.id, "telling node {} that leader {} is alive", candidate_id, leader);
+        }
+        Ok(Response::new(PreVoteResponse { granted: leader.is_none(), leader_id: leader.unwrap_or_default(), term: self.term(), leader_addr, leader_metadata, payload }))
+    }
+
+    async fn take_over(&self, request: Request<TakeOverRequest>) -> Result<Response<TakeOverResponse>, Status> {
+        let TakeOverRequest { leader_id, term, group_id } = request.into_inner();
+        self.check_group(group_id)?;
+        self.check_term(term)?;
+        Ok(Response::new(TakeOverResponse { taken_over: Node::take_over(self, leader_id, term, Cause::TakeOver { leader: leader_id }).await }))
+    }
+
+    async fn join(&self, request: Request<JoinRequest>) -> Result<Response<JoinResponse>, Status> {
+        let JoinRequest { epoch, node, group_id } = request.into_inner();
+        self.check_group(group_id)?;
+        let node = node.ok_or_else(|| ElectionError::InvalidMessage { node: self.id, state: None, reason: "no node to join".to_string() })?;
+        let newcomer = self.peer_of(node.clone())?;
+        self.advance_epoch(epoch)?;
+        let right = self.right.peer();
+        let mut client = self.connect(&right.endpoint).await?;
+        client.reconfigure(self.deadline(ReconfigureRequest { epoch, left: Some(node), right: None, group_id: self.group })).await?;
+        info!(node = self.id, "splicing node {} in before node {} (epoch {})", newcomer.id, right.id, epoch);
+        self.right.retarget(newcomer);
+        // the restart reaches the new node last, once it knows its right neighbour
+        let ring_size = self.ring_size() + 1;
+        client.reelect(self.deadline(self.reelect_request(epoch, ring_size, self.next_term()?))).await?;
+        Ok(Response::new(JoinResponse {
+            right: Some(Neighbor { id: right.id, addr: right.endpoint.uri().to_string() }),
+            ring_size,
+        }))
+    }
+
+    async fn leave(&self, request: Request<LeaveRequest>) -> Result<Response<LeaveResponse>, Status> {
+        let LeaveRequest { epoch, group_id } = request.into_inner();
+        self.check_group(group_id)?;
+        let (left, right) = (self.left.peer(), self.right.peer());
+        if left.id == self.id {
+            let state = self.state.lock().await;
+            return Err(self.wrong_state(&state, "leave a ring of its own").into())
+        }
+        self.advance_epoch(epoch)?;
+        let neighbor = |peer: &Peer| Some(Neighbor { id: peer.id, addr: peer.endpoint.uri().to_string() });
+        self.connect(&left.endpoint).await?
+            .reconfigure(self.deadline(ReconfigureRequest { epoch, left: None, right: neighbor(&right), group_id: self.group })).await?;
+        let mut client = self.connect(&right.endpoint).await?;
+        client.reconfigure(self.deadline(ReconfigureRequest { epoch, left: neighbor(&left), right: None, group_id: self.group })).await?;
+        info!(node = self.id, "leaving, node {} now follows node {} (epoch {})", right.id, left.id, epoch);
+        // the ring lost a node, possibly its leader, and has to agree on the ranking anew
+        let ring_size = self.ring_size().saturating_sub(1);
+        client.reelect(self.deadline(self.reelect_request(epoch, ring_size, self.next_term()?))).await?;
+        self.shutdown();
+        Ok(Response::new(LeaveResponse {}))
+    }
+
+    async fn reelect(&self, request: Request<ReelectRequest>) -> Result<Response<ReelectResponse>, Status> {
+        let caller = request.remote_addr();
+        let ReelectRequest { epoch, ring_size, term, group_id, whole_size, quorate } = request.into_inner();
+        self.check_group(group_id)?;
+        self.check_term(term)?;
+        // the restart is passed on only once, however often it comes around
+        if self.reelection_epoch.load(AtomicOrdering::SeqCst) < epoch {
+            self.admit_restart(caller)?;
+            *self.partition.lock().unwrap() = (whole_size != 0).then_some(Partition { whole: whole_size, quorate });
+        }
+        Node::reelect(self, epoch, ring_size, term);
+        Ok(Response::new(ReelectResponse {}))
+    }
+
+    async fn resize(&self, request: Request<ResizeRequest>) -> Result<Response<ResizeResponse>, Status> {
+        let ResizeRequest { epoch, ring_size, group_id } = request.into_inner();
+        self.check_group(group_id)?;
+        Node::resize(self, epoch, ring_size);
+        Ok(Response::new(ResizeResponse {}))
+    }
+
+    async fn merge(&self, request: Request<MergeRequest>) -> Result<Response<MergeResponse>, Status> {
+        let merge = request.into_inner();
+        self.check_group(merge.group_id)?;
+        self.check_term(merge.term)?;
+        Node::merge(self, merge);
+        Ok(Response::new(MergeResponse {}))
+    }
+
+    async fn introduce(&self, request: Request<IntroductionRequest>) -> Result<Response<IntroductionResponse>, Status> {
+        let IntroductionRequest { id, incarnation, group_id, version, oldest_version } = request.into_inner();
+        self.check_group(group_id)?;
+        if id == self.id && incarnation != self.incarnation {
+            error!(node = self.id, "a neighbour starting up has this node's ID, refusing it");
+            return Err(ElectionError::DuplicateId { node: self.id }.into())
+        }
+        if !compatible(version, oldest_version) {
+            error!(node = self.id, "neighbour {} speaks relay protocol version {}, refusing it", id, version);
+            return Err(ElectionError::IncompatibleVersion { node: self.id, version }.into())
+        }
+        Ok(Response::new(IntroductionResponse {
+            id: self.id, incarnation: self.incarnation, version: RELAY_VERSION, oldest_version: OLDEST_RELAY_VERSION,
+        }))
+    }
+
+    async fn forward_to_leader(&self, request: Request<ForwardRequest>) -> Result<Response<ForwardResponse>, Status> {
+        let ForwardRequest { route, payload, hops, group_id } = request.into_inner();
+        self.check_group(group_id)?;
+        Ok(Response::new(self.forward(route, payload, hops).await?))
+    }
+
+    async fn get_state(&self, request: Request<StateRequest>) -> Result<Response<StateResponse>, Status> {
+        self.check_group(request.get_ref().group_id)?;
+        let state = self.state.lock().await.clone();
+        let (kind, phase) = kind_of(&state);
+        let leader = match state {
+            NodeState::Defeated { leader } => leader,
+            NodeState::Leader => Some(self.id),
+            NodeState::Candidate { .. } => None,
+        };
+        let deputy = self.deputy();
+        Ok(Response::new(StateResponse {
+            id: self.id,
+            kind: kind as i32,
+            phase,
+            leader_id: leader.unwrap_or_default(),
+            leader_known: leader.is_some(),
+            ring_size: self.ring_size(),
+            timers: self.timers.armed().into_iter().map(|(kind, deadline)| ArmedTimer {
+                name: format!("{:?}", kind),
+                remaining_ms: deadline.saturating_duration_since(self.clock.now()).as_millis() as u64,
+            }).collect(),
+            committee: self.committee(),
+            deputy_id: deputy.unwrap_or_default(),
+            deputy_known: deputy.is_some(),
+            left_id: self.left.peer().id,
+            right_id: self.right.peer().id,
+            left_reachability: reachability(self.left.reachable()) as i32,
+            right_reachability: reachability(self.right.reachable()) as i32,
+            term: self.term(),
+            lamport: self.lamport.load(AtomicOrdering::SeqCst),
+            priority: self.priority,
+            group_id: self.group,
+        }))
+    }
+}
+
+/// Asks the neighbours of `node`, unless it starts out knowing its leader,
+/// whether they follow a live leader, so that a restarted node rejoins a
+/// settled ring as a follower instead of starting an election that would
+/// disrupt it. The node only goes on to probe if neither neighbour does,
+/// or neither answers.
+async fn pre_vote(node: &Node) {
+    if !matches!(*node.state.lock().await, NodeState::Candidate { .. }) {
+        return
+    }
+    for neighbor in [&node.left, &node.right] {
+        let peer = neighbor.peer();
+        let ask = async {
+            let mut client = node.connect(&peer.endpoint).await?;
+            Ok::<_, Status>(client.pre_vote(node.deadline(PreVoteRequest { candidate_id: node.id, group_id: node.group })).await?.into_inner())
+        };
+        match ask.await {
+            Ok(PreVoteResponse { granted: false, leader_id, term, leader_addr, leader_metadata, payload }) => {
+                let vouched = LeaderInfo { leader: leader_id, addr: leader_addr, metadata: leader_metadata, payload };
+                if node.follow(vouched, term, Cause::PreVote { voucher: peer.id }).await {
+                    info!(node = node.id, "node {} vouched for leader {} of term {}, following it", peer.id, leader_id, term);
+                }
+                return
+            },
+            Ok(_) => debug!(node = node.id, "node {} knows of no live leader", peer.id),
+            Err(e) => debug!(node = node.id, "cannot ask node {} for a pre-vote: {}", peer.id, e),
+        }
+    }
+}
+
+/// Tells the neighbours of `node` its ID and relay protocol version,
+/// failing if either has the same ID or a version the two cannot relay
+/// messages with. A neighbour that cannot be reached is retried for up to
+/// `patience`, after which, or right away without any, it is not checked.
+/// One from before the introductions speaks version 1.
+async fn introduce(node: &Node, patience: Option<Duration>) -> Result<(), ElectionError> {
+    let deadline = patience.map(|patience| node.clock.now() + patience);
+    for neighbor in [&node.left, &node.right] {
+        let peer = neighbor.peer();
+        let ask = || async {
+            let mut client = node.connect(&peer.endpoint).await?;
+            let request = IntroductionRequest {
+                id: node.id, incarnation: node.incarnation, group_id: node.group, version: RELAY_VERSION, oldest_version: OLDEST_RELAY_VERSION,
+            };
+            Ok::<_, Status>(client.introduce(node.deadline(request)).await?.into_inner())
+        };
+        let answer = match deadline {
+            Some(deadline) => {
+                // a neighbour that refuses this node will not change its mind
+                let answered = || async {
+                    match ask().await {
+                        Err(e) if !matches!(error::reason(&e), Some(Reason::DuplicateId | Reason::IncompatibleVersion)) => Err(e),
+                        answer => Ok(answer),
+                    }
+                };
+                let backoff = RetryPolicy { max_attempts: None, ..node.retry }.backoff(node.seed);
+                let retrying = |e: &Status, delay| debug!(node = node.id, "node {} is not up yet, asking again in {:?}: {}", peer.id, delay, e.message());
+                tokio::select! {
+                    answer = retry(backoff, &*node.clock, answered, retrying) => answer.unwrap_or_else(Err),
+                    _ = node.clock.sleep_until(deadline) => {
+                        warn!(node = node.id, "node {} did not answer within {:?}, starting without it", peer.id, patience.unwrap_or_default());
+                        continue
+                    },
+                }
+            },
+            None => ask().await,
+        };
+        match answer {
+            Ok(IntroductionResponse { id, incarnation, .. }) if id == node.id && incarnation != node.incarnation => {
+                return Err(ElectionError::DuplicateId { node: node.id })
+            },
+            Ok(IntroductionResponse { version, oldest_version, .. }) if !compatible(version, oldest_version) => {
+                return Err(ElectionError::IncompatibleVersion { node: node.id, version })
+            },
+            Ok(IntroductionResponse { id, version, .. }) => {
+                debug!(node = node.id, "neighbour {} introduced itself as {}, speaking relay protocol version {}", peer.id, id, version)
+            },
+            Err(e) => match error::reason(&e) {
+                Some(Reason::DuplicateId) => return Err(ElectionError::DuplicateId { node: node.id }),
+                // the neighbour does not understand this node
+                Some(Reason::IncompatibleVersion) => return Err(ElectionError::IncompatibleVersion { node: peer.id, version: RELAY_VERSION }),
+                _ => debug!(node = node.id, "cannot introduce this node to node {}: {}", peer.id, e),
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Takes part in the election on behalf of `node`, probing its neighbours
+/// while it is a candidate and circulating the leader's notification and
+/// digests once it leads, without serving the node. Runs until the node
+/// shuts down.
+pub async fn node_client(node: Node) {
+    for neighbor in [node.left.clone(), node.right.clone()] {
+        let node = node.clone();
+        // dropping the drain closes the connection to the neighbour
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = node.clone().drain(neighbor) => (),
+                _ = node.stopped() => (),
+            }
+        });
+    }
+    elect(node).await
+}
+
+/// Takes part in the election as the node's timers fire, leaving the
+/// delivery of the messages it queues to whoever drains the queues.
+async fn elect(node: Node) {
+    node.arm_election_deadline();
+    node.timers.set(TimerKind::StartupGrace, node.startup_delay());
+    loop {
+        match node.timers.fired().await {
+            TimerKind::StartupGrace => break,
+            TimerKind::ElectionDeadline => node.time_out(&*node.state.lock().await),
+            _ => (),
+        }
+    }
+
+    node.timers.set(TimerKind::Poll, node.poll_interval());
+    loop {
+        let timer = node.timers.fired().await;
+        node.until_resumed().await;
+        debug!(node = node.id, "client waiting for mutex lock ({:?} timer fired)", timer);
+        let mut state = node.state.lock().await;
+        match (timer, &*state) {
+            (TimerKind::StartupGrace, _) => (),
+            (TimerKind::Lease, NodeState::Leader) if node.lease.as_ref().and_then(|lease| lease.expires()) <= Some(node.clock.now()) => {
+                warn!(node = node.id, "lease ran out without the ring confirming the leadership, starting a new election");
+                drop(state);
+                node.cool_down();
+                node.start_election().await;
+            },
+            (TimerKind::Lease, _) => (),
+            (TimerKind::ElectionDeadline, _) => node.time_out(&state),
+            (TimerKind::Poll, _) if *node.results.borrow() == ElectionResult::TimedOut => (),
+            (_, NodeState::Defeated { .. }) => {
+                // idle until a new election starts
+                info!(node = node.id, "is defeated");
+                node.timers.cancel(TimerKind::Digest);
+            },
+            (TimerKind::Poll, _) => for command in state_machine::react(&state, node.me(), Input::Poll) {
+                match command {
+                    Command::Take(event) => if let Err(e) = node.take(&mut state, event, Cause::Poll) {
+                        warn!(node = node.id, "skipping a reaction to polling: {}", e);
+                    },
+                    Command::SendProbe { phase, headed_left } => {
+                        let target = node.neighbor(headed_left);
+                        let mut span = node.tracer.root("phase");
+                        span.attribute("phase", phase);
+                        let trace = span.context();
+                        *node.phase_span.lock().unwrap() = span;
+                        let peer = target.peer();
+                        async {
+                            info!("sending probe");
+                            let probe = ProbeMessage { sender_id: node.id, headed_left, phase, seq: None, term: node.term(), priority: node.priority, group_id: node.group, hops: 0, hops_remaining: 0, zone: node.zone.clone() };
+                            target.push(Message::Probe(probe), None, trace).await;
+                            node.probes.sent.fetch_add(1, AtomicOrdering::Relaxed);
+                            node.history.candidate(node.id, phase);
+                            debug!("sent a probe");
+                        }.instrument(tracing::info_span!("phase", node = node.id, phase, peer = peer.id, addr = %peer.endpoint.uri())).await;
+                    },
+                    Command::Poll => node.timers.set(TimerKind::Poll, node.poll_interval()),
+                    Command::Announce => {
+                        info!(node = node.id, "is the leader");
+                        let span = node.tracer.root("notification");
+                        let notification = NotifyMessage {
+                            leader_id: node.id, headed_left: true, seq: None, ranking: vec![node.id], leader_addr: node.advertised.clone().unwrap_or_default(),
+                            term: node.term(), priorities: vec![node.priority], group_id: node.group, leader_metadata: node.metadata.to_vec(),
+                            payload: node.leader_info(Some(node.id)).and_then(|info| info.payload),
+                        };
+                        node.left.push(Message::Notify(notification), None, span.context()).await;
+                        if let Some(lease) = &node.lease {
+                            lease.grant(node.clock.now());
+                            node.timers.set(TimerKind::Lease, lease.duration);
+                        }
+                        node.timers.set(TimerKind::Digest, node.digest_interval());
+                    },
+                    command => warn!(node = node.id, "skipping {:?}, which does not follow polling", command),
+                }
+            },
+            (TimerKind::Digest, NodeState::Leader) => {
+                // periodically send the leader's view of the cluster around the ring
+                let ranking = node.ranking.lock().unwrap().clone();
+                let digest = DigestMessage { leader_id: node.id, ring_size: node.ring_size(), seq: None, ranking, term: node.term(), group_id: node.group, hops: 0 };
+                if let Some(lease) = &node.lease {
+                    lease.renewing(node.clock.now());
+                }
+                node.left.push(Message::Digest(digest), None, None).await;
+                node.timers.set(TimerKind::Digest, node.digest_interval());
+            },
+            (TimerKind::Digest, NodeState::Candidate { .. }) => (),
+        }
+    }
+}
+
+/// Raises an alarm whenever the node goes `threshold` without knowing of a
+/// leader, once per leaderless stretch, running `hook` if there is one.
+async fn watch_leader(node: Node, threshold: Duration, hook: Option<String>) {
+    let mut alarmed = None;
+    let mut ticks = node.clock.clone().interval(threshold / 4);
+    while ticks.next().await.is_some() {
+        let since = match node.tenure.leaderless_since() {
+            Some(since) if alarmed != Some(since) => since,
+            _ => continue,
+        };
+        let leaderless = node.clock.now().saturating_duration_since(since);
+        if leaderless < threshold {
+            continue
+        }
+        alarmed = Some(since);
+        warn!(node = node.id, "no leader known for {:?}", leaderless);
+        if let Some(hook) = hook.clone() {
+            let (id, millis) = (node.id, leaderless.as_millis());
+            tokio::task::spawn_blocking(move || {
+                // keep the hook's output out of the message log on stdout
+                let status = std::process::Command::new("sh").arg("-c").arg(&hook)
+                    .stdout(std::io::stderr())
+                    .env("LE_NODE", id.to_string())
+                    .env("LE_LEADERLESS_MS", millis.to_string())
+                    .status();
+                match status {
+                    Ok(status) if status.success() => (),
+                    Ok(status) => error!(node = id, "no-leader hook failed: {}", status),
+                    Err(e) => warn!(node = id, "cannot run the no-leader hook: {}", e),
+                }
+            });
+        }
+    }
+}
+
+/// Starts a new election whenever the node goes `timeout` without hearing
+/// that the leader it follows is alive. The node asks its right neighbour,
+/// which the leader's notification came from, every quarter of `timeout`;
+/// the neighbour answers with when it last heard of the leader, so news of
+/// the leader is passed along the ring hop by hop. The leader's deputy
+/// instead takes over right away once its neighbours confirm the silence,
+/// which the other nodes give half a `timeout` longer before they restart
+/// the election.
+async fn monitor_leader(node: Node, timeout: Duration) {
+    let mut client: Option<(Endpoint, Client)> = None;
+    let mut ticks = node.clock.clone().interval(timeout / 4);
+    while ticks.next().await.is_some() {
+        let leader = match *node.state.lock().await {
+            NodeState::Defeated { leader: Some(leader) } => leader,
+            _ => continue,
+        };
+        let peer = node.right.peer();
+        if !matches!(&client, Some((endpoint, _)) if endpoint.uri() == peer.endpoint.uri()) {
+            client = node.connect(&peer.endpoint).await.ok().map(|connected| (peer.endpoint.clone(), connected));
+        }
+        let asked = node.clock.now();
+        if let Some((_, connected)) = &mut client {
+            match connected.heartbeat(node.deadline(HeartbeatRequest { group_id: node.group })).await {
+                Ok(response) => {
+                    let response = response.into_inner();
+                    let seen = asked.checked_sub(Duration::from_millis(response.leader_seen_ms_ago));
+                    if let Some(seen) = seen.filter(|_| response.leader_known && response.leader_id == leader) {
+                        node.saw_leader(seen);
+                    }
+                },
+                Err(_) => client = None,
+            }
+        }
+
+        let seen = *node.leader_seen.lock().unwrap();
+        let silent = seen.map_or(timeout, |seen| node.clock.now().saturating_duration_since(seen));
+        let patience = match node.deputy() {
+            Some(deputy) if deputy != node.id && deputy != leader => timeout + timeout / 2,
+            _ => timeout,
+        };
+        if silent >= patience {
+            if node.deputy() == Some(node.id) && confirm_silence(&node, leader).await {
+                if let Ok(term) = node.next_term() {
+                    if node.take_over(leader, term, Cause::Failover { leader }).await {
+                        warn!(node = node.id, "no sign of leader {} for {:?}, taking over as its deputy in term {}", leader, silent, term);
+                        continue
+                    }
+                }
+            }
+            warn!(node = node.id, "no sign of leader {} for {:?}, starting a new election", leader, silent);
+            node.start_election().await;
+        }
+    }
+}
+
+/// Asks the neighbours of `node`, the deputy of `leader`, whether they lost
+/// sight of a live leader too, the way a candidate asks for a pre-vote.
+/// Confirms that `leader` is gone, so that the deputy takes over without a
+/// full election, only if a neighbour other than `leader` says so and none
+/// vouches for a live leader.
+async fn confirm_silence(node: &Node, leader: u64) -> bool {
+    let mut confirmed = false;
+    for neighbor in [&node.left, &node.right] {
+        let peer = neighbor.peer();
+        if peer.id == leader || peer.id == node.id {
+            continue
+        }
+        let ask = async {
+            let mut client = node.connect(&peer.endpoint).await?;
+            Ok::<_, Status>(client.pre_vote(node.deadline(PreVoteRequest { candidate_id: node.id, group_id: node.group })).await?.into_inner())
+        };
+        match ask.await {
+            Ok(PreVoteResponse { granted: true, .. }) => confirmed = true,
+            Ok(PreVoteResponse { leader_id, .. }) => {
+                info!(node = node.id, "node {} still sees leader {}, not taking over", peer.id, leader_id);
+                return false
+            },
+            Err(e) => debug!(node = node.id, "cannot ask node {} to confirm leader {} is gone: {}", peer.id, leader, e),
+        }
+    }
+    confirmed
+}
+
+/// Serves the metrics of `node`, listening on `listen`, to Prometheus at
+/// `/metrics` on the port `offset` above, until the node shuts down. With
+/// the `web` feature the port also serves the JSON gateway of [`web::handle`].
+async fn serve_metrics(node: Node, listen: SocketAddr, offset: u16) {
+    let addr = match listen.port().checked_add(offset) {
+        Some(port) => SocketAddr::new(listen.ip(), port),
+        None => {
+            error!(node = node.id, "cannot serve metrics {} ports above port {}", offset, listen.port());
+            return
+        },
+    };
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Method, StatusCode};
+    let make_service = make_service_fn(|_| {
+        let node = node.clone();
+        async move {
+            Ok::<_, std::convert::Infallible>(service_fn(move |request: hyper::Request<Body>| {
+                let node = node.clone();
+                async move {
+                    if (request.method(), request.uri().path()) == (&Method::GET, "/metrics") {
+                        return hyper::Response::builder()
+                            .header(http::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+                            .body(Body::from(node.render_metrics().await))
+                    }
+                    #[cfg(feature = "web")]
+                    if let Some(response) = web::handle(&node, request).await {
+                        return response
+                    }
+                    hyper::Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty())
+                }
+            }))
+        }
+    });
+    let server = match hyper::Server::try_bind(&addr) {
+        Ok(builder) => builder.serve(make_service),
+        Err(e) => {
+            error!(node = node.id, "cannot serve metrics on {}: {}", addr, e);
+            return
+        },
+    };
+    info!(node = node.id, "serving metrics on http://{}/metrics", addr);
+    if let Err(e) = server.with_graceful_shutdown(node.stopped()).await {
+        error!(node = node.id, "stopped serving metrics: {}", e);
+    }
+}
+
+/// Splices `node`, listening on `addr`, into the ring right of its left
+/// neighbour at topology `epoch`, and points it at the right neighbour the
+/// left one had.
+async fn join_ring(node: &Node, epoch: u64, addr: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
+    let left = node.left.peer();
+    let me = Neighbor { id: node.id, addr: node.advertised.clone().unwrap_or_else(|| format!("http://{}", addr)) };
+    let JoinResponse { right, ring_size } = node.connect(&left.endpoint).await?
+        .join(JoinRequest { epoch, node: Some(me), group_id: node.group }).await?
+        .into_inner();
+    let right = node.peer_of(right.ok_or("no right neighbour to take")?)?;
+    info!(node = node.id, "joined the ring of {} nodes between node {} and node {} (epoch {})", ring_size, left.id, right.id, epoch);
+    node.topology_epoch.fetch_max(epoch, AtomicOrdering::SeqCst);
+    node.ring_size.store(ring_size, AtomicOrdering::SeqCst);
+    node.right.retarget(right);
+    Ok(())
+}
+
+/// Lets browsers call `service` over grpc-web, with the `web` feature.
+#[cfg(feature = "web")]
+fn web<S>(service: S) -> impl tower::Service<
+    http::Request<hyper::Body>,
+    Response = http::Response<tonic::body::BoxBody>,
+    Error = S::Error,
+    Future = impl Send + 'static,
+> + tonic::transport::NamedService + Clone + Send + 'static
+where
+    S: tower::Service<http::Request<hyper::Body>, Response = http::Response<tonic::body::BoxBody>>,
+    S: tonic::transport::NamedService + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<Box<dyn std::error::Error + Send + Sync>> + Send,
+{
+    tonic_web::enable(service)
+}
+
+#[cfg(not(feature = "web"))]
+fn web<S>(service: S) -> S {
+    service
+}
+
+/// Waits until the host `endpoint` points at resolves, which the names of
+/// containers only do once the containers are up.
+async fn until_resolvable(node: &Node, endpoint: &Endpoint) {
+    let uri = endpoint.uri();
+    let addr = match uri.host() {
+        Some(host) => format!("{}:{}", host, uri.port_u16().unwrap_or(80)),
+        None => return,
+    };
+    let backoff = RetryPolicy { max_attempts: None, ..node.retry }.backoff(node.seed);
+    let resolve = || tokio::net::lookup_host(addr.clone());
+    let retrying = |e: &std::io::Error, delay| warn!(node = node.id, "cannot resolve {}, retrying in {:?}: {}", addr, delay, e);
+    let _ = retry(backoff, &*node.clock, resolve, retrying).await;
+}
+
+/// Serves `node` on `addr` and takes part in the election, raising the
+/// alarms `config` asks for. Runs until the server fails or the node is
+/// shut down.
+pub async fn run_node(node: Node, addr: SocketAddr, config: &Config) -> Result<(), tonic::transport::Error> {
+    let (health, health_service) = health::service::<LeaderElectionServiceServer<Node>>().await;
+    // shared by all addresses, for the concurrency limit to hold for the node
+    let overload = OverloadLayer::new(&config.middleware);
+    let serve = |addr, tls: Option<Arc<Tls>>| {
+        let mut server = tls::server(&node.timing(), &config.middleware);
+        if let Some(tls) = tls {
+            server = server.tls_config(tls.server.clone())?;
+        }
+        let server = server
+            // grpc-web comes over HTTP/1.1
+            .accept_http1(cfg!(feature = "web"))
+            .layer(node.layers(Side::Server, None).layer(node.auth_layer()).layer(overload.clone()).layer(node.limits.clone()))
+            .add_service(web(compressed!(LeaderElectionServiceServer::new(node.clone()), node.compression)))
+            .add_service(web(compressed!(AdminServiceServer::new(node.clone()), node.compression)))
+            .add_service(health_service.clone());
+        // describes le.proto and the health service to tools like grpcurl
+        #[cfg(feature = "reflection")]
+        let server = server.add_service(tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(leader_election_service::FILE_DESCRIPTOR_SET)
+            .register_encoded_file_descriptor_set(tonic_health::proto::GRPC_HEALTH_V1_FILE_DESCRIPTOR_SET)
+            .build()
+            .expect("the compiled descriptor sets are valid"));
+        Ok(server.serve_with_shutdown(addr, node.stopped()))
+    };
+    let servers = std::iter::once(addr).chain(config.also_listen.iter().copied()).map(|addr| serve_tls(&node, move |tls| serve(addr, tls)));
+    let (served, ()) = futures::future::join(futures::future::try_join_all(servers), participate(node.clone(), addr, config, Some(health))).await;
+    served.map(drop)
+}
+
+/// Reads the certificates of `node` again whenever their files change,
+/// looking every `interval`.
+async fn watch_certificates(node: Node, interval: Duration) {
+    let mut ticks = node.clock.clone().interval(interval);
+    while ticks.next().await.is_some() {
+        if let Err(e) = node.reload_tls() {
+            warn!(node = node.id, "cannot reload its certificates: {}", e);
+        }
+    }
+}
+
+/// Runs the server `serve` makes with the certificates of `node`, and makes
+/// it again with the new ones whenever they are reloaded, until it stops.
+pub(crate) async fn serve_tls<S, F>(node: &Node, serve: S) -> Result<(), tonic::transport::Error>
+where
+    S: Fn(Option<Arc<Tls>>) -> Result<F, tonic::transport::Error>,
+    F: Future<Output = Result<(), tonic::transport::Error>>,
+{
+    let mut reloaded = node.tls.subscribe();
+    loop {
+        let tls = reloaded.borrow_and_update().clone();
+        // dropping the server closes its listener for the next one, and has
+        // the connections it took finish the calls they are in
+        tokio::select! {
+            served = serve(tls)? => return served,
+            Ok(()) = reloaded.changed() => info!(node = node.id, "serving with its reloaded certificates"),
+        }
+    }
+}
+
+/// Takes part in the election as `node`, which the application serves on
+/// `addr` itself, e.g. through an [`embed::LeaderElectionLayer`], and raises
+/// the alarms `config` asks for. Runs until the node is shut down.
+pub async fn take_part(node: Node, addr: SocketAddr, config: &Config) {
+    participate(node, addr, config, None).await
+}
+
+/// Everything [`run_node`] does but serve the node's gRPC services,
+/// reporting whether the node takes part in the election to `health`.
+async fn participate(node: Node, addr: SocketAddr, config: &Config, mut health: Option<HealthReporter>) {
+    let (join, await_neighbours) = (config.join, config.await_neighbours);
+    let metrics = config.metrics_port_offset.map(|offset| serve_metrics(node.clone(), addr, offset));
+    #[cfg(feature = "dashboard")]
+    let dashboard = config.dashboard_port_offset.map(|offset| dashboard::serve(node.clone(), addr, offset));
+    #[cfg(not(feature = "dashboard"))]
+    let dashboard = None::<futures::future::Ready<()>>;
+    let alarm = config.no_leader_alarm.map(|threshold| watch_leader(node.clone(), threshold, config.no_leader_hook.clone()));
+    // followers wait out the leader's lease before they give up on it
+    let monitor = config.leader_timeout.max(config.lease).map(|timeout| monitor_leader(node.clone(), timeout));
+    let liveness = config.liveness_interval.map(|interval| repair::watch_right(node.clone(), interval));
+    let certificates = node.tls.borrow().is_some().then(|| watch_certificates(node.clone(), config.tls_watch_interval));
+    #[cfg(feature = "registry")]
+    let registration = config.register.clone().map(|registration| {
+        let url = node.advertised.clone().unwrap_or_else(|| format!("http://{}", addr));
+        registry::register(node.clone(), registration, config.register_ttl, url)
+    });
+    #[cfg(not(feature = "registry"))]
+    let registration = None::<futures::future::Ready<()>>;
+    let client = async {
+        tokio::select! {
+            _ = async {
+                for neighbor in [&node.left, &node.right] {
+                    until_resolvable(&node, &neighbor.peer().endpoint).await;
+                }
+                if let Some(epoch) = join {
+                    if let Err(e) = join_ring(&node, epoch, addr).await {
+                        error!(node = node.id, "cannot join the ring: {}", e);
+                        node.shutdown();
+                        return
+                    }
+                }
+                // duplicate IDs would both lead once their probes come back
+                if let Err(e) = introduce(&node, await_neighbours).await {
+                    error!(node = node.id, "refusing to start: {}", e);
+                    node.shutdown();
+                    return
+                }
+                pre_vote(&node).await;
+                if let Some(health) = &mut health {
+                    health::report::<LeaderElectionServiceServer<Node>>(health, ServingStatus::Serving).await;
+                }
+                node_client(node.clone()).await
+            } => (),
+            _ = async move {
+                match monitor {
+                    Some(monitor) => monitor.await,
+                    None => futures::future::pending().await,
+                }
+            } => (),
+            _ = async move {
+                match alarm {
+                    Some(alarm) => alarm.await,
+                    None => futures::future::pending().await,
+                }
+            } => (),
+            _ = async move {
+                match liveness {
+                    Some(liveness) => liveness.await,
+                    None => futures::future::pending().await,
+                }
+            } => (),
+            _ = async move {
+                match certificates {
+                    Some(certificates) => certificates.await,
+                    None => futures::future::pending().await,
+                }
+            } => (),
+            _ = node.stopped() => (),
+        }
+        if let Some(health) = &mut health {
+            health::report::<LeaderElectionServiceServer<Node>>(health, ServingStatus::NotServing).await;
+        }
+    };
+    let metrics = async move {
+        if let Some(metrics) = metrics {
+            metrics.await
+        }
+    };
+    let dashboard = async move {
+        if let Some(dashboard) = dashboard {
+            dashboard.await
+        }
+    };
+    // the leader deregisters as it shuts down
+    let registration = async move {
+        if let Some(registration) = registration {
+            registration.await
+        }
+    };
+    futures::future::join4(client, metrics, dashboard, registration).await;
+}
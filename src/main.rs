@@ -1,276 +1,705 @@
-#![recursion_limit = "1024"]
-use std::pin::Pin;
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug};
+use std::future::Future;
+use std::io::{stdin, IsTerminal};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
-use chrono::Utc;
-use tokio::sync::{Mutex, MutexGuard};
-use tokio::time::{sleep, Duration};
-use tonic::{transport::{Channel, Server}, Request, Response, Status};
-use futures::{stream, Stream, StreamExt};
-
-use leader_election_service::leader_election_service_server::{LeaderElectionService, LeaderElectionServiceServer};
-use leader_election_service::leader_election_service_client::LeaderElectionServiceClient;
-use leader_election_service::{NotifyMessage, NotifyResponse, ProbeMessage, ProbeResponse};
-
-pub mod leader_election_service {
-    tonic::include_proto!("me.viluon.le");
+use std::time::Duration;
+
+use futures::stream::FuturesUnordered;
+use futures::{future, FutureExt, StreamExt};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::mpsc;
+use tracing::{error, info};
+use tracing_subscriber::{reload, EnvFilter};
+
+use grpc_le::bully::BullyNode;
+use grpc_le::chang_roberts::ChangRobertsNode;
+use grpc_le::completions::{self, CommandLine, Shell};
+use grpc_le::config::{Algorithm, AuditTarget, Config, LogFilter, LogFormat, Output, Reload};
+use grpc_le::groups::MultiGroupNode;
+use grpc_le::hirschberg_sinclair::HirschbergSinclairNode;
+use grpc_le::mock::ScriptedTransport;
+use grpc_le::simulation::{Chaos, Delivery, Simulation};
+use grpc_le::topology::{NodeSpec, Topology};
+use grpc_le::traces::otlp;
+use grpc_le::transport::{Gate, GatedTransport, ImpairedTransport, Transport};
+use grpc_le::{events, replay, traces, ElectionAlgorithm, Node};
+
+/// How much virtual time a chaotic simulation gets to elect a leader.
+const CHAOS_LIMIT: Duration = Duration::from_secs(600);
+
+/// The commands, each with the words that may follow it, for completions.
+const COMMANDS: [(&str, &[&str]); 6] = [("node", &[]), ("mock-peer", &[]), ("simulate", &[]), ("selftest", &[]),
+    ("trace", &["merge", "replay"]), ("completions", &["bash", "zsh", "fish"])];
+/// The flags of `grpc-le node` besides the settings.
+const NODE_FLAGS: [&str; 5] = ["id", "listen", "left", "right", "join"];
+/// The flags of `grpc-le selftest` besides the settings.
+const SELFTEST_FLAGS: [&str; 2] = ["nodes", "timeout-ms"];
+
+/// Runs either a single node, `grpc-le node --id <n> ...`, or one that
+/// mistreats the messages it relays as a script says, `grpc-le mock-peer
+/// --script <path> --id <n> ...`, or a whole ring in one process, `grpc-le
+/// [simulate] ...`, or merges the event logs of the nodes into one timeline
+/// on stdout, `grpc-le trace merge <log>...`, or replays them through the
+/// state machine, `grpc-le trace replay <log>...`, or checks that a ring of
+/// local nodes elects a leader, `grpc-le selftest [--nodes <n>]
+/// [--timeout-ms <n>] ...`, or prints a script completing its arguments in
+/// a shell, `grpc-le completions <bash|zsh|fish>`. The nodes print their
+/// message log unless run with `--message-log false`, and with `--output
+/// json` the message log, the reports and the `status` command print JSON.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.first().is_some_and(|arg| arg == "selftest") {
+        return selftest(&args[1..]).await
+    }
+    if args.first().is_some_and(|arg| arg == "completions") {
+        let shell = match &args[1..] {
+            [shell] => shell.parse::<Shell>()?,
+            _ => return Err("usage: grpc-le completions bash|zsh|fish".into()),
+        };
+        let mut flags = Config::settings();
+        flags.extend(NODE_FLAGS.iter().chain(&SELFTEST_FLAGS));
+        print!("{}", completions::script(shell, &CommandLine { program: "grpc-le", commands: &COMMANDS, flags: &flags }));
+        return Ok(())
+    }
+    if args.first().is_some_and(|arg| arg == "trace") {
+        let mut args = args;
+        let output = take_output(&mut args)?;
+        let paths = args.iter().skip(2).map(PathBuf::from).collect::<Vec<_>>();
+        let paths = paths.iter().map(PathBuf::as_path).collect::<Vec<_>>();
+        match args.get(1).map(String::as_str) {
+            Some("merge") => {
+                for line in events::merge(&paths)? {
+                    println!("{}", line);
+                }
+            },
+            Some("replay") => {
+                let replayed = replay::replay_files(&paths)?;
+                match output {
+                    Output::Text => {
+                        for (node, state) in &replayed.states {
+                            println!("node {}: {:?}", node, state);
+                        }
+                        for divergence in &replayed.divergences {
+                            println!("diverged: {}", divergence);
+                        }
+                    },
+                    Output::Json => println!("{}", replayed.json()),
+                }
+                if !replayed.divergences.is_empty() {
+                    return Err(format!("{} divergences from the state machine", replayed.divergences.len()).into())
+                }
+            },
+            _ => return Err("usage: grpc-le trace merge|replay [--output json] <log>...".into()),
+        }
+        return Ok(())
+    }
+    let (config, single) = settings(&args)?;
+    let filter = match &config.log_level {
+        Some(level) => EnvFilter::new(level),
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+    // stdout is reserved for the message log
+    let logs = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal());
+    let log_filter: Arc<dyn LogFilter> = match config.log_format {
+        LogFormat::Pretty => {
+            let logs = logs.with_filter_reloading();
+            let handle = logs.reload_handle();
+            logs.init();
+            Arc::new(ReloadableFilter(handle))
+        },
+        LogFormat::Json => {
+            let logs = logs.json().with_filter_reloading();
+            let handle = logs.reload_handle();
+            logs.init();
+            Arc::new(ReloadableFilter(handle))
+        },
+    };
+    let audit_dir = match &config.audit_log {
+        Some(AuditTarget::Dir(dir)) => Some(dir),
+        _ => None,
+    };
+    for dir in config.outbox_dir.iter().chain(&config.state_dir).chain(&config.events_dir).chain(audit_dir) {
+        std::fs::create_dir_all(dir)?;
+    }
+    if single.is_some() && config.chaos.is_some() {
+        return Err("--chaos only applies to simulated rings".into())
+    }
+    #[cfg(feature = "k8s")]
+    let (config, single) = match (config.k8s_service.clone(), single) {
+        (Some(service), None) => {
+            let (spec, ring_size) = grpc_le::k8s::discover(&service, &config).await?;
+            (Config { ring_size: Some(ring_size), ..config }, Some(spec))
+        },
+        (_, single) => (config, single),
+    };
+    let finished_spans = config.otlp_endpoint.as_ref().map(|endpoint| {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(traces::export(endpoint.clone(), config.trace_service.clone(), config.trace_batch_size, config.timing.rpc_deadline, rx));
+        tx
+    });
+    let mut console = Console {
+        // without a ring given up front, stdin starts rings and commands them
+        lines: (single.is_none() && config.nodes.is_empty() && config.topology.is_none()).then(read_lines),
+        interrupt: signal(SignalKind::interrupt())?,
+        terminate: signal(SignalKind::terminate())?,
+        hangup: signal(SignalKind::hangup())?,
+        args,
+        log_filter,
+    };
+    let mut ids = None;
+    while console.interactive() && ids.is_none() {
+        match console.next().await {
+            Some(Command::Start(start)) => ids = Some(start),
+            Some(Command::Exit) | None => return Ok(()),
+            Some(_) => eprintln!("no ring is running, start one first"),
+        }
+    }
+    loop {
+        match ring(&config, single.clone(), ids.as_deref(), &finished_spans, &mut console).await? {
+            Some(next) => ids = Some(next),
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Runs a ring of `--nodes` nodes, 7 by default, on free ports of
+/// localhost with the settings of `args`, and prints how quickly they
+/// agreed on a leader, failing if they did not within `--timeout-ms`.
+async fn selftest(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut nodes, mut timeout) = (7, Duration::from_secs(30));
+    let mut rest = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--nodes" | "--timeout-ms" => {
+                let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+                let invalid = |e: std::num::ParseIntError| format!("invalid {}: {}", arg, e);
+                match &arg[..] {
+                    "--nodes" => nodes = value.parse().map_err(invalid)?,
+                    _ => timeout = Duration::from_millis(value.parse().map_err(invalid)?),
+                }
+            },
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let config = Config::from_args(rest.into_iter())?;
+    if config.algorithm != Algorithm::Ring {
+        return Err("the self-test runs the ring algorithm".into())
+    }
+    let filter = EnvFilter::new(config.log_level.as_deref().unwrap_or("warn"));
+    tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr).init();
+    let report = grpc_le::selftest::run(nodes, &config, timeout).await?;
+    match config.output {
+        Output::Text => eprint!("{}", report),
+        Output::Json => println!("{}", report.json()),
+    }
+    Ok(())
 }
 
-const DELAY_MODIFIER: u64 = 100;
+/// Takes `--output <json|text>` out of `args`, for the commands that take
+/// no other settings.
+fn take_output(args: &mut Vec<String>) -> Result<Output, String> {
+    match args.iter().position(|arg| arg == "--output") {
+        Some(i) if i + 1 < args.len() => {
+            let output = args.remove(i + 1).parse()?;
+            args.remove(i);
+            Ok(output)
+        },
+        Some(_) => Err("--output needs a value".to_string()),
+        None => Ok(Output::Text),
+    }
+}
 
-#[derive(Debug, Clone)]
-pub struct Node {
-    id: u64,
-    left_id: u64,
-    right_id: u64,
-    left_addr: String,
-    right_addr: String,
-    state: Arc<Mutex<NodeState>>
+/// Reads the settings of the process from `args`, those following
+/// `grpc-le`, along with the single node they describe if they do.
+fn settings(args: &[String]) -> Result<(Config, Option<NodeSpec>), String> {
+    let mut args = args.iter().cloned().peekable();
+    match args.next_if(|arg| ["node", "mock-peer", "simulate"].contains(&&arg[..])).as_deref() {
+        Some("node") => {
+            let (spec, config) = Config::node_from_args(args)?;
+            Ok((config, Some(spec)))
+        },
+        Some("mock-peer") => {
+            let (spec, config) = Config::node_from_args(args)?;
+            if config.script.is_none() {
+                return Err("missing --script".into())
+            }
+            Ok((config, Some(spec)))
+        },
+        _ => Ok((Config::from_args(args)?, None)),
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum NodeState {
-    Candidate { phase: u64, last_phase_probed: u64 },
-    Defeated { leader: Option<u64> },
-    Leader,
+/// Changes the filter of the diagnostics written to stderr.
+struct ReloadableFilter<S>(reload::Handle<EnvFilter, S>);
+
+impl<S> Debug for ReloadableFilter<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ReloadableFilter")
+    }
 }
 
-impl Default for NodeState {
-    fn default() -> Self {
-        NodeState::Candidate { phase: 1, last_phase_probed: 0 }
+impl<S: 'static> LogFilter for ReloadableFilter<S> {
+    fn set(&self, directives: &str) -> Result<(), String> {
+        let filter = EnvFilter::try_new(directives).map_err(|e| format!("invalid log level: {}", e))?;
+        self.0.reload(filter).map_err(|e| e.to_string())
     }
 }
 
-impl Node {
-    fn next_phase(&self, state: &mut MutexGuard<NodeState>) {
-        match **state {
-            NodeState::Candidate { phase, last_phase_probed } => {
-                assert!(last_phase_probed == phase);
-                **state = NodeState::Candidate { phase: phase + 1, last_phase_probed };
-            },
-            _ => panic!("next_phase() called on non-candidate node ({:?})", *state)
+/// What stdin or a signal asks of the ring running.
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    /// SIGINT or SIGTERM: shut the ring down and exit.
+    Exit,
+    /// SIGHUP: read the arguments again, along with the config file and the
+    /// environment, and have the ring algorithm's nodes take on the timing,
+    /// the log level, renewed certificates and, for a single node, its
+    /// neighbours.
+    Reload,
+    /// `start ID...`, or just the IDs: replace the ring with one of the nodes
+    /// with the IDs, in order.
+    Start(Vec<u16>),
+    /// `kill ID`: shut the node down.
+    Kill(u16),
+    /// `revive ID`: start a killed node again, from its state file if it
+    /// keeps one.
+    Revive(u16),
+    /// `status`: print where each node stands.
+    Status,
+    /// `pause`: hold the messages the nodes relay to each other back.
+    Pause,
+    /// `step`: let one held message through.
+    Step,
+    /// `resume`: let all messages through again.
+    Resume,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_whitespace().peekable();
+        let command = match words.peek() {
+            Some(word) if word.parse::<u16>().is_ok() => "start",
+            _ => words.next().unwrap_or_default(),
+        };
+        let id = |words: &mut dyn Iterator<Item = &str>| match (words.next().map(str::parse), words.next()) {
+            (Some(Ok(id)), None) => Ok(id),
+            _ => Err(format!("usage: {} ID", command)),
+        };
+        match command {
+            "start" => words.map(str::parse).collect::<Result<_, _>>()
+                .map(Command::Start)
+                .map_err(|_| "usage: start ID...".to_string()),
+            "kill" => id(&mut words).map(Command::Kill),
+            "revive" => id(&mut words).map(Command::Revive),
+            "status" => Ok(Command::Status),
+            "pause" => Ok(Command::Pause),
+            "step" => Ok(Command::Step),
+            "resume" => Ok(Command::Resume),
+            _ => Err(format!("unknown command {:?}, try start, kill, revive, status, pause, step or resume", line.trim())),
         }
     }
+}
 
-    fn defeat(&self, state: &mut MutexGuard<NodeState>) {
-        match **state {
-            NodeState::Candidate { .. } =>
-                **state = NodeState::Defeated { leader: None },
-            NodeState::Defeated { .. } => (),
-            NodeState::Leader => panic!("defeat() called on the leader node ({:?})", **state),
-        }
+/// Where the commands come from: signals, and stdin unless the ring was
+/// given up front.
+struct Console {
+    lines: Option<mpsc::UnboundedReceiver<String>>,
+    interrupt: Signal,
+    terminate: Signal,
+    hangup: Signal,
+    /// The arguments the process was started with, read again on SIGHUP.
+    args: Vec<String>,
+    log_filter: Arc<dyn LogFilter>,
+}
+
+impl Console {
+    /// Whether stdin still takes commands.
+    fn interactive(&self) -> bool {
+        self.lines.is_some()
     }
 
-    async fn defeat_with_leader(&self, leader: u64) {
-        let mut state = self.state.lock().await;
-        let new_state = NodeState::Defeated { leader: Some(leader) };
-        match *state {
-            NodeState::Candidate { .. } => *state = new_state,
-            NodeState::Defeated { .. } => *state = new_state,
-            NodeState::Leader => panic!("defeat_with_leader() called on the leader node ({:?})", *state),
+    /// Reads the settings from the arguments again, along with the single
+    /// node they describe if they do, and changes the log level to theirs.
+    fn reread(&self) -> Result<(Config, Option<NodeSpec>), String> {
+        let (config, single) = settings(&self.args)?;
+        if let Some(level) = &config.log_level {
+            self.log_filter.set(level)?;
         }
+        Ok((config, single))
     }
 
-    fn lead(&self, state: &mut MutexGuard<NodeState>) {
-        match **state {
-            NodeState::Leader => (),
-            NodeState::Candidate { .. } => **state = NodeState::Leader,
-            NodeState::Defeated { .. } => panic!("lead() called on a defeated node ({:?})", *state),
+    /// Waits for the next command, complaining about lines that are none.
+    /// Returns `None` once stdin ends, after which only signals come.
+    async fn next(&mut self) -> Option<Command> {
+        loop {
+            let line = tokio::select! {
+                _ = self.interrupt.recv() => return Some(Command::Exit),
+                _ = self.terminate.recv() => return Some(Command::Exit),
+                _ = self.hangup.recv() => return Some(Command::Reload),
+                line = next_line(&mut self.lines) => line?,
+            };
+            match line.parse() {
+                Ok(command) => return Some(command),
+                Err(e) => eprintln!("{}", e),
+            }
         }
     }
 }
 
-#[tonic::async_trait]
-impl LeaderElectionService for Node {
-    type NotifyElectedRawStream = Pin<Box<dyn Stream<Item = Result<NotifyResponse, Status>> + Send>>;
-    type ProbeRawStream = Pin<Box<dyn Stream<Item = Result<ProbeResponse, Status>> + Send>>;
-
-    async fn probe_raw(&self, request: Request<tonic::Streaming<ProbeMessage>>)
-    -> Result<Response<Self::ProbeRawStream>, Status> {
-        let mut stream = request.into_inner();
-
-        let this = self.clone();
-        let pipe: async_stream::AsyncStream<Result<ProbeResponse, Status>, _> = async_stream::try_stream!{
-            eprintln!("node {} server waiting for probes", this.id);
-            while let Some(req) = stream.next().await {
-                let msg = (req as Result<ProbeMessage, Status>)?;
-                println!("<{}, {}, {}, {}>", this.id, Utc::now().format("%T"), msg.sender_id, this.id);
-                let (addr, target_id) =
-                    if msg.headed_left { (&this.left_addr, this.left_id) } else { (&this.right_addr, this.right_id) };
-                let client = || LeaderElectionServiceClient::connect(addr.clone());
-
-                if msg.sender_id < this.id {
-                    // forward the message
-                    eprintln!("node {} server forwarding probe to {}", this.id, addr);
-                    client().await.unwrap().probe(
-                        this.id,
-                        "server",
-                        target_id,
-                        msg.sender_id,
-                        msg.headed_left,
-                        msg.phase,
-                    );
-                }
-
-                eprintln!("node {} server waiting for lock", this.id);
-
-                loop {
-                    let mut state: MutexGuard<NodeState> = this.state.lock().await;
-                    match *state {
-                        NodeState::Candidate { phase, last_phase_probed } if phase == last_phase_probed => {
-                            use std::cmp::Ordering;
-                            match this.id.cmp(&msg.sender_id) {
-                                Ordering::Less => this.next_phase(&mut state),
-                                Ordering::Equal => this.lead(&mut state),
-                                Ordering::Greater => this.defeat(&mut state),
-                            };
-                            break
-                        },
-                        NodeState::Candidate { .. } => sleep(Duration::from_millis(DELAY_MODIFIER)).await,
-                        _ => break,
-                    };
-                }
-                yield ProbeResponse {};
-                eprintln!("node {} server finished processing a probe!", this.id);
+/// Sends the non-empty lines of stdin down the returned channel, from a
+/// thread of its own: a blocking read would hold up the runtime shutting down.
+fn read_lines() -> mpsc::UnboundedReceiver<String> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        for line in stdin().lines().map_while(Result::ok).filter(|line| !line.trim().is_empty()) {
+            if tx.send(line).is_err() {
+                break
             }
-            eprintln!("node {} server closing connection", this.id);
-        };
+        }
+    });
+    rx
+}
 
-        eprintln!("node {} server establishing connection", self.id);
-        Ok(Response::new(Box::pin(pipe) as Self::ProbeRawStream))
-    }
-
-    async fn notify_elected_raw(&self, request: Request<tonic::Streaming<NotifyMessage>>)
-    -> Result<Response<Self::NotifyElectedRawStream>, Status> {
-        let mut stream = request.into_inner();
-
-        let this = self.clone();
-        let pipe: async_stream::AsyncStream<Result<NotifyResponse, Status>, _> = async_stream::try_stream!{
-            while let Some(req) = stream.next().await {
-                let NotifyMessage { leader_id, headed_left } = req?;
-                println!("<{}, {}, {}, {}>", this.id, Utc::now().format("%T"), leader_id, this.id);
-                if this.id != leader_id {
-                    eprintln!("node {} acknowledging {}'s leadership", this.id, leader_id);
-                    this.defeat_with_leader(leader_id).await;
-
-                    // forward the message
-                    let (addr, target_id) =
-                        if headed_left { (&this.left_addr, this.left_id) }
-                        else { (&this.right_addr, this.right_id) };
-                    eprintln!("node {} forwarding election notification to {}", this.id, addr);
-                    LeaderElectionServiceClient::connect(addr.clone()).await.unwrap()
-                        .notify_elected(this.id, "server", target_id, leader_id, headed_left);
-                };
-                yield NotifyResponse {};
-            }
+/// Receives the next line of `lines`, forgetting them once they end and
+/// never resolving from then on.
+async fn next_line(lines: &mut Option<mpsc::UnboundedReceiver<String>>) -> Option<String> {
+    let line = match lines {
+        Some(receiver) => receiver.recv().await,
+        None => future::pending().await,
+    };
+    if line.is_none() {
+        *lines = None;
+    }
+    line
+}
+
+/// Runs the ring of `single`, the configured nodes or those of `ids` until
+/// its nodes stop, or until the console stops it. Returns the IDs of the
+/// ring to start next, if the console asked for one.
+async fn ring(
+    config: &Config,
+    single: Option<NodeSpec>,
+    ids: Option<&[u16]>,
+    finished_spans: &Option<mpsc::UnboundedSender<otlp::Span>>,
+    console: &mut Console,
+) -> Result<Option<Vec<u16>>, Box<dyn std::error::Error>> {
+    let (mut specs, ring_size, topology) = if let Some(spec) = single {
+        (vec![spec], config.ring_size.unwrap(), None)
+    } else if !config.nodes.is_empty() {
+        (config.nodes.clone(), config.ring_size.unwrap_or(config.nodes.len() as u64), None)
+    } else {
+        let topology = match (&config.topology, ids) {
+            (Some(path), _) => Topology::load(path)?,
+            (None, ids) => Topology::from_ids(ids.unwrap_or_default()),
         };
+        if let Some(path) = &config.save_topology {
+            topology.save(path)?;
+        }
+        (topology.nodes(), config.ring_size.unwrap_or(topology.members.len() as u64), Some(topology))
+    };
+    if let Some(bind) = config.bind {
+        match &mut specs[..] {
+            [spec] => spec.listen = bind,
+            _ => return Err("--bind only applies when running a single node".into()),
+        }
+    }
+    if let Some(priority) = config.priority {
+        match &mut specs[..] {
+            [spec] => spec.priority = priority,
+            _ => return Err("--priority only applies when running a single node".into()),
+        }
+    }
+    if let Some(zone) = &config.zone {
+        match &mut specs[..] {
+            [spec] => spec.zone = Some(zone.clone()),
+            _ => return Err("--zone only applies when running a single node".into()),
+        }
+    }
+    let grouped = !config.groups.is_empty() || !config.roles.is_empty();
+    if config.script.is_some() && (specs.len() != 1 || config.algorithm != Algorithm::Ring || grouped) {
+        return Err("--script only applies when running a single node of the ring algorithm, in the default group".into())
+    }
+    if !config.also_listen.is_empty() && specs.len() != 1 {
+        return Err("--also-listen only applies when running a single node".into())
+    }
+    if config.advertise.is_some() && specs.len() != 1 {
+        return Err("--advertise only applies when running a single node".into())
+    }
+    if config.observer && specs.len() != 1 {
+        return Err("--observer only applies when running a single node".into())
+    }
+    if !config.also_listen.is_empty() && (config.algorithm != Algorithm::Ring || grouped) {
+        return Err("only single-group nodes of the ring algorithm serve on more than one address".into())
+    }
+    if config.register.is_some() && config.algorithm != Algorithm::Ring {
+        return Err("only the ring algorithm's leaders register themselves".into())
+    }
+    if config.await_neighbours.is_some() && config.algorithm != Algorithm::Ring {
+        return Err("only the ring algorithm's nodes wait for their neighbours".into())
+    }
+    if config.election_deadline.is_some() && config.algorithm != Algorithm::Ring {
+        return Err("only the ring algorithm's elections have a deadline".into())
+    }
+    if config.observer && config.algorithm != Algorithm::Ring {
+        return Err("only the ring algorithm has observers".into())
+    }
+    if config.algorithm != Algorithm::Ring && specs.iter().any(|spec| spec.priority != 0) {
+        return Err("only the ring algorithm takes the priorities of the nodes into account".into())
+    }
+    if config.algorithm != Algorithm::Ring && config.preferred_zone.is_some() {
+        return Err("only the ring algorithm prefers leaders in a zone".into())
+    }
+    if (config.quorum.is_some() || config.witness.is_some()) && config.liveness_interval.is_none() {
+        return Err("--quorum and --witness need --liveness-interval-ms to tell that the ring split".into())
+    }
 
-        Ok(Response::new(Box::pin(pipe) as Self::NotifyElectedRawStream))
+    if config.algorithm != Algorithm::Bully && topology.as_ref().is_some_and(|topology| !topology.is_ring()) {
+        return Err("the ring algorithms need the edges of the topology to join the nodes into a ring, in order".into())
     }
-}
 
-impl LeaderElectionServiceClient<Channel> {
-    fn probe(mut self, id: u64, component: &'static str, target: u64, sender_id: u64, headed_left: bool, phase: u64) {
-        let msg = ProbeMessage { sender_id, headed_left, phase };
-        tokio::spawn(async move {
-            println!("<{}, {}, {}, {}>", id, Utc::now().format("%T"), sender_id, target);
-            match self.probe_raw(Request::new(stream::once(async { msg })))
-                .await {
-                    Ok(_) => eprintln!("node {} {} tokio::spawned gRPC call completed", id, component),
-                    Err(e) => eprintln!("node {} {} tokio::spawned gRPC call failed: {}", id, component, e)
-                }
-        });
+    if config.algorithm != Algorithm::Ring && grouped {
+        return Err("only the ring algorithm holds elections in groups and for roles".into())
+    }
+    if config.chaos.is_some() && grouped {
+        return Err("--groups and --roles only apply to real rings, not simulated ones".into())
     }
 
-    fn notify_elected(mut self, id: u64, component: &'static str, target: u64, leader_id: u64, headed_left: bool) {
-        let msg = NotifyMessage { leader_id, headed_left };
-        tokio::spawn(async move {
-            println!("<{}, {}, {}, {}>", id, Utc::now().format("%T"), leader_id, target);
-            match self.notify_elected_raw(Request::new(stream::once(async { msg })))
-                .await {
-                    Ok(_) => eprintln!("node {} {} tokio::spawned gRPC call completed", id, component),
-                    Err(e) => eprintln!("node {} {} tokio::spawned gRPC call failed: {}", id, component, e)
-                }
-        });
+    if let Some(chaos) = config.chaos {
+        return tokio::task::block_in_place(|| simulate(&specs, chaos, config.output)).map(|()| None)
+    }
+
+    if config.algorithm == Algorithm::Bully {
+        // every node has to know the whole cluster
+        let members = &topology.ok_or("the bully algorithm needs the whole cluster, from stdin or --topology")?.members;
+        let mut nodes = vec![];
+        for member in members {
+            info!(node = member.id, "listening on {}", member.addr);
+            nodes.push((BullyNode::new(member.id, members, config)?, member.addr));
+        }
+        return serve_all(nodes, config, console).await
+    }
+    if config.algorithm == Algorithm::ChangRoberts {
+        let mut nodes = vec![];
+        for spec in &specs {
+            info!(node = spec.id, "listening on {}", spec.listen);
+            nodes.push((ChangRobertsNode::new(spec, config)?, spec.listen));
+        }
+        return serve_all(nodes, config, console).await
+    }
+    if config.algorithm == Algorithm::HirschbergSinclair {
+        let mut nodes = vec![];
+        for spec in &specs {
+            info!(node = spec.id, "listening on {}", spec.listen);
+            nodes.push((HirschbergSinclairNode::new(spec, config)?, spec.listen));
+        }
+        return serve_all(nodes, config, console).await
+    }
+
+    if grouped {
+        let roles = config.roles.iter().map(|role| role.name.as_str()).collect::<Vec<_>>();
+        let mut nodes = vec![];
+        for spec in &specs {
+            info!(node = spec.id, "listening on {} for groups {:?} and roles {:?}", spec.listen, config.groups, roles);
+            let node = MultiGroupNode::with_roles(&config.groups, &config.roles, spec, ring_size, config, finished_spans.clone())?;
+            nodes.push((node.with_log_filter(console.log_filter.clone()), spec.listen));
+        }
+        let handles = nodes.iter().map(|(node, _)| node.clone()).collect::<Vec<_>>();
+        let running = future::join_all(nodes.into_iter().map(|(node, addr)| node.run(addr, config)));
+        return serve(running, || handles.iter().for_each(MultiGroupNode::shutdown), console).await
     }
+    explore(&specs, ring_size, config, finished_spans, console).await
 }
 
-async fn node_client(node: Node) -> Option<()> {
-    sleep(Duration::from_millis(2 * DELAY_MODIFIER)).await;
-    let left = LeaderElectionServiceClient::connect(node.left_addr.clone()).await.ok()?;
-    let right = LeaderElectionServiceClient::connect(node.right_addr.clone()).await.ok()?;
+/// Elects a leader among the nodes of `specs` in memory, with the faults of
+/// `chaos`, and fails unless exactly one node ever leads. Prints the report
+/// as JSON if `output` says so.
+fn simulate(specs: &[NodeSpec], chaos: Chaos, output: Output) -> Result<(), Box<dyn std::error::Error>> {
+    let report = Simulation::of(specs, Delivery::Shuffled(chaos.seed)).with_chaos(chaos).run(CHAOS_LIMIT);
+    if output == Output::Json {
+        println!("{}", report.json());
+    }
+    info!("simulation ran for {:?} of virtual time, delivering {} messages despite {:?}", report.elapsed, report.delivered, report.faults);
+    if report.most_leaders > 1 {
+        return Err(format!("{} nodes led at once", report.most_leaders).into())
+    }
+    match report.leader() {
+        Some(leader) => {
+            info!(node = leader, "elected in the simulation");
+            Ok(())
+        },
+        None => Err(format!("no leader elected within {:?}: {:?}", CHAOS_LIMIT, report.results).into()),
+    }
+}
 
-    loop {
-        sleep(Duration::from_millis(DELAY_MODIFIER)).await;
-        eprintln!("node {} client waiting for mutex lock", node.id);
-        let mut state = node.state.lock().await;
-        match *state {
-            NodeState::Candidate { phase, last_phase_probed } if last_phase_probed != phase => {
-                let headed_left = phase % 2 == 0;
-                let (target, addr, target_id) =
-                    if headed_left { (&left, &node.left_addr[..], node.left_id) }
-                    else { (&right, &node.right_addr[..], node.right_id) };
-                *state = NodeState::Candidate { phase, last_phase_probed: phase };
-                eprintln!("node {} sending probe to {} (phase {})", node.id, addr, phase);
-                // FIXME is this correct?
-                target.clone().probe(node.id, "client", target_id, node.id, headed_left, phase);
-                eprintln!("node {} sent a probe", node.id);
-                Some(())
+/// Runs `nodes` until they all stop, or until the console stops them.
+async fn serve_all<A: ElectionAlgorithm>(
+    nodes: Vec<(A, SocketAddr)>,
+    config: &Config,
+    console: &mut Console,
+) -> Result<Option<Vec<u16>>, Box<dyn std::error::Error>> {
+    let handles = nodes.iter().map(|(node, _)| node.clone()).collect::<Vec<_>>();
+    let running = future::join_all(nodes.into_iter().map(|(node, addr)| node.run(addr, config)));
+    serve(running, || handles.iter().for_each(A::shutdown), console).await
+}
+
+/// Awaits the servers `running` until they all stop, or until the console
+/// stops them with `shutdown`. Only the ring algorithm's nodes take more
+/// than starting and exiting, see [`explore`].
+async fn serve(
+    running: impl Future<Output = Vec<Result<(), tonic::transport::Error>>>,
+    shutdown: impl FnOnce(),
+    console: &mut Console,
+) -> Result<Option<Vec<u16>>, Box<dyn std::error::Error>> {
+    tokio::pin!(running);
+    let next = loop {
+        tokio::select! {
+            served = &mut running => {
+                for served in served {
+                    served?;
+                }
+                return Ok(None)
+            },
+            command = console.next() => match command {
+                Some(Command::Exit) => break None,
+                Some(Command::Start(ids)) => break Some(ids),
+                Some(command) => eprintln!("only the ring algorithm's nodes take {:?} commands", command),
+                None => (),
             },
-            NodeState::Candidate { .. } => Some(()),
-            NodeState::Defeated { .. } => {
-                eprintln!("node {} is defeated", node.id);
-                None
+        }
+    };
+    info!("stopping the ring");
+    shutdown();
+    for served in running.await {
+        served?;
+    }
+    Ok(next)
+}
+
+/// Runs the ring algorithm's nodes of `specs` until they all stop, or until
+/// the console stops them, killing, reviving and holding back the messages
+/// of the nodes as it commands.
+async fn explore(
+    specs: &[NodeSpec],
+    ring_size: u64,
+    config: &Config,
+    finished_spans: &Option<mpsc::UnboundedSender<otlp::Span>>,
+    console: &mut Console,
+) -> Result<Option<Vec<u16>>, Box<dyn std::error::Error>> {
+    let gate = Arc::new(Gate::default());
+    let log_filter = console.log_filter.clone();
+    let start = |spec: &NodeSpec| -> Result<Node, Box<dyn std::error::Error>> {
+        info!(node = spec.id, "listening on {}", spec.listen);
+        let node = Node::new(spec, ring_size, config, finished_spans.clone())?;
+        let mut transport: Arc<dyn Transport> = Arc::new(GatedTransport::new(node.transport(), gate.clone()));
+        if let Some(impairment) = config.impairment {
+            transport = Arc::new(ImpairedTransport::new(transport, impairment, spec.id.into()));
+        }
+        if let Some(script) = &config.script {
+            transport = Arc::new(ScriptedTransport::new(transport, script.clone()));
+        }
+        Ok(node.with_transport(transport).with_log_filter(log_filter.clone()))
+    };
+    // the nodes running, or still stopping
+    let mut nodes = BTreeMap::new();
+    let mut running = FuturesUnordered::new();
+    for spec in specs {
+        let node = start(spec)?;
+        nodes.insert(spec.id, node.clone());
+        running.push(run(node, spec, config));
+    }
+    let next = loop {
+        tokio::select! {
+            Some((id, served)) = running.next(), if !running.is_empty() => {
+                nodes.remove(&id);
+                served?;
+                if running.is_empty() && !console.interactive() {
+                    return Ok(None)
+                }
             },
-            NodeState::Leader => {
-                eprintln!("node {} is the leader", node.id);
-                left.clone().notify_elected(node.id, "client", node.left_id, node.id, true);
-                // let _ = right.clone().notify_elected(format!("node {} client", node.id), node.id, false);
-                None
+            command = console.next() => match command {
+                Some(Command::Exit) => break None,
+                Some(Command::Start(ids)) => break Some(ids),
+                Some(Command::Kill(id)) => match nodes.get(&id) {
+                    Some(node) => node.shutdown(),
+                    None => eprintln!("node {} is not running", id),
+                },
+                Some(Command::Revive(id)) => match specs.iter().find(|spec| spec.id == id) {
+                    _ if nodes.contains_key(&id) => eprintln!("node {} is still running", id),
+                    Some(spec) => {
+                        let node = start(spec)?;
+                        nodes.insert(id, node.clone());
+                        running.push(run(node, spec, config));
+                    },
+                    None => eprintln!("node {} is not in the ring", id),
+                },
+                Some(Command::Reload) => match console.reread() {
+                    Ok((reread, single)) => for (&id, node) in &nodes {
+                        let spec = single.as_ref().filter(|spec| spec.id == id);
+                        let (left, right) = (spec.map(|spec| spec.left().clone()), spec.map(|spec| spec.right().clone()));
+                        // the console changed the log level already
+                        if let Err(e) = node.reload(Reload { timing: reread.timing, log_level: None, left, right }) {
+                            error!(node = id, "cannot reload the settings: {}", e);
+                        }
+                    },
+                    Err(e) => error!("cannot reload the settings: {}", e),
+                },
+                Some(Command::Status) => status(specs, &nodes, &gate, config.output).await,
+                Some(Command::Pause) => gate.pause(),
+                Some(Command::Step) if !gate.paused() => eprintln!("the ring is not paused"),
+                Some(Command::Step) => gate.step(),
+                Some(Command::Resume) => gate.resume(),
+                None if running.is_empty() => return Ok(None),
+                None => (),
             },
-        }?
+        }
+    };
+    info!("stopping the ring");
+    nodes.values().for_each(Node::shutdown);
+    while let Some((_, served)) = running.next().await {
+        served?;
     }
+    Ok(next)
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    use futures::future;
-    use std::io::stdin;
+/// Serves `node` as `spec` says until it stops, telling which node it was.
+async fn run(node: Node, spec: &NodeSpec, config: &Config) -> (u16, Result<(), tonic::transport::Error>) {
+    (spec.id, node.run(spec.listen, config).await)
+}
 
-    loop {
-        let mut buffer = String::new();
-        stdin().read_line(&mut buffer)?;
-
-        let node_ids = buffer
-            .split_whitespace()
-            .map(|s| s.parse().unwrap())
-            .collect::<Vec<_>>();
-
-        let first_port = 40000u16;
-        let get_addr = |id: u16| format!("[::1]:{}", first_port + id);
-
-        let mut futures = vec![];
-        for (i, &node_id) in node_ids.iter().enumerate() {
-            let prev_id = node_ids[(node_ids.len() + i - 1) % node_ids.len()];
-            let next_id = node_ids[(i + 1) % node_ids.len()];
-
-            eprintln!("node {} listening on {}", node_id, get_addr(node_id));
-            let node = Node {
-                id: node_id.into(),
-                left_id: prev_id as u64,
-                right_id: next_id as u64,
-                left_addr: "http://".to_string() + &get_addr(prev_id),
-                right_addr: "http://".to_string() + &get_addr(next_id),
-                state: Arc::default(),
+/// Prints where each node of `specs` stands, for the `status` command, as
+/// one JSON object if `output` says so.
+async fn status(specs: &[NodeSpec], nodes: &BTreeMap<u16, Node>, gate: &Gate, output: Output) {
+    if output == Output::Json {
+        let mut states = vec![];
+        for spec in specs {
+            let state = match nodes.get(&spec.id) {
+                Some(node) if node.stopped().now_or_never().is_some() => r#""state": "stopping""#.to_string(),
+                Some(node) => events::state_fields(&node.state().await),
+                None => r#""state": "down""#.to_string(),
             };
-
-            let server = Server::builder()
-                .add_service(LeaderElectionServiceServer::new(node.clone()))
-                .serve(get_addr(node_id).parse().unwrap());
-
-            futures.push(future::join(async move { server.await.expect("oops") }, node_client(node)));
+            states.push(format!(r#"{{"node": {}, {}}}"#, spec.id, state));
         }
-
-        tokio::runtime::Runtime::new()?.block_on(async {
-            future::join_all(futures).await;
-        });
+        eprintln!(r#"{{"nodes": [{}], "paused": {}}}"#, states.join(", "), gate.paused());
+        return
+    }
+    for spec in specs {
+        match nodes.get(&spec.id) {
+            Some(node) if node.stopped().now_or_never().is_some() => eprintln!("{:>5}  stopping", spec.id),
+            Some(node) => eprintln!("{:>5}  {:?}", spec.id, node.state().await),
+            None => eprintln!("{:>5}  down", spec.id),
+        }
+    }
+    if gate.paused() {
+        eprintln!("messages are held back, step through them or resume");
     }
 }
@@ -0,0 +1,338 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use bytes::Buf;
+use http_body::{Body, SizeHint};
+use tokio::time::{Duration, Instant};
+use tonic::body::BoxBody;
+use tower::{Layer, Service};
+
+use crate::leader_election_service::Decision;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 9] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.0];
+
+/// Upper bounds of the request and response size histogram buckets, in bytes.
+const SIZE_BUCKETS: [f64; 8] = [64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0];
+
+/// Which end of an RPC a measurement was taken at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Side {
+    Server,
+    Client,
+}
+
+impl Side {
+    pub fn label(self) -> &'static str {
+        match self {
+            Side::Server => "server",
+            Side::Client => "client",
+        }
+    }
+}
+
+/// Which way the body of an RPC travelled, from the caller's point of view.
+#[derive(Debug, Clone, Copy)]
+enum Direction {
+    Request,
+    Response,
+}
+
+#[derive(Debug)]
+struct Histogram {
+    bounds: &'static [f64],
+    buckets: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram { bounds, buckets: vec![0; bounds.len()], count: 0, sum: 0.0 }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bucket, &bound) in self.buckets.iter_mut().zip(self.bounds) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+
+    /// Appends the series of the histogram `name` with `labels` to `out`.
+    fn render(&self, name: &str, labels: &str, out: &mut String) {
+        for (bucket, bound) in self.buckets.iter().zip(self.bounds) {
+            let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, bucket);
+        }
+        let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, self.count);
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+#[derive(Debug)]
+struct MethodStats {
+    latency: Histogram,
+    request_bytes: Histogram,
+    response_bytes: Histogram,
+    statuses: BTreeMap<String, u64>,
+}
+
+impl MethodStats {
+    /// The latency, request size and response size histograms, in that order.
+    fn histograms(&self) -> [&Histogram; 3] {
+        [&self.latency, &self.request_bytes, &self.response_bytes]
+    }
+}
+
+impl Default for MethodStats {
+    fn default() -> Self {
+        MethodStats {
+            latency: Histogram::new(&LATENCY_BUCKETS),
+            request_bytes: Histogram::new(&SIZE_BUCKETS),
+            response_bytes: Histogram::new(&SIZE_BUCKETS),
+            statuses: BTreeMap::new(),
+        }
+    }
+}
+
+/// How many probes a node sent as a candidate, received, and passed on.
+#[derive(Debug, Default)]
+pub struct ProbeCounts {
+    pub sent: AtomicU64,
+    pub received: AtomicU64,
+    pub forwarded: AtomicU64,
+}
+
+impl Decision {
+    pub fn label(self) -> &'static str {
+        match self {
+            Decision::Ignored => "ignored",
+            Decision::Forwarded => "forwarded",
+            Decision::Defeated => "defeated",
+            Decision::YouWin => "you_win",
+        }
+    }
+}
+
+/// How the neighbours decided on the messages a node sent them, as they
+/// reported when acknowledging them.
+#[derive(Debug, Default)]
+pub struct DecisionCounts {
+    counts: [AtomicU64; 4],
+}
+
+impl DecisionCounts {
+    pub fn record(&self, decision: Decision) {
+        self.counts[decision as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Appends the counts to `out` in the Prometheus text format.
+    pub fn render(&self, node: u64, out: &mut String) {
+        let _ = writeln!(out, "# TYPE grpc_le_peer_decisions_total counter");
+        for decision in [Decision::Ignored, Decision::Forwarded, Decision::Defeated, Decision::YouWin] {
+            let count = self.counts[decision as usize].load(Ordering::Relaxed);
+            let _ = writeln!(out, "grpc_le_peer_decisions_total{{node=\"{}\",decision=\"{}\"}} {}", node, decision.label(), count);
+        }
+    }
+}
+
+/// Latency, size and status-code distributions of every RPC a node serves
+/// or issues, keyed by side and gRPC method path.
+#[derive(Debug, Default)]
+pub struct RpcMetrics {
+    methods: Mutex<BTreeMap<(Side, String), MethodStats>>,
+}
+
+impl RpcMetrics {
+    fn record(&self, side: Side, method: &str, latency: Duration, status: &str) {
+        let mut methods = self.methods.lock().unwrap();
+        let stats = methods.entry((side, method.to_string())).or_default();
+        stats.latency.observe(latency.as_secs_f64());
+        *stats.statuses.entry(status.to_string()).or_default() += 1;
+    }
+
+    fn record_size(&self, side: Side, method: &str, direction: Direction, bytes: u64) {
+        let mut methods = self.methods.lock().unwrap();
+        let stats = methods.entry((side, method.to_string())).or_default();
+        match direction {
+            Direction::Request => stats.request_bytes.observe(bytes as f64),
+            Direction::Response => stats.response_bytes.observe(bytes as f64),
+        }
+    }
+
+    /// Appends the collected metrics to `out` in the Prometheus text format.
+    pub fn render(&self, out: &mut String) {
+        let methods = self.methods.lock().unwrap();
+        for (i, name) in ["grpc_le_rpc_latency_seconds", "grpc_le_rpc_request_bytes", "grpc_le_rpc_response_bytes"].into_iter().enumerate() {
+            let _ = writeln!(out, "# TYPE {} histogram", name);
+            for ((side, method), stats) in methods.iter() {
+                let labels = format!("side=\"{}\",method=\"{}\"", side.label(), method);
+                stats.histograms()[i].render(name, &labels, out);
+            }
+        }
+        let _ = writeln!(out, "# TYPE grpc_le_rpc_responses_total counter");
+        for ((side, method), stats) in methods.iter() {
+            for (status, count) in &stats.statuses {
+                let _ = writeln!(out, "grpc_le_rpc_responses_total{{side=\"{}\",method=\"{}\",status=\"{}\"}} {}",
+                    side.label(), method, status, count);
+            }
+        }
+        let _ = writeln!(out, "# TYPE grpc_le_rpc_errors_total counter");
+        for ((side, method), stats) in methods.iter() {
+            let errors: u64 = stats.statuses.iter().filter(|(status, _)| *status != "0").map(|(_, count)| count).sum();
+            let _ = writeln!(out, "grpc_le_rpc_errors_total{{side=\"{}\",method=\"{}\"}} {}", side.label(), method, errors);
+        }
+    }
+}
+
+/// Tower layer recording per-method latency, request and response sizes and
+/// gRPC status into `RpcMetrics`. Usable on both the server and on client
+/// channels.
+///
+/// Latency is measured until the response head arrives, which for streaming
+/// RPCs is when the stream is established. The status is taken from the
+/// response head as well, so errors reported only in the trailers of an
+/// already established stream count as `0` (OK). Sizes count the bytes of
+/// each body, framing included, and are recorded once the body is dropped,
+/// so a stream counts as one large body when it ends.
+#[derive(Debug, Clone)]
+pub struct MetricsLayer {
+    metrics: Arc<RpcMetrics>,
+    side: Side,
+}
+
+impl MetricsLayer {
+    pub fn new(metrics: Arc<RpcMetrics>, side: Side) -> Self {
+        MetricsLayer { metrics, side }
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = Metered<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Metered { inner, metrics: self.metrics.clone(), side: self.side }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Metered<S> {
+    inner: S,
+    metrics: Arc<RpcMetrics>,
+    side: Side,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for Metered<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+    ReqBody: Countable,
+    ResBody: Body + Unpin,
+{
+    type Response = http::Response<Counted<ResBody>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        let method = request.uri().path().to_string();
+        let (metrics, side) = (self.metrics.clone(), self.side);
+        let tally = |direction| Tally { metrics: metrics.clone(), side, method: method.clone(), direction, bytes: 0 };
+        let request = request.map(|body| body.counted(tally(Direction::Request)));
+        let response_tally = tally(Direction::Response);
+        let start = Instant::now();
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await;
+            let status = match &response {
+                Ok(response) => response.headers().get("grpc-status")
+                    .and_then(|status| status.to_str().ok())
+                    .unwrap_or("0")
+                    .to_string(),
+                Err(_) => "transport error".to_string(),
+            };
+            metrics.record(side, &method, start.elapsed(), &status);
+            response.map(|response| response.map(|inner| Counted { inner, tally: response_tally }))
+        })
+    }
+}
+
+/// The bytes one body of an RPC carried so far, recorded into `RpcMetrics`
+/// when the body is dropped.
+#[derive(Debug)]
+pub struct Tally {
+    metrics: Arc<RpcMetrics>,
+    side: Side,
+    method: String,
+    direction: Direction,
+    bytes: u64,
+}
+
+impl Drop for Tally {
+    fn drop(&mut self) {
+        self.metrics.record_size(self.side, &self.method, self.direction, self.bytes);
+    }
+}
+
+/// A body counting the bytes of the data frames passing through it.
+#[derive(Debug)]
+pub struct Counted<B> {
+    inner: B,
+    tally: Tally,
+}
+
+impl<B: Body + Unpin> Body for Counted<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let data = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(data))) = &data {
+            self.tally.bytes += data.remaining() as u64;
+        }
+        data
+    }
+
+    fn poll_trailers(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Request bodies `Metered` can count while still handing the inner service
+/// the body type it takes: `hyper::Body` on the server, `BoxBody` on clients.
+pub trait Countable: Body + Sized {
+    fn counted(self, tally: Tally) -> Self;
+}
+
+impl Countable for hyper::Body {
+    fn counted(self, tally: Tally) -> Self {
+        // gRPC requests end with their last message and carry no trailers
+        let mut body = Counted { inner: self, tally };
+        hyper::Body::wrap_stream(futures::stream::poll_fn(move |cx| Pin::new(&mut body).poll_data(cx)))
+    }
+}
+
+impl Countable for BoxBody {
+    fn counted(self, tally: Tally) -> Self {
+        BoxBody::new(Counted::<BoxBody> { inner: self, tally })
+    }
+}
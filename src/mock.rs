@@ -0,0 +1,148 @@
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::info;
+
+use crate::leader_election_service::{peer_message, PeerMessage};
+use crate::transport::{Opening, Peer, Transport};
+use crate::RELAY_BUFFER;
+
+/// The kinds of messages a rule of a [`Script`] applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Probe,
+    Notify,
+    Digest,
+    Any,
+}
+
+impl Kind {
+    fn matches(self, message: &PeerMessage) -> bool {
+        use peer_message::Body;
+        matches!((self, &message.body),
+            (Kind::Any, _) | (Kind::Probe, Some(Body::Probe(_))) | (Kind::Notify, Some(Body::Notify(_))) | (Kind::Digest, Some(Body::Digest(_))))
+    }
+}
+
+impl FromStr for Kind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "probe" => Ok(Kind::Probe),
+            "notify" => Ok(Kind::Notify),
+            "digest" => Ok(Kind::Digest),
+            "*" => Ok(Kind::Any),
+            _ => Err(format!("unknown kind of message {:?}, expected probe, notify, digest or *", s)),
+        }
+    }
+}
+
+/// One line of a [`Script`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    /// `delay <kind> <ms>`: holds the messages back for as long, and those
+    /// sent after them with them.
+    Delay(Kind, Duration),
+    /// `drop <kind>`: never sends the messages.
+    Drop(Kind),
+    /// `phase <n>`: sends probes with phase `n`, whatever phase they are of.
+    Phase(u64),
+}
+
+/// What a mock peer, a node run by `grpc-le mock-peer`, does to the messages
+/// it relays to its neighbours, so that a real node can be tried against an
+/// adversarial neighbour that otherwise speaks the protocol like any other.
+/// The rules apply to each message one after the other, e.g.
+///
+/// ```text
+/// # a neighbour that stalls probes and loses notifications
+/// delay probe 500
+/// drop notify
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    pub rules: Vec<Rule>,
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        text.parse().map_err(|e| format!("{}:{}", path.display(), e))
+    }
+}
+
+impl FromStr for Script {
+    type Err = String;
+
+    /// Parses one rule per line, ignoring blank lines and `#` comments.
+    /// Errors start with the number of the line at fault.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = vec![];
+        for (i, line) in s.lines().enumerate() {
+            let words = line.split('#').next().unwrap_or_default().split_whitespace().collect::<Vec<_>>();
+            let number = |word: &str| word.parse::<u64>().map_err(|e| format!("invalid number {:?}: {}", word, e));
+            let rule = match words[..] {
+                [] => continue,
+                ["delay", kind, ms] => kind.parse().and_then(|kind| Ok(Rule::Delay(kind, Duration::from_millis(number(ms)?)))),
+                ["drop", kind] => kind.parse().map(Rule::Drop),
+                ["phase", phase] => number(phase).map(Rule::Phase),
+                _ => Err(format!("expected `delay <kind> <ms>`, `drop <kind>` or `phase <n>`, found {:?}", line.trim())),
+            };
+            rules.push(rule.map_err(|e| format!("{}: {}", i + 1, e))?);
+        }
+        Ok(Script { rules })
+    }
+}
+
+/// Relays messages over another transport as a [`Script`] says. Delayed
+/// messages hold up those sent after them, so the order on each link is
+/// kept.
+#[derive(Debug)]
+pub struct ScriptedTransport {
+    inner: Arc<dyn Transport>,
+    script: Arc<Script>,
+}
+
+impl ScriptedTransport {
+    pub fn new(inner: Arc<dyn Transport>, script: Script) -> Self {
+        ScriptedTransport { inner, script: Arc::new(script) }
+    }
+}
+
+impl Transport for ScriptedTransport {
+    fn relay<'a>(&'a self, peer: &'a Peer, mut messages: mpsc::Receiver<PeerMessage>) -> Opening<'a> {
+        let (passed, rx) = mpsc::channel(RELAY_BUFFER);
+        let (script, to) = (self.script.clone(), peer.id);
+        tokio::spawn(async move {
+            let mut last = Instant::now();
+            'messages: while let Some(mut message) = messages.recv().await {
+                for &rule in &script.rules {
+                    match rule {
+                        Rule::Delay(kind, delay) if kind.matches(&message) => {
+                            last = last.max(Instant::now() + delay);
+                            tokio::time::sleep_until(last).await;
+                        },
+                        Rule::Drop(kind) if kind.matches(&message) => {
+                            info!(peer = to, "script dropping message {}", message.number);
+                            continue 'messages
+                        },
+                        Rule::Phase(phase) => if let Some(peer_message::Body::Probe(probe)) = &mut message.body {
+                            info!(peer = to, "script sending the probe of phase {} as phase {}", probe.phase, phase);
+                            probe.phase = phase;
+                        },
+                        _ => (),
+                    }
+                }
+                if passed.send(message).await.is_err() {
+                    return
+                }
+            }
+        });
+        self.inner.relay(peer, rx)
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use tonic::metadata::AsciiMetadataValue;
+use tonic::transport::Endpoint;
+use tracing::error;
+
+use crate::leader_election_service::{peer_message, DigestMessage, NotifyMessage, PeerMessage, ProbeMessage, Sequence, TraceContext};
+use crate::outbox::Outbox;
+
+/// A message waiting to be sent to a neighbour.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Probe(ProbeMessage),
+    Notify(NotifyMessage),
+    Digest(DigestMessage),
+}
+
+impl Message {
+    /// The message with its sequence number replaced by `seq`.
+    pub fn sequenced(self, seq: Option<Sequence>) -> Self {
+        match self {
+            Message::Probe(msg) => Message::Probe(ProbeMessage { seq, ..msg }),
+            Message::Notify(msg) => Message::Notify(NotifyMessage { seq, ..msg }),
+            Message::Digest(msg) => Message::Digest(DigestMessage { seq, ..msg }),
+        }
+    }
+}
+
+impl From<Message> for peer_message::Body {
+    fn from(message: Message) -> Self {
+        match message {
+            Message::Probe(msg) => peer_message::Body::Probe(msg),
+            Message::Notify(msg) => peer_message::Body::Notify(msg),
+            Message::Digest(msg) => peer_message::Body::Digest(msg),
+        }
+    }
+}
+
+/// What a full queue does with a new message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Wait until the neighbour has taken a message off the queue.
+    Block,
+    /// Discard the oldest queued message to make room.
+    DropOldest,
+    /// Discard the new message if an identical one is already queued (as
+    /// happens with periodic digests), otherwise wait like `Block`.
+    Coalesce,
+}
+
+impl FromStr for DropPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(DropPolicy::Block),
+            "drop-oldest" => Ok(DropPolicy::DropOldest),
+            "coalesce" => Ok(DropPolicy::Coalesce),
+            _ => Err(format!("unknown drop policy {:?}, expected block, drop-oldest or coalesce", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Envelope {
+    pub message: Message,
+    /// Correlation ID of the request that caused this message, if any.
+    pub request_id: Option<AsciiMetadataValue>,
+    /// Sequence number in the outbox, for messages that are kept there.
+    pub seq: Option<u64>,
+    /// The span of the hop that caused this message, if traced.
+    pub trace: Option<TraceContext>,
+}
+
+/// The neighbour a queue's messages go to.
+#[derive(Debug, Clone)]
+pub struct Peer {
+    pub id: u64,
+    pub endpoint: Endpoint,
+}
+
+/// A message sent to a neighbour, kept until it is acknowledged.
+#[derive(Debug)]
+struct Sent {
+    message: PeerMessage,
+    /// Its sequence number in the outbox, if it is kept there.
+    seq: Option<u64>,
+    at: Instant,
+}
+
+/// How long a neighbour takes to acknowledge the messages it is sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Latency {
+    /// The round trip smoothed over the recent acknowledgements, the way TCP
+    /// smooths its round trip time (RFC 6298).
+    pub smoothed: Option<Duration>,
+    /// The round trip of the last message acknowledged.
+    pub last: Option<Duration>,
+    /// How many round trips were measured.
+    pub samples: u64,
+    /// Whether the smoothed round trip is over the slow neighbour threshold.
+    pub slow: bool,
+    /// How many times the neighbour became slow.
+    pub episodes: u64,
+}
+
+impl Latency {
+    /// Takes in a round trip, returning whether the neighbour became slow
+    /// or recovered with it, if either.
+    fn sample(&mut self, rtt: Duration, threshold: Duration) -> Option<bool> {
+        self.smoothed = Some(self.smoothed.map_or(rtt, |smoothed| (smoothed * 7 + rtt) / 8));
+        self.last = Some(rtt);
+        self.samples += 1;
+        let slow = self.smoothed > Some(threshold);
+        if slow == self.slow {
+            return None
+        }
+        self.slow = slow;
+        self.episodes += slow as u64;
+        Some(slow)
+    }
+}
+
+/// The bounded queue of messages headed to one neighbour, drained in order by
+/// a single sender task.
+#[derive(Debug)]
+pub struct NeighborQueue {
+    peer: Mutex<Peer>,
+    retargeted: Notify,
+    capacity: usize,
+    policy: DropPolicy,
+    queue: Mutex<VecDeque<Envelope>>,
+    pushed: Notify,
+    popped: Notify,
+    dropped: AtomicU64,
+    /// Where critical messages are kept until acknowledged, if anywhere.
+    outbox: Option<Outbox>,
+    /// Messages sent but not acknowledged yet, by the number they were sent
+    /// with.
+    unacknowledged: Mutex<BTreeMap<u64, Sent>>,
+    /// Whether the neighbour could be reached when last tried, if it was
+    /// tried since it became the neighbour.
+    reachable: Mutex<Option<bool>>,
+    /// When the node last sent the neighbour a message and last received
+    /// one from it, if it did since it became the neighbour.
+    traffic: Mutex<(Option<Instant>, Option<Instant>)>,
+    latency: Mutex<Latency>,
+}
+
+impl NeighborQueue {
+    /// Creates the queue, starting out with the messages left in `outbox`.
+    pub fn new(peer: Peer, capacity: usize, policy: DropPolicy, outbox: Option<Outbox>) -> Self {
+        let queue = outbox.iter()
+            .flat_map(Outbox::pending)
+            .map(|(seq, message)| Envelope { message, request_id: None, seq: Some(seq), trace: None })
+            .collect();
+        NeighborQueue {
+            peer: Mutex::new(peer),
+            retargeted: Notify::new(),
+            capacity,
+            policy,
+            queue: Mutex::new(queue),
+            pushed: Notify::new(),
+            popped: Notify::new(),
+            dropped: AtomicU64::new(0),
+            outbox,
+            unacknowledged: Mutex::default(),
+            reachable: Mutex::default(),
+            traffic: Mutex::default(),
+            latency: Mutex::default(),
+        }
+    }
+
+    pub fn peer(&self) -> Peer {
+        self.peer.lock().unwrap().clone()
+    }
+
+    /// Points the queue at a different neighbour, which gets the messages
+    /// still queued and those the old one has not acknowledged.
+    pub fn retarget(&self, peer: Peer) {
+        *self.peer.lock().unwrap() = peer;
+        *self.reachable.lock().unwrap() = None;
+        *self.traffic.lock().unwrap() = (None, None);
+        let episodes = self.latency().episodes;
+        *self.latency.lock().unwrap() = Latency { episodes, ..Latency::default() };
+        self.retargeted.notify_one();
+    }
+
+    /// Notes whether the neighbour could be reached just now.
+    pub fn reached(&self, reachable: bool) {
+        *self.reachable.lock().unwrap() = Some(reachable);
+    }
+
+    /// Whether the neighbour could be reached when last tried, if it was.
+    pub fn reachable(&self) -> Option<bool> {
+        *self.reachable.lock().unwrap()
+    }
+
+    /// Waits until the queue is pointed at a different neighbour.
+    pub async fn retargeted(&self) {
+        self.retargeted.notified().await
+    }
+
+    /// Notes that `message`, kept in the outbox under `seq` if at all, is
+    /// being sent as message `number` at `at`.
+    pub fn sent(&self, number: u64, message: PeerMessage, seq: Option<u64>, at: Instant) {
+        self.unacknowledged.lock().unwrap().insert(number, Sent { message, seq, at });
+        self.traffic.lock().unwrap().0 = Some(at);
+    }
+
+    /// Notes that the neighbour sent the node a message at `at`.
+    pub fn received(&self, at: Instant) {
+        self.traffic.lock().unwrap().1 = Some(at);
+    }
+
+    /// When the node last sent the neighbour a message and last received
+    /// one from it, if it did.
+    pub fn traffic(&self) -> (Option<Instant>, Option<Instant>) {
+        *self.traffic.lock().unwrap()
+    }
+
+    /// The messages sent but not acknowledged yet, in the order they were sent.
+    pub fn unacknowledged(&self) -> Vec<PeerMessage> {
+        self.unacknowledged.lock().unwrap().values().map(|sent| sent.message.clone()).collect()
+    }
+
+    /// The number of the oldest message sent but not acknowledged yet.
+    pub fn oldest_unacknowledged(&self) -> Option<u64> {
+        self.unacknowledged.lock().unwrap().keys().next().copied()
+    }
+
+    /// How long the neighbour takes to acknowledge messages.
+    pub fn latency(&self) -> Latency {
+        *self.latency.lock().unwrap()
+    }
+
+    /// Marks message `number` as processed by the neighbour at `at`, along
+    /// with every message sent before it, and takes its round trip as a
+    /// sample of the neighbour's latency. Returns whether the neighbour
+    /// became slower than `threshold` or recovered with it, if either.
+    pub fn acknowledged(&self, number: u64, at: Instant, threshold: Duration) -> Option<bool> {
+        let acknowledged = {
+            let mut unacknowledged = self.unacknowledged.lock().unwrap();
+            let rest = unacknowledged.split_off(&(number + 1));
+            std::mem::replace(&mut *unacknowledged, rest)
+        };
+        let change = acknowledged.get(&number)
+            .and_then(|sent| self.latency.lock().unwrap().sample(at.saturating_duration_since(sent.at), threshold));
+        let kept = acknowledged.values().filter_map(|sent| sent.seq);
+        if let Some(outbox) = &self.outbox {
+            for seq in kept {
+                if let Err(e) = outbox.acknowledged(seq) {
+                    error!("failed to update the outbox for node {}: {}", self.peer().id, e);
+                }
+            }
+        }
+        change
+    }
+
+    /// Forgets the messages sent but not acknowledged yet, counting them as
+    /// dropped, when the neighbour cannot be reached. Any kept in the outbox
+    /// stay there, to be sent again when the node restarts. Returns how many
+    /// there were.
+    pub fn abandon(&self) -> u64 {
+        let abandoned = std::mem::take(&mut *self.unacknowledged.lock().unwrap()).len() as u64;
+        self.dropped.fetch_add(abandoned, Ordering::Relaxed);
+        abandoned
+    }
+
+    /// Queues a message, applying the drop policy if the queue is full. Any
+    /// sequence number the message came with is dropped; a new one is
+    /// assigned when it is sent.
+    pub async fn push(&self, message: Message, request_id: Option<AsciiMetadataValue>, trace: Option<TraceContext>) {
+        let mut envelope = Envelope { message: message.sequenced(None), request_id, seq: None, trace };
+        if self.coalesces(&self.queue.lock().unwrap(), &envelope.message) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return
+        }
+        // logged without holding the queue, for the sender not to wait on the disk
+        match &self.outbox {
+            Some(outbox) if Outbox::keeps(&envelope.message) => match outbox.record(&envelope.message) {
+                Ok(seq) => envelope.seq = Some(seq),
+                Err(e) => error!("failed to record a message for node {} in the outbox: {}", self.peer().id, e),
+            },
+            _ => (),
+        }
+        loop {
+            let queued = {
+                let mut queue = self.queue.lock().unwrap();
+                if self.coalesces(&queue, &envelope.message) {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    Ok(Some(envelope))
+                } else {
+                    let oldest = match queue.len() >= self.capacity && self.policy == DropPolicy::DropOldest {
+                        true => queue.pop_front().inspect(|_| { self.dropped.fetch_add(1, Ordering::Relaxed); }),
+                        false => None,
+                    };
+                    if queue.len() < self.capacity {
+                        queue.push_back(envelope);
+                        self.pushed.notify_one();
+                        Ok(oldest)
+                    } else {
+                        Err(envelope)
+                    }
+                }
+            };
+            match queued {
+                Ok(dropped) => return self.forget(dropped),
+                Err(waiting) => envelope = waiting,
+            }
+            self.popped.notified().await;
+        }
+    }
+
+    /// Whether the coalescing drop policy discards `message`, for an
+    /// identical one is already in `queue`.
+    fn coalesces(&self, queue: &VecDeque<Envelope>, message: &Message) -> bool {
+        self.policy == DropPolicy::Coalesce && queue.iter().any(|queued| queued.message == *message)
+    }
+
+    /// Marks a message dropped from the queue as acknowledged in the outbox,
+    /// if it was kept there, for a restarted node not to send it after all.
+    fn forget(&self, dropped: Option<Envelope>) {
+        if let (Some(outbox), Some(seq)) = (&self.outbox, dropped.and_then(|dropped| dropped.seq)) {
+            if let Err(e) = outbox.acknowledged(seq) {
+                error!("failed to update the outbox for node {}: {}", self.peer().id, e);
+            }
+        }
+    }
+
+    /// Waits for the next message to send.
+    pub async fn pop(&self) -> Envelope {
+        loop {
+            if let Some(envelope) = self.queue.lock().unwrap().pop_front() {
+                self.popped.notify_one();
+                return envelope
+            }
+            self.pushed.notified().await;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    /// How many messages the drop policy has discarded so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
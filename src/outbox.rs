@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use tracing::warn;
+
+use crate::base64;
+use crate::leader_election_service::{DigestMessage, NotifyMessage, Payload, ProbeMessage};
+pub use crate::outbound::Message;
+
+/// A write-ahead log of the messages headed to one neighbour that must not
+/// be lost, so that a node restarted with the same outbox directory still
+/// sends whatever it had not got through before.
+///
+/// Each message gets a sequence number and a line in the log when it is
+/// queued, and an `<seq> ack` line once the neighbour has responded to it.
+/// Leader notifications are always logged; with the `wal` feature probes are
+/// logged too.
+#[derive(Debug)]
+pub struct Outbox {
+    log: Mutex<Log>,
+}
+
+#[derive(Debug)]
+struct Log {
+    file: File,
+    next_seq: u64,
+    pending: BTreeMap<u64, Message>,
+}
+
+impl Outbox {
+    /// Opens the outbox at `path`, picking up whatever an earlier run left
+    /// unacknowledged. Acknowledged entries are dropped from the file, as is
+    /// a last line torn by a crash while it was written, the message of which
+    /// was never queued.
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut pending = BTreeMap::new();
+        let mut next_seq = 0;
+        let lines = contents.lines().collect::<Vec<_>>();
+        for (i, line) in lines.iter().enumerate() {
+            let (seq, entry) = match parse_line(line) {
+                Some(parsed) => parsed,
+                None if i + 1 == lines.len() => {
+                    warn!("dropping the torn last entry {:?} of {}", line, path.display());
+                    break
+                },
+                None => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("malformed outbox entry {:?} in {}", line, path.display()))),
+            };
+            match entry {
+                Some(message) => { pending.insert(seq, message); },
+                None => { pending.remove(&seq); },
+            }
+            next_seq = next_seq.max(seq + 1);
+        }
+
+        // write a fresh file and move it in place, so a crash never leaves half an outbox behind
+        let compacted = pending.iter().map(|(&seq, message)| format_line(seq, message)).collect::<String>();
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, compacted)?;
+        fs::rename(&tmp, &path)?;
+        let file = OpenOptions::new().append(true).open(&path)?;
+        Ok(Outbox { log: Mutex::new(Log { file, next_seq, pending }) })
+    }
+
+    /// Whether messages like this one are worth keeping.
+    pub fn keeps(message: &Message) -> bool {
+        match message {
+            Message::Notify(_) => true,
+            Message::Probe(_) => cfg!(feature = "wal"),
+            Message::Digest(_) => false,
+        }
+    }
+
+    /// The messages not acknowledged yet, in the order they were recorded.
+    pub fn pending(&self) -> Vec<(u64, Message)> {
+        self.log.lock().unwrap().pending.iter().map(|(&seq, message)| (seq, message.clone())).collect()
+    }
+
+    /// Logs a message that is about to be queued and returns its sequence number.
+    pub fn record(&self, message: &Message) -> io::Result<u64> {
+        let mut log = self.log.lock().unwrap();
+        let seq = log.next_seq;
+        log.next_seq += 1;
+        log.file.write_all(format_line(seq, message).as_bytes())?;
+        log.file.sync_data()?;
+        log.pending.insert(seq, message.clone());
+        Ok(seq)
+    }
+
+    /// Logs the acknowledgement of a message by the neighbour.
+    pub fn acknowledged(&self, seq: u64) -> io::Result<()> {
+        let mut log = self.log.lock().unwrap();
+        if log.pending.remove(&seq).is_none() {
+            return Ok(())
+        }
+        if log.pending.is_empty() {
+            // nothing left to replay, start over with an empty file
+            log.file.set_len(0)?;
+        } else {
+            log.file.write_all(format!("{} ack\n", seq).as_bytes())?;
+        }
+        log.file.sync_data()
+    }
+}
+
+/// The log line of `message`, recorded with sequence number `seq`.
+pub fn format_line(seq: u64, message: &Message) -> String {
+    match message {
+        Message::Probe(msg) =>
+            format!("{} probe {} {} {} {} {} {}\n", seq, msg.sender_id, msg.headed_left, msg.phase, msg.term, msg.priority, format_zone(&msg.zone)),
+        Message::Notify(msg) => format!("{} notify {} {} {} {} {} {} {} {}\n",
+            seq, msg.leader_id, msg.headed_left, format_ids(&msg.ranking), msg.term, format_ids(&msg.priorities),
+            format_bytes(msg.leader_addr.as_bytes()), format_bytes(&msg.leader_metadata), format_payload(msg.payload.as_ref())),
+        Message::Digest(msg) =>
+            format!("{} digest {} {} {} {}\n", seq, msg.leader_id, msg.ring_size, format_ids(&msg.ranking), msg.term),
+    }
+}
+
+/// Writes node IDs or other numbers as a single field, `-` standing in for
+/// none.
+fn format_ids(ids: &[u64]) -> String {
+    if ids.is_empty() {
+        return "-".to_string()
+    }
+    ids.iter().map(u64::to_string).collect::<Vec<_>>().join(",")
+}
+
+/// Writes a zone as a single field, `-` standing in for none.
+fn format_zone(zone: &str) -> &str {
+    match zone {
+        "" => "-",
+        zone => zone,
+    }
+}
+
+/// Writes bytes, or text that may hold spaces, as a single base64 field,
+/// `-` standing in for none.
+fn format_bytes(bytes: &[u8]) -> String {
+    match bytes {
+        [] => "-".to_string(),
+        bytes => base64::encode(bytes),
+    }
+}
+
+/// Writes a payload as its content type and data in base64, joined by a
+/// colon, `-` standing in for none.
+fn format_payload(payload: Option<&Payload>) -> String {
+    match payload {
+        Some(payload) => format!("{}:{}", base64::encode(payload.content_type.as_bytes()), base64::encode(&payload.data)),
+        None => "-".to_string(),
+    }
+}
+
+fn parse_bytes(field: &str) -> Option<Vec<u8>> {
+    match field {
+        "-" => Some(Vec::new()),
+        _ => base64::decode(field),
+    }
+}
+
+fn parse_payload(field: &str) -> Option<Option<Payload>> {
+    let (content_type, data) = match field {
+        "-" => return Some(None),
+        _ => field.split_once(':')?,
+    };
+    let content_type = String::from_utf8(base64::decode(content_type)?).ok()?;
+    Some(Some(Payload { content_type, data: base64::decode(data)? }))
+}
+
+fn parse_ids(field: &str) -> Option<Vec<u64>> {
+    match field {
+        "-" => Some(Vec::new()),
+        _ => field.split(',').map(|id| id.parse().ok()).collect(),
+    }
+}
+
+/// Parses an optional number field, zero if it is missing.
+fn parse_optional(field: Option<&&str>) -> Option<u64> {
+    field.map_or(Some(0), |number| number.parse().ok())
+}
+
+/// Parses a log line into its sequence number and either the logged message
+/// or `None` for an acknowledgement.
+pub fn parse_line(line: &str) -> Option<(u64, Option<Message>)> {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    let seq = fields.first()?.parse().ok()?;
+    let entry = match fields[1..] {
+        ["ack"] => None,
+        // logs written before zones existed lack the last field, before
+        // priorities existed the one before it, before terms existed the one
+        // before that, and before rankings existed the one before that, while
+        // those of notifications lack the leader's address, metadata and
+        // payload, all three of which came last; each group's messages go to
+        // an outbox of its own, and the relay stream carries their group
+        ["probe", sender_id, headed_left, phase, ref rest @ ..] if rest.len() <= 3 => Some(Message::Probe(ProbeMessage {
+            sender_id: sender_id.parse().ok()?,
+            headed_left: headed_left.parse().ok()?,
+            phase: phase.parse().ok()?,
+            seq: None,
+            term: parse_optional(rest.first())?,
+            priority: parse_optional(rest.get(1))?,
+            group_id: 0,
+            hops: 0,
+            hops_remaining: 0,
+            zone: rest.get(2).filter(|&&zone| zone != "-").map_or(String::new(), |zone| zone.to_string()),
+        })),
+        ["notify", leader_id, headed_left, ref rest @ ..] if rest.len() <= 3 || rest.len() == 6 => Some(Message::Notify(NotifyMessage {
+            leader_id: leader_id.parse().ok()?,
+            headed_left: headed_left.parse().ok()?,
+            seq: None,
+            ranking: parse_ids(rest.first().unwrap_or(&"-"))?,
+            leader_addr: String::from_utf8(parse_bytes(rest.get(3).unwrap_or(&"-"))?).ok()?,
+            leader_metadata: parse_bytes(rest.get(4).unwrap_or(&"-"))?,
+            payload: parse_payload(rest.get(5).unwrap_or(&"-"))?,
+            term: parse_optional(rest.get(1))?,
+            priorities: parse_ids(rest.get(2).unwrap_or(&"-"))?,
+            group_id: 0,
+        })),
+        ["digest", leader_id, ring_size, ref rest @ ..] if rest.len() <= 2 => Some(Message::Digest(DigestMessage {
+            leader_id: leader_id.parse().ok()?,
+            ring_size: ring_size.parse().ok()?,
+            seq: None,
+            ranking: parse_ids(rest.first().unwrap_or(&"-"))?,
+            term: parse_optional(rest.get(1))?,
+            group_id: 0,
+            hops: 0,
+        })),
+        _ => return None,
+    };
+    Some((seq, entry))
+}
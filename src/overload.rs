@@ -0,0 +1,99 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use hyper::body::HttpBody;
+use tokio::sync::Semaphore;
+use tonic::body::BoxBody;
+use tonic::Status;
+use tower::{Layer, Service};
+
+use crate::config::Middleware;
+
+/// Limits how many calls a node's server handles at once, and how long each
+/// may take to be answered, as its [`Middleware`] says. A call holds its
+/// place until its response ends, so open streams count against the limit
+/// for as long as they stay open. All services the layer wraps share the
+/// limit, however many addresses they are served on.
+#[derive(Debug, Clone)]
+pub struct OverloadLayer {
+    places: Option<Arc<Semaphore>>,
+    timeout: Option<Duration>,
+    load_shed: bool,
+}
+
+impl OverloadLayer {
+    pub fn new(middleware: &Middleware) -> Self {
+        OverloadLayer {
+            places: middleware.concurrency_limit.map(|limit| Arc::new(Semaphore::new(limit))),
+            timeout: middleware.timeout,
+            load_shed: middleware.load_shed,
+        }
+    }
+}
+
+impl<S> Layer<S> for OverloadLayer {
+    type Service = Overload<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Overload { inner, layer: self.clone() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Overload<S> {
+    inner: S,
+    layer: OverloadLayer,
+}
+
+impl<S, ReqBody> Service<http::Request<ReqBody>> for Overload<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        // the clone that was not polled ready takes the place of this one
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let (places, load_shed) = (self.layer.places.clone(), self.layer.load_shed);
+        let answer = async move {
+            let place = match places {
+                Some(places) if load_shed => match places.try_acquire_owned() {
+                    Ok(place) => Some(place),
+                    Err(_) => return Ok(Status::unavailable("the node is handling as many calls as it takes").to_http()),
+                },
+                Some(places) => Some(places.acquire_owned().await.expect("the semaphore is never closed")),
+                None => None,
+            };
+            let response = inner.call(request).await?;
+            Ok(match place {
+                // given back once the response is dropped, having ended
+                Some(place) => response.map(|body| body.map_data(move |data| {
+                    let _held = &place;
+                    data
+                }).boxed_unsync()),
+                None => response,
+            })
+        };
+        match self.layer.timeout {
+            Some(timeout) => Box::pin(async move {
+                match tokio::time::timeout(timeout, answer).await {
+                    Ok(answered) => answered,
+                    Err(_) => Ok(Status::deadline_exceeded("the node did not answer the call in time").to_http()),
+                }
+            }),
+            None => Box::pin(answer),
+        }
+    }
+}
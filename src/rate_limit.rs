@@ -0,0 +1,231 @@
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use tokio::time::{Duration, Instant};
+use tonic::body::BoxBody;
+use tonic::transport::server::{TcpConnectInfo, TlsConnectInfo};
+use tower::{Layer, Service};
+use tracing::warn;
+
+use crate::clock::Clock;
+use crate::error::ElectionError;
+
+/// How often the same probe may reach a node in a term: once, and again
+/// each time its sender restarts and probes anew.
+pub const PROBE_REPEATS: u32 = 4;
+
+/// How many probes, by sender, phase and direction, a node keeps count of in
+/// a term. Far more than a ring's election sends, so only a peer making up
+/// sender IDs runs into it, and its probes are refused rather than counted.
+pub const MAX_PROBES_COUNTED: usize = 1 << 16;
+
+/// The period the restart limits count restarts over.
+const RESTART_PERIOD: Duration = Duration::from_secs(60);
+
+/// Allows `burst` events every `period`, in bursts of as many.
+#[derive(Debug)]
+pub struct TokenBucket {
+    burst: f64,
+    period: Duration,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    /// Allows `rate` events a second.
+    pub fn new(rate: usize, now: Instant) -> Self {
+        TokenBucket::per(rate, Duration::from_secs(1), now)
+    }
+
+    pub fn per(burst: usize, period: Duration, now: Instant) -> Self {
+        TokenBucket { burst: burst as f64, period, tokens: burst as f64, last: now }
+    }
+
+    /// Takes a token, if the bucket refilled one since it ran out.
+    pub fn take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64() / self.period.as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.burst).min(self.burst);
+        self.last = now;
+        if self.tokens < 1.0 {
+            return false
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// Whether the bucket has been left alone long enough to be full again.
+    fn idle(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last) >= self.period
+    }
+}
+
+/// The allowance of calls and messages of the connection a request came
+/// over, which the rate limiting layer hands to the services in the
+/// request's extensions.
+#[derive(Debug, Clone)]
+pub struct ConnectionLimit {
+    pub remote: SocketAddr,
+    bucket: Arc<Mutex<TokenBucket>>,
+    clock: Arc<dyn Clock>,
+    limited: Arc<AtomicU64>,
+}
+
+impl ConnectionLimit {
+    /// Takes one of the connection's allowance, failing once it is used up
+    /// for the moment.
+    pub fn take(&self, node: u64, what: &str) -> Result<(), ElectionError> {
+        if self.bucket.lock().unwrap().take(self.clock.now()) {
+            return Ok(())
+        }
+        let limited = self.limited.fetch_add(1, Ordering::Relaxed) + 1;
+        warn!(node, "refusing {} from {}, which sends too many too fast ({} refused so far)", what, self.remote, limited);
+        Err(ElectionError::RateLimited { node, reason: format!("{} from {}, which sends too many too fast", what, self.remote) })
+    }
+}
+
+/// Refuses calls from a connection that makes more than `rate` calls and
+/// sends more than `rate` messages a second between them, in bursts of as
+/// many, with `RESOURCE_EXHAUSTED`, so that a flooding peer cannot wear the
+/// node down. Streams take their share per message through the
+/// [`ConnectionLimit`] in the extensions of their requests.
+#[derive(Debug, Clone)]
+pub struct RateLimitLayer {
+    node: u64,
+    rate: usize,
+    clock: Arc<dyn Clock>,
+    buckets: Arc<Mutex<HashMap<SocketAddr, Arc<Mutex<TokenBucket>>>>>,
+    limited: Arc<AtomicU64>,
+}
+
+impl RateLimitLayer {
+    pub fn new(node: u64, rate: usize, clock: Arc<dyn Clock>, limited: Arc<AtomicU64>) -> Self {
+        RateLimitLayer { node, rate, clock, buckets: Arc::default(), limited }
+    }
+
+    /// The allowance of the connection from `remote`.
+    fn limit(&self, remote: SocketAddr) -> ConnectionLimit {
+        let mut buckets = self.buckets.lock().unwrap();
+        let now = self.clock.now();
+        if !buckets.contains_key(&remote) {
+            // a full bucket is as good as a new one
+            buckets.retain(|_, bucket| Arc::strong_count(bucket) > 1 || !bucket.lock().unwrap().idle(now));
+        }
+        let bucket = buckets.entry(remote).or_insert_with(|| Arc::new(Mutex::new(TokenBucket::new(self.rate, now)))).clone();
+        ConnectionLimit { remote, bucket, clock: self.clock.clone(), limited: self.limited.clone() }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit { inner, layer: self.clone() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    layer: RateLimitLayer,
+}
+
+impl<S, ReqBody> Service<http::Request<ReqBody>> for RateLimit<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<ReqBody>) -> Self::Future {
+        let extensions = request.extensions();
+        let remote = extensions.get::<TcpConnectInfo>().and_then(TcpConnectInfo::remote_addr)
+            .or_else(|| extensions.get::<TlsConnectInfo<TcpConnectInfo>>().and_then(|info| info.get_ref().remote_addr()));
+        // only connections over TCP have a remote address to be told apart by
+        if let Some(remote) = remote {
+            let limit = self.layer.limit(remote);
+            if let Err(e) = limit.take(self.layer.node, &format!("a call to {}", request.uri().path())) {
+                return Box::pin(async move { Ok(tonic::Status::from(e).to_http()) })
+            }
+            request.extensions_mut().insert(limit);
+        }
+        Box::pin(self.inner.call(request))
+    }
+}
+
+/// Throttles the requests to restart the election, allowing `total` a
+/// minute from all callers together and `per_caller` a minute from each, in
+/// bursts of as many, so that a flapping failure detector or a careless
+/// operator cannot keep the ring from ever settling on a leader.
+#[derive(Debug)]
+pub struct RestartLimit {
+    per_caller: usize,
+    total: Mutex<TokenBucket>,
+    callers: Mutex<HashMap<IpAddr, TokenBucket>>,
+}
+
+impl RestartLimit {
+    pub fn new(total: usize, per_caller: usize, now: Instant) -> Self {
+        RestartLimit { per_caller, total: Mutex::new(TokenBucket::per(total, RESTART_PERIOD, now)), callers: Mutex::default() }
+    }
+
+    /// Takes a restart from the allowance of `caller`, if known, and from
+    /// that of all callers, failing if either is used up for the moment.
+    pub fn admit(&self, node: u64, caller: Option<IpAddr>, now: Instant) -> Result<(), ElectionError> {
+        let refused = |reason: String| Err(ElectionError::RateLimited { node, reason });
+        if let Some(caller) = caller {
+            let mut callers = self.callers.lock().unwrap();
+            if !callers.contains_key(&caller) {
+                // a full bucket is as good as a new one
+                callers.retain(|_, bucket| !bucket.idle(now));
+            }
+            let bucket = callers.entry(caller).or_insert_with(|| TokenBucket::per(self.per_caller, RESTART_PERIOD, now));
+            if !bucket.take(now) {
+                return refused(format!("a restart of the election from {}, which asks for too many too fast", caller))
+            }
+        }
+        if !self.total.lock().unwrap().take(now) {
+            return refused("a restart of the election, of which there are too many too fast".to_string())
+        }
+        Ok(())
+    }
+}
+
+/// Counts the probes each sender sent in each phase and direction of the
+/// current term, of which there should be one each.
+#[derive(Debug, Default)]
+pub struct ProbeLimit {
+    seen: Mutex<BTreeMap<(u64, u64, u64, bool), u32>>,
+}
+
+impl ProbeLimit {
+    /// Records a probe of `term` and fails if its sender sent the same one
+    /// too often, or if the node counts [`MAX_PROBES_COUNTED`] others in the
+    /// term already. Forgets the probes of earlier terms.
+    pub fn admit(&self, node: u64, term: u64, sender: u64, phase: u64, headed_left: bool) -> Result<(), ElectionError> {
+        let mut seen = self.seen.lock().unwrap();
+        if seen.first_key_value().is_some_and(|(&(seen_term, ..), _)| seen_term < term) {
+            seen.retain(|&(seen_term, ..), _| seen_term >= term);
+        }
+        let key = (term, sender, phase, headed_left);
+        if seen.len() >= MAX_PROBES_COUNTED && !seen.contains_key(&key) {
+            return Err(ElectionError::RateLimited { node, reason: format!("probe of node {} in phase {} of term {}, past the {} counted", sender, phase, term, seen.len()) })
+        }
+        let count = seen.entry(key).or_default();
+        *count += 1;
+        if *count <= PROBE_REPEATS {
+            return Ok(())
+        }
+        Err(ElectionError::RateLimited { node, reason: format!("probe {} of node {} in phase {} of term {}", count, sender, phase, term) })
+    }
+}
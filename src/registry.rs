@@ -0,0 +1,193 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, StatusCode};
+use tracing::{info, warn};
+
+use crate::base64;
+use crate::config::{Registration, Store};
+use crate::json::{self, Json};
+use crate::{ElectionResult, Node};
+
+/// The key-value store a leader registers itself in, over its HTTP API.
+struct Registry {
+    store: Store,
+    addr: String,
+    key: String,
+    /// How long the key outlives the leader's last renewal.
+    ttl: Duration,
+    deadline: Duration,
+    client: Client<HttpConnector>,
+}
+
+impl Registry {
+    async fn call(&self, method: Method, path: &str, body: String) -> Result<(StatusCode, String), String> {
+        let request = Request::builder()
+            .method(method)
+            .uri(format!("http://{}{}", self.addr, path))
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .map_err(|e| e.to_string())?;
+        let response = async {
+            let response = self.client.request(request).await?;
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body()).await?;
+            Ok::<_, hyper::Error>((status, String::from_utf8_lossy(&body).into_owned()))
+        };
+        match tokio::time::timeout(self.deadline, response).await {
+            Ok(response) => response.map_err(|e| format!("cannot reach {}: {}", self.addr, e)),
+            Err(_) => Err(format!("no answer from {} within {:?}", self.addr, self.deadline)),
+        }
+    }
+
+    /// Calls the store, failing unless it succeeds, and returns what it
+    /// answered.
+    async fn expect(&self, method: Method, path: &str, body: String) -> Result<String, String> {
+        match self.call(method, path, body).await? {
+            (status, body) if status.is_success() => Ok(body),
+            (status, body) => Err(format!("{} answered {} to {}: {}", self.addr, status, path, body.trim())),
+        }
+    }
+
+    /// Starts a lease, or a session in Consul's words, which the store ends
+    /// unless it is renewed within the TTL, and returns its ID.
+    async fn grant(&self) -> Result<String, String> {
+        let secs = self.ttl.as_secs().max(1);
+        let granted = match self.store {
+            Store::Etcd => self.expect(Method::POST, "/v3/lease/grant", format!("{{\"TTL\": {}}}", secs)).await?,
+            Store::Consul => {
+                let session = format!("{{\"Name\": \"grpc-le\", \"TTL\": \"{}s\", \"Behavior\": \"delete\", \"LockDelay\": \"0s\"}}", secs);
+                self.expect(Method::PUT, "/v1/session/create", session).await?
+            },
+        };
+        match json::parse(&granted)?.get("ID") {
+            Some(Json::String(id)) => Ok(id.clone()),
+            _ => Err(format!("{} granted no lease: {}", self.addr, granted.trim())),
+        }
+    }
+
+    /// Writes `value` under the key for as long as `lease` lasts. Returns
+    /// whether it did, which Consul refuses while another session still
+    /// holds the key.
+    async fn put(&self, lease: &str, value: &str) -> Result<bool, String> {
+        match self.store {
+            Store::Etcd => {
+                // etcd's gateway takes keys and values in base64
+                let put = format!("{{\"key\": \"{}\", \"value\": \"{}\", \"lease\": {:?}}}", base64::encode(self.key.as_bytes()), base64::encode(value.as_bytes()), lease);
+                self.expect(Method::POST, "/v3/kv/put", put).await.map(|_| true)
+            },
+            Store::Consul => {
+                let path = format!("/v1/kv/{}?acquire={}", self.key, lease);
+                self.expect(Method::PUT, &path, value.to_string()).await.map(|acquired| acquired.trim() == "true")
+            },
+        }
+    }
+
+    /// Renews `lease`, returning whether it was still alive to be renewed.
+    async fn renew(&self, lease: &str) -> Result<bool, String> {
+        match self.store {
+            Store::Etcd => {
+                let renewed = self.expect(Method::POST, "/v3/lease/keepalive", format!("{{\"ID\": {:?}}}", lease)).await?;
+                // an expired lease is renewed for no time at all, which the gateway leaves out
+                let ttl = json::parse(&renewed)?.get("result").and_then(|result| match result.get("TTL") {
+                    Some(Json::String(ttl)) => ttl.parse::<i64>().ok(),
+                    _ => None,
+                });
+                Ok(ttl.is_some_and(|ttl| ttl > 0))
+            },
+            Store::Consul => match self.call(Method::PUT, &format!("/v1/session/renew/{}", lease), String::new()).await? {
+                (StatusCode::NOT_FOUND, _) => Ok(false),
+                (status, _) if status.is_success() => Ok(true),
+                (status, body) => Err(format!("{} answered {} to renewing the session: {}", self.addr, status, body.trim())),
+            },
+        }
+    }
+
+    /// Ends `lease`, which deletes the key along with it.
+    async fn revoke(&self, lease: &str) -> Result<(), String> {
+        match self.store {
+            Store::Etcd => self.expect(Method::POST, "/v3/lease/revoke", format!("{{\"ID\": {:?}}}", lease)).await.map(drop),
+            Store::Consul => self.expect(Method::PUT, &format!("/v1/session/destroy/{}", lease), String::new()).await.map(drop),
+        }
+    }
+}
+
+/// Registers `node` in the store of `registration` for as long as it leads,
+/// so that systems that do not speak gRPC can find the leader with their
+/// own service discovery. The key holds the node's ID, its URL `url` and the
+/// TTL, e.g.
+///
+/// ```json
+/// {"id": 1, "addr": "http://[::1]:40001", "ttl_ms": 10000}
+/// ```
+///
+/// and goes away along with the lease it is attached to: once the node stops
+/// leading or shuts down, or `ttl` after it last renewed the lease if it can
+/// no longer reach the store. The leaders of groups other than the default
+/// one register under `<key>/<group>`. Runs until the node shuts down.
+pub async fn register(node: Node, registration: Registration, ttl: Duration, url: String) {
+    let Registration { store, addr, key } = registration;
+    let key = match node.group {
+        0 => key,
+        group => format!("{}/{}", key, group),
+    };
+    let registry = Registry { store, addr, key, ttl, deadline: node.timing().rpc_deadline, client: Client::new() };
+    let value = format!("{{\"id\": {}, \"addr\": {:?}, \"ttl_ms\": {}}}", node.id, url, ttl.as_millis());
+    let mut results = node.subscribe();
+    let mut ticks = node.clock.clone().interval(ttl / 3);
+    // the lease, and whether the key is attached to it yet
+    let mut lease: Option<(String, bool)> = None;
+    loop {
+        let leads = *results.borrow_and_update() == ElectionResult::Leader;
+        match (leads, lease.take()) {
+            (true, None) => match registry.grant().await {
+                Ok(granted) => {
+                    lease = Some((granted, false));
+                    continue
+                },
+                Err(e) => warn!(node = node.id, "cannot register as the leader in {}: {}", registry.addr, e),
+            },
+            (true, Some((granted, false))) => match registry.put(&granted, &value).await {
+                Ok(true) => {
+                    info!(node = node.id, "registered as the leader under {} in {}", registry.key, registry.addr);
+                    lease = Some((granted, true));
+                },
+                Ok(false) => {
+                    warn!(node = node.id, "{} still holds {} for the previous leader", registry.addr, registry.key);
+                    lease = Some((granted, false));
+                },
+                // the lease may have run out in the meantime
+                Err(e) => warn!(node = node.id, "cannot register as the leader in {}: {}", registry.addr, e),
+            },
+            (true, Some((granted, true))) => match registry.renew(&granted).await {
+                Ok(true) => lease = Some((granted, true)),
+                Ok(false) => {
+                    warn!(node = node.id, "the registration in {} ran out, registering again", registry.addr);
+                    continue
+                },
+                Err(e) => {
+                    warn!(node = node.id, "cannot renew the registration in {}: {}", registry.addr, e);
+                    lease = Some((granted, true));
+                },
+            },
+            (false, Some((granted, _))) => deregister(&node, &registry, &granted).await,
+            (false, None) => (),
+        }
+        tokio::select! {
+            _ = ticks.next() => (),
+            changed = results.changed() => if changed.is_err() { break },
+            _ = node.stopped() => break,
+        }
+    }
+    if let Some((granted, _)) = lease {
+        deregister(&node, &registry, &granted).await;
+    }
+}
+
+async fn deregister(node: &Node, registry: &Registry, lease: &str) {
+    match registry.revoke(lease).await {
+        Ok(()) => info!(node = node.id, "deregistered as the leader from {}", registry.addr),
+        Err(e) => warn!(node = node.id, "cannot deregister from {}, leaving it to the TTL: {}", registry.addr, e),
+    }
+}
@@ -0,0 +1,392 @@
+use std::sync::atomic::Ordering as AtomicOrdering;
+
+use futures::StreamExt;
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+use crate::error::ElectionError;
+use crate::events::Repair;
+use crate::leader_election_service::{HeartbeatRequest, HeartbeatResponse, MergeRequest, Neighbor, ReconfigureRequest, ReelectRequest, ResizeRequest};
+use crate::outbound::Peer;
+use crate::{Client, Node, NodeState};
+
+/// How many nodes past its right neighbour a node keeps track of, and so
+/// how many failed nodes in a row the ring survives.
+pub const SUCCESSORS: usize = 3;
+
+/// How many heartbeats in a row the right neighbour may fail to answer
+/// before it is taken for dead.
+const LIVENESS_FAILURES: u32 = 3;
+
+/// What a node knows of the ring beyond its neighbours, learned from the
+/// heartbeats of its right neighbour.
+#[derive(Debug, Default)]
+pub struct Membership {
+    /// How the ring reaches the node, as its right neighbour knows it.
+    addr: Option<String>,
+    /// The nodes past the right neighbour, nearest first.
+    successors: Vec<Neighbor>,
+    /// The newest epoch of a reconfiguration known around the ring.
+    epoch: u64,
+    /// The right neighbour the node lost as the ring split, which it keeps
+    /// checking on to merge the segments once it answers again.
+    severed: Option<Neighbor>,
+}
+
+/// How a ring split by a partition stands, as the segment a node is in
+/// knows it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Partition {
+    /// How many nodes the ring had before it split.
+    pub whole: u64,
+    /// Whether the segment may elect a leader, for holding a quorum or the
+    /// witness.
+    pub quorate: bool,
+}
+
+fn neighbor(peer: &Peer) -> Neighbor {
+    Neighbor { id: peer.id, addr: peer.endpoint.uri().to_string() }
+}
+
+impl Node {
+    /// The node's part of a heartbeat response: its left neighbour, the
+    /// nodes following it and the newest epoch it knows of.
+    pub(crate) fn membership(&self) -> (Neighbor, Vec<Neighbor>, u64) {
+        let membership = self.membership.lock().unwrap();
+        let mut successors = vec![neighbor(&self.right.peer())];
+        successors.extend(membership.successors.iter().cloned());
+        successors.truncate(SUCCESSORS);
+        let epoch = membership.epoch.max(self.topology_epoch.load(AtomicOrdering::SeqCst));
+        (neighbor(&self.left.peer()), successors, epoch)
+    }
+
+    /// How the ring stands if it split, as far as the node knows.
+    pub fn partition(&self) -> Option<Partition> {
+        *self.partition.lock().unwrap()
+    }
+
+    /// Asks for a restart of the election in `term` for the ring change of
+    /// `epoch`, in the segment the node is in if the ring split.
+    pub(crate) fn reelect_request(&self, epoch: u64, ring_size: u64, term: u64) -> ReelectRequest {
+        let Partition { whole, quorate } = self.partition().unwrap_or(Partition { whole: 0, quorate: false });
+        ReelectRequest { epoch, ring_size, term, group_id: self.group, whole_size: whole, quorate }
+    }
+
+    /// How many nodes of a ring of `whole` nodes a segment needs to elect
+    /// a leader.
+    fn quorum(&self, whole: u64) -> u64 {
+        self.quorum.unwrap_or(whole / 2 + 1)
+    }
+
+    /// Whether the segment of `members` of a ring of `whole` nodes may elect
+    /// a leader: if it holds a quorum, or the witness where no other segment
+    /// can hold a quorum.
+    fn quorate(&self, members: &[u64], whole: u64) -> bool {
+        let (size, quorum) = (members.len() as u64, self.quorum(whole));
+        size >= quorum || self.witness.is_some_and(|witness| members.contains(&witness)) && whole.saturating_sub(size) < quorum
+    }
+
+    /// Stops checking on the right neighbour lost as the ring split, once
+    /// the segments were merged.
+    pub(crate) fn healed(&self) {
+        self.membership.lock().unwrap().severed = None;
+    }
+
+    /// Learns the ring beyond the right neighbour from its heartbeat.
+    fn learn_membership(&self, heartbeat: &HeartbeatResponse) {
+        let mut membership = self.membership.lock().unwrap();
+        if let Some(left) = heartbeat.left.as_ref().filter(|left| left.id == self.id) {
+            membership.addr = Some(left.addr.clone());
+        }
+        membership.successors = heartbeat.successors.clone();
+        membership.epoch = membership.epoch.max(heartbeat.epoch);
+    }
+
+    /// Splices the right neighbour `dead` out of the ring, making the first
+    /// live node past it the right neighbour. Only if the leader was among
+    /// the nodes gone, or there was none, does the node restart the election
+    /// around the repaired ring; otherwise it only tells the ring its new
+    /// size. Either way it records the repair as an event.
+    async fn repair(&self, dead: &Peer) -> Result<(), ElectionError> {
+        let (addr, successors, epoch) = {
+            let membership = self.membership.lock().unwrap();
+            (membership.addr.clone(), membership.successors.clone(), membership.epoch)
+        };
+        let failed = |reason: String| ElectionError::RepairFailed { node: self.id, reason };
+        let addr = addr.ok_or_else(|| failed(format!("node {} never said how it reaches this node", dead.id)))?;
+        let epoch = epoch.max(self.topology_epoch.load(AtomicOrdering::SeqCst)) + 1;
+        let me = Neighbor { id: self.id, addr };
+        for (skipped, successor) in successors.iter().enumerate() {
+            if successor.id == self.id {
+                break
+            }
+            let peer = self.peer_of(successor.clone())?;
+            let spliced = async {
+                let mut client = self.connect(&peer.endpoint).await?;
+                client.reconfigure(self.deadline(ReconfigureRequest { epoch, left: Some(me.clone()), right: None, group_id: self.group })).await?;
+                Ok::<_, Box<dyn std::error::Error>>(client)
+            };
+            let mut client = match spliced.await {
+                Ok(client) => client,
+                Err(e) => {
+                    warn!(node = self.id, "cannot splice node {} in for node {} either: {}", successor.id, dead.id, e);
+                    continue
+                },
+            };
+            self.advance_epoch(epoch)?;
+            let repairs = self.repairs.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+            info!(node = self.id, "node {} is gone, now followed by node {} (epoch {}, {} repairs so far)", dead.id, peer.id, epoch, repairs);
+            self.right.retarget(peer);
+            self.membership.lock().unwrap().successors.clear();
+            let gone = std::iter::once(dead.id).chain(successors[..skipped].iter().map(|skipped| skipped.id)).collect::<Vec<_>>();
+            let ring_size = self.ring_size().saturating_sub(gone.len() as u64).max(1);
+            let leader = match *self.state.lock().await {
+                NodeState::Leader => Some(self.id),
+                NodeState::Defeated { leader: Some(leader) } if !gone.contains(&leader) => Some(leader),
+                _ => None,
+            };
+            let passed = match leader {
+                Some(leader) => {
+                    info!(node = self.id, "leader {} survived, keeping it in a ring of {} nodes", leader, ring_size);
+                    client.resize(self.deadline(ResizeRequest { epoch, ring_size, group_id: self.group })).await
+                        .map(drop).map_err(|e| format!("cannot tell the repaired ring its size: {}", e))
+                },
+                // the ring lost its leader, or had none to keep
+                None => match self.next_term() {
+                    Ok(term) => client.reelect(self.deadline(self.reelect_request(epoch, ring_size, term))).await
+                        .map(drop).map_err(|e| format!("cannot restart the election around the repaired ring: {}", e)),
+                    Err(e) => Err(format!("cannot restart the election around the repaired ring: {}", e)),
+                },
+            };
+            if let Err(e) = passed {
+                warn!(node = self.id, "{}", e);
+            }
+            if let Some(events) = &self.events {
+                let repair = Repair { gone, right: successor.id, epoch, ring_size, leader };
+                events.repair(self.clock.wall_now(), self.lamport.load(AtomicOrdering::SeqCst), &repair);
+            }
+            return Ok(())
+        }
+        if self.quorum.is_none() && self.witness.is_none() {
+            return Err(failed(format!("no node past node {} answers", dead.id)))
+        }
+        warn!(node = self.id, "no node past node {} answers, taking the ring for split", dead.id);
+        let gone = std::iter::once(dead.id).chain(successors.iter().map(|successor| successor.id)).collect();
+        self.close_segment(dead, me, gone, epoch).await
+    }
+
+    /// Closes the segment of a split ring the node is in, whose right end it
+    /// is as `me`, into a ring of its own, after `dead` and the nodes `gone`
+    /// past it stopped answering. Walks the segment to the left through the
+    /// heartbeats of its nodes, up to the first whose left neighbour does
+    /// not answer either, and makes that node its right neighbour. Restarts
+    /// the election around the segment, in which the nodes abstain unless
+    /// the segment is quorate, and keeps checking on `dead` to merge the
+    /// segments again.
+    async fn close_segment(&self, dead: &Peer, me: Neighbor, gone: Vec<u64>, epoch: u64) -> Result<(), ElectionError> {
+        let failed = |reason: String| ElectionError::RepairFailed { node: self.id, reason };
+        let (mut members, mut head, mut next, mut epoch) = (vec![self.id], me.clone(), neighbor(&self.left.peer()), epoch);
+        while next.id != self.id && (members.len() as u64) < self.ring_size() {
+            let peer = self.peer_of(next.clone())?;
+            let heartbeat = async {
+                let mut client = self.connect(&peer.endpoint).await?;
+                Ok::<_, Box<dyn std::error::Error>>(client.heartbeat(self.deadline(HeartbeatRequest { group_id: self.group })).await?.into_inner())
+            };
+            let heartbeat = match heartbeat.await {
+                Ok(heartbeat) => heartbeat,
+                Err(e) => {
+                    info!(node = self.id, "node {} does not answer either, the segment ends at node {}: {}", next.id, head.id, e);
+                    break
+                },
+            };
+            members.push(next.id);
+            epoch = epoch.max(heartbeat.epoch + 1);
+            match heartbeat.left {
+                Some(left) => head = std::mem::replace(&mut next, left),
+                None => break,
+            }
+        }
+        if next.id == self.id {
+            return Err(failed(format!("the ring reaches the node from the left past node {} after all", head.id)))
+        }
+        let whole = self.partition().map_or(self.ring_size(), |partition| partition.whole);
+        let partition = Partition { whole, quorate: self.quorate(&members, whole) };
+        let ring_size = members.len() as u64;
+        let right = self.peer_of(head.clone())?;
+        match head.id == self.id {
+            true => self.left.retarget(right.clone()),
+            false => {
+                let mut client = self.connect(&right.endpoint).await?;
+                client.reconfigure(self.deadline(ReconfigureRequest { epoch, left: Some(me), right: None, group_id: self.group })).await
+                    .map_err(|e| failed(format!("cannot close the ring at node {}: {}", head.id, e)))?;
+            },
+        }
+        self.advance_epoch(epoch)?;
+        let repairs = self.repairs.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+        match partition.quorate {
+            true => info!(node = self.id, "closing the {} quorate nodes of {} left into a ring (epoch {}, {} repairs so far)", ring_size, whole, epoch, repairs),
+            false => warn!(node = self.id, "closing the {} nodes of {} left into a ring without a leader (epoch {}, {} repairs so far)", ring_size, whole, epoch, repairs),
+        }
+        self.right.retarget(right);
+        {
+            let mut membership = self.membership.lock().unwrap();
+            membership.successors.clear();
+            membership.severed = Some(neighbor(dead));
+        }
+        *self.partition.lock().unwrap() = Some(partition);
+        if let Some(events) = &self.events {
+            let repair = Repair { gone, right: head.id, epoch, ring_size, leader: None };
+            events.repair(self.clock.wall_now(), self.lamport.load(AtomicOrdering::SeqCst), &repair);
+        }
+        self.reelect(epoch, ring_size, self.next_term()?);
+        Ok(())
+    }
+
+    /// Merges the segment of a split ring the node closed with the segment
+    /// of `severed`, the right neighbour it lost as the ring split, which
+    /// answered `heartbeat` again, if the node's segment wins: the one of
+    /// the newer epoch, else the quorate one, else the one closed by the
+    /// smaller node. The other segment's closing node merges them
+    /// otherwise. The ends of both segments take each other for neighbours
+    /// again, and the whole ring follows the leader of the winning segment,
+    /// whose rival steps down, or elects one if it had none. Returns whether
+    /// the node merged the segments.
+    async fn merge_segments(&self, severed: Neighbor, heartbeat: HeartbeatResponse) -> Result<bool, ElectionError> {
+        let failed = |reason: String| ElectionError::RepairFailed { node: self.id, reason };
+        // how the other segment was closed
+        let closer = match heartbeat.left {
+            Some(left) if left.id != self.id => left,
+            _ => return Ok(false),
+        };
+        let (addr, epoch) = {
+            let membership = self.membership.lock().unwrap();
+            (membership.addr.clone(), membership.epoch.max(self.topology_epoch.load(AtomicOrdering::SeqCst)))
+        };
+        let ours = (epoch, self.partition().is_some_and(|partition| partition.quorate), std::cmp::Reverse(self.id));
+        let theirs = (heartbeat.epoch, heartbeat.quorate, std::cmp::Reverse(closer.id));
+        if ours <= theirs {
+            return Ok(false)
+        }
+        let me = Neighbor { id: self.id, addr: addr.ok_or_else(|| failed(format!("node {} never said how it reaches this node", severed.id)))? };
+        let epoch = epoch.max(heartbeat.epoch) + 1;
+        let head = neighbor(&self.right.peer());
+        // the other segment's closer and the node's right neighbour may be
+        // the ends of their segments on both sides
+        let mut changes: Vec<(Neighbor, Option<Neighbor>, Option<Neighbor>)> = vec![];
+        for (node, left, right) in [(severed.clone(), Some(me.clone()), None), (closer.clone(), None, Some(head.clone())), (head, Some(closer), None)] {
+            match changes.iter_mut().find(|(changed, ..)| changed.id == node.id) {
+                Some((_, l, r)) => (*l, *r) = (l.take().or(left), r.take().or(right)),
+                None => changes.push((node, left, right)),
+            }
+        }
+        for (node, left, right) in changes {
+            if node.id == self.id {
+                if let Some(left) = left {
+                    self.left.retarget(self.peer_of(left)?);
+                }
+                continue
+            }
+            let peer = self.peer_of(node.clone())?;
+            let reconfigured = async {
+                let mut client = self.connect(&peer.endpoint).await?;
+                client.reconfigure(self.deadline(ReconfigureRequest { epoch, left, right, group_id: self.group })).await?;
+                Ok::<_, Box<dyn std::error::Error>>(())
+            };
+            reconfigured.await.map_err(|e| failed(format!("cannot merge the segments at node {}: {}", node.id, e)))?;
+        }
+        self.advance_epoch(epoch)?;
+        self.right.retarget(self.peer_of(severed.clone())?);
+        self.membership.lock().unwrap().successors.clear();
+        self.healed();
+
+        let ring_size = self.ring_size() + heartbeat.ring_size;
+        let whole = self.partition().map_or(0, |partition| partition.whole).max(heartbeat.whole_size);
+        let quorate = ring_size >= self.quorum(whole) || ours.1 || theirs.1;
+        let term = self.term().max(heartbeat.term).checked_add(1).ok_or(ElectionError::TermsExhausted { node: self.id })?;
+        let leader = match *self.state.lock().await {
+            NodeState::Leader => Some(self.id),
+            NodeState::Defeated { leader } => leader,
+            NodeState::Candidate { .. } => None,
+        };
+        info!(node = self.id, "merging the {} nodes past node {} back into a ring of {} (epoch {}), under leader {:?}",
+            heartbeat.ring_size, severed.id, ring_size, epoch, leader);
+        *self.partition.lock().unwrap() = (ring_size < whole).then_some(Partition { whole, quorate });
+        match leader {
+            Some(leader) => {
+                let leader_addr = match leader == self.id {
+                    true => me.addr,
+                    false => self.leader_info(Some(leader)).map(|info| info.addr).unwrap_or_default(),
+                };
+                let (whole_size, quorate) = self.partition().map_or((0, false), |partition| (partition.whole, partition.quorate));
+                self.merge(MergeRequest { epoch, ring_size, leader_id: leader, leader_addr, term, whole_size, quorate, group_id: self.group });
+            },
+            None => self.reelect(epoch, ring_size, term),
+        }
+        Ok(true)
+    }
+}
+
+/// Merges the segment `node` closed with that of `severed`, if it answers
+/// again and the other segment's closer does not merge them itself.
+async fn merge(node: &Node, severed: Neighbor) {
+    if node.right.peer().id == severed.id {
+        // the other segment's closer merged them
+        node.healed();
+        return
+    }
+    let heartbeat = async {
+        let mut client = node.connect(&node.peer_of(severed.clone())?.endpoint).await?;
+        Ok::<_, Box<dyn std::error::Error>>(client.heartbeat(node.deadline(HeartbeatRequest { group_id: node.group })).await?.into_inner())
+    };
+    let heartbeat = match heartbeat.await {
+        Ok(heartbeat) => heartbeat,
+        Err(_) => return,
+    };
+    if let Err(e) = node.merge_segments(severed, heartbeat).await {
+        warn!(node = node.id, "cannot merge the segments of the ring: {}", e);
+    }
+}
+
+/// Checks every `interval` that the right neighbour of `node` is alive,
+/// learning the nodes past it from its heartbeats, and splices it out of
+/// the ring once it fails to answer `LIVENESS_FAILURES` heartbeats in a row.
+pub async fn watch_right(node: Node, interval: Duration) {
+    let (mut watched, mut client): (Option<Peer>, Option<Client>) = (None, None);
+    let mut failures = 0;
+    let mut ticks = node.clock.clone().interval(interval);
+    while ticks.next().await.is_some() {
+        let severed = node.membership.lock().unwrap().severed.clone();
+        if let Some(severed) = severed {
+            merge(&node, severed).await;
+        }
+        let peer = node.right.peer();
+        if watched.as_ref().map(|watched| watched.endpoint.uri()) != Some(peer.endpoint.uri()) {
+            (failures, client) = (0, None);
+            watched = Some(peer.clone());
+        }
+        if client.is_none() {
+            client = node.connect(&peer.endpoint).await.ok();
+        }
+        let answer = match &mut client {
+            Some(connected) => connected.heartbeat(node.deadline(HeartbeatRequest { group_id: node.group })).await.map_err(|e| e.to_string()),
+            None => Err("cannot connect".to_string()),
+        };
+        node.right.reached(answer.is_ok());
+        match answer {
+            Ok(heartbeat) => {
+                failures = 0;
+                node.learn_membership(&heartbeat.into_inner());
+                continue
+            },
+            Err(e) => {
+                (failures, client) = (failures + 1, None);
+                warn!(node = node.id, "node {} missed a heartbeat ({} in a row): {}", peer.id, failures, e);
+            },
+        }
+        if failures >= LIVENESS_FAILURES {
+            if let Err(e) = node.repair(&peer).await {
+                warn!(node = node.id, "cannot repair the ring: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use crate::events;
+use crate::json::{self, Json};
+use crate::leader_election_service::Decision;
+use crate::state_machine::{self, apply, react, standing, Command, Event, Input, Me, Refused};
+use crate::NodeState;
+
+/// A node's state as its event log records it, which leaves out whether a
+/// candidate probed its phase yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recorded {
+    Candidate { phase: u64 },
+    Defeated { leader: Option<u64> },
+    Leader,
+}
+
+impl From<&NodeState> for Recorded {
+    fn from(state: &NodeState) -> Self {
+        match *state {
+            NodeState::Candidate { phase, .. } => Recorded::Candidate { phase },
+            NodeState::Defeated { leader } => Recorded::Defeated { leader },
+            NodeState::Leader => Recorded::Leader,
+        }
+    }
+}
+
+/// Where the state machine parts ways with what a node recorded, the lines
+/// counted from one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    /// The node decided on the probe of `sender` other than the state
+    /// machine does.
+    Decision { line: usize, node: u64, sender: u64, recorded: Decision, replayed: Decision },
+    /// The node sent the probe of another phase than the state machine does.
+    Probe { line: usize, node: u64, recorded: u64, replayed: u64 },
+    /// The state machine refused a transition the node's messages called for.
+    Refused { line: usize, node: u64, action: &'static str },
+    /// The node ended up in another state than its messages leave it in.
+    State { node: u64, recorded: Recorded, replayed: Recorded },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::Decision { line, node, sender, recorded, replayed } => write!(f, "line {}: node {} decided {} on the probe of node {}, the state machine {}",
+                line, node, recorded.label(), sender, replayed.label()),
+            Divergence::Probe { line, node, recorded, replayed } => write!(f, "line {}: node {} probed phase {}, the state machine phase {}",
+                line, node, recorded, replayed),
+            Divergence::Refused { line, node, action } => write!(f, "line {}: the state machine refused to let node {} {}", line, node, action),
+            Divergence::State { node, recorded, replayed } => write!(f, "node {} ended up {:?}, its messages leave it {:?}", node, recorded, replayed),
+        }
+    }
+}
+
+/// What replaying an event log came to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replay {
+    /// The state the messages of each node leave it in.
+    pub states: BTreeMap<u64, NodeState>,
+    /// Everything the nodes did that the state machine would not have, in
+    /// the order of the log, and the nodes that ended up elsewhere last.
+    pub divergences: Vec<Divergence>,
+}
+
+impl Replay {
+    /// The outcome as a JSON object, each divergence described as it is
+    /// displayed, e.g.
+    ///
+    /// ```json
+    /// {"states": [{"node": 1, "state": "leader"}, {"node": 2, "state": "defeated", "leader": 1}],
+    ///  "divergences": ["line 12: node 2 decided forwarded on the probe of node 4, the state machine defeated"]}
+    /// ```
+    pub fn json(&self) -> String {
+        let states = self.states.iter().map(|(node, state)| format!(r#"{{"node": {}, {}}}"#, node, events::state_fields(state))).collect::<Vec<_>>();
+        let divergences = self.divergences.iter().map(|divergence| json::quote(&divergence.to_string())).collect::<Vec<_>>();
+        format!(r#"{{"states": [{}], "divergences": [{}]}}"#, states.join(", "), divergences.join(", "))
+    }
+}
+
+/// One node as the replay drives it.
+#[derive(Debug, Default)]
+struct Replaying {
+    state: NodeState,
+    term: u64,
+    /// What a probe called for that waits until the node probed its phase.
+    deferred: Option<Event>,
+    /// The last state the node recorded, if any.
+    recorded: Option<Recorded>,
+}
+
+impl Replaying {
+    /// Feeds `input` to node `me` with all that follows from it, returning
+    /// what the node does about the input itself or why it could not.
+    fn feed(&mut self, me: Me, input: Input, prefer: impl Fn(u64, u64) -> u64) -> Result<Vec<Command>, Refused> {
+        let mut inputs = VecDeque::from([input]);
+        let mut first = None;
+        while let Some(input) = inputs.pop_front() {
+            let commands = react(&self.state, me, input);
+            for &command in &commands {
+                match command {
+                    Command::Take(event) => {
+                        self.state = apply(&self.state, me.id, event, &prefer)?;
+                        inputs.extend(self.deferred.take().map(Input::Settle));
+                    },
+                    Command::Defer(event) => self.deferred = Some(event),
+                    _ => (),
+                }
+            }
+            first.get_or_insert(commands);
+        }
+        Ok(first.unwrap_or_default())
+    }
+}
+
+fn number(json: &Json, key: &str) -> Option<u64> {
+    match json.get(key) {
+        Some(Json::Number(n)) => Some(*n as u64),
+        _ => None,
+    }
+}
+
+fn string<'a>(json: &'a Json, key: &str) -> Option<&'a str> {
+    match json.get(key) {
+        Some(Json::String(s)) => Some(s),
+        _ => None,
+    }
+}
+
+fn decision(label: &str) -> Option<Decision> {
+    [Decision::Ignored, Decision::Forwarded, Decision::Defeated, Decision::YouWin].into_iter().find(|decision| decision.label() == label)
+}
+
+fn recorded(json: &Json) -> Result<Recorded, String> {
+    match string(json, "state") {
+        Some("candidate") => Ok(Recorded::Candidate { phase: number(json, "phase").ok_or("expected the \"phase\" of a candidate")? }),
+        Some("defeated") => Ok(Recorded::Defeated { leader: number(json, "leader") }),
+        Some("leader") => Ok(Recorded::Leader),
+        state => Err(format!("unknown state {:?}", state)),
+    }
+}
+
+/// Feeds the messages each node of an event log received, and its own probes
+/// as the polls that sent them, back into the [`state_machine`], and checks
+/// that it decides on every probe as the node did and leaves each node in
+/// the state it recorded last. A node starts over as a candidate whenever
+/// its log moves on to a later term. The log may be that of one node or the
+/// [`events::merge`]d logs of many, in which each node's events keep their
+/// order.
+///
+/// The nodes are ranked by the priorities their probes carry, as if the
+/// ring preferred no zone and nobody sat the election out. What the nodes
+/// were told to do besides, e.g. to step down, does not replay, so turns up
+/// as a divergence.
+pub fn replay(timeline: &[String]) -> Result<Replay, String> {
+    let lines = timeline.iter().enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| json::parse(line).map(|json| (i + 1, json)).map_err(|e| format!("line {}: {}", i + 1, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let priorities = lines.iter()
+        .filter(|(_, json)| string(json, "kind") == Some("probe"))
+        .filter_map(|(_, json)| Some((number(json, "value")?, number(json, "priority").unwrap_or(0))))
+        .collect::<BTreeMap<_, _>>();
+    let priority = |id: u64| priorities.get(&id).copied().unwrap_or(0);
+    let prefer = |a: u64, b: u64| match standing(priority(a), false, a) <= standing(priority(b), false, b) {
+        true => a,
+        false => b,
+    };
+    let mut nodes = BTreeMap::<u64, Replaying>::new();
+    let mut divergences = vec![];
+    for (line, json) in &lines {
+        let missing = |key: &str| format!("line {}: expected a {:?}", line, key);
+        let id = number(json, "node").ok_or_else(|| missing("node"))?;
+        let me = Me { id, priority: priority(id), preferred: false, abstains: false };
+        let node = nodes.entry(id).or_default();
+        if let Some(term) = number(json, "term").filter(|&term| term > node.term) {
+            *node = Replaying { term, recorded: node.recorded, ..Replaying::default() };
+        }
+        let refused = |Refused(action)| Divergence::Refused { line: *line, node: id, action };
+        let (kind, value) = (string(json, "kind"), number(json, "value"));
+        match string(json, "event") {
+            Some("state") => node.recorded = Some(recorded(json).map_err(|e| format!("line {}: {}", line, e))?),
+            Some("sent") if kind == Some("probe") && value == Some(id) => {
+                let phase = number(json, "phase").ok_or_else(|| missing("phase"))?;
+                match node.feed(me, Input::Poll, prefer) {
+                    Ok(commands) => divergences.extend(commands.into_iter().find_map(|command| match command {
+                        Command::SendProbe { phase: replayed, .. } if replayed != phase => Some(Divergence::Probe { line: *line, node: id, recorded: phase, replayed }),
+                        _ => None,
+                    })),
+                    Err(e) => divergences.push(refused(e)),
+                }
+            },
+            Some("received") => {
+                let decided = string(json, "decision").and_then(decision).ok_or_else(|| missing("decision"))?;
+                let value = value.ok_or_else(|| missing("value"))?;
+                match kind {
+                    // dropped unseen, as retransmissions and messages of earlier terms are
+                    _ if decided == Decision::Ignored => (),
+                    Some("probe") => {
+                        let input = Input::Probe { sender: value, priority: priority(value), preferred: false };
+                        match node.feed(me, input, prefer) {
+                            Ok(commands) => divergences.extend(commands.into_iter().find_map(|command| match command {
+                                Command::Decide(replayed) if replayed != decided => Some(Divergence::Decision { line: *line, node: id, sender: value, recorded: decided, replayed }),
+                                _ => None,
+                            })),
+                            Err(e) => divergences.push(refused(e)),
+                        }
+                    },
+                    // the notification came back to the leader
+                    Some("notify") if decided == Decision::YouWin => (),
+                    Some("notify") => node.state = state_machine::defeat_with_leader(&node.state, id, value, prefer).state,
+                    _ => (),
+                }
+            },
+            _ => (),
+        }
+    }
+    for (&id, node) in &nodes {
+        let replayed = Recorded::from(&node.state);
+        if let Some(recorded) = node.recorded.filter(|&recorded| recorded != replayed) {
+            divergences.push(Divergence::State { node: id, recorded, replayed });
+        }
+    }
+    Ok(Replay { states: nodes.into_iter().map(|(id, node)| (id, node.state)).collect(), divergences })
+}
+
+/// Merges the event logs at `paths` and [`replay`]s them.
+pub fn replay_files(paths: &[&Path]) -> io::Result<Replay> {
+    replay(&events::merge(paths)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
@@ -0,0 +1,103 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use http::header::HeaderValue;
+use tokio::time::Instant;
+use tonic::metadata::{AsciiMetadataValue, MetadataMap};
+use tonic::transport::server::TcpConnectInfo;
+use tower::{Layer, Service};
+use tracing::{debug, Instrument};
+
+use crate::metrics::Side;
+
+/// Metadata key carrying the correlation ID of a request across hops.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The correlation ID of an inbound request, to be passed on to the requests
+/// it causes.
+pub fn request_id(metadata: &MetadataMap) -> Option<AsciiMetadataValue> {
+    metadata.get(REQUEST_ID_HEADER).cloned()
+}
+
+/// Tower layer logging every RPC with its method, peer, correlation ID and
+/// outcome, within a span carrying the same fields. On the client side it
+/// assigns each request an ID unique to the node unless one is already
+/// present; the server side logs the ID it was given, so a request can be
+/// followed from one node to the next.
+#[derive(Debug, Clone)]
+pub struct RequestLogLayer {
+    node: u64,
+    side: Side,
+    next_id: Arc<AtomicU64>,
+    /// The address of the remote end, for client channels. Servers learn it
+    /// from each connection instead.
+    peer: Option<Arc<str>>,
+}
+
+impl RequestLogLayer {
+    pub fn new(node: u64, side: Side, next_id: Arc<AtomicU64>, peer: Option<&str>) -> Self {
+        RequestLogLayer { node, side, next_id, peer: peer.map(Arc::from) }
+    }
+}
+
+impl<S> Layer<S> for RequestLogLayer {
+    type Service = RequestLog<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestLog { inner, layer: self.clone() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestLog<S> {
+    inner: S,
+    layer: RequestLogLayer,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for RequestLog<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Error: std::fmt::Display,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<ReqBody>) -> Self::Future {
+        let RequestLogLayer { node, side, ref next_id, ref peer } = self.layer;
+        if !request.headers().contains_key(REQUEST_ID_HEADER) {
+            let id = format!("{}-{}", node, next_id.fetch_add(1, Ordering::Relaxed));
+            request.headers_mut().insert(REQUEST_ID_HEADER, HeaderValue::from_str(&id).expect("IDs are ASCII"));
+        }
+        let request_id = request.headers()[REQUEST_ID_HEADER].to_str().unwrap_or("?").to_string();
+        let method = request.uri().path().to_string();
+        let peer = peer.as_deref().map(str::to_string)
+            .or_else(|| request.extensions().get::<TcpConnectInfo>()
+                .and_then(|info| info.remote_addr())
+                .map(|addr| addr.to_string()))
+            .unwrap_or_else(|| "unknown peer".to_string());
+
+        let span = tracing::info_span!("rpc", node, side = %side.label(), method = %method, peer = %peer, request_id = %request_id);
+        let start = Instant::now();
+        let response = span.in_scope(|| self.inner.call(request));
+        Box::pin(async move {
+            let response = response.await;
+            let outcome = match &response {
+                Ok(response) => format!("status {}", response.headers().get("grpc-status")
+                    .and_then(|status| status.to_str().ok())
+                    .unwrap_or("0")),
+                Err(e) => format!("transport error: {}", e),
+            };
+            debug!(elapsed = ?start.elapsed(), "{}", outcome);
+            response
+        }.instrument(span))
+    }
+}
@@ -0,0 +1,87 @@
+use std::future::Future;
+
+use tokio::time::Duration;
+
+use crate::clock::Clock;
+use crate::traces::splitmix64;
+use crate::{DELAY_MODIFIER, MAX_RETRY_DELAY};
+
+/// How a node retries calls to its neighbours that failed. The delay between
+/// attempts doubles every time, and a random part of it is cut off, so that
+/// nodes cut off by the same fault do not all come back at once.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// How many times to try a call, the first time included, before giving
+    /// up on it. Without a limit the node keeps trying.
+    pub max_attempts: Option<u32>,
+    /// The delay before the first retry.
+    pub initial_delay: Duration,
+    /// The longest delay between two attempts.
+    pub max_delay: Duration,
+    /// The fraction of each delay that may be cut off at random.
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy { max_attempts: None, initial_delay: Duration::from_millis(DELAY_MODIFIER), max_delay: MAX_RETRY_DELAY, jitter: 0.5 }
+    }
+}
+
+impl RetryPolicy {
+    /// The delays between the attempts at one call, jittered by a generator
+    /// seeded with `seed`, which should differ between nodes.
+    pub fn backoff(&self, seed: u64) -> Backoff {
+        Backoff { policy: *self, attempts: 1, delay: self.initial_delay.min(self.max_delay), seed }
+    }
+}
+
+/// The delays before each retry of a call, until the [`RetryPolicy`] gives up.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    policy: RetryPolicy,
+    /// How many attempts were made so far.
+    attempts: u32,
+    /// The next delay, before jitter.
+    delay: Duration,
+    seed: u64,
+}
+
+impl Iterator for Backoff {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Duration> {
+        if self.policy.max_attempts.is_some_and(|max| self.attempts >= max) {
+            return None
+        }
+        self.attempts += 1;
+        let random = (splitmix64(self.seed.wrapping_add(self.attempts.into())) >> 11) as f64 / (1u64 << 53) as f64;
+        let delay = self.delay.mul_f64(1.0 - self.policy.jitter * random);
+        self.delay = (self.delay * 2).min(self.policy.max_delay);
+        Some(delay)
+    }
+}
+
+/// Makes `call` until it succeeds or `backoff` runs out, waiting on `clock`
+/// between the attempts and telling `retrying` about every failed one that
+/// is retried. Returns the last error if none succeeds.
+pub(crate) async fn retry<T, E, F: Future<Output = Result<T, E>>>(
+    mut backoff: Backoff,
+    clock: &dyn Clock,
+    mut call: impl FnMut() -> F,
+    mut retrying: impl FnMut(&E, Duration),
+) -> Result<T, E> {
+    loop {
+        let error = match call().await {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+        match backoff.next() {
+            Some(delay) => {
+                retrying(&error, delay);
+                clock.sleep_until(clock.now() + delay).await;
+            },
+            None => return Err(error),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::fmt::{self, Display};
+use std::net::{SocketAddr, TcpListener};
+
+use futures::future::join_all;
+use tokio::time::{Duration, Instant};
+
+use crate::builder::NodeHandle;
+use crate::config::Config;
+use crate::{ElectionResult, Node, NodeState};
+
+/// How a ring elected its leader in a [`run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub leader: u64,
+    /// How long each node took to learn who leads, by ID.
+    pub decided: Vec<(u64, Duration)>,
+    /// How long each node took to know that the whole ring acknowledged the
+    /// leader, by ID.
+    pub acknowledged: Vec<(u64, Duration)>,
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "node {} leads all {} nodes", self.leader, self.decided.len())?;
+        for (what, times) in [("learned the leader", &self.decided), ("saw the ring acknowledge it", &self.acknowledged)] {
+            let mut times = times.iter().map(|&(_, time)| time).collect::<Vec<_>>();
+            times.sort();
+            writeln!(f, "{}: min {:?}, median {:?}, max {:?}", what, times[0], times[times.len() / 2], times[times.len() - 1])?;
+        }
+        Ok(())
+    }
+}
+
+impl Report {
+    /// The report as a JSON object, the times in milliseconds, e.g.
+    ///
+    /// ```json
+    /// {"leader": 1, "nodes": [{"id": 1, "decided_ms": 212.4, "acknowledged_ms": 230.1}, ...]}
+    /// ```
+    pub fn json(&self) -> String {
+        let nodes = self.decided.iter().zip(&self.acknowledged)
+            .map(|(&(id, decided), &(_, acknowledged))| format!(r#"{{"id": {}, "decided_ms": {}, "acknowledged_ms": {}}}"#,
+                id, decided.as_secs_f64() * 1000.0, acknowledged.as_secs_f64() * 1000.0))
+            .collect::<Vec<_>>();
+        format!(r#"{{"leader": {}, "nodes": [{}]}}"#, self.leader, nodes.join(", "))
+    }
+}
+
+/// Starts a ring of `nodes` nodes on free ports of localhost, configured
+/// by `config`, and checks that they all elect the same leader within
+/// `timeout` and agree on it: exactly one leads and the others follow it.
+/// Shuts the ring down before returning, and fails with what went wrong.
+pub async fn run(nodes: u16, config: &Config, timeout: Duration) -> Result<Report, String> {
+    if nodes < 2 {
+        return Err("a ring needs at least 2 nodes".to_string())
+    }
+    // the listeners close before the nodes take the ports over
+    let addrs = (0..nodes)
+        .map(|_| TcpListener::bind("127.0.0.1:0").and_then(|listener| listener.local_addr()))
+        .collect::<Result<Vec<SocketAddr>, _>>()
+        .map_err(|e| format!("cannot find free ports: {}", e))?;
+    let n = nodes as usize;
+    let start = Instant::now();
+    let handles = (0..n)
+        .map(|i| {
+            let (left, right) = ((i + n - 1) % n, (i + 1) % n);
+            Node::builder()
+                .id(i as u16 + 1)
+                .listen(addrs[i].to_string())
+                .left(left as u16 + 1, addrs[left].to_string())
+                .right(right as u16 + 1, addrs[right].to_string())
+                .ring_size(nodes.into())
+                .config(config.clone())
+                .build()
+        })
+        .collect::<Result<Vec<NodeHandle>, _>>();
+    let handles = match handles {
+        Ok(handles) => handles,
+        Err(e) => return Err(format!("cannot start the ring: {}", e)),
+    };
+    let elected = join_all(handles.iter().map(|handle| elect(handle.node(), start)));
+    let outcome = match tokio::time::timeout(timeout, elected).await {
+        Ok(times) => check(&handles, times).await,
+        Err(_) => {
+            let mut undecided = vec![];
+            for handle in &handles {
+                if matches!(*handle.node().subscribe().borrow(), ElectionResult::Undecided | ElectionResult::TimedOut) {
+                    undecided.push(handle.node().id().to_string());
+                }
+            }
+            Err(format!("no leader acknowledged within {:?}, nodes {} know of none", timeout, undecided.join(", ")))
+        },
+    };
+    for handle in handles {
+        if let Err(e) = handle.shutdown().await {
+            return Err(format!("a node failed: {}", e))
+        }
+    }
+    outcome
+}
+
+/// Waits until `node` knows the whole ring acknowledged its leader, and
+/// returns how long after `start` it learned of the leader and of the
+/// acknowledgement.
+async fn elect(node: &Node, start: Instant) -> (u64, Duration, Duration) {
+    let mut results = node.subscribe();
+    while *results.borrow_and_update() == ElectionResult::Undecided {
+        // the sender lives as long as the node
+        let _ = results.changed().await;
+    }
+    let decided = start.elapsed();
+    let leader = node.await_ring_acknowledged().await;
+    (leader, decided, start.elapsed())
+}
+
+/// Checks that the nodes of `handles` agree on the leader, as `times` say
+/// they acknowledged.
+async fn check(handles: &[NodeHandle], times: Vec<(u64, Duration, Duration)>) -> Result<Report, String> {
+    let leader = times[0].0;
+    let mut report = Report { leader, decided: vec![], acknowledged: vec![] };
+    for (handle, (acknowledged, decided, settled)) in handles.iter().zip(times) {
+        let node = handle.node();
+        let expected = match node.id() == leader {
+            true => NodeState::Leader,
+            false => NodeState::Defeated { leader: Some(leader) },
+        };
+        match node.state().await {
+            _ if acknowledged != leader => return Err(format!("node {} acknowledged {} as the leader, not {}", node.id(), acknowledged, leader)),
+            state if state != expected => return Err(format!("node {} is {:?} under leader {}", node.id(), state, leader)),
+            _ => (),
+        }
+        report.decided.push((node.id(), decided));
+        report.acknowledged.push((node.id(), settled));
+    }
+    Ok(report)
+}
@@ -0,0 +1,95 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use tracing::{debug, info, warn};
+
+use crate::leader_election_service::Sequence;
+use crate::MAX_PEERS;
+
+/// How many missing messages per sender are remembered, so that a late
+/// arrival can be told apart from a retransmission.
+const MISSING_LIMIT: usize = 256;
+
+#[derive(Debug, Clone, Default)]
+struct Lane {
+    /// When the lane last took a message, counted in messages.
+    used: u64,
+    incarnation: u64,
+    last: u64,
+    missing: BTreeSet<u64>,
+}
+
+/// The sequence numbers a node has seen from each of its senders, used to
+/// apply every message to the state machine exactly once.
+///
+/// Each neighbour sends its messages in order over a single relay stream, so
+/// a jump by more than one means messages were lost on the way, and anything
+/// numbered at or below the last message seen is a retransmission, unless it
+/// is one of the missing messages arriving out of order.
+///
+/// Senders are whatever the messages claim, so only the [`MAX_PEERS`] lanes
+/// used last are kept.
+#[derive(Debug, Default)]
+pub struct Receipts {
+    lanes: Mutex<HashMap<(u64, bool), Lane>>,
+    /// How many numbered messages arrived, to tell the lanes' ages by.
+    uses: AtomicU64,
+    /// Retransmitted messages that were acknowledged but not applied again.
+    pub duplicates: AtomicU64,
+    /// Messages that never arrived, judging by the numbering.
+    pub gaps: AtomicU64,
+    /// Messages that arrived after ones sent later than them.
+    pub reordered: AtomicU64,
+}
+
+impl Receipts {
+    /// Records the arrival of a message and returns whether it should be
+    /// applied. Messages without a sequence number are always applied.
+    pub fn accept(&self, node: u64, seq: Option<&Sequence>) -> bool {
+        let seq = match seq {
+            Some(seq) => seq,
+            None => return true,
+        };
+        let used = self.uses.fetch_add(1, Ordering::Relaxed);
+        let mut lanes = self.lanes.lock().unwrap();
+        let key = (seq.sender, seq.leftward);
+        if lanes.len() >= MAX_PEERS && !lanes.contains_key(&key) {
+            if let Some(oldest) = lanes.iter().min_by_key(|(_, lane)| lane.used).map(|(&key, _)| key) {
+                lanes.remove(&oldest);
+                debug!(node, "forgetting the numbering of node {} to make room for node {}", oldest.0, seq.sender);
+            }
+        }
+        let lane = lanes.entry(key).or_insert_with(|| Lane { incarnation: seq.incarnation, ..Lane::default() });
+        lane.used = used;
+        if seq.incarnation < lane.incarnation {
+            info!(node, "ignoring message {} from an earlier run of node {}", seq.number, seq.sender);
+            return false
+        }
+        if seq.incarnation > lane.incarnation {
+            *lane = Lane { used, incarnation: seq.incarnation, ..Lane::default() };
+        }
+        if seq.number <= lane.last && lane.missing.remove(&seq.number) {
+            let reordered = self.reordered.fetch_add(1, Ordering::Relaxed) + 1;
+            warn!(node, "message {} from {} arrived out of order ({} so far)", seq.number, seq.sender, reordered);
+            return true
+        }
+        if seq.number <= lane.last {
+            let duplicates = self.duplicates.fetch_add(1, Ordering::Relaxed) + 1;
+            debug!(node, "ignoring retransmitted message {} from {} ({} duplicates so far)", seq.number, seq.sender, duplicates);
+            return false
+        }
+        if seq.number > lane.last + 1 {
+            let missing = seq.number - lane.last - 1;
+            let gaps = self.gaps.fetch_add(missing, Ordering::Relaxed).saturating_add(missing);
+            warn!(node, "{} messages from {} went missing before message {} ({} so far)", missing, seq.sender, seq.number, gaps);
+            let first = (lane.last + 1).max(seq.number.saturating_sub(MISSING_LIMIT as u64));
+            lane.missing.extend(first..seq.number);
+            while lane.missing.len() > MISSING_LIMIT {
+                lane.missing.pop_first();
+            }
+        }
+        lane.last = seq.number;
+        true
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use tokio::sync::{mpsc, watch};
+use tokio::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::config::Config;
+use crate::leader_election_service::{PeerMessage, Sequence};
+use crate::outbound::Envelope;
+use crate::topology::{NodeSpec, Topology};
+use crate::{elect, ElectionResult, Node, DELAY_MODIFIER, RELAY_VERSION};
+
+/// How long a link stalls after losing a message, until the sender's relay
+/// sends it again.
+const RETRANSMIT_AFTER: Duration = Duration::from_millis(DELAY_MODIFIER);
+/// The longest a link can be held up by a chaotic delay.
+const MAX_DELAY: Duration = Duration::from_millis(DELAY_MODIFIER / 2);
+/// The longest a crashed node stays down.
+const MAX_DOWNTIME: Duration = Duration::from_millis(10 * DELAY_MODIFIER);
+
+/// A link from a node to one of its neighbours: the sender's ID and whether
+/// the link leads left.
+type Link = (u64, bool);
+
+/// The order in which the simulated network delivers the messages in flight.
+/// Messages from a node to one of its neighbours always arrive in the order
+/// they were sent, as over a relay stream; only the links are interleaved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// Serves the links with messages waiting in turn, by sender ID, the
+    /// rightward link of each node before its leftward one.
+    RoundRobin,
+    /// Picks the link to deliver on next at random, the same way for the
+    /// same seed.
+    Shuffled(u64),
+}
+
+/// Faults the simulated network injects, each with the probability of it
+/// hitting a delivery. They are the faults the relays and the receipts are
+/// meant to hide from the election:
+///
+/// - a dropped message stalls its link until the sender sends it again,
+/// - a delayed message holds up its link, so the order on it is kept,
+/// - a duplicated message arrives twice, the second time to be recognised
+///   by its sequence number and ignored,
+/// - a crashed node stops exchanging messages for a while and comes back
+///   with the state it had, as if restarted from stable storage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Chaos {
+    pub drop: f64,
+    pub delay: f64,
+    pub duplicate: f64,
+    pub crash: f64,
+    /// Seeds the random choice of faults.
+    pub seed: u64,
+}
+
+impl Default for Chaos {
+    fn default() -> Self {
+        Chaos { drop: 0.05, delay: 0.1, duplicate: 0.05, crash: 0.01, seed: 0 }
+    }
+}
+
+/// How many faults a simulation injected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Faults {
+    pub dropped: u64,
+    pub delayed: u64,
+    pub duplicated: u64,
+    pub crashed: u64,
+}
+
+/// How a simulated election ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// Where each node stood when the simulation stopped, by ID.
+    pub results: BTreeMap<u64, ElectionResult>,
+    /// How many messages the network delivered.
+    pub delivered: u64,
+    /// The virtual time the simulation ran for.
+    pub elapsed: Duration,
+    /// The most nodes that considered themselves the leader at once. The
+    /// election is only safe if it never exceeded one.
+    pub most_leaders: usize,
+    pub faults: Faults,
+}
+
+impl Report {
+    /// The nodes that consider themselves the leader.
+    pub fn leaders(&self) -> Vec<u64> {
+        self.results.iter().filter(|(_, result)| **result == ElectionResult::Leader).map(|(id, _)| *id).collect()
+    }
+
+    /// The leader, if exactly one node leads and all others follow it.
+    pub fn leader(&self) -> Option<u64> {
+        match self.leaders()[..] {
+            [leader] => self.results.iter()
+                .all(|(&id, result)| id == leader || *result == ElectionResult::Defeated { leader })
+                .then_some(leader),
+            _ => None,
+        }
+    }
+
+    /// The report as a JSON object, the virtual time in milliseconds, e.g.
+    ///
+    /// ```json
+    /// {"leader": 2, "elapsed_ms": 4120, "delivered": 96, "most_leaders": 1,
+    ///  "faults": {"dropped": 3, "delayed": 9, "duplicated": 4, "crashed": 1},
+    ///  "results": [{"id": 2, "result": "leader"}, {"id": 5, "result": "defeated", "leader": 2}, ...]}
+    /// ```
+    pub fn json(&self) -> String {
+        let results = self.results.iter().map(|(id, result)| match result {
+            ElectionResult::Undecided => format!(r#"{{"id": {}, "result": "undecided"}}"#, id),
+            ElectionResult::Leader => format!(r#"{{"id": {}, "result": "leader"}}"#, id),
+            ElectionResult::Defeated { leader } => format!(r#"{{"id": {}, "result": "defeated", "leader": {}}}"#, id, leader),
+            ElectionResult::TimedOut => format!(r#"{{"id": {}, "result": "timed-out"}}"#, id),
+        }).collect::<Vec<_>>();
+        let Faults { dropped, delayed, duplicated, crashed } = self.faults;
+        format!(r#"{{"leader": {}, "elapsed_ms": {}, "delivered": {}, "most_leaders": {}, "faults": {{"dropped": {}, "delayed": {}, "duplicated": {}, "crashed": {}}}, "results": [{}]}}"#,
+            self.leader().map_or("null".to_string(), |leader| leader.to_string()), self.elapsed.as_millis(), self.delivered, self.most_leaders,
+            dropped, delayed, duplicated, crashed, results.join(", "))
+    }
+}
+
+/// A ring of [`Node`]s run in one process on tokio's virtual clock, passing
+/// their messages to each other in memory instead of over gRPC. The network
+/// is a single loop delivering one message at a time in the order `Delivery`
+/// dictates, so runs with the same IDs and delivery order are repeatable.
+#[derive(Debug)]
+pub struct Simulation {
+    nodes: BTreeMap<u64, Node>,
+    delivery: Delivery,
+    /// State of the generator behind `Delivery::Shuffled`.
+    rng: u64,
+    /// The link served last by `Delivery::RoundRobin`.
+    turn: Option<Link>,
+    chaos: Option<Chaos>,
+    /// Whether to stop as soon as every node knows the outcome.
+    until_decided: bool,
+    /// Messages taken from the nodes' queues and not delivered yet, with
+    /// the nodes they are headed to. Only links with messages have a wire.
+    wires: BTreeMap<Link, VecDeque<(u64, PeerMessage)>>,
+    /// How many messages went out on each link, for their sequence numbers.
+    sent: BTreeMap<Link, u64>,
+    /// Links held up by a delay or a lost message, until when.
+    stalled: BTreeMap<Link, Instant>,
+    /// Crashed nodes, until they come back.
+    down: BTreeMap<u64, Instant>,
+    faults: Faults,
+    /// How long each message takes to arrive.
+    pub hop: Duration,
+}
+
+impl Simulation {
+    /// Sets up the ring of nodes with the given IDs, in order.
+    pub fn new(ids: &[u16], delivery: Delivery) -> Self {
+        Simulation::of(&Topology::from_ids(ids).nodes(), delivery)
+    }
+
+    /// Sets up the ring of the nodes `specs` describe.
+    pub fn of(specs: &[NodeSpec], delivery: Delivery) -> Self {
+        let rng = match delivery {
+            Delivery::Shuffled(seed) => seed,
+            Delivery::RoundRobin => 0,
+        };
+        let config = Config { queue_capacity: 1024, seed: Some(rng), ..Config::default() };
+        let nodes = specs.iter()
+            .map(|spec| Node::new(spec, specs.len() as u64, &config, None).expect("simulated nodes have no files to open"))
+            .map(|node| (node.id, node))
+            .collect();
+        Simulation {
+            nodes, delivery, rng, turn: None, chaos: None, until_decided: false,
+            wires: BTreeMap::new(), sent: BTreeMap::new(), stalled: BTreeMap::new(), down: BTreeMap::new(),
+            faults: Faults::default(),
+            hop: Duration::from_millis(1),
+        }
+    }
+
+    /// Lets the network inject the faults of `chaos`.
+    pub fn with_chaos(mut self, chaos: Chaos) -> Self {
+        self.rng = self.rng.wrapping_add(chaos.seed.wrapping_mul(0x2545_f491_4f6c_dd1d));
+        self.chaos = Some(chaos);
+        self
+    }
+
+    /// Stops the simulation as soon as every node knows the outcome, without
+    /// waiting for the messages in flight, like the digests of a leader that
+    /// never stop going round a large ring.
+    pub fn until_decided(mut self) -> Self {
+        self.until_decided = true;
+        self
+    }
+
+    /// Runs the election until every node knows its outcome and no messages
+    /// are in flight, or until `limit` of virtual time passed.
+    pub fn run(self, limit: Duration) -> Report {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .expect("cannot start the simulation runtime")
+            .block_on(self.elect(limit))
+    }
+
+    async fn elect(mut self, limit: Duration) -> Report {
+        let start = Instant::now();
+        let (outgoing, mut queued) = mpsc::unbounded_channel();
+        let (changes, mut changed) = mpsc::unbounded_channel();
+        for node in self.nodes.values() {
+            tokio::spawn(elect(node.clone()));
+            for leftward in [true, false] {
+                tokio::spawn(forward(node.clone(), leftward, outgoing.clone()));
+            }
+            tokio::spawn(follow(node.id, node.results.subscribe(), changes.clone()));
+        }
+        let mut results = self.results();
+        let mut undecided = results.values().filter(|result| **result == ElectionResult::Undecided).count();
+        let (mut delivered, mut leaders, mut most_leaders) = (0, 0, 0);
+        loop {
+            while let Ok((link, target, envelope)) = queued.try_recv() {
+                self.send(link, target, envelope);
+            }
+            while let Ok((id, result)) = changed.try_recv() {
+                let previous = results.insert(id, result);
+                leaders += (result == ElectionResult::Leader) as usize;
+                leaders -= (previous == Some(ElectionResult::Leader)) as usize;
+                undecided += (result == ElectionResult::Undecided) as usize;
+                undecided -= (previous == Some(ElectionResult::Undecided)) as usize;
+                most_leaders = most_leaders.max(leaders);
+            }
+            let now = Instant::now();
+            self.stalled.retain(|_, until| *until > now);
+            self.down.retain(|_, until| *until > now);
+            let links = self.wires.iter()
+                .filter(|(link, _)| !self.stalled.contains_key(link))
+                .filter(|((sender, _), wire)| !self.down.contains_key(sender) && !self.down.contains_key(&wire[0].0))
+                .map(|(link, _)| *link)
+                .collect::<Vec<_>>();
+            let in_flight = !self.wires.is_empty();
+            if (undecided == 0 && (!in_flight || self.until_decided)) || start.elapsed() >= limit {
+                break
+            }
+            if links.is_empty() {
+                tokio::time::sleep(self.hop).await;
+                continue
+            }
+            let link = self.pick(&links);
+            if self.strike(link) {
+                continue
+            }
+            let wire = self.wires.get_mut(&link).expect("picked links have messages");
+            let (target, message) = wire.pop_front().expect("wires have messages");
+            if wire.is_empty() {
+                self.wires.remove(&link);
+            }
+            tokio::time::sleep(self.hop).await;
+            match self.nodes.get(&target) {
+                Some(node) => match node.receive(message).await {
+                    Ok(_) => delivered += 1,
+                    Err(e) => warn!(node = target, "rejected a simulated message: {}", e),
+                },
+                None => warn!(node = link.0, "sent a message to unknown node {}", target),
+            }
+            // let the node's messages and result reach the network
+            let () = tokio::task::yield_now().await;
+        }
+        for node in self.nodes.values() {
+            node.shutdown();
+        }
+        Report { results: self.results(), delivered, elapsed: start.elapsed(), most_leaders, faults: self.faults }
+    }
+
+    /// Puts a message a node queued for `target` onto the wire of `link`,
+    /// numbering it the way the relays do.
+    fn send(&mut self, link: Link, target: u64, envelope: Envelope) {
+        let (sender, leftward) = link;
+        let node = &self.nodes[&sender];
+        let Envelope { message, request_id, trace, .. } = envelope;
+        let number = self.sent.entry(link).or_default();
+        *number += 1;
+        let message = message.sequenced(Some(Sequence { sender, incarnation: node.incarnation, number: *number, leftward }));
+        let message = PeerMessage {
+            body: Some(message.into()),
+            request_id: request_id.and_then(|id| id.to_str().ok().map(str::to_string)).unwrap_or_default(),
+            trace,
+            lamport: node.tick(),
+            signature: vec![],
+            group_id: node.group,
+            role: String::new(),
+            version: RELAY_VERSION,
+            number: *number,
+        };
+        self.wires.entry(link).or_default().push_back((target, message));
+    }
+
+    /// Rolls the dice for the faults of the chaos mode, if it is on, before
+    /// a message goes out on `link`. Returns whether the delivery is off.
+    fn strike(&mut self, link: Link) -> bool {
+        let chaos = match self.chaos {
+            Some(chaos) => chaos,
+            None => return false,
+        };
+        let now = Instant::now();
+        if self.chance(chaos.crash) {
+            let ids = self.nodes.keys().copied().collect::<Vec<_>>();
+            let crashed = ids[(self.random() % ids.len() as u64) as usize];
+            let downtime = MAX_DOWNTIME.mul_f64(self.fraction());
+            self.down.insert(crashed, now + downtime);
+            self.faults.crashed += 1;
+            warn!(node = crashed, "crashing for {:?}", downtime);
+            return true
+        }
+        if self.chance(chaos.drop) {
+            self.stalled.insert(link, now + RETRANSMIT_AFTER);
+            self.faults.dropped += 1;
+            return true
+        }
+        if self.chance(chaos.delay) {
+            let delay = MAX_DELAY.mul_f64(self.fraction());
+            self.stalled.insert(link, now + delay);
+            self.faults.delayed += 1;
+            return true
+        }
+        if self.chance(chaos.duplicate) {
+            let wire = self.wires.get_mut(&link).expect("picked links have messages");
+            let copy = wire[0].clone();
+            wire.push_front(copy);
+            self.faults.duplicated += 1;
+        }
+        false
+    }
+
+    fn results(&self) -> BTreeMap<u64, ElectionResult> {
+        self.nodes.iter().map(|(&id, node)| (id, *node.results.borrow())).collect()
+    }
+
+    /// Chooses the link to deliver a message on next.
+    fn pick(&mut self, links: &[Link]) -> Link {
+        match self.delivery {
+            Delivery::RoundRobin => {
+                let next = links.iter().find(|&&link| Some(link) > self.turn).unwrap_or(&links[0]);
+                *self.turn.insert(*next)
+            },
+            Delivery::Shuffled(_) => links[(self.random() % links.len() as u64) as usize],
+        }
+    }
+
+    /// The next number of the splitmix64 generator.
+    fn random(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A random number in `[0, 1)`.
+    fn fraction(&mut self) -> f64 {
+        (self.random() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        self.fraction() < probability
+    }
+}
+
+/// Hands the messages `node` queues for its neighbour on one side to the
+/// network, with the neighbour they are headed to, the way its relay would
+/// take them off the queue.
+async fn forward(node: Node, leftward: bool, network: mpsc::UnboundedSender<(Link, u64, Envelope)>) {
+    let queue = node.neighbor(leftward).clone();
+    loop {
+        let envelope = queue.pop().await;
+        if network.send(((node.id, leftward), queue.peer().id, envelope)).is_err() {
+            return
+        }
+    }
+}
+
+/// Tells the network about every change of the result of node `id`, so it
+/// need not look at every node after every delivery.
+async fn follow(id: u64, mut results: watch::Receiver<ElectionResult>, network: mpsc::UnboundedSender<(u64, ElectionResult)>) {
+    while results.changed().await.is_ok() {
+        if network.send((id, *results.borrow_and_update())).is_err() {
+            return
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use tonic::transport::server::{TcpConnectInfo, TlsConnectInfo};
+
+use crate::x509;
+
+/// The SPIFFE ID of the X.509 SVID `der`: the only URI among its subject
+/// alternative names, if that is a SPIFFE ID.
+pub fn spiffe_id(der: &[u8]) -> Option<String> {
+    match x509::uris(der).as_slice() {
+        [uri] if uri.starts_with("spiffe://") => Some(uri.clone()),
+        _ => None,
+    }
+}
+
+/// The SPIFFE ID of the certificate the peer presented for the connection
+/// a call with `extensions` came in over, if it presented an SVID.
+pub fn peer_id(extensions: &http::Extensions) -> Option<String> {
+    let certificates = extensions.get::<TlsConnectInfo<TcpConnectInfo>>()?.peer_certs()?;
+    spiffe_id(certificates.first()?.get_ref())
+}
+
+/// Refuses a pattern that is not a SPIFFE ID, nor a trust domain or path
+/// ending in `/*`.
+pub fn check_pattern(pattern: &str) -> Result<(), String> {
+    let id = pattern.strip_suffix("/*").unwrap_or(pattern);
+    match id.strip_prefix("spiffe://") {
+        Some(rest) if !rest.is_empty() && !rest.starts_with('/') && !rest.ends_with('/') && !rest.contains('*') => Ok(()),
+        _ => Err(format!("{:?} is not a SPIFFE ID like spiffe://<trust domain>/<path>, nor one ending in /*", pattern)),
+    }
+}
+
+/// Whether `id` matches `pattern`: is the ID it names, or one under the
+/// trust domain or path it names before a trailing `/*`.
+pub fn matches(pattern: &str, id: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => id.len() > prefix.len() && id.starts_with(prefix),
+        None => id == pattern,
+    }
+}
@@ -0,0 +1,60 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::NodeState;
+
+/// Where a node keeps the state it was last in, so that a node restarted
+/// with the same state directory comes back knowing the leader instead of
+/// as a fresh candidate disrupting a settled ring.
+///
+/// The file holds the state on one line, `leader`, `defeated <leader>`,
+/// `defeated` or `candidate <phase>`, and the election term on another,
+/// `term <n>`.
+#[derive(Debug)]
+pub struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    pub fn new(path: PathBuf) -> Self {
+        StateFile { path }
+    }
+
+    /// The term an earlier run left off in, and its state if it knew the
+    /// leader. An election in progress is not worth resuming, the probes it
+    /// was waiting for are gone.
+    pub fn load(&self) -> io::Result<(Option<NodeState>, u64)> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((None, 0)),
+            Err(e) => return Err(e),
+        };
+        let malformed = || io::Error::new(io::ErrorKind::InvalidData, format!("malformed state {:?} in {}", contents, self.path.display()));
+        let (mut state, mut term) = (None, 0);
+        for line in contents.lines() {
+            match line.split_whitespace().collect::<Vec<_>>()[..] {
+                ["leader"] => state = Some(NodeState::Leader),
+                ["defeated", leader] => state = Some(NodeState::Defeated { leader: Some(leader.parse().map_err(|_| malformed())?) }),
+                ["defeated"] | ["candidate", _] => state = None,
+                ["term", n] => term = n.parse().map_err(|_| malformed())?,
+                _ => return Err(malformed()),
+            }
+        }
+        Ok((state, term))
+    }
+
+    /// Records `state` in `term`, replacing the file in one go, so a crash
+    /// never leaves half a state behind.
+    pub fn save(&self, state: &NodeState, term: u64) -> io::Result<()> {
+        let line = match state {
+            NodeState::Leader => "leader".to_string(),
+            NodeState::Defeated { leader: Some(leader) } => format!("defeated {}", leader),
+            NodeState::Defeated { leader: None } => "defeated".to_string(),
+            NodeState::Candidate { phase, .. } => format!("candidate {}", phase),
+        };
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, format!("{}\nterm {}\n", line, term))?;
+        fs::rename(&tmp, &self.path)
+    }
+}
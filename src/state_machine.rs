@@ -0,0 +1,201 @@
+use std::cmp::{Ordering, Reverse};
+
+use crate::leader_election_service::Decision;
+use crate::NodeState;
+
+/// What moves a node of the ring algorithm from one [`NodeState`] to another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The node sent the probe of its current phase.
+    Probe,
+    /// The probe of the current phase came back, so the node moves on.
+    NextPhase,
+    /// The probe of a candidate preferred over the node passed through it.
+    Defeat,
+    /// The node's own probe went around the whole ring.
+    Lead,
+    /// A notification told the node that `leader` was elected.
+    Notified { leader: u64 },
+}
+
+/// A transition the state rules out, e.g. leading once defeated, naming
+/// what the node was asked to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Refused(pub &'static str);
+
+/// Where a notification leaves a node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notified {
+    /// The leader whose notification keeps circulating.
+    pub winner: u64,
+    /// The leader the node already knew of, if it was another.
+    pub conflict: Option<u64>,
+    pub state: NodeState,
+}
+
+/// Marks the current phase of a candidate as probed.
+pub fn probe(state: &NodeState) -> Result<NodeState, Refused> {
+    match *state {
+        NodeState::Candidate { phase, last_phase_probed } if last_phase_probed != phase => {
+            Ok(NodeState::Candidate { phase, last_phase_probed: phase })
+        },
+        _ => Err(Refused("probe its phase")),
+    }
+}
+
+/// Moves a candidate that probed its current phase on to the next one.
+pub fn next_phase(state: &NodeState) -> Result<NodeState, Refused> {
+    match *state {
+        NodeState::Candidate { phase, last_phase_probed } if last_phase_probed == phase => {
+            Ok(NodeState::Candidate { phase: phase + 1, last_phase_probed })
+        },
+        _ => Err(Refused("advance to the next phase")),
+    }
+}
+
+/// Defeats a candidate. A defeated node stays as it is.
+pub fn defeat(state: &NodeState) -> Result<NodeState, Refused> {
+    match state {
+        NodeState::Candidate { .. } => Ok(NodeState::Defeated { leader: None }),
+        NodeState::Defeated { .. } => Ok(state.clone()),
+        NodeState::Leader => Err(Refused("be defeated")),
+    }
+}
+
+/// Makes a candidate the leader. The leader stays as it is.
+pub fn lead(state: &NodeState) -> Result<NodeState, Refused> {
+    match state {
+        NodeState::Candidate { .. } | NodeState::Leader => Ok(NodeState::Leader),
+        NodeState::Defeated { .. } => Err(Refused("lead")),
+    }
+}
+
+/// Follows `leader`, as node `id` notified of its election. A node that
+/// already knows of a different leader, or is one, follows whichever of the
+/// two `prefer` picks, staying the leader if it picks the node itself.
+pub fn defeat_with_leader(state: &NodeState, id: u64, leader: u64, prefer: impl FnOnce(u64, u64) -> u64) -> Notified {
+    let known = match *state {
+        NodeState::Candidate { .. } | NodeState::Defeated { leader: None } => None,
+        NodeState::Defeated { leader: Some(known) } => Some(known),
+        NodeState::Leader => Some(id),
+    };
+    let (winner, conflict) = match known {
+        Some(known) if known != leader => (prefer(known, leader), Some(known)),
+        _ => (leader, None),
+    };
+    let state = match winner == id {
+        true => state.clone(),
+        false => NodeState::Defeated { leader: Some(winner) },
+    };
+    Notified { winner, conflict, state }
+}
+
+/// Takes node `id` from `state` through `event`, resolving conflicting
+/// leaders with `prefer`.
+pub fn apply(state: &NodeState, id: u64, event: Event, prefer: impl FnOnce(u64, u64) -> u64) -> Result<NodeState, Refused> {
+    match event {
+        Event::Probe => probe(state),
+        Event::NextPhase => next_phase(state),
+        Event::Defeat => defeat(state),
+        Event::Lead => lead(state),
+        Event::Notified { leader } => Ok(defeat_with_leader(state, id, leader, prefer).state),
+    }
+}
+
+/// Where a node of `priority` stands in elections, the lower the better:
+/// nodes of a higher priority come first, of the same priority those in the
+/// ring's `preferred` zone, and after that those with the smaller ID,
+/// mirroring the ordering used by `probe_raw`.
+pub fn standing(priority: u64, preferred: bool, id: u64) -> (Reverse<u64>, bool, u64) {
+    (Reverse(priority), !preferred, id)
+}
+
+/// The node a [`react`]ion is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Me {
+    pub id: u64,
+    pub priority: u64,
+    /// Whether the node is in the ring's preferred zone.
+    pub preferred: bool,
+    /// Whether the node sits out the election, letting every probe by.
+    pub abstains: bool,
+}
+
+/// What happens to a node of the ring algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    /// The poll timer fired.
+    Poll,
+    /// The probe of candidate `sender`, of `priority` and from the preferred
+    /// zone if `preferred`, reached the node.
+    Probe { sender: u64, priority: u64, preferred: bool },
+    /// A probe that reached the node earlier calls for `event`, which waits
+    /// until the node probed its own phase.
+    Settle(Event),
+}
+
+/// What a node does about an [`Input`], in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Take the transition, which the state allows.
+    Take(Event),
+    /// Send the probe of `phase` to the left neighbour if `headed_left`, or
+    /// else the right one.
+    SendProbe { phase: u64, headed_left: bool },
+    /// Pass the probe that came in on, the way it was headed.
+    ForwardProbe,
+    /// Tell the neighbour the probe came from what became of it.
+    Decide(Decision),
+    /// Feed `Input::Settle` with the event back in once the state changes.
+    Defer(Event),
+    /// Send the leader's notification around the ring.
+    Announce,
+    /// Arm the poll timer again.
+    Poll,
+}
+
+/// Decides what node `me` in `state` does about `input`, leaving the clock,
+/// the network and the state itself to whoever drives the node, be it a
+/// runtime or a simulator.
+pub fn react(state: &NodeState, me: Me, input: Input) -> Vec<Command> {
+    let mut commands = vec![];
+    let event = match input {
+        Input::Poll => {
+            match *state {
+                NodeState::Candidate { phase, last_phase_probed } if last_phase_probed != phase => {
+                    commands.extend([Command::Take(Event::Probe), Command::SendProbe { phase, headed_left: phase % 2 == 0 }, Command::Poll]);
+                },
+                NodeState::Candidate { .. } => commands.push(Command::Poll),
+                NodeState::Leader => commands.push(Command::Announce),
+                NodeState::Defeated { .. } => (),
+            }
+            return commands
+        },
+        Input::Probe { sender, priority, preferred } => {
+            let (sender, own) = (standing(priority, preferred, sender), standing(me.priority, me.preferred, me.id));
+            let decision = match sender.cmp(&own) {
+                Ordering::Equal => Decision::YouWin,
+                _ if me.abstains => Decision::Forwarded,
+                Ordering::Less => Decision::Forwarded,
+                Ordering::Greater => Decision::Defeated,
+            };
+            if decision == Decision::Forwarded {
+                commands.push(Command::ForwardProbe);
+            }
+            commands.push(Command::Decide(decision));
+            match own.cmp(&sender) {
+                Ordering::Less => Event::NextPhase,
+                Ordering::Equal => Event::Lead,
+                Ordering::Greater => Event::Defeat,
+            }
+        },
+        Input::Settle(event) => event,
+    };
+    match *state {
+        NodeState::Candidate { phase, last_phase_probed } if phase == last_phase_probed => commands.push(Command::Take(event)),
+        // the probe of the current phase goes out first
+        NodeState::Candidate { .. } => commands.push(Command::Defer(event)),
+        _ => (),
+    }
+    commands
+}
@@ -0,0 +1,116 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use tokio::time::{Duration, Instant};
+
+use crate::leader_election_service::{peer_message, MessageCounts, StatsWindow};
+
+/// How much time each bucket of counts covers.
+const BUCKET: Duration = Duration::from_secs(10);
+
+/// The windows the counts are summed over, the longest last.
+const WINDOWS: [Duration; 3] = [Duration::from_secs(60), Duration::from_secs(5 * 60), Duration::from_secs(15 * 60)];
+
+/// What the node counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stat {
+    ProbesReceived,
+    NotificationsReceived,
+    DigestsReceived,
+    ProbesSent,
+    NotificationsSent,
+    DigestsSent,
+    /// Changes of the node's state.
+    Transitions,
+    /// Relay streams to a neighbour opened again after one broke.
+    Reconnects,
+    /// Messages the node rejected and neighbours it could not reach.
+    Errors,
+}
+
+const STATS: usize = Stat::Errors as usize + 1;
+
+impl Stat {
+    /// What receiving or sending `body` counts as.
+    pub fn message(body: &peer_message::Body, sent: bool) -> Self {
+        match (body, sent) {
+            (peer_message::Body::Probe(_), false) => Stat::ProbesReceived,
+            (peer_message::Body::Notify(_), false) => Stat::NotificationsReceived,
+            (peer_message::Body::Digest(_), false) => Stat::DigestsReceived,
+            (peer_message::Body::Probe(_), true) => Stat::ProbesSent,
+            (peer_message::Body::Notify(_), true) => Stat::NotificationsSent,
+            (peer_message::Body::Digest(_), true) => Stat::DigestsSent,
+        }
+    }
+}
+
+/// Counts of what one node did over the last 1, 5 and 15 minutes, for
+/// operators querying a node without a metrics stack. The counts are kept in
+/// buckets of ten seconds, the oldest dropped as they fall out of the longest
+/// window, so a window reaches back up to ten seconds further than it says.
+#[derive(Debug)]
+pub struct Stats {
+    start: Instant,
+    /// The counts of each bucket since the start that saw any, by its index.
+    buckets: Mutex<VecDeque<(u64, [u64; STATS])>>,
+}
+
+impl Stats {
+    pub fn new(start: Instant) -> Self {
+        Stats { start, buckets: Mutex::default() }
+    }
+
+    fn bucket(&self, at: Instant) -> u64 {
+        (at.saturating_duration_since(self.start).as_nanos() / BUCKET.as_nanos()) as u64
+    }
+
+    /// Counts one `stat` at `now`.
+    pub fn record(&self, stat: Stat, now: Instant) {
+        let bucket = self.bucket(now);
+        let mut buckets = self.buckets.lock().unwrap();
+        match buckets.back_mut() {
+            Some((last, counts)) if *last == bucket => counts[stat as usize] += 1,
+            _ => {
+                let mut counts = [0; STATS];
+                counts[stat as usize] = 1;
+                buckets.push_back((bucket, counts));
+            },
+        }
+        let kept = (WINDOWS[WINDOWS.len() - 1].as_nanos() / BUCKET.as_nanos()) as u64;
+        while buckets.front().is_some_and(|&(first, _)| first + kept < bucket) {
+            buckets.pop_front();
+        }
+    }
+
+    /// The counts of each window as of `now`, the shortest first.
+    pub fn windows(&self, now: Instant) -> Vec<StatsWindow> {
+        let bucket = self.bucket(now);
+        let buckets = self.buckets.lock().unwrap();
+        WINDOWS.iter().map(|window| {
+            let span = (window.as_nanos() / BUCKET.as_nanos()) as u64;
+            let mut counts = [0; STATS];
+            for (_, bucket_counts) in buckets.iter().filter(|&&(at, _)| at + span >= bucket) {
+                for (count, added) in counts.iter_mut().zip(bucket_counts) {
+                    *count += added;
+                }
+            }
+            let count = |stat: Stat| counts[stat as usize];
+            StatsWindow {
+                seconds: window.as_secs(),
+                received: Some(MessageCounts {
+                    probes: count(Stat::ProbesReceived),
+                    notifications: count(Stat::NotificationsReceived),
+                    digests: count(Stat::DigestsReceived),
+                }),
+                sent: Some(MessageCounts {
+                    probes: count(Stat::ProbesSent),
+                    notifications: count(Stat::NotificationsSent),
+                    digests: count(Stat::DigestsSent),
+                }),
+                transitions: count(Stat::Transitions),
+                reconnects: count(Stat::Reconnects),
+                errors: count(Stat::Errors),
+            }
+        }).collect()
+    }
+}
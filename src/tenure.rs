@@ -0,0 +1,84 @@
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use tokio::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct Inner {
+    leader: Option<u64>,
+    /// When `leader` last changed.
+    since: Instant,
+    terms: u64,
+    changes: u64,
+    /// How long the node went without a leader until it last learned of one.
+    last_election: Option<f64>,
+}
+
+/// Who leads the ring as far as one node knows, and for how long, for the
+/// leadership tenure metrics.
+#[derive(Debug)]
+pub struct Tenure {
+    node: u64,
+    inner: Mutex<Inner>,
+}
+
+impl Tenure {
+    pub fn new(node: u64, now: Instant) -> Self {
+        Tenure { node, inner: Mutex::new(Inner { leader: None, since: now, terms: 0, changes: 0, last_election: None }) }
+    }
+
+    /// Records the leader the node knows of after a state change. Returns
+    /// how long the node went without a leader, if it just learned of one.
+    pub fn observe(&self, leader: Option<u64>, now: Instant) -> Option<Duration> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.leader == leader {
+            return None
+        }
+        let mut leaderless = None;
+        if leader.is_some() {
+            inner.changes += 1;
+            if inner.leader.is_none() {
+                let elapsed = now.saturating_duration_since(inner.since);
+                inner.last_election = Some(elapsed.as_secs_f64());
+                leaderless = Some(elapsed);
+            }
+        }
+        if leader == Some(self.node) {
+            inner.terms += 1;
+        }
+        inner.leader = leader;
+        inner.since = now;
+        leaderless
+    }
+
+    /// When the node last lost track of the leader, if it does not know of one now.
+    pub fn leaderless_since(&self) -> Option<Instant> {
+        let inner = self.inner.lock().unwrap();
+        inner.leader.is_none().then_some(inner.since)
+    }
+
+    /// Appends the tenure metrics to `out` in the Prometheus text format.
+    pub fn render(&self, now: Instant, out: &mut String) {
+        let inner = self.inner.lock().unwrap();
+        let elapsed = now.saturating_duration_since(inner.since).as_secs_f64();
+        let (tenure, leaderless) = match inner.leader {
+            Some(leader) if leader == self.node => (elapsed, 0.0),
+            Some(_) => (0.0, 0.0),
+            None => (0.0, elapsed),
+        };
+        let metrics = [
+            ("grpc_le_leader_tenure_seconds", "gauge", tenure),
+            ("grpc_le_leaderless_seconds", "gauge", leaderless),
+            ("grpc_le_terms_served_total", "counter", inner.terms as f64),
+            ("grpc_le_leader_changes_total", "counter", inner.changes as f64),
+        ];
+        for (name, kind, value) in metrics {
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{}{{node=\"{}\"}} {}", name, self.node, value);
+        }
+        if let Some(duration) = inner.last_election {
+            let _ = writeln!(out, "# TYPE grpc_le_election_duration_seconds gauge");
+            let _ = writeln!(out, "grpc_le_election_duration_seconds{{node=\"{}\"}} {}", self.node, duration);
+        }
+    }
+}
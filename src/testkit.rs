@@ -0,0 +1,170 @@
+use std::future::Future;
+use std::time::Duration;
+
+use crate::builder::{NodeBuilder, NodeHandle};
+use tonic::Request;
+
+use crate::config::Config;
+use crate::leader_election_service::admin_service_server::AdminService;
+use crate::leader_election_service::DumpStateRequest;
+use crate::{ElectionResult, Node};
+
+/// How often [`wait_until`] checks its condition.
+const POLL: Duration = Duration::from_millis(10);
+
+/// Addresses on localhost that nothing listens on, from ports the OS picked,
+/// so that tests running side by side do not collide.
+pub fn free_addrs<const N: usize>() -> [String; N] {
+    let listeners = [(); N].map(|()| std::net::TcpListener::bind("[::1]:0").unwrap());
+    listeners.map(|listener| listener.local_addr().unwrap().to_string())
+}
+
+/// Waits until `condition` holds, checking it every few milliseconds.
+/// Returns whether it held within `timeout`.
+pub async fn wait_until<F: Future<Output = bool>>(timeout: Duration, mut condition: impl FnMut() -> F) -> bool {
+    tokio::time::timeout(timeout, async {
+        while !condition().await {
+            tokio::time::sleep(POLL).await;
+        }
+    }).await.is_ok()
+}
+
+/// How [`TestRing::build`] sets up each node past its place in the ring.
+type Setup = Box<dyn Fn(u16, NodeBuilder) -> NodeBuilder + Send + Sync>;
+
+/// A ring of nodes 1 to `n` served in the test's process on free ports of
+/// localhost, for applications to test their leader-only logic against
+/// a real election, e.g.
+///
+/// ```text
+/// let mut ring = TestRing::start(3, Config { liveness_interval: Some(Duration::from_millis(100)), ..Config::default() });
+/// assert_eq!(ring.await_leader(Duration::from_secs(5)).await, Some(1));
+/// ring.crash(1).await;
+/// assert_eq!(ring.await_leader(Duration::from_secs(5)).await, Some(2));
+/// ring.restart(1).await;
+/// assert_eq!(ring.await_leader(Duration::from_secs(5)).await, Some(1));
+/// ring.shutdown().await;
+/// ```
+///
+/// The nodes talk gRPC to each other, as noticing a crashed node and
+/// closing the ring around it goes through the heartbeats and
+/// reconfigurations only they serve; [`crate::simulation`] runs a ring
+/// purely in memory instead. Each node's left neighbour is the one with the
+/// ID below, node `n` that of node 1. The ring notices a crash only with a
+/// [`Config::liveness_interval`].
+pub struct TestRing {
+    addrs: Vec<String>,
+    setup: Setup,
+    /// The running nodes by ID, less one, none for the crashed ones.
+    nodes: Vec<Option<NodeHandle>>,
+}
+
+impl TestRing {
+    /// Starts a ring of `size` nodes, each with `config`.
+    pub fn start(size: u16, config: Config) -> Self {
+        TestRing::build(size, move |_, node| node.config(config.clone()))
+    }
+
+    /// Starts a ring of `size` nodes, each as `setup` makes it from a
+    /// builder with its ID, address, neighbours and ring size, e.g. to give
+    /// the nodes priorities or zones.
+    pub fn build(size: u16, setup: impl Fn(u16, NodeBuilder) -> NodeBuilder + Send + Sync + 'static) -> Self {
+        assert!(size >= 2, "a ring of {} nodes has no neighbours", size);
+        let addrs = (0..size).map(|_| {
+            let [addr] = free_addrs();
+            addr
+        }).collect();
+        let mut ring = TestRing { addrs, setup: Box::new(setup), nodes: vec![] };
+        ring.nodes = (1..=size).map(|id| Some(ring.spawn(id))).collect();
+        ring
+    }
+
+    fn spawn(&self, id: u16) -> NodeHandle {
+        let size = self.addrs.len() as u16;
+        let (left, right) = ((id + size - 2) % size + 1, id % size + 1);
+        let node = Node::builder().id(id).listen(self.addr(id)).left(left, self.addr(left)).right(right, self.addr(right)).ring_size(size as u64);
+        (self.setup)(id, node).build().unwrap_or_else(|e| panic!("cannot start node {}: {}", id, e))
+    }
+
+    /// The address node `id` is served on, whether it runs or not.
+    pub fn addr(&self, id: u16) -> &str {
+        &self.addrs[id as usize - 1]
+    }
+
+    /// Node `id`, which has to be running.
+    pub fn node(&self, id: u16) -> &Node {
+        self.handle(id).node()
+    }
+
+    /// The handle of node `id`, which has to be running.
+    pub fn handle(&self, id: u16) -> &NodeHandle {
+        self.nodes[id as usize - 1].as_ref().unwrap_or_else(|| panic!("node {} crashed", id))
+    }
+
+    /// The nodes that run, by ID.
+    pub fn running(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.iter().flatten().map(NodeHandle::node)
+    }
+
+    /// Stops node `id` as if its process died, and waits until it has.
+    pub async fn crash(&mut self, id: u16) {
+        let handle = self.nodes[id as usize - 1].take().unwrap_or_else(|| panic!("node {} crashed already", id));
+        handle.shutdown().await.unwrap_or_else(|e| panic!("node {} failed: {}", id, e));
+    }
+
+    /// Starts crashed node `id` again at its address, with whatever state it
+    /// kept in its [`Config::state_dir`], if any, and splices it back into
+    /// the ring right of the nearest running node below it.
+    pub async fn restart(&mut self, id: u16) {
+        assert!(self.nodes[id as usize - 1].is_none(), "node {} is running", id);
+        let size = self.addrs.len() as u16;
+        let left = (1..size).map(|below| (id + size - 1 - below) % size + 1)
+            .find(|&left| self.nodes[left as usize - 1].is_some())
+            .unwrap_or_else(|| panic!("no node runs to take node {} back", id));
+        let mut epoch = 0;
+        for node in self.running() {
+            let state = AdminService::dump_state(node, Request::new(DumpStateRequest::default())).await
+                .unwrap_or_else(|e| panic!("cannot ask node {} for its epoch: {}", node.id(), e.message()));
+            epoch = epoch.max(state.into_inner().topology_epoch);
+        }
+        let node = Node::builder().id(id).listen(self.addr(id)).left(left, self.addr(left)).right(left, self.addr(left));
+        let node = (self.setup)(id, node).join(epoch + 1).build().unwrap_or_else(|e| panic!("cannot restart node {}: {}", id, e));
+        self.nodes[id as usize - 1] = Some(node);
+    }
+
+    /// Waits until every running node follows the same running leader, or
+    /// is it, and the whole ring acknowledged it. Returns the leader, unless
+    /// the ring did not converge within `timeout`.
+    pub async fn await_leader(&self, timeout: Duration) -> Option<u64> {
+        tokio::time::timeout(timeout, async {
+            loop {
+                if let Some(leader) = self.agreed_leader().await {
+                    return leader
+                }
+                tokio::time::sleep(POLL).await;
+            }
+        }).await.ok()
+    }
+
+    /// The leader every running node acknowledged, if they agree on one
+    /// that runs and leads.
+    async fn agreed_leader(&self) -> Option<u64> {
+        let mut acknowledged = vec![];
+        for node in self.running() {
+            acknowledged.push(node.await_ring_acknowledged().await);
+        }
+        let leader = acknowledged.first().copied().filter(|first| acknowledged.iter().all(|other| other == first))?;
+        self.running()
+            .any(|node| node.id() == leader && *node.subscribe().borrow() == ElectionResult::Leader)
+            .then_some(leader)
+    }
+
+    /// Shuts down the nodes still running.
+    pub async fn shutdown(self) {
+        for (id, handle) in (1..).zip(self.nodes) {
+            if let Some(handle) = handle {
+                handle.shutdown().await.unwrap_or_else(|e| panic!("node {} failed: {}", id, e));
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::{Duration, Instant};
+
+use crate::clock::Clock;
+
+/// The timers a node can have armed. Each kind is armed at most once at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TimerKind {
+    /// Grace period for the neighbours to come up before the first probe.
+    StartupGrace,
+    /// Next check of whether the candidate should probe its next phase.
+    Poll,
+    /// Next circulation of the leader digest.
+    Digest,
+    /// Expiry of the leader's lease.
+    Lease,
+    /// The end of the time the election has to find a leader.
+    ElectionDeadline,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Armed {
+    deadline: Instant,
+    generation: u64,
+}
+
+/// A node's timers, driven by the node's clock. Expired timers are delivered
+/// as events through `fired()`, in the order they expire.
+#[derive(Debug)]
+pub struct Timers {
+    clock: Arc<dyn Clock>,
+    armed: std::sync::Mutex<(u64, HashMap<TimerKind, Armed>)>,
+    fired_tx: mpsc::UnboundedSender<TimerKind>,
+    fired_rx: Mutex<mpsc::UnboundedReceiver<TimerKind>>,
+}
+
+impl Timers {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        let (fired_tx, fired_rx) = mpsc::unbounded_channel();
+        Timers { clock, armed: Default::default(), fired_tx, fired_rx: Mutex::new(fired_rx) }
+    }
+
+    /// Arms the timer to fire `after` from now, replacing any earlier deadline.
+    pub fn set(self: &Arc<Self>, kind: TimerKind, after: Duration) {
+        let deadline = self.clock.now() + after;
+        let generation = {
+            let mut armed = self.armed.lock().unwrap();
+            armed.0 += 1;
+            let generation = armed.0;
+            armed.1.insert(kind, Armed { deadline, generation });
+            generation
+        };
+
+        let this = self.clone();
+        tokio::spawn(async move {
+            this.clock.sleep_until(deadline).await;
+            let still_armed = {
+                let mut armed = this.armed.lock().unwrap();
+                match armed.1.get(&kind) {
+                    Some(timer) if timer.generation == generation => armed.1.remove(&kind).is_some(),
+                    _ => false,
+                }
+            };
+            if still_armed {
+                let _ = this.fired_tx.send(kind);
+            }
+        });
+    }
+
+    /// Disarms the timer. Returns whether it was armed.
+    pub fn cancel(&self, kind: TimerKind) -> bool {
+        self.armed.lock().unwrap().1.remove(&kind).is_some()
+    }
+
+    /// All armed timers, soonest first.
+    pub fn armed(&self) -> Vec<(TimerKind, Instant)> {
+        let mut armed = self.armed.lock().unwrap().1.iter()
+            .map(|(&kind, timer)| (kind, timer.deadline))
+            .collect::<Vec<_>>();
+        armed.sort_by_key(|&(kind, deadline)| (deadline, kind));
+        armed
+    }
+
+    /// Waits for the next timer to expire.
+    pub async fn fired(&self) -> TimerKind {
+        self.fired_rx.lock().await.recv().await.expect("the sender lives as long as the receiver")
+    }
+}
@@ -0,0 +1,90 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity, Server, ServerTlsConfig};
+
+use crate::config::{Config, Middleware, TimingConfig};
+
+/// Mutual TLS for both ends of a node's connections: the node presents its
+/// certificate either way and only talks to peers whose certificates the CA
+/// signed.
+#[derive(Debug, Clone)]
+pub struct Tls {
+    pub server: ServerTlsConfig,
+    client: ClientTlsConfig,
+    /// The certificate, key and CA the configurations were read from.
+    paths: [PathBuf; 3],
+    domain: Option<String>,
+    /// When the newest of the files was last modified before being read.
+    modified: Option<SystemTime>,
+}
+
+fn read(path: &Path) -> io::Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| io::Error::new(e.kind(), format!("cannot read {}: {}", path.display(), e)))
+}
+
+/// When the newest of the files at `paths` was last modified, if any can tell.
+fn modified(paths: &[PathBuf]) -> Option<SystemTime> {
+    paths.iter().filter_map(|path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()).max()
+}
+
+impl Tls {
+    /// Reads the certificates `config` names, if it enables TLS.
+    pub fn load(config: &Config) -> io::Result<Option<Self>> {
+        let (cert, key, ca) = match (&config.tls_cert, &config.tls_key, &config.tls_ca) {
+            (None, None, None) => return Ok(None),
+            (Some(cert), Some(key), Some(ca)) => (cert, key, ca),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "TLS needs all of --tls-cert, --tls-key and --tls-ca")),
+        };
+        Self::read([cert.clone(), key.clone(), ca.clone()], config.tls_domain.clone()).map(Some)
+    }
+
+    fn read(paths: [PathBuf; 3], domain: Option<String>) -> io::Result<Self> {
+        // taken first, for a file changing while it is read to be read again
+        let modified = modified(&paths);
+        let [cert, key, ca] = &paths;
+        let identity = Identity::from_pem(read(cert)?, read(key)?);
+        let ca = Certificate::from_pem(read(ca)?);
+        let mut client = ClientTlsConfig::new().ca_certificate(ca.clone()).identity(identity.clone());
+        if let Some(domain) = &domain {
+            client = client.domain_name(domain);
+        }
+        Ok(Tls { server: ServerTlsConfig::new().identity(identity).client_ca_root(ca), client, paths, domain, modified })
+    }
+
+    /// The certificates read again, if any of their files changed since they
+    /// were last read, e.g. for a certificate renewed before it expires.
+    pub fn reread(&self) -> io::Result<Option<Self>> {
+        match modified(&self.paths) {
+            modified if modified == self.modified => Ok(None),
+            _ => Self::read(self.paths.clone(), self.domain.clone()).map(Some),
+        }
+    }
+}
+
+/// The endpoint of the node at `url`, connected to over TLS if `tls` is given,
+/// giving up on connecting and keeping the connection alive as `timing` says.
+pub fn endpoint(url: String, tls: Option<&Tls>, timing: &TimingConfig) -> Result<Endpoint, String> {
+    let mut endpoint = Endpoint::from_shared(url).map_err(|e| e.to_string())?.connect_timeout(timing.connect_timeout);
+    if let Some(interval) = timing.keepalive_interval {
+        endpoint = endpoint
+            .tcp_keepalive(Some(interval))
+            .http2_keep_alive_interval(interval)
+            .keep_alive_timeout(timing.keepalive_timeout)
+            .keep_alive_while_idle(timing.keepalive_while_idle);
+    }
+    match tls {
+        Some(tls) => endpoint.tls_config(tls.client.clone()).map_err(|e| format!("{:?}", e)),
+        None => Ok(endpoint),
+    }
+}
+
+/// A server for a node, keeping its clients' connections alive as `timing`
+/// says and taking as many streams on each as `middleware` lets it.
+pub fn server(timing: &TimingConfig, middleware: &Middleware) -> Server {
+    Server::builder()
+        .tcp_keepalive(timing.keepalive_interval)
+        .http2_keepalive_interval(timing.keepalive_interval)
+        .http2_keepalive_timeout(Some(timing.keepalive_timeout))
+        .max_concurrent_streams(middleware.streams_per_connection)
+}
@@ -0,0 +1,298 @@
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+
+use crate::json::{self, Json};
+
+const FIRST_PORT: u16 = 40000;
+
+/// A node and the address it listens on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    pub id: u16,
+    pub addr: SocketAddr,
+    /// The name the other nodes know the node by, e.g. that of its container,
+    /// if they cannot reach it at `addr`.
+    pub host: Option<String>,
+    /// How much the ring algorithm prefers the node as the leader, before
+    /// its ID.
+    pub priority: u64,
+}
+
+impl Member {
+    /// gRPC URL the other nodes reach the node at.
+    pub fn url(&self) -> String {
+        match &self.host {
+            Some(host) => format!("http://{}:{}", host, self.addr.port()),
+            None => format!("http://{}", self.addr),
+        }
+    }
+}
+
+/// A neighbour a node sends to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub id: u16,
+    /// gRPC URL, e.g. `http://[::1]:40005`.
+    pub url: String,
+}
+
+/// Everything needed to run one node: where it listens and which neighbours
+/// it sends to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeSpec {
+    pub id: u16,
+    pub listen: SocketAddr,
+    /// How much the ring algorithm prefers the node as the leader, before
+    /// its ID.
+    pub priority: u64,
+    /// The failure domain the node runs in, e.g. its availability zone or
+    /// rack, which the ring algorithm prefers as the leader's if it is the
+    /// ring's preferred zone.
+    pub zone: Option<String>,
+    /// In a ring, the left neighbour first and the right one last, which are
+    /// one and the same in a ring of two.
+    pub neighbors: Vec<Link>,
+}
+
+impl NodeSpec {
+    /// A node of a ring, between `left` and `right`.
+    pub fn ring(id: u16, listen: SocketAddr, left: Link, right: Link) -> Self {
+        let neighbors = match left == right {
+            true => vec![left],
+            false => vec![left, right],
+        };
+        NodeSpec { id, listen, priority: 0, zone: None, neighbors }
+    }
+
+    pub fn left(&self) -> &Link {
+        &self.neighbors[0]
+    }
+
+    pub fn right(&self) -> &Link {
+        &self.neighbors[self.neighbors.len() - 1]
+    }
+}
+
+/// The nodes of a connected graph and the edges between them, by default a
+/// ring of the nodes in order, each sending left to the one before it and
+/// right to the one after it. Saved as
+///
+/// ```json
+/// {"nodes": [{"id": 5, "addr": "[::1]:40005"}, {"id": 9, "addr": "[::]:40009", "host": "node9"}, {"id": 7, "priority": 2}],
+///  "edges": [[5, 9], [5, 7]]}
+/// ```
+///
+/// where `addr` may be left out to use the default port for the ID, `host`
+/// unless the others reach the node by another name, `priority` unless the
+/// node is preferred as the leader over nodes with smaller IDs, and `edges`
+/// to make a ring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Topology {
+    pub members: Vec<Member>,
+    /// The pairs of nodes that are each other's neighbours, if the nodes do
+    /// not make up a ring in order.
+    pub edges: Option<Vec<(u16, u16)>>,
+}
+
+impl Topology {
+    /// A ring of the given nodes on localhost, each listening on port 40000
+    /// plus its ID.
+    pub fn from_ids(ids: &[u16]) -> Self {
+        Topology { members: ids.iter().map(|&id| Member { id, addr: default_addr(id), host: None, priority: 0 }).collect(), edges: None }
+    }
+
+    /// Whether the nodes make up a ring in order, which the ring algorithms
+    /// need.
+    pub fn is_ring(&self) -> bool {
+        let edges = match &self.edges {
+            Some(edges) => edges,
+            None => return true,
+        };
+        let n = self.members.len();
+        let ring = (0..n).filter(|&i| n > 2 || i + 1 < n).map(|i| (self.members[i].id, self.members[(i + 1) % n].id));
+        undirected(ring) == undirected(edges.iter().copied())
+    }
+
+    /// The nodes, each linked to its neighbours. In a ring, those are the
+    /// ones before and after it.
+    pub fn nodes(&self) -> Vec<NodeSpec> {
+        let n = self.members.len();
+        let link = |member: &Member| Link { id: member.id, url: member.url() };
+        let edges = match &self.edges {
+            Some(edges) if !self.is_ring() => edges,
+            _ => return (0..n).map(|i| NodeSpec {
+                priority: self.members[i].priority,
+                ..NodeSpec::ring(self.members[i].id, self.members[i].addr, link(&self.members[(i + n - 1) % n]), link(&self.members[(i + 1) % n]))
+            }).collect(),
+        };
+        self.members.iter().map(|member| NodeSpec {
+            id: member.id,
+            listen: member.addr,
+            priority: member.priority,
+            zone: None,
+            neighbors: self.members.iter()
+                .filter(|other| edges.iter().any(|&edge| edge == (member.id, other.id) || edge == (other.id, member.id)))
+                .map(link)
+                .collect(),
+        }).collect()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), reason));
+        let text = std::fs::read_to_string(path)?;
+        let json = json::parse(&text).map_err(invalid)?;
+        Topology::from_json(&json).map_err(invalid)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let nodes = self.members.iter()
+            .map(|member| {
+                let mut node = format!("    {{\"id\": {}, \"addr\": \"{}\"", member.id, member.addr);
+                if let Some(host) = &member.host {
+                    node += &format!(", \"host\": {:?}", host);
+                }
+                if member.priority != 0 {
+                    node += &format!(", \"priority\": {}", member.priority);
+                }
+                node + "}"
+            })
+            .collect::<Vec<_>>();
+        let edges = match &self.edges {
+            Some(edges) => {
+                let edges = edges.iter().map(|(a, b)| format!("[{}, {}]", a, b)).collect::<Vec<_>>();
+                format!(",\n  \"edges\": [{}]", edges.join(", "))
+            },
+            None => String::new(),
+        };
+        std::fs::write(path, format!("{{\n  \"nodes\": [\n{}\n  ]{}\n}}\n", nodes.join(",\n"), edges))
+    }
+
+    fn from_json(json: &Json) -> Result<Self, String> {
+        let nodes = match json.get("nodes") {
+            Some(Json::Array(nodes)) => nodes,
+            _ => return Err("expected an object with a \"nodes\" array".to_string()),
+        };
+        let members = nodes.iter().enumerate().map(|(i, node)| {
+            let id = match node.get("id") {
+                Some(&Json::Number(id)) if id.fract() == 0.0 && (0.0..=u16::MAX as f64).contains(&id) => id as u16,
+                _ => return Err(format!("node {} needs an integer \"id\" between 0 and {}", i, u16::MAX)),
+            };
+            let addr = match node.get("addr") {
+                None => default_addr(id),
+                Some(Json::String(addr)) => addr.parse().map_err(|e| format!("invalid address of node {}: {}", id, e))?,
+                Some(_) => return Err(format!("the address of node {} must be a string", id)),
+            };
+            let host = match node.get("host") {
+                None => None,
+                Some(Json::String(host)) => Some(host.clone()),
+                Some(_) => return Err(format!("the host of node {} must be a string", id)),
+            };
+            let priority = match node.get("priority") {
+                None => 0,
+                Some(&Json::Number(priority)) if priority.fract() == 0.0 && priority >= 0.0 => priority as u64,
+                Some(_) => return Err(format!("the priority of node {} must be a non-negative integer", id)),
+            };
+            Ok(Member { id, addr, host, priority })
+        }).collect::<Result<Vec<_>, _>>()?;
+        if members.is_empty() {
+            return Err("the topology has no nodes".to_string())
+        }
+        let edges = match json.get("edges") {
+            None => None,
+            Some(Json::Array(edges)) => Some(edges.iter().map(|edge| match edge {
+                Json::Array(ends) => match ends[..] {
+                    [Json::Number(a), Json::Number(b)] if a != b && [a, b].iter().all(|&id| members.iter().any(|m| m.id as f64 == id)) =>
+                        Ok((a as u16, b as u16)),
+                    _ => Err("each edge must join two different nodes of the topology".to_string()),
+                },
+                _ => Err("each edge must be a pair of node IDs".to_string()),
+            }).collect::<Result<Vec<_>, _>>()?),
+            Some(_) => return Err("\"edges\" must be an array".to_string()),
+        };
+        let topology = Topology { members, edges };
+        if !topology.is_connected() {
+            return Err("the nodes are not all connected".to_string())
+        }
+        Ok(topology)
+    }
+
+    /// Whether every node can reach every other over the edges.
+    fn is_connected(&self) -> bool {
+        let edges = match &self.edges {
+            Some(edges) => edges,
+            None => return true,
+        };
+        let mut reached = vec![self.members[0].id];
+        let mut i = 0;
+        while let Some(&id) = reached.get(i) {
+            for &(a, b) in edges {
+                let other = match (a == id, b == id) {
+                    (true, _) => b,
+                    (_, true) => a,
+                    _ => continue,
+                };
+                if !reached.contains(&other) {
+                    reached.push(other);
+                }
+            }
+            i += 1;
+        }
+        self.members.iter().all(|member| reached.contains(&member.id))
+    }
+}
+
+/// The edges sorted, without duplicates, each from the smaller ID.
+fn undirected(edges: impl Iterator<Item = (u16, u16)>) -> Vec<(u16, u16)> {
+    let mut edges = edges.map(|(a, b)| (a.min(b), a.max(b))).collect::<Vec<_>>();
+    edges.sort_unstable();
+    edges.dedup();
+    edges
+}
+
+/// The gRPC URL of a node given as `host:port`, `ipv4:port`, `[ipv6]:port`
+/// or a URL with an `http://` or `https://` scheme, which may leave the
+/// port out. Fails with what is wrong with the address.
+pub fn grpc_url(addr: &str) -> Result<String, String> {
+    let (scheme, authority) = match addr.split_once("://") {
+        Some((scheme @ ("http" | "https"), rest)) => (Some(scheme), rest.strip_suffix('/').unwrap_or(rest)),
+        Some((scheme, _)) => return Err(format!("unsupported scheme {}://, expected http:// or https://", scheme)),
+        None => (None, addr),
+    };
+    if authority.contains('/') {
+        return Err("expected nothing after the port".to_string())
+    }
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(bracketed) => {
+            let (ip, rest) = bracketed.split_once(']').ok_or("unclosed [ around the IPv6 address")?;
+            ip.parse::<Ipv6Addr>().map_err(|e| format!("invalid IPv6 address {:?}: {}", ip, e))?;
+            match rest {
+                "" => (ip, None),
+                rest => (ip, Some(rest.strip_prefix(':').ok_or("expected :<port> after the IPv6 address")?)),
+            }
+        },
+        None if authority.matches(':').count() > 1 => return Err("IPv6 addresses go in brackets, e.g. [::1]:40001".to_string()),
+        None => match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    if host.is_empty() {
+        return Err("missing host".to_string())
+    }
+    if host.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        host.parse::<Ipv4Addr>().map_err(|e| format!("invalid IPv4 address {:?}: {}", host, e))?;
+    }
+    if !authority.starts_with('[') && !host.chars().all(|c| c.is_ascii_alphanumeric() || "-._".contains(c)) {
+        return Err(format!("invalid host {:?}", host))
+    }
+    match (port, scheme) {
+        (Some(port), _) if port.parse::<u16>().map_or(true, |port| port == 0) => Err(format!("invalid port {:?}", port)),
+        (None, None) => Err("missing port, e.g. host:40001".to_string()),
+        _ => Ok(format!("{}://{}", scheme.unwrap_or("http"), authority)),
+    }
+}
+
+pub fn default_addr(id: u16) -> SocketAddr {
+    format!("[::1]:{}", FIRST_PORT + id).parse().unwrap()
+}
@@ -0,0 +1,220 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+use tracing::warn;
+
+use crate::clock::Clock;
+use crate::leader_election_service::TraceContext;
+
+// the upstream field names make for variants like `AnyValue::Value::IntValue`
+#[allow(clippy::enum_variant_names)]
+pub mod otlp {
+    tonic::include_proto!("opentelemetry.proto.collector.trace.v1");
+}
+
+use otlp::trace_service_client::TraceServiceClient;
+use otlp::{any_value, span::SpanKind, AnyValue, ExportTraceServiceRequest, InstrumentationScope, KeyValue};
+use otlp::{Resource, ResourceSpans, ScopeSpans};
+
+/// How long finished spans may wait for their batch to fill up before they
+/// are exported anyway.
+const EXPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The metadata key of a W3C trace context, with which a client streaming
+/// messages to a node makes their hops part of its own trace.
+pub const TRACEPARENT: &str = "traceparent";
+
+/// Starts the spans of one node's election traces. Traces are sampled by
+/// their ID, so a decision made at the root holds for every hop.
+#[derive(Debug)]
+pub struct Tracer {
+    node: u64,
+    clock: Arc<dyn Clock>,
+    seed: u64,
+    next_id: AtomicU64,
+    sample_ratio: f64,
+    /// Where finished spans go; without an exporter no spans are recorded.
+    finished: Option<mpsc::UnboundedSender<otlp::Span>>,
+}
+
+impl Tracer {
+    /// Creates a tracer whose IDs are derived from `seed`, which should be
+    /// unique to the node and run.
+    pub fn new(node: u64, clock: Arc<dyn Clock>, seed: u64, sample_ratio: f64, finished: Option<mpsc::UnboundedSender<otlp::Span>>) -> Self {
+        Tracer { node, clock, seed, next_id: AtomicU64::new(0), sample_ratio, finished }
+    }
+
+    fn id(&self) -> u64 {
+        splitmix64(self.seed.wrapping_add(self.next_id.fetch_add(1, Ordering::Relaxed)))
+    }
+
+    /// Starts a new trace.
+    pub fn root(&self, name: &str) -> Span {
+        let low = self.id();
+        let sampled = self.sample_ratio >= 1.0 || (low as f64) < self.sample_ratio * u64::MAX as f64;
+        if !sampled {
+            return Span::default()
+        }
+        let trace_id = [self.id().to_be_bytes(), low.to_be_bytes()].concat();
+        self.start(name, SpanKind::Internal, trace_id, Vec::new())
+    }
+
+    /// Starts a span for a hop of the trace `parent` belongs to. Messages
+    /// that arrive without a trace get no span.
+    pub fn child(&self, name: &str, parent: Option<&TraceContext>) -> Span {
+        match parent {
+            Some(parent) => self.start(name, SpanKind::Server, parent.trace_id.clone(), parent.span_id.clone()),
+            None => Span::default(),
+        }
+    }
+
+    fn start(&self, name: &str, kind: SpanKind, trace_id: Vec<u8>, parent_span_id: Vec<u8>) -> Span {
+        let finished = match &self.finished {
+            Some(finished) => finished.clone(),
+            None => return Span::default(),
+        };
+        let span = otlp::Span {
+            trace_id,
+            span_id: self.id().to_be_bytes().to_vec(),
+            parent_span_id,
+            name: name.to_string(),
+            kind: kind as i32,
+            start_time_unix_nano: self.clock.wall_now().timestamp_nanos() as u64,
+            end_time_unix_nano: 0,
+            attributes: Vec::new(),
+        };
+        let mut span = Span { active: Some(ActiveSpan { span, clock: self.clock.clone(), finished }) };
+        span.attribute("node", self.node);
+        span
+    }
+}
+
+#[derive(Debug)]
+struct ActiveSpan {
+    span: otlp::Span,
+    clock: Arc<dyn Clock>,
+    finished: mpsc::UnboundedSender<otlp::Span>,
+}
+
+/// A span in progress, which ends when dropped. Spans of unsampled traces,
+/// or of nodes without an exporter, record nothing.
+#[derive(Debug, Default)]
+pub struct Span {
+    active: Option<ActiveSpan>,
+}
+
+impl Span {
+    pub fn attribute(&mut self, key: &str, value: u64) {
+        if let Some(active) = &mut self.active {
+            active.span.attributes.push(KeyValue {
+                key: key.to_string(),
+                value: Some(AnyValue { value: Some(any_value::Value::IntValue(value as i64)) }),
+            });
+        }
+    }
+
+    /// The context to send along with the messages this span causes.
+    pub fn context(&self) -> Option<TraceContext> {
+        self.active.as_ref().map(|active| TraceContext {
+            trace_id: active.span.trace_id.clone(),
+            span_id: active.span.span_id.clone(),
+        })
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(mut active) = self.active.take() {
+            active.span.end_time_unix_nano = active.clock.wall_now().timestamp_nanos() as u64;
+            let _ = active.finished.send(active.span);
+        }
+    }
+}
+
+/// Reads a W3C trace context, `00-<trace ID>-<parent span ID>-<flags>` in
+/// hex. Contexts of unsampled traces count as none.
+pub fn parse_traceparent(value: &str) -> Option<TraceContext> {
+    let hex = |digits: &str, len: usize| match digits.len() == 2 * len {
+        true => (0..len).map(|i| u8::from_str_radix(&digits[2 * i..2 * i + 2], 16).ok()).collect::<Option<Vec<_>>>(),
+        false => None,
+    };
+    match value.trim().split('-').collect::<Vec<_>>()[..] {
+        ["00", trace_id, span_id, flags] if hex(flags, 1)?[0] & 1 == 1 => {
+            let (trace_id, span_id) = (hex(trace_id, 16)?, hex(span_id, 8)?);
+            let valid = trace_id.iter().chain(&span_id).any(|&byte| byte != 0);
+            valid.then_some(TraceContext { trace_id, span_id })
+        },
+        _ => None,
+    }
+}
+
+/// Writes `context` as a W3C trace context of a sampled trace.
+pub fn traceparent(context: &TraceContext) -> String {
+    let hex = |bytes: &[u8]| bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+    format!("00-{}-{}-01", hex(&context.trace_id), hex(&context.span_id))
+}
+
+/// Ships finished spans to the OTLP collector at `endpoint` in batches of up
+/// to `batch_size`, as spans of `service`. Batches that cannot be delivered
+/// within `deadline` are dropped.
+pub async fn export(endpoint: String, service: String, batch_size: usize, deadline: Duration, mut finished: mpsc::UnboundedReceiver<otlp::Span>) {
+    let mut client = None;
+    let mut batch = Vec::new();
+    let mut open = true;
+    while open {
+        let full = match tokio::time::timeout(EXPORT_INTERVAL, finished.recv()).await {
+            Ok(Some(span)) => {
+                batch.push(span);
+                batch.len() >= batch_size
+            },
+            Ok(None) => {
+                open = false;
+                true
+            },
+            Err(_) => true,
+        };
+        if !full || batch.is_empty() {
+            continue
+        }
+
+        let spans = std::mem::take(&mut batch);
+        let count = spans.len();
+        let request = ExportTraceServiceRequest {
+            resource_spans: vec![ResourceSpans {
+                resource: Some(Resource {
+                    attributes: vec![KeyValue {
+                        key: "service.name".to_string(),
+                        value: Some(AnyValue { value: Some(any_value::Value::StringValue(service.clone())) }),
+                    }],
+                }),
+                scope_spans: vec![ScopeSpans {
+                    scope: Some(InstrumentationScope { name: "grpc-le".to_string(), version: env!("CARGO_PKG_VERSION").to_string() }),
+                    spans,
+                }],
+            }],
+        };
+        let connected = match &mut client {
+            Some(connected) => connected,
+            None => match TraceServiceClient::connect(endpoint.clone()).await {
+                Ok(connected) => client.insert(connected),
+                Err(e) => {
+                    warn!("dropping {} spans, cannot reach the OTLP collector at {}: {}", count, endpoint, e);
+                    continue
+                },
+            },
+        };
+        if let Err(e) = connected.export(crate::deadline(request, deadline)).await {
+            warn!("dropping {} spans, the OTLP collector at {} rejected them: {}", count, endpoint, e);
+        }
+    }
+}
+
+/// Scrambles a counter into an ID that looks random.
+pub(crate) fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
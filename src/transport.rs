@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use std::sync::{Arc, Mutex};
+
+use futures::{future, Stream, StreamExt};
+use tokio::sync::{mpsc, Notify};
+use tokio::time::Instant;
+use tokio_stream::wrappers::ReceiverStream;
+use tower::ServiceBuilder;
+use tracing::warn;
+
+use crate::auth::SigningLayer;
+use crate::compression::compressed;
+use crate::config::Compression;
+use crate::leader_election_service::leader_election_service_client::LeaderElectionServiceClient;
+use crate::leader_election_service::{PeerAck, PeerMessage};
+use crate::metrics::{MetricsLayer, RpcMetrics, Side};
+use crate::request_log::RequestLogLayer;
+use crate::{Client, Node, RELAY_BUFFER};
+pub use crate::outbound::Peer;
+
+/// The acknowledgements a neighbour sends back on a relay stream, one per
+/// message it processed.
+pub type Acks = Pin<Box<dyn Stream<Item = PeerAck> + Send>>;
+
+/// Opening a relay stream, resolving to its acknowledgements.
+pub type Opening<'a> = Pin<Box<dyn Future<Output = Result<Acks, Box<dyn Error + Send + Sync>>> + Send + 'a>>;
+
+/// How a node's messages reach its neighbours. Probes, notifications and
+/// digests alike go to a neighbour in order over a single relay stream, and
+/// the election only ever queues them, so another backend, e.g. over QUIC or
+/// raw TCP, only has to carry these streams.
+pub trait Transport: Debug + Send + Sync {
+    /// Opens a relay stream to `peer` carrying `messages`. The stream breaks
+    /// when the acknowledgements end.
+    fn relay<'a>(&'a self, peer: &'a Peer, messages: mpsc::Receiver<PeerMessage>) -> Opening<'a>;
+}
+
+/// Relays messages over the `Relay` RPC of the neighbours' gRPC services,
+/// keeping a connection to each until a stream over it fails.
+#[derive(Debug)]
+pub struct GrpcTransport {
+    node: u64,
+    request_ids: Arc<AtomicU64>,
+    rpc_metrics: Arc<RpcMetrics>,
+    compression: Option<Compression>,
+    clients: Mutex<BTreeMap<String, Client>>,
+}
+
+impl GrpcTransport {
+    pub(crate) fn new(node: u64, request_ids: Arc<AtomicU64>, rpc_metrics: Arc<RpcMetrics>, compression: Option<Compression>) -> Self {
+        GrpcTransport { node, request_ids, rpc_metrics, compression, clients: Mutex::default() }
+    }
+}
+
+impl Transport for GrpcTransport {
+    fn relay<'a>(&'a self, peer: &'a Peer, messages: mpsc::Receiver<PeerMessage>) -> Opening<'a> {
+        Box::pin(async move {
+            let uri = peer.endpoint.uri().to_string();
+            let cached = self.clients.lock().unwrap().get(&uri).cloned();
+            let mut client = match cached {
+                Some(client) => client,
+                None => {
+                    let channel = peer.endpoint.connect().await?;
+                    let layers = ServiceBuilder::new()
+                        .layer(RequestLogLayer::new(self.node, Side::Client, self.request_ids.clone(), Some(&uri)))
+                        .layer(MetricsLayer::new(self.rpc_metrics.clone(), Side::Client))
+                        // relayed messages carry signatures of their own
+                        .layer(SigningLayer::new(None));
+                    let client = compressed!(LeaderElectionServiceClient::new(layers.service(channel)), self.compression);
+                    self.clients.lock().unwrap().insert(uri.clone(), client.clone());
+                    client
+                },
+            };
+            // the request log layer reports the outcome
+            match client.relay(ReceiverStream::new(messages)).await {
+                Ok(response) => Ok(Box::pin(response.into_inner()
+                    .take_while(|ack| future::ready(ack.is_ok()))
+                    .filter_map(|ack| future::ready(ack.ok()))) as Acks),
+                Err(status) => {
+                    // reconnect next time, to whichever neighbour it is by then
+                    self.clients.lock().unwrap().remove(&uri);
+                    Err(status.into())
+                },
+            }
+        })
+    }
+}
+
+/// Relays messages between nodes of the same process by handing them to the
+/// receiving [`Node`] directly, for tests. Each node has to be added to the
+/// network to be reachable.
+#[derive(Debug, Default)]
+pub struct MemoryTransport {
+    nodes: Mutex<BTreeMap<u64, Node>>,
+}
+
+impl MemoryTransport {
+    /// Makes `node` reachable by its ID.
+    pub fn add(&self, node: Node) {
+        self.nodes.lock().unwrap().insert(node.id(), node);
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn relay<'a>(&'a self, peer: &'a Peer, mut messages: mpsc::Receiver<PeerMessage>) -> Opening<'a> {
+        Box::pin(async move {
+            let node = self.nodes.lock().unwrap().get(&peer.id).cloned()
+                .ok_or_else(|| format!("node {} is not on the network", peer.id))?;
+            let (acks, rx) = mpsc::channel(RELAY_BUFFER);
+            tokio::spawn(async move {
+                while let Some(message) = messages.recv().await {
+                    let ack = match node.receive(message).await {
+                        Ok(ack) => ack,
+                        Err(e) => {
+                            warn!(node = node.id(), "rejected a relayed message: {}", e);
+                            return
+                        },
+                    };
+                    if acks.send(ack).await.is_err() {
+                        return
+                    }
+                }
+            });
+            Ok(Box::pin(ReceiverStream::new(rx)) as Acks)
+        })
+    }
+}
+
+/// Holds back the messages of the nodes relaying through it while paused,
+/// letting them through one at a time on each step, so that an election can
+/// be followed message by message.
+#[derive(Debug, Default)]
+pub struct Gate {
+    /// How many more messages may pass while paused, none if not paused.
+    credit: Mutex<Option<u64>>,
+    opened: Notify,
+}
+
+impl Gate {
+    /// Holds the next messages back until a step or resuming.
+    pub fn pause(&self) {
+        self.credit.lock().unwrap().get_or_insert(0);
+    }
+
+    /// Lets one more message through, if paused.
+    pub fn step(&self) {
+        if let Some(credit) = self.credit.lock().unwrap().as_mut() {
+            *credit += 1;
+        }
+        self.opened.notify_waiters();
+    }
+
+    /// Lets all messages through again.
+    pub fn resume(&self) {
+        self.credit.lock().unwrap().take();
+        self.opened.notify_waiters();
+    }
+
+    pub fn paused(&self) -> bool {
+        self.credit.lock().unwrap().is_some()
+    }
+
+    /// Waits for a message to be let through.
+    async fn pass(&self) {
+        loop {
+            let opened = self.opened.notified();
+            match &mut *self.credit.lock().unwrap() {
+                None => return,
+                Some(credit) if *credit > 0 => {
+                    *credit -= 1;
+                    return
+                },
+                Some(_) => (),
+            }
+            opened.await;
+        }
+    }
+}
+
+/// Relays messages over another transport once a [`Gate`] lets them through.
+#[derive(Debug)]
+pub struct GatedTransport {
+    inner: Arc<dyn Transport>,
+    gate: Arc<Gate>,
+}
+
+impl GatedTransport {
+    pub fn new(inner: Arc<dyn Transport>, gate: Arc<Gate>) -> Self {
+        GatedTransport { inner, gate }
+    }
+}
+
+impl Transport for GatedTransport {
+    fn relay<'a>(&'a self, peer: &'a Peer, mut messages: mpsc::Receiver<PeerMessage>) -> Opening<'a> {
+        let (passed, rx) = mpsc::channel(RELAY_BUFFER);
+        let gate = self.gate.clone();
+        tokio::spawn(async move {
+            while let Some(message) = messages.recv().await {
+                gate.pass().await;
+                if passed.send(message).await.is_err() {
+                    return
+                }
+            }
+        });
+        self.inner.relay(peer, rx)
+    }
+}
+
+/// How an [`ImpairedTransport`] degrades the links between the nodes, as a
+/// slow or lossy network would.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Impairment {
+    /// How long each message takes to reach the neighbour.
+    pub latency: Duration,
+    /// How much more or less than the latency a message may take, at most.
+    pub jitter: Duration,
+    /// The probability that a message never arrives, until it is sent
+    /// again over a new stream.
+    pub loss: f64,
+}
+
+/// Relays messages over another transport late, or not at all, as an
+/// [`Impairment`] says. Late messages hold up those sent after them, so the
+/// order on each link is kept.
+#[derive(Debug)]
+pub struct ImpairedTransport {
+    inner: Arc<dyn Transport>,
+    impairment: Impairment,
+    /// State of the splitmix64 generator the delays and losses are drawn from.
+    rng: Arc<AtomicU64>,
+}
+
+impl ImpairedTransport {
+    pub fn new(inner: Arc<dyn Transport>, impairment: Impairment, seed: u64) -> Self {
+        ImpairedTransport { inner, impairment, rng: Arc::new(AtomicU64::new(seed)) }
+    }
+}
+
+/// A random number in `[0, 1)`, the next of the splitmix64 generator in `rng`.
+pub(crate) fn fraction(rng: &AtomicU64) -> f64 {
+    let mut z = rng.fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    ((z ^ (z >> 31)) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+impl Transport for ImpairedTransport {
+    fn relay<'a>(&'a self, peer: &'a Peer, mut messages: mpsc::Receiver<PeerMessage>) -> Opening<'a> {
+        let (passed, rx) = mpsc::channel(RELAY_BUFFER);
+        let (Impairment { latency, jitter, loss }, rng) = (self.impairment, self.rng.clone());
+        tokio::spawn(async move {
+            let mut last = Instant::now();
+            while let Some(message) = messages.recv().await {
+                if fraction(&rng) < loss {
+                    continue
+                }
+                let delay = latency.saturating_sub(jitter) + (2 * jitter).mul_f64(fraction(&rng));
+                last = last.max(Instant::now() + delay);
+                tokio::time::sleep_until(last).await;
+                if passed.send(message).await.is_err() {
+                    return
+                }
+            }
+        });
+        self.inner.relay(peer, rx)
+    }
+}
@@ -0,0 +1,29 @@
+use crate::leader_election_service::ProbeMessage;
+
+/// Every phase eliminates at least half of the remaining candidates, so even
+/// a ring with 2^64 nodes settles well before this many phases.
+pub const MAX_PHASE: u64 = 2 * u64::BITS as u64;
+
+/// Checks an inbound probe before it is allowed anywhere near the node state.
+/// The error is a human-readable reason, meant for `Status::invalid_argument`.
+pub fn probe(msg: &ProbeMessage) -> Result<(), String> {
+    if msg.phase == 0 {
+        return Err("probe phase must be at least 1".into());
+    }
+    if msg.phase > MAX_PHASE {
+        return Err(format!("probe phase {} exceeds the maximum of {}", msg.phase, MAX_PHASE));
+    }
+    // zones are single words, as --zone has them, and logged as such
+    if msg.zone.contains(char::is_whitespace) {
+        return Err(format!("probe zone {:?} is not a single word", msg.zone));
+    }
+    Ok(())
+}
+
+/// Upper bound on the phase a probe can carry in a ring of `ring_size` nodes.
+/// At least half of the candidates drop out in every phase, so a ring settles
+/// within about `log2(ring_size)` phases; the bound leaves a 2x margin.
+pub fn max_phase_for(ring_size: u64) -> u64 {
+    let log2 = u64::BITS - ring_size.max(1).saturating_sub(1).leading_zeros();
+    (2 * (log2 as u64 + 1)).min(MAX_PHASE)
+}
@@ -0,0 +1,89 @@
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::{json, Value};
+use tonic::{Code, Status};
+
+use crate::leader_election_service::leader_election_service_server::LeaderElectionService;
+use crate::leader_election_service::{peer_message, LeaderRequest, PeerMessage, ProbeMessage};
+use crate::Node;
+
+/// Answers the requests of the JSON gateway: `GET /leader` like the
+/// `GetLeader` RPC, and `POST /probe` with the fields of a probe, e.g.
+/// `{"sender_id": 1, "headed_left": true, "phase": 1}`, by handling the probe
+/// as if a neighbour had relayed it, in the node's current term and without a
+/// priority unless it names them, and answering with the node's decision on it, e.g.
+/// `{"decision": "forwarded"}`. Returns `None` for any other request.
+pub async fn handle(node: &Node, request: Request<Body>) -> Option<http::Result<Response<Body>>> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/leader") => match node.get_leader(tonic::Request::new(LeaderRequest { group_id: node.group(), ..Default::default() })).await {
+            Ok(leader) => {
+                let leader = leader.into_inner();
+                respond(StatusCode::OK, json!({
+                    "leader_id": leader.leader_id,
+                    "leader_known": leader.leader_known,
+                    "leader_addr": leader.leader_addr,
+                    "leader_metadata": String::from_utf8_lossy(&leader.leader_metadata),
+                    "payload": leader.payload.map(|payload| json!({
+                        "content_type": payload.content_type,
+                        "data": String::from_utf8_lossy(&payload.data),
+                    })),
+                    "fencing_token": leader.fencing_token,
+                    "provisional": leader.provisional,
+                }))
+            },
+            Err(status) => failed(status),
+        },
+        (&Method::POST, "/probe") => {
+            let body = hyper::body::to_bytes(request.into_body()).await.ok();
+            match body.and_then(|body| serde_json::from_slice(&body).ok()).as_ref().and_then(|value| probe(value, node.term(), node.group())) {
+                Some(probe) => {
+                    let message = PeerMessage { body: Some(peer_message::Body::Probe(probe)), request_id: String::new(), trace: None, lamport: 0, signature: vec![], group_id: node.group(), role: node.role().unwrap_or_default().to_string(), ..Default::default() };
+                    match node.receive(message).await {
+                        Ok(ack) => respond(StatusCode::OK, json!({ "decision": ack.decision().label() })),
+                        Err(e) => failed(e.into()),
+                    }
+                },
+                None => respond(StatusCode::BAD_REQUEST, json!({
+                    "error": "expected {\"sender_id\": <id>, \"headed_left\": <bool>, \"phase\": <n>}",
+                })),
+            }
+        },
+        _ => return None,
+    };
+    Some(response)
+}
+
+fn probe(value: &Value, term: u64, group: u64) -> Option<ProbeMessage> {
+    Some(ProbeMessage {
+        sender_id: value.get("sender_id")?.as_u64()?,
+        headed_left: value.get("headed_left")?.as_bool()?,
+        phase: value.get("phase")?.as_u64()?,
+        seq: None,
+        term: value.get("term").map_or(Some(term), Value::as_u64)?,
+        priority: value.get("priority").map_or(Some(0), Value::as_u64)?,
+        group_id: group,
+        hops: 0,
+        hops_remaining: 0,
+        zone: value.get("zone").map_or(Some(""), Value::as_str)?.to_string(),
+    })
+}
+
+fn respond(status: StatusCode, body: Value) -> http::Result<Response<Body>> {
+    Response::builder()
+        .status(status)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+}
+
+fn failed(status: Status) -> http::Result<Response<Body>> {
+    let code = match status.code() {
+        Code::InvalidArgument => StatusCode::BAD_REQUEST,
+        Code::FailedPrecondition | Code::Aborted => StatusCode::CONFLICT,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    respond(code, json!({ "error": status.message() }))
+}
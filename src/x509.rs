@@ -0,0 +1,76 @@
+const SEQUENCE: u8 = 0x30;
+const OCTET_STRING: u8 = 0x04;
+const OID: u8 = 0x06;
+/// The optional, explicitly tagged version and extensions of a certificate.
+const VERSION: u8 = 0xa0;
+const EXTENSIONS: u8 = 0xa3;
+/// A name of the subjectAltName extension that is a URI.
+const URI_NAME: u8 = 0x86;
+/// 2.5.29.17, the subjectAltName extension.
+const SUBJECT_ALT_NAME: [u8; 3] = [0x55, 0x1d, 0x11];
+
+/// Splits the DER value at the start of `input` into its tag, its contents
+/// and what follows it, unless it is cut short.
+fn tlv(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = match first {
+        len @ 0..=0x7f => (len as usize, rest),
+        0x81..=0x84 => {
+            let (len, rest) = rest.split_at_checked((first & 0x7f) as usize)?;
+            (len.iter().fold(0, |len, &byte| len << 8 | byte as usize), rest)
+        },
+        _ => return None,
+    };
+    let (contents, rest) = rest.split_at_checked(len)?;
+    Some((tag, contents, rest))
+}
+
+/// The tags and contents of the DER values `input` holds one after another,
+/// up to the first that is cut short.
+fn values(mut input: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    std::iter::from_fn(move || {
+        let (tag, contents, rest) = tlv(input)?;
+        input = rest;
+        Some((tag, contents))
+    })
+}
+
+/// The contents of the one DER value with `tag` that `input` holds.
+fn expect(tag: u8, input: &[u8]) -> Option<&[u8]> {
+    match tlv(input)? {
+        (found, contents, []) if found == tag => Some(contents),
+        _ => None,
+    }
+}
+
+/// The fields of the to-be-signed part of the DER certificate `der`, from
+/// its serial number on.
+fn fields(der: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let (tag, certificate, _) = tlv(der)?;
+    let (_, tbs) = values(certificate).next().filter(|&(tbs_tag, _)| tag == SEQUENCE && tbs_tag == SEQUENCE)?;
+    let mut fields = values(tbs).collect::<Vec<_>>();
+    if fields.first().is_some_and(|&(tag, _)| tag == VERSION) {
+        fields.remove(0);
+    }
+    Some(fields)
+}
+
+/// The URIs among the subject alternative names of the DER certificate
+/// `der`, none if it cannot be read.
+pub fn uris(der: &[u8]) -> Vec<String> {
+    let extensions = fields(der).into_iter().flatten()
+        .find(|&(tag, _)| tag == EXTENSIONS)
+        .and_then(|(_, extensions)| expect(SEQUENCE, extensions));
+    let names = values(extensions.unwrap_or_default()).find_map(|(_, extension)| {
+        let mut parts = values(extension);
+        let (_, oid) = parts.next().filter(|&(tag, _)| tag == OID)?;
+        // the criticality in between is optional
+        let (_, value) = parts.find(|&(tag, _)| tag == OCTET_STRING).filter(|_| oid == SUBJECT_ALT_NAME)?;
+        expect(SEQUENCE, value)
+    });
+    values(names.unwrap_or_default())
+        .filter(|&(tag, _)| tag == URI_NAME)
+        .filter_map(|(_, uri)| String::from_utf8(uri.to_vec()).ok())
+        .collect()
+}
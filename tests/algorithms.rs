@@ -0,0 +1,114 @@
+use std::future::ready;
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+use grpc_le::bully::BullyNode;
+use grpc_le::chang_roberts::ChangRobertsNode;
+use grpc_le::config::{Config, TimingConfig};
+use grpc_le::hirschberg_sinclair::HirschbergSinclairNode;
+use grpc_le::testkit::wait_until;
+use grpc_le::topology::{Member, Topology};
+use grpc_le::{ElectionAlgorithm, ElectionResult, Node};
+
+/// The ring every algorithm elects a leader of, in an order the IDs neither
+/// rise nor fall in.
+const IDS: [u16; 8] = [5, 3, 8, 1, 7, 2, 6, 4];
+
+/// A ring of `ids` on ports nothing listens on.
+fn topology(ids: &[u16]) -> Topology {
+    let listeners = ids.iter().map(|_| TcpListener::bind("[::1]:0").unwrap()).collect::<Vec<_>>();
+    let members = ids.iter().zip(&listeners)
+        .map(|(&id, listener)| Member { id, addr: listener.local_addr().unwrap(), host: None, priority: 0 })
+        .collect();
+    Topology { members, edges: None }
+}
+
+/// Runs `nodes` until each knows the leader, and returns the leader each
+/// one knows.
+async fn elect<A: ElectionAlgorithm>(nodes: &[(A, SocketAddr)]) -> Vec<u64> {
+    for (node, addr) in nodes {
+        let (node, addr) = (node.clone(), *addr);
+        tokio::spawn(async move { node.run(addr, &Config::default()).await });
+    }
+    let elected = tokio::time::timeout(Duration::from_secs(10), async {
+        while nodes.iter().any(|(node, _)| *node.subscribe().borrow() == ElectionResult::Undecided) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await;
+    assert!(elected.is_ok(), "{:?}", nodes.iter().map(|(node, _)| *node.subscribe().borrow()).collect::<Vec<_>>());
+    // the last announcements may still be on their way around the ring
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let leaders = nodes.iter().map(|(node, _)| match *node.subscribe().borrow() {
+        ElectionResult::Leader => node.id(),
+        ElectionResult::Defeated { leader } => leader,
+        ref result => panic!("node {} ended in {:?}", node.id(), result),
+    }).collect();
+    nodes.iter().for_each(|(node, _)| node.shutdown());
+    leaders
+}
+
+#[tokio::test]
+async fn every_algorithm_elects_the_same_leader() {
+    let config = Config::default();
+    let ring = topology(&IDS);
+    let specs = ring.nodes();
+    let ring_nodes = specs.iter().map(|spec| (Node::new(spec, specs.len() as u64, &config, None).unwrap(), spec.listen)).collect::<Vec<_>>();
+    let expected = elect(&ring_nodes).await[0];
+    assert_eq!(expected, 1);
+
+    let ring = topology(&IDS);
+    let specs = ring.nodes();
+    let chang_roberts = specs.iter().map(|spec| (ChangRobertsNode::new(spec, &config).unwrap(), spec.listen)).collect::<Vec<_>>();
+    assert_eq!(elect(&chang_roberts).await, [expected; IDS.len()], "Chang-Roberts");
+
+    let ring = topology(&IDS);
+    let bully = ring.members.iter().map(|member| (BullyNode::new(member.id, &ring.members, &config).unwrap(), member.addr)).collect::<Vec<_>>();
+    assert_eq!(elect(&bully).await, [expected; IDS.len()], "bully");
+
+    let ring = topology(&IDS);
+    let specs = ring.nodes();
+    let hirschberg_sinclair = specs.iter().map(|spec| (HirschbergSinclairNode::new(spec, &config).unwrap(), spec.listen)).collect::<Vec<_>>();
+    assert_eq!(elect(&hirschberg_sinclair).await, [expected; IDS.len()], "Hirschberg-Sinclair");
+}
+
+#[tokio::test]
+async fn hirschberg_sinclair_takes_o_n_log_n_messages() {
+    let ids = (1..=16).rev().collect::<Vec<u16>>();
+    let ring = topology(&ids);
+    let specs = ring.nodes();
+    let nodes = specs.iter().map(|spec| (HirschbergSinclairNode::new(spec, &Config::default()).unwrap(), spec.listen)).collect::<Vec<_>>();
+    assert_eq!(elect(&nodes).await, [1; 16]);
+
+    // at most n / (2^(k-1) + 1) candidates in phase k, each sending 4 * 2^k
+    // probes and replies, over log n + 1 phases, plus the n announcements
+    let n = ids.len() as u64;
+    let bound = 8 * n * (n.ilog2() as u64 + 1) + n;
+    let sent = nodes.iter().map(|(node, _)| node.messages_sent()).sum::<u64>();
+    assert!(sent <= bound, "{} messages for {} nodes, more than {}", sent, n, bound);
+}
+
+#[tokio::test]
+async fn a_better_node_starting_late_bullies_its_way_to_the_lead() {
+    let config = Config { timing: TimingConfig { startup_grace: Duration::from_millis(100), ..TimingConfig::default() }, ..Config::default() };
+    let cluster = topology(&IDS);
+    let nodes = cluster.members.iter().map(|member| (BullyNode::new(member.id, &cluster.members, &config).unwrap(), member.addr)).collect::<Vec<_>>();
+    let run = |(node, addr): &(BullyNode, SocketAddr)| {
+        let (node, addr, config) = (node.clone(), *addr, config.clone());
+        tokio::spawn(async move { node.run(addr, &config).await });
+    };
+    let leader_of = |node: &BullyNode| match *node.subscribe().borrow() {
+        ElectionResult::Leader => Some(node.id()),
+        ElectionResult::Defeated { leader } => Some(leader),
+        _ => None,
+    };
+    let (one, rest): (Vec<_>, Vec<_>) = nodes.iter().partition(|(node, _)| node.id() == 1);
+
+    // node 2 is the best of the nodes running
+    rest.iter().for_each(|node| run(node));
+    assert!(wait_until(Duration::from_secs(10), || ready(rest.iter().all(|(node, _)| leader_of(node) == Some(2)))).await);
+
+    run(one[0]);
+    let took_over = wait_until(Duration::from_secs(10), || ready(nodes.iter().all(|(node, _)| leader_of(node) == Some(1))));
+    assert!(took_over.await, "{:?}", nodes.iter().map(|(node, _)| *node.subscribe().borrow()).collect::<Vec<_>>());
+    nodes.iter().for_each(|(node, _)| node.shutdown());
+}
@@ -0,0 +1,756 @@
+use std::future::ready;
+use std::sync::Arc;
+use std::time::Duration;
+
+use grpc_le::auth::{Auth, SigningLayer};
+use grpc_le::config::{AuditTarget, Compression, Config, Middleware, Reload, TimingConfig};
+use grpc_le::spiffe;
+use grpc_le::testkit::{free_addrs, wait_until, TestRing};
+use grpc_le::topology::grpc_url;
+use grpc_le::leader_election_service::admin_service_client::AdminServiceClient;
+use grpc_le::leader_election_service::admin_service_server::AdminService;
+use grpc_le::leader_election_service::leader_election_service_server::LeaderElectionService;
+use grpc_le::leader_election_service::leader_election_service_client::LeaderElectionServiceClient;
+use grpc_le::leader_election_service::state_response::Kind;
+use grpc_le::leader_election_service::{AuditLogRequest, DumpStateRequest, ElectionHistoryRequest, ForceStateRequest, LeaderRequest, LeaveRequest, MetricsRequest, Neighbor, PauseRequest, ReconfigureRequest, StateRequest, StatsRequest, StepDownRequest, TransferLeadershipRequest, TriggerReelectionRequest, UpdateConfigRequest};
+use grpc_le::builder::{NodeBuilder, NodeHandle};
+use grpc_le::{ElectionResult, Node};
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity};
+use tonic::{Code, Request, Status};
+use tower::ServiceBuilder;
+
+/// Nodes 1 and 2 of a ring of two, each as `build` makes it from a builder
+/// with its ID, neighbour and free address, once node 1 leads.
+async fn elected_pair(build: impl Fn(u16, NodeBuilder) -> NodeBuilder) -> [NodeHandle; 2] {
+    let [one, two] = free_addrs();
+    let node = |id, listen: &str, other: &str| build(id, Node::builder().id(id).listen(listen).left(3 - id, other).right(3 - id, other)).build().unwrap();
+    let nodes = [node(1, &one, &two), node(2, &two, &one)];
+    let elected = tokio::time::timeout(Duration::from_secs(5), nodes[0].node().await_ring_acknowledged()).await;
+    assert_eq!(elected, Ok(1));
+    nodes
+}
+
+/// A ring of nodes 1 to `size`, each with `config`, once node 1 leads it.
+async fn ring(size: u16, config: Config) -> TestRing {
+    let ring = TestRing::start(size, config);
+    assert_eq!(ring.await_leader(Duration::from_secs(10)).await, Some(1));
+    ring
+}
+
+/// Whether `node` comes to know `result` of the election within five
+/// seconds.
+async fn comes_to(node: &Node, result: ElectionResult) -> bool {
+    wait_until(Duration::from_secs(5), || ready(*node.subscribe().borrow() == result)).await
+}
+
+/// Whether `node` leads if it is `leader`, or follows it if not.
+fn follows(node: &Node, leader: u64) -> bool {
+    *node.subscribe().borrow() == match node.id() {
+        id if id == leader => ElectionResult::Leader,
+        _ => ElectionResult::Defeated { leader },
+    }
+}
+
+/// How many nodes `node` counts in its ring.
+async fn ring_size(node: &Node) -> u64 {
+    node.get_state(Request::new(StateRequest::default())).await.unwrap().into_inner().ring_size
+}
+
+/// Whether every node of `ring` that runs counts `size` nodes in it.
+async fn sized(ring: &TestRing, size: u64) -> bool {
+    for node in ring.running() {
+        if ring_size(node).await != size {
+            return false
+        }
+    }
+    true
+}
+
+/// Why building node 1 of a ring of three fails with `left` for its left
+/// neighbour's URL.
+async fn error_with_left(left: &str) -> String {
+    Node::builder().id(1).left(3, left).right(2, "[::1]:40002").ring_size(3).build().unwrap_err()
+}
+
+#[tokio::test]
+async fn the_builder_rejects_invalid_neighbour_urls() {
+    assert!(error_with_left("ftp://[::1]:40003").await.contains("expected http:// or https://"));
+    assert!(error_with_left("http://[::1:40003").await.starts_with("invalid left neighbour URL"));
+}
+
+#[test]
+fn neighbour_addresses_become_grpc_urls() {
+    for (addr, url) in [("le-3:40003", "http://le-3:40003"), ("127.0.0.1:40003", "http://127.0.0.1:40003"), ("[::1]:40003", "http://[::1]:40003"),
+        ("https://le-3.example/", "https://le-3.example"), ("http://[fe80::1]:40003", "http://[fe80::1]:40003")] {
+        assert_eq!(grpc_url(addr).as_deref(), Ok(url));
+    }
+    for (addr, error) in [("::1:40003", "brackets"), ("le-3", "missing port"), ("127.0.0.300:40003", "invalid IPv4"), ("[::g]:40003", "invalid IPv6"),
+        ("le-3:0", "invalid port"), ("le-3:40003/ring", "nothing after the port"), ("le 3:40003", "invalid host"), (":40003", "missing host")] {
+        assert!(grpc_url(addr).unwrap_err().contains(error), "{:?}", addr);
+    }
+}
+
+#[tokio::test]
+async fn the_builder_asks_for_what_it_cannot_guess() {
+    assert_eq!(Node::builder().left(3, "[::1]:40003").build().unwrap_err(), "missing id");
+    assert_eq!(Node::builder().id(1).left(3, "[::1]:40003").build().unwrap_err(), "missing right neighbour");
+    let different = Node::builder().id(1).left(3, "[::1]:40003").right(2, "[::1]:40002");
+    assert_eq!(different.build().unwrap_err(), "missing ring size");
+    let alone = Node::builder().id(1).left(2, "[::1]:40002").right(2, "[::1]:40002").ring_size(1);
+    assert_eq!(alone.build().unwrap_err(), "a ring of 1 nodes has no neighbours");
+}
+
+#[tokio::test]
+async fn a_built_node_serves_until_shut_down() {
+    let handle = Node::builder().id(1).listen("[::1]:0").left(2, "[::1]:1").right(2, "[::1]:1").build().unwrap();
+    assert_eq!(handle.node().id(), 1);
+    handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn a_reloaded_node_reelects_only_for_new_neighbours() {
+    let handle = Node::builder().id(1).listen("[::1]:0").left(2, "[::1]:1").right(2, "[::1]:1").build().unwrap();
+    let reload = |settings: &[(&str, &str)]| {
+        Reload::parse(TimingConfig::default(), settings.iter().map(|&(name, value)| (name.to_string(), value.to_string()))).unwrap()
+    };
+    assert!(!handle.node().reload(reload(&[("poll-interval-ms", "50"), ("left", "2=[::1]:1")])).unwrap());
+    assert!(handle.node().reload(reload(&[("right", "3=[::1]:2")])).unwrap());
+    assert!(Reload::parse(TimingConfig::default(), [("ring-size".to_string(), "3".to_string())]).is_err());
+    handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn a_reload_that_cannot_be_taken_on_changes_nothing() {
+    let handle = Node::builder().id(1).listen("[::1]:0").left(2, "[::1]:1").right(2, "[::1]:1").build().unwrap();
+    let reload = |settings: &[(&str, &str)]| {
+        Reload::parse(TimingConfig::default(), settings.iter().map(|&(name, value)| (name.to_string(), value.to_string()))).unwrap()
+    };
+    let term = handle.node().term();
+    // a node built without a log filter cannot change the log level
+    let error = handle.node().reload(reload(&[("right", "3=[::1]:2"), ("log-level", "debug")])).unwrap_err();
+    assert_eq!(error.to_string(), "node 1 cannot reload its configuration: the node cannot change the log level");
+    assert_eq!(Status::from(error).code(), Code::InvalidArgument);
+    assert_eq!(handle.node().term(), term);
+    // node 3 is still new to it
+    assert!(handle.node().reload(reload(&[("right", "3=[::1]:2")])).unwrap());
+    handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn a_node_waits_for_a_neighbour_starting_late() {
+    let config = Config { await_neighbours: Some(Duration::from_secs(5)), ..Config::default() };
+    let [one_addr, two_addr] = free_addrs();
+    let one = Node::builder().id(1).listen(&one_addr).left(2, &two_addr).right(2, &two_addr).config(config.clone()).build().unwrap();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let two = Node::builder().id(2).listen(&two_addr).left(1, &one_addr).right(1, &one_addr).config(config).build().unwrap();
+    let elected = tokio::time::timeout(Duration::from_secs(5), one.node().await_ring_acknowledged()).await;
+    assert_eq!(elected, Ok(1));
+    two.shutdown().await.unwrap();
+    one.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn a_node_gives_up_on_an_election_past_its_deadline() {
+    let config = Config { election_deadline: Some(Duration::from_millis(500)), ..Config::default() };
+    let [one, two, three] = free_addrs();
+    let one = Node::builder().id(1).listen(one).left(3, three).right(2, two).ring_size(3).config(config).build().unwrap();
+    assert!(comes_to(one.node(), ElectionResult::TimedOut).await);
+    let timeout = one.node().election_timeout().unwrap();
+    assert_eq!((timeout.deadline_ms, timeout.phase), (500, 1));
+    assert_eq!(timeout.neighbors.iter().map(|neighbor| (neighbor.id, neighbor.received_known)).collect::<Vec<_>>(), [(3, false), (2, false)]);
+    one.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn a_new_leader_gets_a_larger_fencing_token() {
+    let [one, two] = elected_pair(|_, node| node).await;
+    let first = one.node().fencing_token().await;
+    assert!(first.is_some());
+    assert_eq!(two.node().fencing_token().await, first);
+
+    // node 2 takes over, node 1 sitting the election out
+    assert!(one.node().step_down().await);
+    assert!(comes_to(one.node(), ElectionResult::Defeated { leader: 2 }).await);
+    let second = one.node().fencing_token().await;
+    assert!(second > first, "{:?} after {:?}", second, first);
+    assert_eq!(two.node().fencing_token().await, second);
+    two.shutdown().await.unwrap();
+    one.shutdown().await.unwrap();
+}
+
+/// Whether `node` holds the lease on its leadership.
+async fn leased(node: &Node) -> bool {
+    let state = node.get_state(Request::new(StateRequest::default())).await.unwrap().into_inner();
+    state.timers.iter().any(|timer| timer.name == "Lease")
+}
+
+#[tokio::test]
+async fn only_the_leader_holds_the_lease_and_only_while_the_ring_confirms_it() {
+    let config = Config { lease: Some(Duration::from_millis(600)), ..Config::default() };
+    let [one, two] = elected_pair(|_, node| node.config(config.clone())).await;
+    assert!(leased(one.node()).await);
+    assert!(!leased(two.node()).await);
+    // the digests coming back around renew it
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert!(follows(one.node(), 1));
+    assert!(leased(one.node()).await);
+
+    // given up by stepping down, and taken by the new leader
+    assert!(one.node().step_down().await);
+    assert!(comes_to(one.node(), ElectionResult::Defeated { leader: 2 }).await);
+    assert!(comes_to(two.node(), ElectionResult::Leader).await);
+    assert!(!leased(one.node()).await);
+    assert!(wait_until(Duration::from_secs(5), || leased(two.node())).await);
+
+    // runs out without node 1 passing the digests around
+    one.shutdown().await.unwrap();
+    assert!(wait_until(Duration::from_secs(5), || async { !follows(two.node(), 2) }).await);
+    assert!(!leased(two.node()).await);
+    two.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn a_node_counts_what_it_did_in_the_last_minutes() {
+    let [one, two] = elected_pair(|_, node| node).await;
+
+    let stats = one.node().get_stats(Request::new(StatsRequest::default())).await.unwrap().into_inner();
+    assert_eq!(stats.windows.iter().map(|window| window.seconds).collect::<Vec<_>>(), [60, 300, 900]);
+    let minute = &stats.windows[0];
+    assert!(minute.sent.as_ref().unwrap().probes > 0);
+    assert!(minute.received.as_ref().unwrap().probes > 0);
+    assert!(minute.transitions > 0);
+    for window in &stats.windows[1..] {
+        assert_eq!(window.sent, minute.sent);
+        assert_eq!(window.transitions, minute.transitions);
+    }
+    // candidates are counted from phase 1, where node 1 stood itself
+    let history = one.node().get_election_history(Request::new(ElectionHistoryRequest::default())).await.unwrap().into_inner();
+    let election = history.elections.last().unwrap();
+    assert!(election.candidates[0] > 0, "{:?}", election.candidates);
+    two.shutdown().await.unwrap();
+    one.shutdown().await.unwrap();
+}
+
+/// The value of the series `name` with `labels` in the metrics text `text`.
+fn series(text: &str, name: &str, labels: &str) -> Option<f64> {
+    let prefix = format!("{}{{{}}} ", name, labels);
+    text.lines().find_map(|line| line.strip_prefix(&prefix)).map(|value| value.parse().unwrap())
+}
+
+#[tokio::test]
+async fn a_node_measures_the_calls_it_serves() {
+    let [one, two] = elected_pair(|_, node| node).await;
+    let mut client = LeaderElectionServiceClient::connect(format!("http://{}", one.addr())).await.unwrap();
+    assert_eq!(client.get_leader(LeaderRequest::default()).await.unwrap().into_inner().leader_id, 1);
+
+    // the server tallies the bodies once it is done with them
+    let labels = "side=\"server\",method=\"/me.viluon.le.LeaderElectionService/GetLeader\"";
+    let metrics = || {
+        let mut client = client.clone();
+        async move { client.get_metrics(MetricsRequest::default()).await.unwrap().into_inner().text }
+    };
+    assert!(wait_until(Duration::from_secs(5), || async { series(&metrics().await, "grpc_le_rpc_response_bytes_count", labels) == Some(1.0) }).await);
+    let measured = metrics().await;
+    assert_eq!(series(&measured, "grpc_le_rpc_latency_seconds_count", labels), Some(1.0));
+    // an empty message is just its 5 byte frame header
+    assert_eq!(series(&measured, "grpc_le_rpc_request_bytes_sum", labels), Some(5.0));
+    assert!(series(&measured, "grpc_le_rpc_response_bytes_sum", labels) > Some(5.0), "{}", measured);
+    assert_eq!(series(&measured, "grpc_le_rpc_response_bytes_bucket", &format!("{},le=\"+Inf\"", labels)), Some(1.0));
+    two.shutdown().await.unwrap();
+    one.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn a_node_compresses_its_answers_for_the_clients_that_take_them() {
+    let config = Config { compression: Some(Compression::Gzip), ..Config::default() };
+    // the pair only elects if the nodes take each other's gzip
+    let [one, two] = elected_pair(|_, node| node.config(config.clone())).await;
+    let url = format!("http://{}", one.addr());
+    let encoding = |response: &tonic::Response<_>| response.metadata().get("grpc-encoding").map(|encoding| encoding.to_str().unwrap().to_string());
+
+    let mut gzip = LeaderElectionServiceClient::connect(url.clone()).await.unwrap().send_gzip().accept_gzip();
+    let answer = gzip.get_leader(LeaderRequest::default()).await.unwrap();
+    assert_eq!((encoding(&answer).as_deref(), answer.into_inner().leader_id), (Some("gzip"), 1));
+    let mut plain = LeaderElectionServiceClient::connect(url).await.unwrap();
+    let answer = plain.get_leader(LeaderRequest::default()).await.unwrap();
+    assert_eq!((encoding(&answer), answer.into_inner().leader_id), (None, 1));
+    two.shutdown().await.unwrap();
+    one.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn a_node_sheds_the_calls_beyond_its_concurrency_limit() {
+    let middleware = Middleware { concurrency_limit: Some(8), timeout: Some(Duration::from_secs(5)), load_shed: true, ..Middleware::default() };
+    let [one, two] = elected_pair(|id, node| match id {
+        1 => node.middleware(middleware),
+        _ => node,
+    }).await;
+
+    // each open stream holds its place, beside those of node 2
+    let mut client = LeaderElectionServiceClient::connect(format!("http://{}", one.addr())).await.unwrap();
+    let mut watches = vec![];
+    let shed = loop {
+        assert!(watches.len() < 8, "no call was shed");
+        match client.watch_leader(LeaderRequest::default()).await {
+            Ok(watch) => watches.push(watch),
+            Err(status) => break status,
+        }
+    };
+    assert_eq!(shed.code(), Code::Unavailable, "{}", shed);
+
+    drop(watches);
+    let answered = wait_until(Duration::from_secs(5), || {
+        let mut client = client.clone();
+        async move { client.get_leader(LeaderRequest::default()).await.is_ok() }
+    });
+    assert!(answered.await);
+    two.shutdown().await.unwrap();
+    one.shutdown().await.unwrap();
+}
+
+#[test]
+fn only_the_ring_algorithm_takes_an_auth_key() {
+    let args = |algorithm: &str| ["--algorithm", algorithm, "--auth-key", "/etc/grpc-le/key"].map(str::to_string).into_iter();
+    assert!(Config::from_args(args("ring")).is_ok());
+    for algorithm in ["bully", "chang-roberts", "hs"] {
+        assert!(Config::from_args(args(algorithm)).unwrap_err().contains("--auth-key"), "{}", algorithm);
+    }
+}
+
+#[test]
+fn only_the_ring_algorithm_takes_queue_outbox_and_rate_settings() {
+    for (flag, value) in [("queue-capacity", "8"), ("drop-policy", "drop-oldest"), ("outbox-dir", "/var/lib/grpc-le"), ("rate-limit", "10")] {
+        let args = |algorithm: &str| ["--algorithm", algorithm, &format!("--{}", flag), value].map(str::to_string).into_iter();
+        assert!(Config::from_args(args("ring")).is_ok(), "{}", flag);
+        for algorithm in ["bully", "chang-roberts", "hs"] {
+            assert!(Config::from_args(args(algorithm)).unwrap_err().contains(flag), "{} with {}", flag, algorithm);
+        }
+    }
+}
+
+#[test]
+fn only_the_command_line_logs_messages_by_default() {
+    assert!(!Config::default().message_log);
+    assert!(Config::from_args(std::iter::empty()).unwrap().message_log);
+    let quiet = ["--message-log", "false"].map(str::to_string).into_iter();
+    assert!(!Config::from_args(quiet).unwrap().message_log);
+}
+
+#[tokio::test]
+async fn admin_calls_are_audited_with_their_caller_and_outcome() {
+    let dir = std::env::temp_dir().join(format!("grpc-le-admin-audit-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = Config { audit_log: Some(AuditTarget::Dir(dir.clone())), ..Config::default() };
+    let [one, two] = elected_pair(|_, node| node.config(config.clone())).await;
+    let mut client = AdminServiceClient::connect(format!("http://{}", one.addr())).await.unwrap();
+    assert!(client.step_down(StepDownRequest::default()).await.unwrap().into_inner().stepped_down);
+    let refused = client.force_state(ForceStateRequest { kind: Kind::Candidate as i32, phase: 0, ..ForceStateRequest::default() }).await;
+    assert_eq!(refused.unwrap_err().code(), Code::InvalidArgument);
+    assert!(!AdminService::pause(one.node(), Request::new(PauseRequest::default())).await.unwrap().into_inner().was_paused);
+    // asking about the node changes nothing worth auditing
+    client.dump_state(DumpStateRequest::default()).await.unwrap();
+
+    let audit = client.get_audit_log(AuditLogRequest::default()).await.unwrap().into_inner();
+    assert_eq!(audit.admin_calls_total, 3);
+    let calls = audit.admin_calls.iter().map(|call| (call.call.as_str(), call.ok)).collect::<Vec<_>>();
+    assert_eq!(calls, [("StepDown", true), ("ForceState", false), ("Pause", true)]);
+    let [step_down, force_state, pause] = &audit.admin_calls[..] else { unreachable!() };
+    assert!(step_down.caller.starts_with("[::1]:"), "{:?}", step_down);
+    assert_eq!(step_down.outcome, "StepDownResponse { stepped_down: true }");
+    assert!(force_state.arguments.contains("phase: 0") && force_state.outcome.contains("candidates start from phase 1"), "{:?}", force_state);
+    assert_eq!(pause.caller, "");
+    let logged = std::fs::read_to_string(dir.join("1.audit.log")).unwrap();
+    assert!(logged.lines().any(|line| line.contains("StepDown(StepDownRequest { group_id: 0 }) by [::1]:") && line.contains("succeeded")), "{}", logged);
+    assert!(logged.lines().any(|line| line.contains("Pause(") && line.contains("by in-process")), "{}", logged);
+    std::fs::remove_dir_all(dir).unwrap();
+    two.shutdown().await.unwrap();
+    one.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn only_signed_calls_change_the_election() {
+    let key = std::env::temp_dir().join(format!("grpc-le-auth-key-{}", std::process::id()));
+    std::fs::write(&key, "secret\n").unwrap();
+    let config = Config { auth_key: Some(key.clone()), ..Config::default() };
+    let [one, two] = elected_pair(|_, node| node.config(config.clone())).await;
+    let url = format!("http://{}", one.addr());
+
+    let mut unsigned = AdminServiceClient::connect(url.clone()).await.unwrap();
+    let refused = unsigned.step_down(StepDownRequest::default()).await.unwrap_err();
+    assert_eq!(refused.code(), Code::Unauthenticated, "{}", refused);
+    // asking about the election needs no secret
+    let mut client = LeaderElectionServiceClient::connect(url.clone()).await.unwrap();
+    assert_eq!(client.get_leader(LeaderRequest::default()).await.unwrap().into_inner().leader_id, 1);
+
+    let channel = Endpoint::from_shared(url).unwrap().connect().await.unwrap();
+    let signing = SigningLayer::new(Some(Arc::new(Auth::read(&key).unwrap())));
+    let mut signed = AdminServiceClient::new(ServiceBuilder::new().layer(signing).service(channel));
+    assert!(signed.step_down(StepDownRequest::default()).await.unwrap().into_inner().stepped_down);
+    // node 1 signed the restart it passed on to node 2
+    assert!(comes_to(one.node(), ElectionResult::Defeated { leader: 2 }).await);
+    std::fs::remove_file(key).unwrap();
+    two.shutdown().await.unwrap();
+    one.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn the_deputy_takes_over_from_a_silent_leader() {
+    let config = Config { lease: Some(Duration::from_millis(1200)), ..Config::default() };
+    let addrs = free_addrs::<3>();
+    let addr = |id: u16| &addrs[id as usize - 1];
+    let node = |id, left, right| Node::builder().id(id).listen(addr(id)).left(left, addr(left)).right(right, addr(right)).ring_size(3).config(config.clone()).build().unwrap();
+    // node 2 reaches node 3 on its left without going through node 1
+    let (one, two, three) = (node(1, 2, 3), node(2, 3, 1), node(3, 1, 2));
+    let elected = tokio::time::timeout(Duration::from_secs(5), three.node().await_ring_acknowledged()).await;
+    assert_eq!(elected, Ok(1));
+    let term = two.node().term();
+    let mut results = three.node().subscribe();
+    let restarted = tokio::spawn(async move {
+        while *results.borrow_and_update() != (ElectionResult::Defeated { leader: 2 }) {
+            if *results.borrow() == ElectionResult::Undecided {
+                return true
+            }
+            results.changed().await.unwrap();
+        }
+        false
+    });
+
+    one.shutdown().await.unwrap();
+    // node 2 is next in line, and node 3 follows it without an election
+    let followed = tokio::time::timeout(Duration::from_secs(5), restarted).await;
+    assert!(matches!(followed, Ok(Ok(false))), "{:?}", followed);
+    assert_eq!(*two.node().subscribe().borrow(), ElectionResult::Leader);
+    assert_eq!((two.node().term(), three.node().term()), (term + 1, term + 1));
+    three.shutdown().await.unwrap();
+    two.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn a_repair_that_loses_no_leader_keeps_it() {
+    let mut ring = ring(4, Config { liveness_interval: Some(Duration::from_millis(100)), ..Config::default() }).await;
+    let term = ring.node(1).term();
+    let mut results = ring.node(2).subscribe();
+    results.borrow_and_update();
+
+    ring.crash(3).await;
+    assert!(wait_until(Duration::from_secs(5), || sized(&ring, 3)).await);
+    for node in ring.running() {
+        assert_eq!(node.term(), term, "node {}", node.id());
+    }
+    // node 2 never went through an election
+    assert!(tokio::time::timeout(Duration::from_millis(1), results.changed()).await.is_err(), "{:?}", *results.borrow());
+    ring.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_repair_that_loses_the_leader_elects_another() {
+    let mut ring = ring(4, Config { liveness_interval: Some(Duration::from_millis(100)), ..Config::default() }).await;
+    let term = ring.node(4).term();
+
+    // node 4 splices node 2 in and restarts the election around the ring of three
+    ring.crash(1).await;
+    assert_eq!(ring.await_leader(Duration::from_secs(5)).await, Some(2));
+    for node in ring.running() {
+        assert_eq!(ring_size(node).await, 3, "node {}", node.id());
+        assert!(node.term() > term, "node {}", node.id());
+    }
+    ring.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_node_throttles_the_restarts_it_is_asked_for() {
+    let config = Config { restart_limit: 3, restart_limit_per_caller: 2, ..Config::default() };
+    let [addr] = free_addrs();
+    let handle = Node::builder().id(1).listen(&addr).left(2, "[::1]:1").right(2, "[::1]:1").config(config).build().unwrap();
+    let connect = || AdminServiceClient::connect(format!("http://{}", addr));
+    assert!(wait_until(Duration::from_secs(5), || async { connect().await.is_ok() }).await);
+    let mut client = connect().await.unwrap();
+    for _ in 0..2 {
+        assert!(client.trigger_reelection(TriggerReelectionRequest::default()).await.is_ok());
+    }
+    let refused = client.trigger_reelection(TriggerReelectionRequest::default()).await.unwrap_err();
+    assert_eq!(refused.code(), Code::ResourceExhausted, "{}", refused);
+    // a caller without an address only counts towards the limit of all callers
+    let node = handle.node();
+    assert!(AdminService::trigger_reelection(node, Request::new(TriggerReelectionRequest::default())).await.is_ok());
+    let refused = AdminService::trigger_reelection(node, Request::new(TriggerReelectionRequest::default())).await.unwrap_err();
+    assert_eq!(refused.code(), Code::ResourceExhausted, "{}", refused);
+    handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn a_node_presents_renewed_certificates_without_an_election() {
+    let certs = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/certs");
+    let read = |name: &str| std::fs::read(certs.join(name)).unwrap();
+    let dir = std::env::temp_dir().join(format!("grpc-le-tls-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // node 1's certificate is renewed by a CA its peers trust as well
+    let issue = |node: &str, cert: &str| {
+        std::fs::write(dir.join(format!("{}.pem", node)), read(&format!("{}.pem", cert))).unwrap();
+        std::fs::write(dir.join(format!("{}.key", node)), read(&format!("{}.key", cert))).unwrap();
+    };
+    issue("one", "node1");
+    issue("two", "node1");
+    std::fs::write(dir.join("ca.pem"), [read("ca1.pem"), read("ca2.pem")].concat()).unwrap();
+    let config = |node: &str| Config {
+        tls_cert: Some(dir.join(format!("{}.pem", node))), tls_key: Some(dir.join(format!("{}.key", node))), tls_ca: Some(dir.join("ca.pem")),
+        tls_domain: Some("localhost".to_string()), ..Config::default()
+    };
+    let [one, two] = elected_pair(|id, node| node.config(config(["one", "two"][id as usize - 1]))).await;
+    let url = format!("https://{}", one.addr());
+    let term = one.node().term();
+
+    // only trusts what the second CA signed
+    let client = || async {
+        let tls = ClientTlsConfig::new().domain_name("localhost").ca_certificate(Certificate::from_pem(read("ca2.pem")))
+            .identity(Identity::from_pem(read("node2.pem"), read("node2.key")));
+        let channel = Endpoint::from_shared(url.clone()).unwrap().tls_config(tls).unwrap().connect().await?;
+        Ok::<_, tonic::transport::Error>(LeaderElectionServiceClient::new(channel))
+    };
+    assert!(client().await.is_err());
+    assert!(matches!(one.node().reload_tls(), Ok(false)));
+    issue("one", "node2");
+    assert!(matches!(one.node().reload_tls(), Ok(true)));
+    assert!(wait_until(Duration::from_secs(5), || async { client().await.is_ok() }).await);
+    let answered = client().await.unwrap().get_leader(LeaderRequest::default()).await.unwrap().into_inner();
+    assert_eq!(answered.leader_id, 1);
+    assert_eq!((one.node().term(), two.node().term()), (term, term));
+    std::fs::remove_dir_all(dir).unwrap();
+    two.shutdown().await.unwrap();
+    one.shutdown().await.unwrap();
+}
+
+#[test]
+fn spiffe_id_patterns_match_the_ids_under_them() {
+    for pattern in ["spiffe://ring.test/node/1", "spiffe://ring.test/node/*", "spiffe://ring.test/*"] {
+        assert_eq!(spiffe::check_pattern(pattern), Ok(()));
+    }
+    for pattern in ["https://ring.test/node/1", "spiffe://", "spiffe:///node", "spiffe://ring.test/node/", "spiffe://ring.test/node-*", "*"] {
+        assert!(spiffe::check_pattern(pattern).is_err(), "{}", pattern);
+    }
+    assert!(spiffe::matches("spiffe://ring.test/node/1", "spiffe://ring.test/node/1"));
+    assert!(!spiffe::matches("spiffe://ring.test/node/1", "spiffe://ring.test/node/12"));
+    assert!(spiffe::matches("spiffe://ring.test/node/*", "spiffe://ring.test/node/12"));
+    assert!(!spiffe::matches("spiffe://ring.test/node/*", "spiffe://ring.test/node/"));
+    assert!(!spiffe::matches("spiffe://ring.test/node/*", "spiffe://ring.test/nodes/1"));
+    assert!(!spiffe::matches("spiffe://ring.test/*", "spiffe://ring.tester/node/1"));
+}
+
+#[tokio::test]
+async fn nodes_only_serve_peers_with_the_spiffe_ids_they_allow() {
+    let certs = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/certs");
+    let read = |name: &str| std::fs::read(certs.join(name)).unwrap();
+    let config = |id: u16| Config {
+        tls_cert: Some(certs.join(format!("spiffe-node{}.pem", id))), tls_key: Some(certs.join(format!("spiffe-node{}.key", id))),
+        tls_ca: Some(certs.join("spiffe-ca.pem")), tls_domain: Some("localhost".to_string()),
+        spiffe_ids: vec!["spiffe://ring.test/node/*".to_string()], ..Config::default()
+    };
+    let [one, two] = elected_pair(|id, node| node.config(config(id))).await;
+    let client = |cert: &str| {
+        let tls = ClientTlsConfig::new().domain_name("localhost").ca_certificate(Certificate::from_pem(read("spiffe-ca.pem")))
+            .identity(Identity::from_pem(read(&format!("{}.pem", cert)), read(&format!("{}.key", cert))));
+        let endpoint = Endpoint::from_shared(format!("https://{}", one.addr())).unwrap().tls_config(tls).unwrap();
+        async move { LeaderElectionServiceClient::new(endpoint.connect().await.unwrap()) }
+    };
+    let answered = client("spiffe-node2").await.get_leader(LeaderRequest::default()).await.unwrap();
+    assert_eq!(answered.into_inner().leader_id, 1);
+    // the CA signed it, but for another trust domain
+    let refused = client("spiffe-other").await.get_leader(LeaderRequest::default()).await.unwrap_err();
+    assert_eq!(refused.code(), Code::PermissionDenied, "{}", refused);
+    assert!(refused.message().contains("spiffe://other.test/node/3"), "{}", refused);
+    two.shutdown().await.unwrap();
+    one.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn a_handover_makes_the_named_node_lead_the_next_term() {
+    let ring = ring(4, Config::default()).await;
+    let term = ring.node(1).term();
+    let transfer = |target_id, target_addr: &str| {
+        let request = TransferLeadershipRequest { target_id, target_addr: target_addr.to_string(), group_id: 0 };
+        AdminService::transfer_leadership(ring.node(1), Request::new(request))
+    };
+    // node 3 is no neighbour of node 1's
+    let refused = transfer(3, "").await.unwrap_err();
+    assert_eq!(refused.code(), Code::InvalidArgument, "{}", refused);
+    assert!(follows(ring.node(1), 1));
+
+    let transferred = transfer(3, ring.addr(3)).await.unwrap().into_inner();
+    assert_eq!((transferred.transferred, transferred.term), (true, term + 1));
+    assert_eq!(ring.await_leader(Duration::from_secs(10)).await, Some(3));
+    for node in ring.running() {
+        assert_eq!(node.term(), term + 1, "node {}", node.id());
+    }
+    ring.shutdown().await;
+}
+
+#[tokio::test]
+async fn the_ring_closes_around_nodes_joining_and_leaving_and_elects_again() {
+    let ring = ring(3, Config::default()).await;
+    let term = ring.node(1).term();
+
+    // node 4 comes in right of node 3, which had node 1 on its right
+    let [addr] = free_addrs();
+    let four = Node::builder().id(4).listen(&addr).left(3, ring.addr(3)).right(3, ring.addr(3)).join(1).build().unwrap();
+    let nodes = ring.running().chain([four.node()]).collect::<Vec<_>>();
+    let joined = || async {
+        for node in &nodes {
+            if ring_size(node).await != 4 || !follows(node, 1) || node.term() <= term {
+                return false
+            }
+        }
+        true
+    };
+    assert!(wait_until(Duration::from_secs(10), joined).await);
+    let state = four.node().get_state(Request::new(StateRequest::default())).await.unwrap().into_inner();
+    assert_eq!((state.left_id, state.right_id), (3, 1));
+    let term = four.node().term();
+
+    // the leader leaves, and node 4 and node 2 are joined to each other
+    let left = LeaderElectionService::leave(ring.node(1), Request::new(LeaveRequest { epoch: 2, group_id: 0 })).await;
+    assert!(left.is_ok(), "{:?}", left);
+    let rest = &nodes[1..];
+    let reelected = || async {
+        for node in rest {
+            if ring_size(node).await != 3 || !follows(node, 2) || node.term() <= term {
+                return false
+            }
+        }
+        true
+    };
+    assert!(wait_until(Duration::from_secs(10), reelected).await);
+    let state = four.node().get_state(Request::new(StateRequest::default())).await.unwrap().into_inner();
+    assert_eq!((state.left_id, state.right_id), (3, 2));
+    four.shutdown().await.unwrap();
+    ring.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_reconfigured_node_retargets_its_neighbours_and_reelects_only_when_told_of_new_ones() {
+    let ring = ring(3, Config::default()).await;
+    let term = ring.node(1).term();
+    let neighbour = |id| Some(Neighbor { id, addr: ring.addr(id as u16).to_string() });
+    let reconfigure = |node, epoch, left, right| {
+        LeaderElectionService::reconfigure(ring.node(node), Request::new(ReconfigureRequest { epoch, left, right, group_id: 0 }))
+    };
+    let neighbours = |node| {
+        let node = ring.node(node);
+        async move {
+            let state = node.get_state(Request::new(StateRequest::default())).await.unwrap().into_inner();
+            (state.left_id, state.right_id)
+        }
+    };
+
+    // nodes 1 and 3 close the ring around node 2 without an election
+    assert!(reconfigure(1, 1, None, neighbour(3)).await.is_ok());
+    assert!(reconfigure(3, 1, neighbour(1), None).await.is_ok());
+    assert_eq!((neighbours(1).await, neighbours(3).await), ((3, 3), (1, 1)));
+    let stale = reconfigure(1, 1, None, neighbour(2)).await.unwrap_err();
+    assert_eq!(stale.code(), Code::Aborted, "{}", stale);
+    assert_eq!(neighbours(1).await, (3, 3));
+    for node in ring.running() {
+        assert_eq!(node.term(), term, "node {}", node.id());
+        assert!(follows(node, 1), "node {}", node.id());
+    }
+
+    // told of the neighbour it has, node 1 carries on; told of node 2, it elects again
+    let update = |right: u64| {
+        let settings = [("right".to_string(), format!("{}={}", right, ring.addr(right as u16)))].into();
+        AdminService::update_config(ring.node(1), Request::new(UpdateConfigRequest { settings, group_id: 0 }))
+    };
+    assert!(!update(3).await.unwrap().into_inner().reelected);
+    assert_eq!(ring.node(1).term(), term);
+    assert!(reconfigure(3, 2, neighbour(2), None).await.is_ok());
+    assert!(update(2).await.unwrap().into_inner().reelected);
+    assert_eq!(neighbours(1).await, (3, 2));
+    let reelected = || ready(ring.running().all(|node| follows(node, 1) && node.term() > term));
+    assert!(wait_until(Duration::from_secs(10), reelected).await);
+    ring.shutdown().await;
+}
+
+/// A ring of six, which needs `quorum` nodes to elect a leader in a
+/// segment, after nodes 3 to 6 crash and split it.
+async fn split_ring(quorum: u64) -> TestRing {
+    let config = Config { liveness_interval: Some(Duration::from_millis(100)), quorum: Some(quorum), ..Config::default() };
+    let mut ring = ring(6, config).await;
+    // more nodes in a row than node 2 knows to skip
+    for id in 3..=6 {
+        ring.crash(id).await;
+    }
+    assert!(wait_until(Duration::from_secs(5), || ready(ring.running().all(|node| node.partition().is_some()))).await);
+    ring
+}
+
+#[tokio::test]
+async fn a_quorate_segment_of_a_split_ring_elects_a_provisional_leader() {
+    let ring = split_ring(2).await;
+    for node in ring.running() {
+        assert_eq!(node.partition().map(|partition| (partition.whole, partition.quorate)), Some((6, true)));
+    }
+    let leader = || async { ring.node(2).get_leader(Request::new(LeaderRequest::default())).await.unwrap().into_inner() };
+    assert!(wait_until(Duration::from_secs(5), || async { leader().await.leader_known }).await);
+    let led = leader().await;
+    assert_eq!((led.leader_id, led.provisional), (1, true));
+    ring.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_minority_segment_of_a_split_ring_elects_no_leader() {
+    let ring = split_ring(3).await;
+    for node in ring.running() {
+        assert_eq!(node.partition().map(|partition| (partition.whole, partition.quorate)), Some((6, false)));
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    for node in ring.running() {
+        let leader = node.get_leader(Request::new(LeaderRequest::default())).await.unwrap().into_inner();
+        assert!(!leader.leader_known, "node {} follows {}", node.id(), leader.leader_id);
+    }
+    ring.shutdown().await;
+}
+
+#[tokio::test]
+async fn the_segments_of_a_healed_ring_merge_under_the_leader_of_the_newer_one() {
+    let ring = split_ring(2).await;
+    // the other segment closed into a ring of its own, whose node 3 node 2
+    // cannot reach yet
+    let config = Config { liveness_interval: Some(Duration::from_millis(100)), quorum: Some(2), ..Config::default() };
+    let [hidden] = free_addrs();
+    let addr = |id: u16| match id {
+        3 => &hidden,
+        _ => ring.addr(id),
+    };
+    let node = |id: u16, left: u16, right: u16| {
+        Node::builder().id(id).listen(addr(id)).left(left, addr(left)).right(right, addr(right)).ring_size(4).config(config.clone()).build().unwrap()
+    };
+    let others = [node(3, 6, 4), node(4, 3, 5), node(5, 4, 6), node(6, 5, 3)];
+    let elected = tokio::time::timeout(Duration::from_secs(5), others[1].node().await_ring_acknowledged()).await;
+    assert_eq!(elected, Ok(3));
+    let nodes = ring.running().chain(others.iter().map(NodeHandle::node)).collect::<Vec<_>>();
+    let term = nodes.iter().map(|node| node.term()).max().unwrap();
+
+    let (proxy, target) = (tokio::net::TcpListener::bind(ring.addr(3)).await.unwrap(), hidden.parse::<std::net::SocketAddr>().unwrap());
+    tokio::spawn(async move {
+        while let Ok((mut inbound, _)) = proxy.accept().await {
+            tokio::spawn(async move {
+                if let Ok(mut outbound) = tokio::net::TcpStream::connect(target).await {
+                    let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+                }
+            });
+        }
+    });
+    let merged = wait_until(Duration::from_secs(10), || ready(nodes.iter().all(|node| follows(node, 1) && node.partition().is_none())));
+    assert!(merged.await);
+    for node in &nodes {
+        assert_eq!((ring_size(node).await, node.term()), (6, term + 1), "node {}", node.id());
+    }
+    let leader = others[1].node().get_leader(Request::new(LeaderRequest::default())).await.unwrap().into_inner();
+    assert_eq!((leader.leader_id, leader.provisional), (1, false));
+    // node 3 stepped down for the leader of the segment closed later
+    let audit = AdminService::get_audit_log(others[0].node(), Request::new(AuditLogRequest::default())).await.unwrap().into_inner();
+    let stepped_down = audit.transitions.iter().any(|transition| transition.from == "Leader" && transition.cause.contains("merge"));
+    assert!(stepped_down, "{:?}", audit.transitions);
+    for node in others {
+        node.shutdown().await.unwrap();
+    }
+    ring.shutdown().await;
+}
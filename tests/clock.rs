@@ -0,0 +1,62 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use grpc_le::clock::{Clock, MockClock};
+use grpc_le::config::Config;
+use grpc_le::topology::Topology;
+use grpc_le::transport::MemoryTransport;
+use grpc_le::{node_client, ElectionResult, Node};
+use tokio::time::timeout;
+
+#[tokio::test]
+async fn a_mock_clock_only_moves_when_told_to() {
+    let clock = Arc::new(MockClock::new());
+    let (start, wall) = (clock.now(), clock.wall_now());
+    let deadline = start + Duration::from_secs(10);
+    let sleeper = tokio::spawn({
+        let clock = clock.clone();
+        async move { clock.sleep_until(deadline).await }
+    });
+    assert!(timeout(Duration::from_millis(50), clock.sleep_until(deadline)).await.is_err());
+    assert_eq!(clock.now(), start);
+
+    clock.advance(Duration::from_secs(4));
+    assert!(timeout(Duration::from_millis(50), clock.sleep_until(deadline)).await.is_err());
+    clock.advance(Duration::from_secs(6));
+    assert!(timeout(Duration::from_secs(1), sleeper).await.is_ok());
+    assert_eq!(clock.now() - start, Duration::from_secs(10));
+    assert_eq!(clock.wall_now() - wall, chrono::Duration::seconds(10));
+}
+
+#[tokio::test]
+async fn nodes_on_a_mock_clock_elect_only_as_it_moves() {
+    let clock = Arc::new(MockClock::new());
+    let specs = Topology::from_ids(&[1, 2, 3]).nodes();
+    let network = Arc::new(MemoryTransport::default());
+    let nodes = specs.iter()
+        .map(|spec| Node::with_clock(clock.clone(), spec, specs.len() as u64, &Config::default(), None).unwrap().with_transport(network.clone()))
+        .collect::<Vec<_>>();
+    for node in &nodes {
+        network.add(node.clone());
+        tokio::spawn(node_client(node.clone()));
+    }
+    // the startup grace never runs out on its own
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(nodes.iter().all(|node| *node.subscribe().borrow() == ElectionResult::Undecided));
+
+    let elected = timeout(Duration::from_secs(5), async {
+        while nodes.iter().any(|node| *node.subscribe().borrow() == ElectionResult::Undecided) {
+            clock.advance(Duration::from_millis(50));
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await;
+    assert!(elected.is_ok());
+    for node in &nodes {
+        let result = match node.id() {
+            1 => ElectionResult::Leader,
+            _ => ElectionResult::Defeated { leader: 1 },
+        };
+        assert_eq!(*node.subscribe().borrow(), result, "node {}", node.id());
+    }
+    nodes.iter().for_each(Node::shutdown);
+}
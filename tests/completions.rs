@@ -0,0 +1,29 @@
+use grpc_le::completions::{script, CommandLine, Shell};
+use grpc_le::config::{Config, Output};
+
+#[test]
+fn every_completed_setting_is_one_the_nodes_take() {
+    for setting in Config::settings() {
+        // a value that none takes is fine, an unknown setting is not
+        let parsed = Config::from_args([format!("--{}", setting), "x".to_string()].into_iter());
+        assert_ne!(parsed.err(), Some(format!("unknown argument \"--{}\"", setting)));
+    }
+    assert_eq!(Config::from_args(["--output".to_string(), "json".to_string()].into_iter()).unwrap().output, Output::Json);
+    assert!("yaml".parse::<Output>().is_err());
+}
+
+#[test]
+fn each_shell_completes_the_commands_and_flags() {
+    let command = CommandLine { program: "le-admin", commands: &[("status", &[]), ("completions", &["bash", "zsh", "fish"])], flags: &["peers", "output"] };
+    let bash = script(Shell::Bash, &command);
+    assert!(bash.contains(r#"words="status completions""#) && bash.contains(r#"completions) words="bash zsh fish" ;;"#));
+    assert!(bash.contains(r#"words="--peers --output""#) && bash.ends_with("complete -o default -F _le_admin le-admin\n"));
+    let zsh = script(Shell::Zsh, &command);
+    assert!(zsh.starts_with("#compdef le-admin\n") && zsh.contains("compadd -- --peers --output") && zsh.contains("completions) compadd -- bash zsh fish ;;"));
+    assert_eq!(script(Shell::Fish, &command), "complete -c le-admin -n __fish_use_subcommand -a 'status completions'
+complete -c le-admin -n '__fish_seen_subcommand_from completions' -a 'bash zsh fish'
+complete -c le-admin -l peers
+complete -c le-admin -l output
+");
+    assert_eq!("tcsh".parse::<Shell>(), Err("unknown shell \"tcsh\", expected bash, zsh or fish".to_string()));
+}
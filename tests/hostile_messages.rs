@@ -0,0 +1,269 @@
+use std::sync::{Arc, Once};
+use std::time::Duration;
+
+use futures::StreamExt;
+use prost::Message;
+use proptest::prelude::*;
+use tokio::sync::mpsc;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity};
+use tonic::body::BoxBody;
+use tonic::metadata::MetadataValue;
+use tonic::{Code, Request, Status};
+use tower::{ServiceBuilder, ServiceExt};
+
+use grpc_le::auth::{Auth, SigningLayer};
+use grpc_le::config::Config;
+use grpc_le::json::{self, Json};
+use grpc_le::leader_election_service::admin_service_client::AdminServiceClient;
+use grpc_le::leader_election_service::leader_election_service_client::LeaderElectionServiceClient;
+use grpc_le::leader_election_service::state_response::Kind;
+use grpc_le::leader_election_service::{ForceStateRequest, LeaderRequest, StepDownRequest, TakeOverRequest};
+use grpc_le::leader_election_service::peer_message::Body;
+use grpc_le::testkit::free_addrs;
+use grpc_le::topology::Topology;
+use grpc_le::transport::{MemoryTransport, Peer, Transport};
+use grpc_le::{node_client, DigestMessage, ElectionResult, Node, NodeState, NotifyMessage, PeerMessage, ProbeMessage, Sequence};
+
+/// Turns a panic in any task into a failed run, as a fuzzer would, rather
+/// than the end of the one task the runtime carries on without.
+fn abort_on_panic() {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let report = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            report(info);
+            std::process::abort();
+        }));
+    });
+}
+
+/// Starts a ring of 3, 5 and 7 over an in-memory network.
+fn ring() -> (Arc<MemoryTransport>, Vec<Node>) {
+    ring_with(&Config::default())
+}
+
+fn ring_with(config: &Config) -> (Arc<MemoryTransport>, Vec<Node>) {
+    let specs = Topology::from_ids(&[3, 5, 7]).nodes();
+    let network = Arc::new(MemoryTransport::default());
+    let nodes = specs.iter()
+        .map(|spec| Node::new(spec, specs.len() as u64, config, None).unwrap().with_transport(network.clone()))
+        .collect::<Vec<_>>();
+    for node in &nodes {
+        network.add(node.clone());
+        tokio::spawn(node_client(node.clone()));
+    }
+    (network, nodes)
+}
+
+/// Relays `messages` to node 5 of the ring on `network` as if its left
+/// neighbour sent them.
+async fn relay(network: &MemoryTransport, messages: Vec<PeerMessage>) {
+    let (tx, rx) = mpsc::channel(messages.len().max(1));
+    for message in messages {
+        tx.send(message).await.unwrap();
+    }
+    drop(tx);
+    let target = Peer { id: 5, endpoint: Endpoint::from_static("http://[::1]:40005") };
+    let mut acks = network.relay(&target, rx).await.unwrap();
+    while acks.next().await.is_some() {}
+}
+
+fn paused_runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread().enable_all().start_paused(true).build().unwrap()
+}
+
+/// Relays `messages` to node 5 of a running ring of 3, 5 and 7, then lets
+/// the ring run on with whatever they did.
+fn feed(messages: Vec<PeerMessage>) {
+    abort_on_panic();
+    paused_runtime().block_on(async {
+        let (network, _) = ring();
+        relay(&network, messages).await;
+        tokio::time::sleep(Duration::from_secs(30)).await;
+    });
+}
+
+/// IDs, phases, terms and counts at the edges of their range, the IDs of the
+/// ring among them.
+fn weird() -> BoxedStrategy<u64> {
+    prop_oneof![Just(0), Just(1), Just(3), Just(5), Just(7), Just(128), Just(129), Just(u64::MAX - 1), Just(u64::MAX), any::<u64>()].boxed()
+}
+
+fn sequence() -> impl Strategy<Value = Option<Sequence>> {
+    proptest::option::of((weird(), weird(), weird(), any::<bool>())
+        .prop_map(|(sender, incarnation, number, leftward)| Sequence { sender, incarnation, number, leftward }))
+}
+
+fn probe() -> BoxedStrategy<Body> {
+    (weird(), any::<bool>(), weird(), sequence(), weird(), weird(), weird(), weird())
+        .prop_map(|(sender_id, headed_left, phase, seq, term, priority, hops, hops_remaining)| Body::Probe(ProbeMessage {
+            sender_id, headed_left, phase, seq, term, priority, group_id: 0, hops, hops_remaining, zone: String::new(),
+        }))
+        .boxed()
+}
+
+fn notify() -> BoxedStrategy<Body> {
+    let ids = || proptest::collection::vec(weird(), 0..5);
+    (weird(), any::<bool>(), sequence(), ids(), ".{0,8}", weird(), ids())
+        .prop_map(|(leader_id, headed_left, seq, ranking, leader_addr, term, priorities)| Body::Notify(NotifyMessage {
+            leader_id, headed_left, seq, ranking, leader_addr, term, priorities, ..NotifyMessage::default()
+        }))
+        .boxed()
+}
+
+fn digest() -> BoxedStrategy<Body> {
+    (weird(), weird(), sequence(), proptest::collection::vec(weird(), 0..5), weird(), weird())
+        .prop_map(|(leader_id, ring_size, seq, ranking, term, hops)| Body::Digest(DigestMessage {
+            leader_id, ring_size, seq, ranking, term, group_id: 0, hops,
+        }))
+        .boxed()
+}
+
+/// A message that decodes, but of values no well-behaved node would send.
+fn message() -> impl Strategy<Value = PeerMessage> {
+    (proptest::option::of(prop_oneof![probe(), notify(), digest()]), weird(), weird())
+        .prop_map(|(body, lamport, number)| PeerMessage { body, lamport, number, ..PeerMessage::default() })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn no_weird_message_panics_a_node(messages in proptest::collection::vec(message(), 1..8)) {
+        feed(messages);
+    }
+
+    #[test]
+    fn no_bytes_panic_a_node(frames in proptest::collection::vec(proptest::collection::vec(any::<u8>(), 0..64), 1..8)) {
+        feed(frames.iter().filter_map(|frame| PeerMessage::decode(&frame[..]).ok()).collect());
+    }
+}
+
+#[test]
+fn the_last_term_does_not_stop_the_ring_restarting() {
+    abort_on_panic();
+    paused_runtime().block_on(async {
+        let (network, nodes) = ring();
+        let probe = ProbeMessage { sender_id: 9, phase: 1, term: u64::MAX, ..ProbeMessage::default() };
+        relay(&network, vec![PeerMessage { body: Some(Body::Probe(probe)), ..PeerMessage::default() }]).await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let node = &nodes[1];
+        assert!(node.term() < u64::MAX, "node 5 followed the probe to term {}", node.term());
+
+        let term = node.term();
+        assert!(node.start_election().await);
+        assert_eq!(node.term(), term + 1);
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        for node in &nodes {
+            let elected = match node.id() {
+                3 => NodeState::Leader,
+                _ => NodeState::Defeated { leader: Some(3) },
+            };
+            assert_eq!(node.state().await, elected, "node {}", node.id());
+        }
+    });
+}
+
+#[test]
+fn a_digest_of_another_leader_is_recorded_as_an_anomaly() {
+    abort_on_panic();
+    let dir = std::env::temp_dir().join(format!("grpc-le-anomalies-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    paused_runtime().block_on(async {
+        let (network, nodes) = ring_with(&Config { events_dir: Some(dir.clone()), ..Config::default() });
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        let digest = DigestMessage { leader_id: 9, ring_size: 3, term: nodes[1].term(), ..DigestMessage::default() };
+        relay(&network, vec![PeerMessage { body: Some(Body::Digest(digest)), ..PeerMessage::default() }]).await;
+    });
+    let events = std::fs::read_to_string(dir.join("5.events.jsonl")).unwrap();
+    let anomalies = events.lines().map(|line| json::parse(line).unwrap()).filter(|event| matches!(event.get("event"), Some(Json::String(kind)) if kind == "anomaly")).collect::<Vec<_>>();
+    // the digest goes around the ring and comes by again
+    assert!(!anomalies.is_empty(), "{}", events);
+    for anomaly in anomalies {
+        assert!(matches!(anomaly.get("kind"), Some(Json::String(kind)) if kind == "diverged_digest"), "{:?}", anomaly);
+        assert!(matches!(anomaly.get("detail"), Some(Json::String(detail)) if detail.contains("leader 9")), "{:?}", anomaly);
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn peers_only_make_the_calls_the_acl_grants_them() {
+    let certs = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/certs");
+    let read = |name: &str| std::fs::read(certs.join(name)).unwrap();
+    let acl = ["spiffe://ring.test/node/*=elect", "spiffe://ring.test/monitor=read", "spiffe://other.test/*=admin+read"];
+    let config = |id: u16| Config {
+        tls_cert: Some(certs.join(format!("spiffe-node{}.pem", id))), tls_key: Some(certs.join(format!("spiffe-node{}.key", id))),
+        tls_ca: Some(certs.join("spiffe-ca.pem")), tls_domain: Some("localhost".to_string()),
+        acl: acl.iter().map(|grant| grant.parse().unwrap()).collect(), ..Config::default()
+    };
+    let addrs = free_addrs::<2>();
+    let nodes = [(1, &addrs[0], &addrs[1]), (2, &addrs[1], &addrs[0])]
+        .map(|(id, listen, other)| Node::builder().id(id).listen(listen).left(3 - id, other).right(3 - id, other).config(config(id)).build().unwrap());
+    assert_eq!(tokio::time::timeout(Duration::from_secs(5), nodes[0].node().await_ring_acknowledged()).await, Ok(1));
+
+    let channel = |cert: &str| {
+        let tls = ClientTlsConfig::new().domain_name("localhost").ca_certificate(Certificate::from_pem(read("spiffe-ca.pem")))
+            .identity(Identity::from_pem(read(&format!("{}.pem", cert)), read(&format!("{}.key", cert))));
+        let endpoint = Endpoint::from_shared(format!("https://{}", addrs[0])).unwrap().tls_config(tls).unwrap();
+        async move { endpoint.connect().await.unwrap() }
+    };
+    let denied = |status: Status| assert_eq!(status.code(), Code::PermissionDenied, "{}", status);
+    // monitoring may ask, but neither step the leader down nor take over
+    let monitor = channel("spiffe-monitor").await;
+    assert_eq!(LeaderElectionServiceClient::new(monitor.clone()).get_leader(LeaderRequest::default()).await.unwrap().into_inner().leader_id, 1);
+    denied(AdminServiceClient::new(monitor.clone()).step_down(StepDownRequest::default()).await.unwrap_err());
+    denied(LeaderElectionServiceClient::new(monitor).take_over(TakeOverRequest::default()).await.unwrap_err());
+    // a node takes part in the election, but is not an admin
+    let node = channel("spiffe-node2").await;
+    denied(AdminServiceClient::new(node.clone()).step_down(StepDownRequest::default()).await.unwrap_err());
+    denied(LeaderElectionServiceClient::new(node).get_leader(LeaderRequest::default()).await.unwrap_err());
+    let admin = channel("spiffe-other").await;
+    assert!(AdminServiceClient::new(admin).step_down(StepDownRequest::default()).await.unwrap().into_inner().stepped_down);
+    for node in nodes {
+        node.shutdown().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn a_captured_call_signature_signs_nothing_else_and_only_once() {
+    let key = std::env::temp_dir().join(format!("grpc-le-replay-key-{}", std::process::id()));
+    std::fs::write(&key, "secret\n").unwrap();
+    let config = Config { auth_key: Some(key.clone()), ..Config::default() };
+    let addrs = free_addrs::<2>();
+    let nodes = [(1, &addrs[0], &addrs[1]), (2, &addrs[1], &addrs[0])]
+        .map(|(id, listen, other)| Node::builder().id(id).listen(listen).left(3 - id, other).right(3 - id, other).config(config.clone()).build().unwrap());
+    assert_eq!(tokio::time::timeout(Duration::from_secs(5), nodes[1].node().await_ring_acknowledged()).await, Ok(1));
+
+    // an eavesdropper sees the signature of the call node 2 is told to follow node 1 with
+    let channel = Endpoint::from_shared(format!("http://{}", addrs[1])).unwrap().connect().await.unwrap();
+    let captured = Arc::new(std::sync::Mutex::new(None));
+    let wire = {
+        let captured = captured.clone();
+        tower::service_fn(move |request: http::Request<BoxBody>| {
+            *captured.lock().unwrap() = request.headers().get("le-signature").cloned();
+            channel.clone().oneshot(request)
+        })
+    };
+    let signing = SigningLayer::new(Some(Arc::new(Auth::read(&key).unwrap())));
+    let mut signed = AdminServiceClient::new(ServiceBuilder::new().layer(signing).service(wire));
+    let follow = ForceStateRequest { kind: Kind::Defeated as i32, leader_id: 1, leader_known: true, ..ForceStateRequest::default() };
+    signed.force_state(follow.clone()).await.unwrap();
+    let signature: MetadataValue<_> = captured.lock().unwrap().take().unwrap().to_str().unwrap().parse().unwrap();
+
+    let mut replaying = AdminServiceClient::connect(format!("http://{}", addrs[1])).await.unwrap();
+    let replay = |message: ForceStateRequest| {
+        let mut request = Request::new(message);
+        request.metadata_mut().insert("le-signature", signature.clone());
+        request
+    };
+    let lead = ForceStateRequest { kind: Kind::Leader as i32, ..ForceStateRequest::default() };
+    let refused = replaying.force_state(replay(lead)).await.unwrap_err();
+    assert_eq!(refused.code(), Code::Unauthenticated, "{}", refused);
+    assert_eq!(*nodes[1].node().subscribe().borrow(), ElectionResult::Defeated { leader: 1 });
+    let refused = replaying.force_state(replay(follow)).await.unwrap_err();
+    assert_eq!(refused.code(), Code::Unauthenticated, "{}", refused);
+    std::fs::remove_file(key).unwrap();
+    for node in nodes {
+        node.shutdown().await.unwrap();
+    }
+}
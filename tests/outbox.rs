@@ -0,0 +1,170 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::StreamExt;
+use tokio::sync::mpsc;
+use tonic::transport::Endpoint;
+
+use grpc_le::config::Config;
+use grpc_le::leader_election_service::peer_message::Body;
+use grpc_le::outbox::{format_line, parse_line, Message, Outbox};
+use grpc_le::topology::Topology;
+use grpc_le::transport::{MemoryTransport, Peer, Transport};
+use grpc_le::{DigestMessage, Node, NotifyMessage, Payload, PeerMessage, ProbeMessage};
+
+/// A path of its own for each test's outbox, which does not exist yet.
+fn outbox_path(test: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("grpc-le-{}-{}.outbox", test, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn messages() -> [Message; 3] {
+    [
+        Message::Probe(ProbeMessage { sender_id: 3, headed_left: true, phase: 2, term: 4, priority: 1, zone: "eu-west-1a".to_string(), ..ProbeMessage::default() }),
+        Message::Notify(NotifyMessage {
+            leader_id: 3, ranking: vec![3, 5, 7], term: 4, priorities: vec![1, 0, 0], leader_addr: "http://le-3:40003".to_string(),
+            leader_metadata: b"rack 12".to_vec(), payload: Some(Payload { content_type: "application/json".to_string(), data: b"{}".to_vec() }),
+            ..NotifyMessage::default()
+        }),
+        Message::Digest(DigestMessage { leader_id: 3, ring_size: 3, ranking: vec![3, 5, 7], term: 4, ..DigestMessage::default() }),
+    ]
+}
+
+#[test]
+fn logged_messages_read_back_the_same() {
+    for (seq, message) in messages().into_iter().enumerate() {
+        let line = format_line(seq as u64, &message);
+        assert!(line.ends_with('\n'), "{:?}", line);
+        assert_eq!(parse_line(line.trim_end()), Some((seq as u64, Some(message))), "{:?}", line);
+    }
+    // whatever bytes the leader attached
+    for data in [&b""[..], b"\0", b"\xff\n", b"a b c", b" -:\t"] {
+        let payload = Some(Payload { content_type: String::from_utf8_lossy(data).into_owned(), data: data.to_vec() });
+        let notify = Message::Notify(NotifyMessage { leader_id: 3, leader_metadata: data.to_vec(), payload, ..NotifyMessage::default() });
+        assert_eq!(parse_line(format_line(1, &notify).trim_end()), Some((1, Some(notify))), "{:?}", data);
+    }
+    // whatever single word the prober's --zone was, or none
+    for zone in ["", "eu-west-1a", "rack:12/row=3", "zône"] {
+        let probe = Message::Probe(ProbeMessage { sender_id: 3, zone: zone.to_string(), ..ProbeMessage::default() });
+        assert_eq!(parse_line(format_line(1, &probe).trim_end()), Some((1, Some(probe))), "{:?}", zone);
+    }
+    assert_eq!(parse_line("7 ack"), Some((7, None)));
+    // written before zones, priorities, terms and rankings existed
+    let old = Message::Notify(NotifyMessage { leader_id: 3, headed_left: true, ..NotifyMessage::default() });
+    assert_eq!(parse_line("1 notify 3 true"), Some((1, Some(old))));
+    let unpadded = "1 notify 3 true - 0 - - cmFjaw -";
+    for malformed in ["", "ack", "1", "1 nack", "1 probe 3 true", "x probe 3 true 2", "1 notify 3 yes", unpadded, "1 notify 3 true - 0 - - - e30="] {
+        assert_eq!(parse_line(malformed), None, "{:?}", malformed);
+    }
+}
+
+#[test]
+fn a_reopened_outbox_keeps_only_what_was_not_acknowledged() {
+    let path = outbox_path("half-acknowledged");
+    let outbox = Outbox::open(path.clone()).unwrap();
+    let seqs = messages().iter().map(|message| outbox.record(message).unwrap()).collect::<Vec<_>>();
+    outbox.acknowledged(seqs[0]).unwrap();
+    outbox.acknowledged(seqs[2]).unwrap();
+    drop(outbox);
+
+    let reopened = Outbox::open(path.clone()).unwrap();
+    assert_eq!(reopened.pending(), [(seqs[1], messages()[1].clone())]);
+    // the acknowledged entries are compacted away, and new ones carry on numbering
+    assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+    assert_eq!(reopened.record(&messages()[0]).unwrap(), seqs[2] + 1);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn a_torn_last_entry_is_dropped_and_others_are_not() {
+    let path = outbox_path("torn");
+    let outbox = Outbox::open(path.clone()).unwrap();
+    let seq = outbox.record(&messages()[1]).unwrap();
+    drop(outbox);
+    OpenOptions::new().append(true).open(&path).unwrap().write_all(b"1 notify 3 tr").unwrap();
+
+    let reopened = Outbox::open(path.clone()).unwrap();
+    assert_eq!(reopened.pending(), [(seq, messages()[1].clone())]);
+    drop(reopened);
+
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b"1 notify 3 tr\n").unwrap();
+    file.write_all(format_line(2, &messages()[0]).as_bytes()).unwrap();
+    assert_eq!(Outbox::open(path.clone()).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn a_probe_zoned_in_several_words_is_refused_before_the_outbox() {
+    let dir = std::env::temp_dir().join(format!("grpc-le-zoned-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = Config { outbox_dir: Some(dir.clone()), ..Config::default() };
+    let specs = Topology::from_ids(&[3, 5, 7]).nodes();
+    let network = Arc::new(MemoryTransport::default());
+    let nodes = specs.iter()
+        .map(|spec| Node::new(spec, specs.len() as u64, &config, None).unwrap().with_transport(network.clone()))
+        .collect::<Vec<_>>();
+    for node in &nodes {
+        network.add(node.clone());
+    }
+
+    // a probe that would be passed on, but for its zone
+    let probe = ProbeMessage { sender_id: 9, phase: 1, zone: "eu west\n1a".to_string(), ..ProbeMessage::default() };
+    let (tx, rx) = mpsc::channel(1);
+    tx.send(PeerMessage { body: Some(Body::Probe(probe)), ..PeerMessage::default() }).await.unwrap();
+    drop(tx);
+    let target = Peer { id: 5, endpoint: Endpoint::from_static("http://[::1]:40005") };
+    // the stream ends at once, where an accepted probe would be passed on
+    let mut acks = network.relay(&target, rx).await.unwrap();
+    let refused = tokio::time::timeout(Duration::from_secs(1), acks.next()).await;
+    assert!(matches!(refused, Ok(None)), "{:?}", refused);
+
+    // the outboxes of a restarted node read back
+    for node in &nodes {
+        node.shutdown();
+    }
+    for entry in std::fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        let pending = Outbox::open(path.clone()).unwrap_or_else(|e| panic!("{}: {}", path.display(), e)).pending();
+        assert!(pending.is_empty(), "{}: {:?}", path.display(), pending);
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn a_notification_dropped_from_a_full_queue_is_not_sent_after_a_restart() {
+    let dir = std::env::temp_dir().join(format!("grpc-le-dropped-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let args = ["--drop-policy", "drop-oldest", "--queue-capacity", "1", "--outbox-dir", dir.to_str().unwrap()];
+    let config = Config::from_args(args.into_iter().map(str::to_string)).unwrap();
+    let specs = Topology::from_ids(&[3, 5, 7]).nodes();
+    let network = Arc::new(MemoryTransport::default());
+    let nodes = specs.iter()
+        .map(|spec| Node::new(spec, specs.len() as u64, &config, None).unwrap().with_transport(network.clone()))
+        .collect::<Vec<_>>();
+    for node in &nodes {
+        network.add(node.clone());
+    }
+
+    // nothing sends node 5's messages on, so the second pushes out the first
+    let (tx, rx) = mpsc::channel(2);
+    for term in [1, 2] {
+        let notify = NotifyMessage { leader_id: 3, term, ..NotifyMessage::default() };
+        tx.send(PeerMessage { body: Some(Body::Notify(notify)), ..PeerMessage::default() }).await.unwrap();
+    }
+    drop(tx);
+    let target = Peer { id: 5, endpoint: Endpoint::from_static("http://[::1]:40005") };
+    let acks = network.relay(&target, rx).await.unwrap().collect::<Vec<_>>().await;
+    assert_eq!(acks.len(), 2, "{:?}", acks);
+    for node in &nodes {
+        node.shutdown();
+    }
+
+    let pending = Outbox::open(dir.join("5-to-7.outbox")).unwrap().pending();
+    assert!(matches!(pending.as_slice(), [(_, Message::Notify(notify))] if notify.term == 2), "{:?}", pending);
+    std::fs::remove_dir_all(dir).unwrap();
+}
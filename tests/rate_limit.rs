@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use grpc_le::rate_limit::{ProbeLimit, TokenBucket, MAX_PROBES_COUNTED, PROBE_REPEATS};
+use tokio::time::Instant;
+use tonic::{Code, Status};
+
+#[test]
+fn a_token_bucket_allows_a_burst_and_refills_at_its_rate() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(4, start);
+    assert_eq!((0..5).map(|_| bucket.take(start)).collect::<Vec<_>>(), [true, true, true, true, false]);
+    // a quarter of a second brings back one token
+    assert!(!bucket.take(start + Duration::from_millis(200)));
+    assert!(bucket.take(start + Duration::from_millis(250)));
+    assert!(!bucket.take(start + Duration::from_millis(250)));
+    // and a long pause no more than a burst
+    let later = start + Duration::from_secs(60);
+    assert_eq!((0..5).filter(|_| bucket.take(later)).count(), 4);
+
+    let mut minutely = TokenBucket::per(2, Duration::from_secs(60), start);
+    assert!(minutely.take(start) && minutely.take(start) && !minutely.take(start));
+    assert!(minutely.take(start + Duration::from_secs(30)));
+}
+
+#[test]
+fn a_sender_repeating_a_probe_is_refused_and_others_are_not() {
+    let limit = ProbeLimit::default();
+    for _ in 0..PROBE_REPEATS {
+        assert!(limit.admit(1, 5, 3, 0, true).is_ok());
+    }
+    let refused = Status::from(limit.admit(1, 5, 3, 0, true).unwrap_err());
+    assert_eq!(refused.code(), Code::ResourceExhausted, "{}", refused);
+    // another phase, direction or sender counts apart
+    assert!(limit.admit(1, 5, 3, 1, true).is_ok());
+    assert!(limit.admit(1, 5, 3, 0, false).is_ok());
+    assert!(limit.admit(1, 5, 4, 0, true).is_ok());
+    // and a new term starts counting afresh
+    assert!(limit.admit(1, 6, 3, 0, true).is_ok());
+}
+
+#[test]
+fn a_peer_making_up_senders_cannot_grow_the_probe_counts_without_bound() {
+    let limit = ProbeLimit::default();
+    for sender in 0..MAX_PROBES_COUNTED as u64 {
+        assert!(limit.admit(1, 5, sender, 0, true).is_ok());
+    }
+    let refused = Status::from(limit.admit(1, 5, MAX_PROBES_COUNTED as u64, 0, true).unwrap_err());
+    assert_eq!(refused.code(), Code::ResourceExhausted, "{}", refused);
+    // the senders counted already go on being counted
+    assert!(limit.admit(1, 5, 0, 0, true).is_ok());
+    // until the ring moves on to the next term
+    assert!(limit.admit(1, 6, MAX_PROBES_COUNTED as u64, 0, true).is_ok());
+}
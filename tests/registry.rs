@@ -0,0 +1,89 @@
+#![cfg(feature = "registry")]
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Response, Server};
+
+use grpc_le::config::Config;
+use grpc_le::testkit::{free_addrs, wait_until};
+use grpc_le::Node;
+
+/// The calls an etcd gateway was made, by path and body.
+type Calls = Arc<Mutex<Vec<(String, String)>>>;
+
+/// Serves the part of etcd's JSON gateway a leader registers itself over
+/// on `addr`, granting lease 7 and keeping it alive, and records the calls.
+fn etcd(addr: SocketAddr) -> Calls {
+    let calls = Calls::default();
+    let recorded = calls.clone();
+    let make_service = make_service_fn(move |_| {
+        let calls = recorded.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: hyper::Request<Body>| {
+                let calls = calls.clone();
+                async move {
+                    let path = request.uri().path().to_string();
+                    let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                    let answer = match path.as_str() {
+                        "/v3/lease/grant" => r#"{"ID": "7", "TTL": "1"}"#,
+                        "/v3/lease/keepalive" => r#"{"result": {"ID": "7", "TTL": "1"}}"#,
+                        _ => "{}",
+                    };
+                    calls.lock().unwrap().push((path, String::from_utf8_lossy(&body).into_owned()));
+                    Ok::<_, Infallible>(Response::new(Body::from(answer)))
+                }
+            }))
+        }
+    });
+    tokio::spawn(Server::bind(&addr).serve(make_service));
+    calls
+}
+
+/// Whether the store was called at `path` with a body containing `part`.
+fn called(calls: &Calls, path: &str, part: &str) -> bool {
+    calls.lock().unwrap().iter().any(|(called, body)| called == path && body.contains(part))
+}
+
+#[tokio::test]
+async fn the_leader_registers_while_it_leads_and_deregisters_as_it_goes() {
+    let [store, one, two] = free_addrs();
+    let calls = etcd(store.parse().unwrap());
+    let config = Config {
+        register: Some(format!("etcd://{}/services/le/leader", store).parse().unwrap()),
+        register_ttl: Duration::from_secs(1),
+        ..Config::default()
+    };
+    let node = |id, listen: &str, other: &str| Node::builder().id(id).listen(listen).left(3 - id, other).right(3 - id, other).config(config.clone()).build().unwrap();
+    let (one, two) = (node(1, &one, &two), node(2, &two, &one));
+
+    // the key and the value go over the gateway in base64
+    let key = "c2VydmljZXMvbGUvbGVhZGVy";
+    let value = base64(&format!("{{\"id\": 1, \"addr\": \"http://{}\", \"ttl_ms\": 1000}}", one.addr()));
+    assert!(wait_until(Duration::from_secs(10), || async { called(&calls, "/v3/kv/put", &value) }).await, "{:?}", calls.lock().unwrap());
+    assert!(called(&calls, "/v3/kv/put", key) && called(&calls, "/v3/kv/put", "\"lease\": \"7\""));
+    assert!(wait_until(Duration::from_secs(5), || async { called(&calls, "/v3/lease/keepalive", "\"7\"") }).await);
+    assert!(!called(&calls, "/v3/lease/revoke", ""));
+
+    one.shutdown().await.unwrap();
+    assert!(called(&calls, "/v3/lease/revoke", "\"ID\": \"7\""), "{:?}", calls.lock().unwrap());
+    // node 2 never led, so it never registered
+    let puts = calls.lock().unwrap().iter().filter(|(path, _)| path == "/v3/kv/put").count();
+    assert_eq!(puts, 1);
+    two.shutdown().await.unwrap();
+}
+
+/// `text` in base64, as etcd's gateway takes keys and values.
+fn base64(text: &str) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    text.as_bytes().chunks(3).flat_map(|chunk| {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| bits | (byte as u32) << (16 - 8 * i));
+        (0..4).map(move |i| match i <= chunk.len() {
+            true => ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char,
+            false => '=',
+        })
+    }).collect()
+}
@@ -0,0 +1,34 @@
+use std::path::Path;
+
+use grpc_le::leader_election_service::Decision;
+use grpc_le::replay::{replay, replay_files, Divergence, Recorded};
+use grpc_le::NodeState;
+
+/// An election of a ring of nodes 3, 1, 4 and 2, as they logged it.
+const RING_OF_FOUR: &str = "tests/recordings/ring_of_four.jsonl";
+
+#[test]
+fn a_recorded_election_replays_to_the_same_states() {
+    let replayed = replay_files(&[Path::new(RING_OF_FOUR)]).unwrap();
+    assert_eq!(replayed.divergences, []);
+    let leaders = |leader| NodeState::Defeated { leader: Some(leader) };
+    assert!(replayed.json().starts_with(r#"{"states": [{"node": 1, "state": "leader"}, {"node": 2, "state": "defeated", "leader": 1}, "#));
+    assert!(replayed.json().ends_with(r#"], "divergences": []}"#));
+    assert_eq!(replayed.states.into_iter().collect::<Vec<_>>(), [(1, NodeState::Leader), (2, leaders(1)), (3, leaders(1)), (4, leaders(1))]);
+}
+
+#[test]
+fn replaying_finds_what_the_state_machine_would_not_have_done() {
+    let timeline = std::fs::read_to_string(RING_OF_FOUR).unwrap().lines().map(|line| {
+        // node 2 lets node 4's probe past it, and believes itself the leader
+        if line.contains(r#""node": 2, "event": "received""#) && line.contains(r#""value": 4"#) {
+            return line.replace(r#""decision": "defeated""#, r#""decision": "forwarded""#)
+        }
+        line.replace(r#""node": 2, "event": "state", "term": 0, "state": "defeated", "leader": 1"#, r#""node": 2, "event": "state", "term": 0, "state": "leader""#)
+    }).collect::<Vec<_>>();
+    let replayed = replay(&timeline).unwrap();
+    assert!(matches!(replayed.divergences[..], [
+        Divergence::Decision { node: 2, sender: 4, recorded: Decision::Forwarded, replayed: Decision::Defeated, .. },
+        Divergence::State { node: 2, recorded: Recorded::Leader, replayed: Recorded::Defeated { leader: Some(1) } },
+    ]), "{:?}", replayed.divergences);
+}
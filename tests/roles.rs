@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+use grpc_le::config::{Config, Role};
+use grpc_le::groups::MultiGroupNode;
+use grpc_le::leader_election_service::leader_election_service_client::LeaderElectionServiceClient;
+use grpc_le::leader_election_service::LeaderRequest;
+use grpc_le::testkit::free_addrs;
+use grpc_le::topology::{Link, NodeSpec};
+use tonic::Code;
+
+#[test]
+fn roles_parse_with_an_optional_priority() {
+    assert_eq!("scheduler".parse::<Role>(), Ok(Role { name: "scheduler".to_string(), priority: None }));
+    assert_eq!("compactor=2".parse::<Role>(), Ok(Role { name: "compactor".to_string(), priority: Some(2) }));
+    assert!("".parse::<Role>().is_err());
+    assert!("two words".parse::<Role>().is_err());
+    assert!("compactor=high".parse::<Role>().is_err());
+}
+
+#[tokio::test]
+async fn each_role_elects_a_leader_of_its_own() {
+    // ports the OS picked and nothing listens on any more
+    let [one, two] = free_addrs();
+    let link = |id, addr| Link { id, url: format!("http://{}", addr) };
+    let roles = |compactor| ["scheduler".parse::<Role>().unwrap(), Role { name: "compactor".to_string(), priority: compactor }];
+    let config = Config::default();
+    let nodes = [
+        MultiGroupNode::with_roles(&[], &roles(None), &NodeSpec::ring(1, one.parse().unwrap(), link(2, &two), link(2, &two)), 2, &config, None).unwrap(),
+        // node 2 is preferred only as the compactor
+        MultiGroupNode::with_roles(&[], &roles(Some(1)), &NodeSpec::ring(2, two.parse().unwrap(), link(1, &one), link(1, &one)), 2, &config, None).unwrap(),
+    ];
+    for (node, addr) in nodes.iter().zip([&one, &two]) {
+        let (node, addr) = (node.clone(), addr.parse().unwrap());
+        tokio::spawn(async move { node.run(addr, &Config::default()).await });
+    }
+    let elected = tokio::time::timeout(Duration::from_secs(5), async {
+        while nodes.iter().any(|node| node.role_leader("scheduler").is_none() || node.role_leader("compactor").is_none()) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await;
+    assert!(elected.is_ok());
+    for node in &nodes {
+        assert_eq!(node.role_leader("scheduler"), Some(1), "node {}", node.id());
+        assert_eq!(node.role_leader("compactor"), Some(2), "node {}", node.id());
+        assert_eq!(node.role_leader("janitor"), None);
+    }
+
+    let mut client = LeaderElectionServiceClient::connect(format!("http://{}", one)).await.unwrap();
+    let leader = client.get_leader(LeaderRequest { role: "compactor".to_string(), ..LeaderRequest::default() }).await.unwrap().into_inner();
+    assert_eq!((leader.leader_known, leader.leader_id), (true, 2));
+    let unknown = client.get_leader(LeaderRequest { role: "janitor".to_string(), ..LeaderRequest::default() }).await.unwrap_err();
+    assert_eq!(unknown.code(), Code::NotFound, "{}", unknown);
+    nodes.iter().for_each(MultiGroupNode::shutdown);
+}
@@ -0,0 +1,13 @@
+use std::time::Duration;
+
+use grpc_le::config::Config;
+use grpc_le::selftest;
+
+#[tokio::test]
+async fn a_local_ring_passes_the_self_test() {
+    let report = selftest::run(3, &Config::default(), Duration::from_secs(20)).await.unwrap();
+    assert_eq!(report.leader, 1);
+    assert_eq!(report.acknowledged.iter().map(|&(id, _)| id).collect::<Vec<_>>(), [1, 2, 3]);
+    assert!(report.json().starts_with(r#"{"leader": 1, "nodes": [{"id": 1, "decided_ms": "#), "{}", report.json());
+    assert!(selftest::run(1, &Config::default(), Duration::from_secs(1)).await.is_err());
+}
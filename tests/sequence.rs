@@ -0,0 +1,91 @@
+use std::sync::atomic::Ordering;
+
+use grpc_le::leader_election_service::Sequence;
+use grpc_le::sequence::Receipts;
+
+/// Message `number` of run `incarnation` of node 2, sent to its right.
+fn seq(incarnation: u64, number: u64) -> Sequence {
+    Sequence { sender: 2, incarnation, number, leftward: false }
+}
+
+/// Whether `receipts` takes each of `numbers` from the first run of node 2.
+fn accept_all(receipts: &Receipts, numbers: impl IntoIterator<Item = u64>) -> Vec<bool> {
+    numbers.into_iter().map(|number| receipts.accept(1, Some(&seq(1, number)))).collect()
+}
+
+fn counts(receipts: &Receipts) -> (u64, u64, u64) {
+    (receipts.duplicates.load(Ordering::SeqCst), receipts.gaps.load(Ordering::SeqCst), receipts.reordered.load(Ordering::SeqCst))
+}
+
+#[test]
+fn messages_in_order_are_applied_once() {
+    let receipts = Receipts::default();
+    assert!(receipts.accept(1, None));
+    assert!(receipts.accept(1, None));
+    assert_eq!(accept_all(&receipts, [1, 2, 3, 3, 2, 4]), [true, true, true, false, false, true]);
+    assert_eq!(counts(&receipts), (2, 0, 0));
+}
+
+#[test]
+fn each_sender_and_direction_is_numbered_apart() {
+    let receipts = Receipts::default();
+    assert!(receipts.accept(1, Some(&seq(1, 1))));
+    assert!(receipts.accept(1, Some(&Sequence { leftward: true, ..seq(1, 1) })));
+    assert!(receipts.accept(1, Some(&Sequence { sender: 3, ..seq(1, 1) })));
+    assert!(!receipts.accept(1, Some(&seq(1, 1))));
+    assert_eq!(counts(&receipts), (1, 0, 0));
+}
+
+#[test]
+fn a_new_run_of_the_sender_starts_numbering_afresh() {
+    let receipts = Receipts::default();
+    assert_eq!(accept_all(&receipts, [1, 2, 3]), [true; 3]);
+    assert!(receipts.accept(1, Some(&seq(2, 1))));
+    assert!(receipts.accept(1, Some(&seq(2, 2))));
+    // what the earlier run still had on its way
+    assert!(!receipts.accept(1, Some(&seq(1, 4))));
+    assert!(!receipts.accept(1, Some(&seq(2, 2))));
+    assert_eq!(counts(&receipts), (1, 0, 0));
+}
+
+#[test]
+fn missing_messages_count_as_gaps_until_they_arrive_late() {
+    let receipts = Receipts::default();
+    assert_eq!(accept_all(&receipts, [1, 4]), [true, true]);
+    assert_eq!(counts(&receipts), (0, 2, 0));
+    // 3 and 2 arrive after 4, and only once
+    assert_eq!(accept_all(&receipts, [3, 2, 3, 5]), [true, true, false, true]);
+    assert_eq!(counts(&receipts), (1, 2, 2));
+}
+
+#[test]
+fn only_the_latest_missing_messages_are_remembered() {
+    let receipts = Receipts::default();
+    // 998 missing, of which the last 256 are remembered
+    assert_eq!(accept_all(&receipts, [1, 1000]), [true, true]);
+    assert_eq!(accept_all(&receipts, [743, 744, 999]), [false, true, true]);
+    assert_eq!(counts(&receipts), (1, 998, 2));
+
+    let receipts = Receipts::default();
+    // 198 then 99 missing, past the limit by the 41 oldest
+    assert_eq!(accept_all(&receipts, [1, 200, 300]), [true; 3]);
+    assert_eq!(accept_all(&receipts, [42, 43, 199, 201]), [false, true, true, true]);
+    assert_eq!(counts(&receipts), (1, 297, 3));
+}
+
+#[test]
+fn made_up_senders_wear_out_only_the_lanes_not_heard_from_lately() {
+    let receipts = Receipts::default();
+    assert_eq!(accept_all(&receipts, [1, 2]), [true, true]);
+    let forged = |sender| Sequence { sender, ..seq(1, 1) };
+    for sender in 100..10_100 {
+        assert!(receipts.accept(1, Some(&forged(sender))));
+        if sender % 1000 == 0 {
+            assert!(!receipts.accept(1, Some(&seq(1, 2))));
+        }
+    }
+    // node 2 kept its lane, the first made-up senders lost theirs
+    assert!(!receipts.accept(1, Some(&seq(1, 2))));
+    assert!(!receipts.accept(1, Some(&forged(10_099))));
+    assert!(receipts.accept(1, Some(&forged(100))));
+}
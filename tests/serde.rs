@@ -0,0 +1,20 @@
+#![cfg(feature = "serde")]
+
+use grpc_le::leader_election_service::peer_message::Body;
+use grpc_le::{PeerMessage, ProbeMessage, Sequence};
+
+#[test]
+fn a_relayed_probe_round_trips_through_json() {
+    let probe = ProbeMessage {
+        sender_id: 3, headed_left: true, phase: 2, seq: Some(Sequence { sender: 4, incarnation: 7, number: 12, leftward: false }),
+        term: 1, priority: 0, group_id: 0, hops: 1, hops_remaining: 0, zone: "eu-west-1a".to_string(),
+    };
+    let json = serde_json::to_string(&probe).unwrap();
+    assert_eq!(json, concat!(
+        r#"{"sender_id":3,"headed_left":true,"phase":2,"seq":{"sender":4,"incarnation":7,"number":12,"leftward":false},"#,
+        r#""term":1,"priority":0,"group_id":0,"hops":1,"hops_remaining":0,"zone":"eu-west-1a"}"#,
+    ));
+    let message = PeerMessage { body: Some(Body::Probe(probe)), lamport: 5, number: 12, version: 2, ..PeerMessage::default() };
+    let parsed: PeerMessage = serde_json::from_str(&serde_json::to_string(&message).unwrap()).unwrap();
+    assert_eq!(parsed, message);
+}
@@ -0,0 +1,336 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use grpc_le::config::Config;
+use grpc_le::json::{self, Json};
+use grpc_le::mock::{Script, ScriptedTransport};
+use grpc_le::simulation::{Chaos, Delivery, Report, Simulation};
+use grpc_le::leader_election_service::leader_election_service_server::LeaderElectionService;
+use grpc_le::leader_election_service::PreVoteRequest;
+use grpc_le::topology::Topology;
+use grpc_le::transport::{Gate, GatedTransport, MemoryTransport};
+use grpc_le::{node_client, ElectionResult, LeaderPayload, Node, Payload};
+use tonic::Request;
+
+/// Far more virtual time than any of these elections needs.
+const LIMIT: Duration = Duration::from_secs(60);
+
+fn elect(ids: &[u16], delivery: Delivery) -> Report {
+    Simulation::new(ids, delivery).run(LIMIT)
+}
+
+fn assert_elects_smallest(ids: &[u16], delivery: Delivery) {
+    let report = elect(ids, delivery);
+    let smallest = *ids.iter().min().unwrap() as u64;
+    assert_eq!(report.leader(), Some(smallest), "ring {:?} with {:?} ended in {:?}", ids, delivery, report.results);
+}
+
+/// Every ordering of `ids`, by Heap's algorithm.
+fn permutations(mut ids: Vec<u16>) -> Vec<Vec<u16>> {
+    fn permute(k: usize, ids: &mut Vec<u16>, out: &mut Vec<Vec<u16>>) {
+        if k <= 1 {
+            out.push(ids.clone());
+            return
+        }
+        for i in 0..k {
+            permute(k - 1, ids, out);
+            ids.swap(if k.is_multiple_of(2) { i } else { 0 }, k - 1);
+        }
+    }
+    let mut out = vec![];
+    permute(ids.len(), &mut ids, &mut out);
+    out
+}
+
+#[test]
+fn elects_one_leader_in_rings_of_every_size() {
+    for size in 2..=16u16 {
+        // scatter the IDs so the smallest is not always first
+        let ids = (0..size).map(|i| (i * 7 + 3) % 31 + 1).collect::<Vec<_>>();
+        assert_elects_smallest(&ids, Delivery::RoundRobin);
+    }
+}
+
+#[test]
+fn elects_one_leader_for_every_permutation_of_ids() {
+    for ids in permutations(vec![4, 8, 15, 16, 23]) {
+        assert_elects_smallest(&ids, Delivery::RoundRobin);
+    }
+}
+
+#[test]
+fn elects_one_leader_whatever_the_delivery_order() {
+    for seed in 0..32 {
+        assert_elects_smallest(&[9, 2, 7, 5, 11, 3, 8], Delivery::Shuffled(seed));
+    }
+}
+
+#[test]
+fn elects_the_node_of_the_highest_priority() {
+    let mut topology = Topology::from_ids(&[6, 1, 9, 4, 12, 3]);
+    for (member, priority) in topology.members.iter_mut().zip([0, 0, 2, 1, 2, 0]) {
+        member.priority = priority;
+    }
+    for seed in 0..8 {
+        let report = Simulation::of(&topology.nodes(), Delivery::Shuffled(seed)).run(LIMIT);
+        // of the nodes of priority 2, the one with the smaller ID
+        assert_eq!(report.leader(), Some(9), "seed {} ended in {:?}", seed, report.results);
+    }
+}
+
+#[test]
+fn runs_with_the_same_seed_are_identical() {
+    let ids = [6, 1, 9, 4, 12, 3];
+    assert_eq!(elect(&ids, Delivery::Shuffled(42)), elect(&ids, Delivery::Shuffled(42)));
+}
+
+#[test]
+fn elects_one_leader_despite_chaos() {
+    let ids = [12, 5, 9, 2, 14, 7, 3, 10];
+    for seed in 0..32 {
+        let chaos = Chaos { drop: 0.1, delay: 0.2, duplicate: 0.1, crash: 0.05, seed };
+        let report = Simulation::new(&ids, Delivery::Shuffled(seed)).with_chaos(chaos).run(LIMIT);
+        assert!(report.faults.dropped + report.faults.delayed + report.faults.duplicated + report.faults.crashed > 0);
+        // safety: never two leaders at once
+        assert!(report.most_leaders <= 1, "seed {} let {} nodes lead at once", seed, report.most_leaders);
+        // liveness: eventually a leader everyone follows
+        assert_eq!(report.leader(), Some(2), "seed {} ended in {:?} after {:?}", seed, report.results, report.faults);
+    }
+}
+
+#[test]
+fn reports_the_simulation_as_json() {
+    let chaos = Chaos { drop: 0.1, delay: 0.2, duplicate: 0.1, crash: 0.0, seed: 7 };
+    let report = Simulation::new(&[4, 2, 9], Delivery::Shuffled(7)).with_chaos(chaos).run(LIMIT);
+    let json = json::parse(&report.json()).unwrap();
+    assert!(matches!(json.get("leader"), Some(Json::Number(leader)) if *leader == 2.0), "{}", report.json());
+    assert!(matches!(json.get("results"), Some(Json::Array(results)) if results.len() == 3));
+    assert!(matches!(json.get("faults").and_then(|faults| faults.get("dropped")), Some(Json::Number(_))));
+}
+
+#[tokio::test(start_paused = true)]
+async fn elects_one_leader_over_the_memory_transport() {
+    let specs = Topology::from_ids(&[7, 3, 10, 5]).nodes();
+    let network = Arc::new(MemoryTransport::default());
+    let nodes = specs.iter()
+        .map(|spec| Node::new(spec, specs.len() as u64, &Config::default(), None).unwrap().with_transport(network.clone()))
+        .collect::<Vec<_>>();
+    for node in &nodes {
+        network.add(node.clone());
+        tokio::spawn(node_client(node.clone()));
+    }
+    for node in &nodes {
+        let mut results = node.subscribe();
+        let decided = async {
+            while *results.borrow_and_update() == ElectionResult::Undecided {
+                results.changed().await.unwrap();
+            }
+        };
+        tokio::time::timeout(LIMIT, decided).await.expect("every node learns the outcome");
+        let expected = if node.id() == 3 { ElectionResult::Leader } else { ElectionResult::Defeated { leader: 3 } };
+        assert_eq!(*results.borrow(), expected, "node {}", node.id());
+    }
+}
+
+/// Waits until every node of `nodes` follows `leader`, which leads.
+async fn await_leader(nodes: &[Node], leader: u64) {
+    for node in nodes {
+        let mut results = node.subscribe();
+        let expected = if node.id() == leader { ElectionResult::Leader } else { ElectionResult::Defeated { leader } };
+        let settled = async {
+            while *results.borrow_and_update() != expected {
+                results.changed().await.unwrap();
+            }
+        };
+        tokio::time::timeout(LIMIT, settled).await.unwrap_or_else(|_| panic!("node {} ended in {:?}", node.id(), *results.borrow()));
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_node_that_stepped_down_sits_out_the_elections_of_its_cooldown() {
+    let specs = Topology::from_ids(&[7, 3, 10, 5]).nodes();
+    let network = Arc::new(MemoryTransport::default());
+    let config = Config { step_down_cooldown: Some(Duration::from_secs(120)), ..Config::default() };
+    let nodes = specs.iter()
+        .map(|spec| Node::new(spec, specs.len() as u64, &config, None).unwrap().with_transport(network.clone()))
+        .collect::<Vec<_>>();
+    for node in &nodes {
+        network.add(node.clone());
+        tokio::spawn(node_client(node.clone()));
+    }
+    await_leader(&nodes, 3).await;
+    let (three, five) = (&nodes[1], &nodes[3]);
+    assert!(three.step_down().await);
+    // the memory transport does not pass the restart on, node 5 starts it
+    assert!(five.start_election().await);
+    await_leader(&nodes, 5).await;
+
+    // node 3 sits out the next election too, which it would not without a cooldown
+    assert!(five.start_election().await);
+    await_leader(&nodes, 5).await;
+    tokio::time::sleep(Duration::from_secs(120)).await;
+    assert!(five.start_election().await);
+    await_leader(&nodes, 3).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn hooks_run_as_the_nodes_win_and_lose() {
+    let specs = Topology::from_ids(&[7, 3, 10, 5]).nodes();
+    let network = Arc::new(MemoryTransport::default());
+    let (outcomes, mut told) = tokio::sync::mpsc::unbounded_channel();
+    for spec in &specs {
+        let node = Node::new(spec, specs.len() as u64, &Config::default(), None).unwrap().with_transport(network.clone());
+        // does not keep the other hooks from running
+        node.on_leader_changed(|_, _| async { panic!("a faulty hook") });
+        let (elected, defeated) = (outcomes.clone(), outcomes.clone());
+        let id = node.id();
+        node.on_elected(move || {
+            let elected = elected.clone();
+            async move { elected.send((id, ElectionResult::Leader)).unwrap() }
+        });
+        node.on_defeated(move |leader| {
+            let defeated = defeated.clone();
+            async move { defeated.send((id, ElectionResult::Defeated { leader })).unwrap() }
+        });
+        network.add(node.clone());
+        tokio::spawn(node_client(node));
+    }
+    let mut outcomes = vec![];
+    while outcomes.len() < specs.len() {
+        outcomes.push(tokio::time::timeout(LIMIT, told.recv()).await.expect("every node runs a hook").unwrap());
+    }
+    outcomes.sort_by_key(|&(id, _)| id);
+    let defeated = ElectionResult::Defeated { leader: 3 };
+    assert_eq!(outcomes, [(3, ElectionResult::Leader), (5, defeated), (7, defeated), (10, defeated)]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_partitioned_node_is_refused_a_pre_vote_without_bumping_the_term() {
+    let specs = Topology::from_ids(&[7, 3, 10, 5]).nodes();
+    let network = Arc::new(MemoryTransport::default());
+    let gate = Arc::new(Gate::default());
+    let nodes = specs.iter()
+        .map(|spec| Node::new(spec, specs.len() as u64, &Config::default(), None).unwrap())
+        .map(|node| match node.id() {
+            5 => node.with_transport(Arc::new(GatedTransport::new(network.clone(), gate.clone()))),
+            _ => node.with_transport(network.clone()),
+        })
+        .collect::<Vec<_>>();
+    for node in &nodes {
+        network.add(node.clone());
+        tokio::spawn(node_client(node.clone()));
+    }
+    await_leader(&nodes, 3).await;
+    let terms = nodes.iter().map(Node::term).collect::<Vec<_>>();
+    let pre_vote = |node: &Node, candidate_id| {
+        let request = Request::new(PreVoteRequest { candidate_id, group_id: 0 });
+        let node = node.clone();
+        async move { LeaderElectionService::pre_vote(&node, request).await.unwrap().into_inner() }
+    };
+
+    // the digests of leader 3 go round through node 5, and no further
+    gate.pause();
+    tokio::time::sleep(Duration::from_secs(10)).await;
+    assert!(pre_vote(&nodes[2], 5).await.granted, "node 10 still sees the leader");
+    for neighbour in [1, 3] {
+        let answer = pre_vote(&nodes[neighbour], 10).await;
+        assert_eq!((answer.granted, answer.leader_id, answer.term), (false, 3, terms[neighbour]), "node {}", nodes[neighbour].id());
+    }
+    assert_eq!(nodes.iter().map(Node::term).collect::<Vec<_>>(), terms);
+    assert_eq!(*nodes[2].subscribe().borrow(), ElectionResult::Defeated { leader: 3 });
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_node_restarted_with_its_state_dir_comes_back_following_the_leader() {
+    let dir = std::env::temp_dir().join(format!("grpc-le-state-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = Config { state_dir: Some(dir.clone()), ..Config::default() };
+    let specs = Topology::from_ids(&[7, 3, 10, 5]).nodes();
+    let network = Arc::new(MemoryTransport::default());
+    let node = |spec, config: &Config| Node::new(spec, specs.len() as u64, config, None).unwrap().with_transport(network.clone());
+    let mut nodes = specs.iter().map(|spec| node(spec, &config)).collect::<Vec<_>>();
+    for node in &nodes {
+        network.add(node.clone());
+        tokio::spawn(node_client(node.clone()));
+    }
+    await_leader(&nodes, 3).await;
+    assert!(nodes[0].start_election().await);
+    let term = nodes[0].term();
+    let reelected = async {
+        while nodes.iter().any(|node| node.term() < term) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(LIMIT, reelected).await.expect("every node takes up the new term");
+    await_leader(&nodes, 3).await;
+
+    // without its state, node 10 would come back a candidate of the first term
+    nodes[2].shutdown();
+    let forgetful = node(&specs[2], &Config::default());
+    assert_eq!((*forgetful.subscribe().borrow(), forgetful.term()), (ElectionResult::Undecided, 0));
+    nodes[2] = node(&specs[2], &config);
+    assert_eq!((*nodes[2].subscribe().borrow(), nodes[2].term()), (ElectionResult::Defeated { leader: 3 }, term));
+    network.add(nodes[2].clone());
+    tokio::spawn(node_client(nodes[2].clone()));
+    tokio::time::sleep(Duration::from_secs(10)).await;
+    await_leader(&nodes, 3).await;
+    assert!(nodes.iter().all(|node| node.term() == term), "{:?}", nodes.iter().map(Node::term).collect::<Vec<_>>());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// Tells the ring the term of each election the node wins.
+#[derive(Debug)]
+struct Epoch;
+
+impl LeaderPayload for Epoch {
+    fn payload(&self, term: u64) -> Payload {
+        Payload { content_type: "text/plain".to_string(), data: format!("epoch {}", term).into_bytes() }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn the_followers_hooks_get_the_leaders_payload() {
+    let specs = Topology::from_ids(&[7, 3, 10, 5]).nodes();
+    let network = Arc::new(MemoryTransport::default());
+    let (payloads, mut told) = tokio::sync::mpsc::unbounded_channel();
+    for spec in &specs {
+        let node = Node::new(spec, specs.len() as u64, &Config::default(), None).unwrap().with_transport(network.clone()).with_payload(Arc::new(Epoch));
+        let (payloads, id) = (payloads.clone(), node.id());
+        node.on_leader_changed(move |leader, payload| {
+            let payloads = payloads.clone();
+            async move { payloads.send((id, leader, payload.map(|payload| payload.data))).unwrap() }
+        });
+        network.add(node.clone());
+        tokio::spawn(node_client(node));
+    }
+    let mut told_of = vec![];
+    while told_of.len() < specs.len() {
+        told_of.push(tokio::time::timeout(LIMIT, told.recv()).await.expect("every node runs its hook").unwrap());
+    }
+    told_of.sort_by_key(|&(id, ..)| id);
+    let epoch = Some(b"epoch 0".to_vec());
+    assert_eq!(told_of, [(3, Some(3), epoch.clone()), (5, Some(3), epoch.clone()), (7, Some(3), epoch.clone()), (10, Some(3), epoch)]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn elects_the_leader_past_a_scripted_peer() {
+    assert_eq!("drop digest\nphase x".parse::<Script>().unwrap_err(), "2: invalid number \"x\": invalid digit found in string");
+    let script = "# slow, and loses the digests\ndelay * 300\ndrop digest".parse::<Script>().unwrap();
+    let specs = Topology::from_ids(&[7, 3, 10, 5]).nodes();
+    let network = Arc::new(MemoryTransport::default());
+    let nodes = specs.iter()
+        .map(|spec| Node::new(spec, specs.len() as u64, &Config::default(), None).unwrap())
+        .map(|node| match node.id() {
+            10 => node.with_transport(Arc::new(ScriptedTransport::new(network.clone(), script.clone()))),
+            _ => node.with_transport(network.clone()),
+        })
+        .collect::<Vec<_>>();
+    for node in &nodes {
+        network.add(node.clone());
+        tokio::spawn(node_client(node.clone()));
+    }
+    for node in &nodes {
+        let ring_acknowledged = tokio::time::timeout(LIMIT, node.await_ring_acknowledged()).await;
+        assert_eq!(ring_acknowledged, Ok(3), "node {}", node.id());
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::VecDeque;
+
+use proptest::prelude::*;
+
+use grpc_le::state_machine::{apply, react, Command, Event, Input, Me};
+use grpc_le::leader_election_service::Decision;
+use grpc_le::NodeState;
+
+/// How the ring algorithm ranks nodes of the same priority.
+fn prefer(a: u64, b: u64) -> u64 {
+    a.min(b)
+}
+
+fn event() -> impl Strategy<Value = Event> {
+    prop_oneof![
+        Just(Event::Probe),
+        Just(Event::NextPhase),
+        Just(Event::Defeat),
+        Just(Event::Lead),
+        (1..8u64).prop_map(|leader| Event::Notified { leader }),
+    ]
+}
+
+/// The transitions node `id` takes through `events` from its initial state,
+/// skipping those its state refuses.
+fn transitions(id: u64, events: &[Event]) -> Vec<(NodeState, Event, NodeState)> {
+    let mut state = NodeState::default();
+    let mut taken = vec![];
+    for &event in events {
+        if let Ok(next) = apply(&state, id, event, prefer) {
+            taken.push((state, event, next.clone()));
+            state = next;
+        }
+    }
+    taken
+}
+
+/// Runs the election over a ring of `ids` by [`react`] alone, polling every
+/// node each round and delivering the probes sent in between, until someone
+/// announces itself the leader or `rounds` run out. Returns every node that
+/// did.
+fn elect(ids: &[u64], rounds: usize) -> Vec<u64> {
+    let n = ids.len();
+    let mut states = vec![NodeState::default(); n];
+    let mut deferred = vec![None; n];
+    // probes in flight: (receiver, sender, the way they head)
+    let mut probes = VecDeque::new();
+    let mut leaders = vec![];
+    let me = |i: usize| Me { id: ids[i], priority: 0, preferred: false, abstains: false };
+    let neighbor = |i: usize, left: bool| if left { (i + n - 1) % n } else { (i + 1) % n };
+    for _ in 0..rounds {
+        let mut inputs: VecDeque<_> = (0..n).map(|i| (i, Input::Poll, None)).collect();
+        while let Some((i, input, headed_left)) = inputs.pop_front().or_else(|| {
+            probes.pop_front().map(|(i, sender, left): (usize, u64, bool)| (i, Input::Probe { sender, priority: 0, preferred: false }, Some(left)))
+        }) {
+            for command in react(&states[i], me(i), input) {
+                match command {
+                    Command::Take(event) => {
+                        states[i] = apply(&states[i], ids[i], event, prefer).expect("the state allows what it reacts with");
+                        if let Some(event) = deferred[i].take() {
+                            inputs.push_back((i, Input::Settle(event), None));
+                        }
+                    },
+                    Command::SendProbe { headed_left, .. } => probes.push_back((neighbor(i, headed_left), ids[i], headed_left)),
+                    Command::ForwardProbe => {
+                        let (headed_left, Input::Probe { sender, .. }) = (headed_left.unwrap(), input) else { unreachable!() };
+                        probes.push_back((neighbor(i, headed_left), sender, headed_left));
+                    },
+                    Command::Defer(event) => deferred[i] = Some(event),
+                    Command::Announce => leaders.push(ids[i]),
+                    Command::Decide(_) | Command::Poll => (),
+                }
+            }
+        }
+        if !leaders.is_empty() {
+            break
+        }
+    }
+    leaders
+}
+
+proptest! {
+    #[test]
+    fn the_sans_io_core_elects_the_best_node(ids in Just((1..12u64).collect::<Vec<_>>()).prop_shuffle(), n in 2..12usize) {
+        let ids = &ids[..n];
+        let best = *ids.iter().min().unwrap();
+        prop_assert_eq!(elect(ids, 8 * n), vec![best]);
+    }
+
+
+    #[test]
+    fn a_leader_is_only_defeated_by_a_preferred_leader(id in 1..8u64, events in prop::collection::vec(event(), 0..64)) {
+        for (before, event, after) in transitions(id, &events) {
+            if before == NodeState::Leader && matches!(after, NodeState::Defeated { .. }) {
+                prop_assert!(matches!(event, Event::Notified { leader } if prefer(id, leader) == leader && leader != id),
+                    "leader {} defeated by {:?}", id, event);
+            }
+        }
+    }
+
+    #[test]
+    fn a_leader_refuses_to_be_defeated(id in 1..8u64, events in prop::collection::vec(event(), 0..64)) {
+        for (_, _, after) in transitions(id, &events).into_iter().filter(|(_, _, after)| *after == NodeState::Leader) {
+            prop_assert!(apply(&after, id, Event::Defeat, prefer).is_err());
+        }
+    }
+
+    #[test]
+    fn the_phase_is_monotonic(id in 1..8u64, events in prop::collection::vec(event(), 0..64)) {
+        for (before, event, after) in transitions(id, &events) {
+            match (before, after) {
+                (NodeState::Candidate { phase, last_phase_probed }, NodeState::Candidate { phase: next, last_phase_probed: probed }) => {
+                    prop_assert!(phase <= next && next <= phase + 1, "phase {} became {} on {:?}", phase, next, event);
+                    prop_assert!(last_phase_probed <= probed && probed <= next, "probed phase {} became {} on {:?}", last_phase_probed, probed, event);
+                },
+                (before, after @ NodeState::Candidate { .. }) => prop_assert!(false, "{:?} became {:?} again on {:?}", before, after, event),
+                _ => (),
+            }
+        }
+    }
+
+    #[test]
+    fn a_known_leader_is_never_forgotten(id in 1..8u64, events in prop::collection::vec(event(), 0..64)) {
+        for (before, event, after) in transitions(id, &events) {
+            if let NodeState::Defeated { leader: Some(leader) } = before {
+                prop_assert!(matches!(after, NodeState::Defeated { leader: Some(_) }),
+                    "following {}, {:?} led to {:?}", leader, event, after);
+            }
+        }
+    }
+}
+
+#[test]
+fn the_preferred_zone_breaks_ties_between_priorities() {
+    let candidate = NodeState::Candidate { phase: 1, last_phase_probed: 1 };
+    let decide = |me: Me, input: Input| react(&candidate, me, input).into_iter().find_map(|command| match command {
+        Command::Decide(decision) => Some(decision),
+        _ => None,
+    });
+    let me = Me { id: 1, priority: 0, preferred: false, abstains: false };
+    assert_eq!(decide(me, Input::Probe { sender: 5, priority: 0, preferred: true }), Some(Decision::Forwarded));
+    assert_eq!(decide(me, Input::Probe { sender: 5, priority: 0, preferred: false }), Some(Decision::Defeated));
+    // the priority still comes first
+    let me = Me { priority: 1, ..me };
+    assert_eq!(decide(me, Input::Probe { sender: 5, priority: 0, preferred: true }), Some(Decision::Defeated));
+}
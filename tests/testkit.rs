@@ -0,0 +1,49 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use grpc_le::config::Config;
+use grpc_le::leader_election_service::leader_election_service_server::LeaderElectionService;
+use grpc_le::leader_election_service::StateRequest;
+use grpc_le::testkit::{wait_until, TestRing};
+use tonic::Request;
+
+const CONVERGED: Duration = Duration::from_secs(5);
+
+#[tokio::test]
+async fn a_test_ring_elects_again_around_a_crashed_leader_and_takes_it_back() {
+    let config = Config { liveness_interval: Some(Duration::from_millis(100)), ..Config::default() };
+    let mut ring = TestRing::start(4, config);
+    assert_eq!(ring.await_leader(CONVERGED).await, Some(1));
+    ring.crash(1).await;
+    assert_eq!(ring.await_leader(CONVERGED).await, Some(2));
+    assert_eq!(ring.running().map(|node| node.id()).collect::<Vec<_>>(), [2, 3, 4]);
+
+    ring.restart(1).await;
+    assert_eq!(ring.await_leader(CONVERGED).await, Some(1));
+    for node in ring.running() {
+        let state = node.get_state(Request::new(StateRequest::default())).await.unwrap().into_inner();
+        assert_eq!(state.ring_size, 4, "node {}", node.id());
+    }
+    ring.shutdown().await;
+}
+
+#[tokio::test]
+async fn leader_only_work_moves_with_the_leader_of_a_test_ring() {
+    let config = Config { liveness_interval: Some(Duration::from_millis(100)), ..Config::default() };
+    let leading = Arc::new(AtomicU64::new(0));
+    let mut ring = TestRing::start(3, config);
+    for node in ring.running() {
+        let (elected, id) = (leading.clone(), node.id());
+        node.on_elected(move || {
+            let elected = elected.clone();
+            async move { elected.store(id, Ordering::SeqCst) }
+        });
+    }
+    assert_eq!(ring.await_leader(CONVERGED).await, Some(1));
+    assert!(wait_until(CONVERGED, || async { leading.load(Ordering::SeqCst) == 1 }).await);
+    ring.crash(1).await;
+    assert!(wait_until(CONVERGED, || async { leading.load(Ordering::SeqCst) == 2 }).await);
+    assert!(!wait_until(Duration::from_millis(100), || async { leading.load(Ordering::SeqCst) == 3 }).await);
+    ring.shutdown().await;
+}